@@ -0,0 +1,56 @@
+//! Ping hosts with a `Pinger`, counting probe events through a hook instead
+//! of having pingoc print anything.
+//!
+//! Usage: cargo run --example ping_events -- <ipv4>...
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::net::Ipv4Addr;
+use std::rc::Rc;
+use std::time::Duration;
+
+use pingoc::ping::pinger::{Pinger, ProbeEvent};
+
+#[derive(Debug, Default)]
+struct Counts {
+    sent: usize,
+    replies: usize,
+    lost: usize,
+    errors: usize,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let hosts = std::env::args()
+        .skip(1)
+        .map(|host| host.parse::<Ipv4Addr>())
+        .collect::<Result<Vec<_>, _>>()?;
+    if hosts.is_empty() {
+        return Err("usage: ping_events <ipv4>...".into());
+    }
+
+    let counts = Rc::new(RefCell::new(Counts::default()));
+    let hook_counts = Rc::clone(&counts);
+
+    let mut pinger = Pinger::new(&hosts, Duration::from_millis(200))?
+        .with_count(3)
+        .with_interval(Duration::from_millis(200))
+        .on_event(move |event| {
+            let mut counts = hook_counts.borrow_mut();
+            match event {
+                ProbeEvent::Sent { .. } => counts.sent += 1,
+                ProbeEvent::Reply(_) => counts.replies += 1,
+                ProbeEvent::Timeout { .. } => counts.lost += 1,
+                ProbeEvent::IcmpError { .. }
+                | ProbeEvent::NotSent { .. }
+                | ProbeEvent::Error { .. } => counts.errors += 1,
+                _ => {}
+            }
+        });
+
+    pinger.send_all()?;
+    pinger.collect(Duration::from_secs(1));
+    pinger.expire()?;
+
+    println!("{:?}", counts.borrow());
+    Ok(())
+}
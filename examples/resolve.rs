@@ -0,0 +1,40 @@
+//! Resolve a name by iterating from the root servers and print every record
+//! of the response.
+//!
+//! Usage: cargo run --example resolve -- <name> [type]
+
+use std::error::Error;
+
+use pingoc::dns::query::DnsQueryType;
+use pingoc::dns::resolve::recursive_lookup;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let name = args.next().ok_or("usage: resolve <name> [type]")?;
+    let query_type = match args.next() {
+        Some(query_type) => query_type.parse::<DnsQueryType>()?,
+        None => DnsQueryType::A,
+    };
+
+    let response = recursive_lookup(&name, query_type)?;
+
+    println!(
+        ";; status: {:?}, id: {}",
+        response.header.response_code, response.header.id
+    );
+    for (section, records) in [
+        ("ANSWER", &response.answers),
+        ("AUTHORITY", &response.authorities),
+        ("ADDITIONAL", &response.additional),
+    ] {
+        if records.is_empty() {
+            continue;
+        }
+        println!("\n;; {section} SECTION:");
+        for record in records {
+            println!("{record}");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Serve a small zone from an in-process authoritative server and resolve
+//! names against it with pingoc's own resolver.
+//!
+//! Usage: cargo run --example serve --features server
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::thread;
+
+use pingoc::dns::query::{DnsQueryClass, DnsQueryType};
+use pingoc::dns::record::DnsRecord;
+use pingoc::dns::serve::DnsServer;
+use pingoc::resolve::Resolver;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut zone = HashMap::new();
+    zone.insert(
+        ("host.example.test".to_string(), DnsQueryType::A),
+        vec![DnsRecord::A {
+            domain: "host.example.test".to_string(),
+            class: DnsQueryClass::IN,
+            addr: Ipv4Addr::new(192, 0, 2, 10),
+            ttl: 300,
+        }],
+    );
+    zone.insert(
+        ("v6.example.test".to_string(), DnsQueryType::AAAA),
+        vec![DnsRecord::AAAA {
+            domain: "v6.example.test".to_string(),
+            class: DnsQueryClass::IN,
+            addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+            ttl: 300,
+        }],
+    );
+
+    let server = DnsServer::bind("127.0.0.1:0", zone)?;
+    let address = server.local_addr()?;
+    thread::spawn(move || {
+        if let Err(err) = server.serve() {
+            eprintln!("server stopped: {err}");
+        }
+    });
+
+    let mut resolver = Resolver::new().with_servers(vec![(address.ip(), address.port())]);
+    let v4 = resolver.resolve_v4("host.example.test")?;
+    println!("host.example.test -> {v4}");
+    let v6 = resolver.resolve_v6("v6.example.test")?;
+    println!("v6.example.test -> {v6}");
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Send echo requests with a sweep of identifiers, sequence numbers and
+//! payloads through the socket directly, printing every message received in
+//! return as a hex dump.
+//!
+//! Identifiers only reach the wire unchanged over a raw socket, so run as
+//! root or with CAP_NET_RAW.
+//!
+//! Usage: cargo run --example sweep_ids -- <ipv4> [count]
+
+use std::error::Error;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use pingoc::icmp::buffer::hexdump;
+use pingoc::icmp::packet::IcmpPacket;
+use pingoc::icmp::socket::IcmpSocket;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let destination: Ipv4Addr = args
+        .next()
+        .ok_or("usage: sweep_ids <ipv4> [count]")?
+        .parse()?;
+    let count: u16 = args.next().map_or(Ok(8), |count| count.parse())?;
+
+    let socket = IcmpSocket::new(Duration::from_millis(200))?;
+    println!("{:?} socket", socket.kind());
+
+    for i in 0..count {
+        // Spread the identifiers over the whole range, edges included
+        let id = (u32::from(i) * 0xFFFF / u32::from(count.max(2) - 1)) as u16;
+        let mut packet = IcmpPacket::echo_request(id, i.wrapping_mul(0x101), 0);
+        packet.payload = (0..i).map(|byte| byte as u8).collect();
+
+        let sent = socket.send_raw(packet, destination)?;
+        println!("sent id={id:#06x}");
+        print!("{}", hexdump(&sent));
+
+        // Whatever arrives before the read timeout expires
+        while let Ok((packet, bytes, source)) = socket.recv_raw() {
+            let parsed = packet.map_or("unparsable".to_string(), |packet| {
+                format!("{:?} {:?}", packet.msg_type, packet.content)
+            });
+            println!("  from {source}: {parsed}");
+            print!("{}", hexdump(&bytes));
+        }
+    }
+
+    Ok(())
+}
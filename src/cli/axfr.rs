@@ -0,0 +1,48 @@
+use clap::Args;
+use std::error::Error;
+use std::time::Duration;
+
+use pingoc::dns::axfr::zone_transfer;
+use pingoc::resolve::resolve_hostname;
+
+use super::duration::parse_duration;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Command-line arguments for the axfr subcommand
+#[derive(Args, Debug)]
+pub struct AxfrArgs {
+    /// Zone to transfer
+    zone: String,
+
+    /// Server to transfer the zone from, e.g. `@ns1.example.com`
+    server: String,
+
+    /// TCP port of the server
+    #[arg(short, long, default_value_t = 53)]
+    port: u16,
+
+    /// How long to wait for the connection and each message, e.g. `5s`
+    #[arg(short, long, default_value = "10", value_parser = parse_duration)]
+    timeout: Duration,
+
+    /// Abort the transfer after this many records
+    #[arg(long, default_value_t = 1_000_000)]
+    max_records: usize,
+}
+
+pub fn axfr_handler(args: AxfrArgs) -> Result<()> {
+    let server = args.server.strip_prefix('@').unwrap_or(&args.server);
+    let ip = resolve_hostname(server)?;
+
+    let count = zone_transfer(
+        &args.zone,
+        (ip, args.port),
+        args.timeout,
+        args.max_records,
+        |record| println!("{record}"),
+    )?;
+    println!(";; {count} records transferred from {server} ({ip})");
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr, UdpSocket};
+
+use pingoc::dns::resolv_conf::{ResolvConf, RESOLV_CONF_PATH};
+use pingoc::icmp::socket::{IcmpSocket, IcmpSocketKind};
+use pingoc::resolve::Resolver;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Optional cargo features and whether they were compiled in
+const FEATURES: &[(&str, bool)] = &[
+    ("testing", cfg!(feature = "testing")),
+    ("server", cfg!(feature = "server")),
+    ("serialize", cfg!(feature = "serialize")),
+    ("metrics", cfg!(feature = "metrics")),
+];
+
+/// Print what this build of pingoc supports and what the environment allows
+pub fn capabilities_handler() -> Result<()> {
+    println!("pingoc {}", env!("CARGO_PKG_VERSION"));
+
+    println!("\nICMP sockets:");
+    let datagram = IcmpSocket::probe(IcmpSocketKind::Datagram);
+    let raw = IcmpSocket::probe(IcmpSocketKind::Raw);
+    print_probe("unprivileged (SOCK_DGRAM)", &datagram);
+    print_probe("raw (SOCK_RAW)", &raw);
+    if let Some(range) = ping_group_range() {
+        println!("  {:<28}{range}", "net.ipv4.ping_group_range");
+    }
+    let usable = match (&datagram, &raw) {
+        (Ok(_), _) => "yes, without privileges",
+        (_, Ok(_)) => "yes, with raw socket privileges",
+        _ => "no",
+    };
+    println!("  {:<28}{usable}", "can ping");
+
+    println!("\nIPv6:");
+    print_probe("loopback configured", &ipv6_loopback());
+    print_probe("unprivileged ICMPv6", &probe_icmpv6(libc::SOCK_DGRAM));
+    print_probe("raw ICMPv6", &probe_icmpv6(libc::SOCK_RAW));
+
+    println!("\nDNS:");
+    let conf = ResolvConf::system();
+    let resolver = Resolver::from_resolv_conf(&conf.clone().unwrap_or_default());
+    let servers: Vec<String> = resolver
+        .servers()
+        .iter()
+        .map(|&server| SocketAddr::from(server).to_string())
+        .collect();
+    let configured_by = match conf {
+        Some(conf) if !conf.nameservers.is_empty() => RESOLV_CONF_PATH,
+        _ => "built-in default",
+    };
+    println!(
+        "  {:<28}{} ({configured_by})",
+        "default servers",
+        servers.join(", ")
+    );
+    if !resolver.search().domains().is_empty() {
+        println!(
+            "  {:<28}{}",
+            "search domains",
+            resolver.search().domains().join(" ")
+        );
+    }
+    let root_hints: Vec<String> = resolver
+        .root_hints()
+        .iter()
+        .map(|hint| hint.address.to_string())
+        .collect();
+    println!("  {:<28}{}", "root hints", root_hints.join(", "));
+
+    println!("\nFeatures:");
+    for (feature, enabled) in FEATURES {
+        let state = if *enabled { "enabled" } else { "disabled" };
+        println!("  {feature:<28}{state}");
+    }
+
+    Ok(())
+}
+
+fn print_probe(name: &str, result: &io::Result<()>) {
+    match result {
+        Ok(()) => println!("  {name:<28}yes"),
+        Err(err) => println!("  {name:<28}no ({err})"),
+    }
+}
+
+/// The range of group ids allowed to open unprivileged ICMP sockets
+fn ping_group_range() -> Option<String> {
+    let range = fs::read_to_string("/proc/sys/net/ipv4/ping_group_range").ok()?;
+    Some(range.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Whether IPv6 is enabled, judged by binding to the IPv6 loopback address
+fn ipv6_loopback() -> io::Result<()> {
+    UdpSocket::bind((Ipv6Addr::LOCALHOST, 0))?;
+    Ok(())
+}
+
+fn probe_icmpv6(socket_type: libc::c_int) -> io::Result<()> {
+    let socket = unsafe { libc::socket(libc::AF_INET6, socket_type, libc::IPPROTO_ICMPV6) };
+    if socket < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { libc::close(socket) };
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use clap::Args;
+use std::error::Error;
+use std::net::IpAddr;
+
+use pingoc::dns::packet::DnsPacket;
+use pingoc::dns::query::{DnsQueryClass, DnsQueryType};
+use pingoc::dns::resolve::{
+    lookup_with_options, recursive_lookup_from, LookupOptions, RootHint, TraceStep,
+    ITERATION_TIMEOUT,
+};
+use pingoc::resolve::{is_null_mx, resolve_mx, reverse_name, Resolver};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Command-line arguments for the dig subcommand
+#[derive(Args, Debug)]
+pub struct DigArgs {
+    /// Name to query
+    name: String,
+
+    /// Record type to query (A, AAAA, MX, TXT, ... or TYPE<n>). Without one,
+    /// IP addresses are looked up as PTR, names whose first label starts
+    /// with `_` as SRV and anything else as A.
+    query_type: Option<DnsQueryType>,
+
+    /// Class to query (IN, CH, HS, ...), e.g. `-c CH version.bind TXT`
+    #[arg(short = 'c', long = "class", default_value = "IN")]
+    query_class: DnsQueryClass,
+
+    /// Query this server instead of iterating from the root servers
+    #[arg(short, long)]
+    server: Option<IpAddr>,
+
+    /// Send the query from this local port rather than an ephemeral one,
+    /// e.g. to test firewall rules keyed on the source port
+    #[arg(long, value_name = "PORT", requires = "server")]
+    query_port: Option<u16>,
+
+    /// Clear the RD bit, for asking an authoritative --server directly
+    #[arg(long)]
+    norecurse: bool,
+
+    /// List the mail exchangers of the name along with their addresses
+    #[arg(long)]
+    mx: bool,
+
+    /// Show every server asked while iterating from the root, with the
+    /// referral it gave and how long it took
+    #[arg(long, conflicts_with = "server")]
+    trace: bool,
+
+    /// Iterate from this server rather than the root servers, e.g. a private
+    /// root. Repeat to list fallbacks, tried in order until one answers.
+    #[arg(long = "root", value_name = "ADDR", conflicts_with = "server")]
+    roots: Vec<IpAddr>,
+
+    /// For CNAME queries, follow the CNAMEs to the end of the chain rather
+    /// than showing only the first target
+    #[arg(long)]
+    chain: bool,
+}
+
+pub fn dig_handler(args: DigArgs) -> Result<()> {
+    if args.mx {
+        return mx_handler(&args.name);
+    }
+
+    let (name, query_type) = question(&args.name, args.query_type);
+    let options = LookupOptions {
+        query_class: args.query_class,
+        recursion_desired: !args.norecurse,
+        source_port: args.query_port,
+        ..Default::default()
+    };
+    let response = match args.server {
+        Some(server) => lookup_with_options(&name, query_type, (server, 53), &options)?,
+        // Referrals only exist for IN, other classes are served by the server asked
+        None if args.query_class != DnsQueryClass::IN => {
+            return Err(format!("Querying class {} requires --server", args.query_class).into());
+        }
+        None => {
+            let root_hints = match args.roots.as_slice() {
+                [] => RootHint::root_servers(),
+                roots => roots.iter().map(|&root| RootHint::from(root)).collect(),
+            };
+            let mut trace = |step: &TraceStep| {
+                if args.trace {
+                    print_trace_step(step);
+                }
+            };
+            recursive_lookup_from(
+                &name,
+                query_type,
+                &root_hints,
+                Some(ITERATION_TIMEOUT),
+                &mut trace,
+            )?
+        }
+    };
+    print_response(&response);
+    if let Some(server) = args.server.filter(|_| response.lacks_recursion()) {
+        println!(
+            "\n;; WARNING: server {server} does not provide recursion; \
+             use recursive mode or a recursive resolver"
+        );
+    }
+    if query_type == DnsQueryType::CNAME {
+        print_cname(&args, &response);
+    }
+
+    Ok(())
+}
+
+/// The name and type to query for `name`. An explicit type always wins,
+/// otherwise one is guessed from the name, and IP addresses asked for PTR
+/// records are looked up under their reverse name.
+fn question(name: &str, query_type: Option<DnsQueryType>) -> (String, DnsQueryType) {
+    let ip = name.parse::<IpAddr>().ok();
+    let query_type = query_type.unwrap_or(match ip {
+        Some(_) => DnsQueryType::PTR,
+        None if name.starts_with('_') => DnsQueryType::SRV,
+        None => DnsQueryType::A,
+    });
+
+    match ip {
+        Some(ip) if query_type == DnsQueryType::PTR => (reverse_name(ip), query_type),
+        _ => (name.to_string(), query_type),
+    }
+}
+
+fn print_trace_step(step: &TraceStep) {
+    let outcome = match &step.referral {
+        Some((zone, nameservers)) if step.answers == 0 => {
+            format!("referral to {zone}. via {}", nameservers.join(", "))
+        }
+        _ => format!("{} answers, {:?}", step.answers, step.response_code),
+    };
+    println!(
+        ";; {}. {} from {} ({}) in {:.1} ms{}: {outcome}",
+        step.query_name,
+        step.query_type,
+        step.nameserver,
+        step.address,
+        step.elapsed.as_secs_f64() * 1000.0,
+        if step.over_tcp { " over TCP" } else { "" }
+    );
+}
+
+/// Print where the CNAME of the name points, or the whole chain with --chain
+fn print_cname(args: &DigArgs, response: &DnsPacket) {
+    let targets: Vec<String> = if args.chain {
+        let resolver = match args.server {
+            Some(server) => Resolver::new().with_servers(vec![(server, 53)]),
+            None => Resolver::new(),
+        };
+        resolver.resolve_cname_chain(&args.name)
+    } else {
+        response
+            .cname_chain(&args.name)
+            .into_iter()
+            .take(1)
+            .map(str::to_string)
+            .collect()
+    };
+
+    if targets.is_empty() {
+        println!("\n;; {} has no CNAME", args.name);
+    } else {
+        println!("\n;; CNAME: {} -> {}", args.name, targets.join(" -> "));
+    }
+}
+
+fn mx_handler(domain: &str) -> Result<()> {
+    let exchangers = resolve_mx(domain)?;
+
+    if is_null_mx(&exchangers) {
+        println!("{domain} accepts no mail (null MX)");
+        return Ok(());
+    }
+
+    for (priority, host, addresses) in exchangers {
+        let addresses: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+        println!("{priority:>5} {host}\t{}", addresses.join(", "));
+    }
+
+    Ok(())
+}
+
+fn print_response(response: &DnsPacket) {
+    println!(
+        ";; status: {:?}, id: {}",
+        response.response_code(),
+        response.header.id
+    );
+    if response.incomplete {
+        println!(
+            ";; WARNING: response ended partway through a record, showing the records before it"
+        );
+    }
+
+    for question in &response.questions {
+        println!(
+            "\n;; QUESTION SECTION:\n;{}.\tIN\t{}",
+            question.name, question.query_type
+        );
+    }
+
+    for (section, records) in [
+        ("ANSWER", &response.answers),
+        ("AUTHORITY", &response.authorities),
+        ("ADDITIONAL", &response.additional),
+    ] {
+        if records.is_empty() {
+            continue;
+        }
+        println!("\n;; {section} SECTION:");
+        for record in records {
+            println!("{record}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_type_is_guessed_from_the_name() {
+        assert_eq!(
+            question("192.0.2.1", None),
+            ("1.2.0.192.in-addr.arpa".to_string(), DnsQueryType::PTR)
+        );
+        let (name, query_type) = question("2001:db8::1", None);
+        assert!(name.ends_with(".8.b.d.0.1.0.0.2.ip6.arpa"), "{name}");
+        assert_eq!(query_type, DnsQueryType::PTR);
+        assert_eq!(
+            question("_sip._tcp.example.com", None),
+            ("_sip._tcp.example.com".to_string(), DnsQueryType::SRV)
+        );
+        assert_eq!(
+            question("example.com", None),
+            ("example.com".to_string(), DnsQueryType::A)
+        );
+    }
+
+    #[test]
+    fn explicit_query_type_wins() {
+        assert_eq!(
+            question("192.0.2.1", Some(DnsQueryType::A)),
+            ("192.0.2.1".to_string(), DnsQueryType::A)
+        );
+        assert_eq!(
+            question("_sip._tcp.example.com", Some(DnsQueryType::TXT)),
+            ("_sip._tcp.example.com".to_string(), DnsQueryType::TXT)
+        );
+        assert_eq!(
+            question("example.com", Some(DnsQueryType::MX)),
+            ("example.com".to_string(), DnsQueryType::MX)
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::mpsc::{self, Receiver};
+
+/// A command given by pressing a single key while pinging
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCommand {
+    /// Stop and print the statistics, like Ctrl-C
+    Quit,
+    /// Forget the statistics collected so far
+    ResetStats,
+    /// Stop sending requests until pressed again
+    TogglePause,
+}
+
+/// The command bound to `key`, if any
+pub fn command_for_key(key: u8) -> Option<KeyCommand> {
+    match key.to_ascii_lowercase() {
+        b'q' => Some(KeyCommand::Quit),
+        b'r' => Some(KeyCommand::ResetStats),
+        b'p' | b' ' => Some(KeyCommand::TogglePause),
+        _ => None,
+    }
+}
+
+/// Single-key commands read from the terminal by a background thread.
+///
+/// The terminal is put in noncanonical mode without echo, so keys take
+/// effect without Enter, and restored when this is dropped. Ctrl-C still
+/// interrupts as usual.
+pub struct KeyCommands {
+    commands: Receiver<KeyCommand>,
+    _terminal: TerminalMode,
+}
+
+impl KeyCommands {
+    /// Start reading commands, `None` if stdin isn't a terminal or its mode
+    /// can't be changed
+    pub fn start() -> Option<Self> {
+        let terminal = TerminalMode::noncanonical()?;
+        let (sender, commands) = mpsc::channel();
+
+        // Blocks on stdin for the rest of the run, it ends with the process
+        std::thread::spawn(move || {
+            while let Some(key) = read_key() {
+                if let Some(command) = command_for_key(key) {
+                    if sender.send(command).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Some(Self {
+            commands,
+            _terminal: terminal,
+        })
+    }
+
+    /// Commands given since the last call, in the order the keys were pressed
+    pub fn pending(&self) -> impl Iterator<Item = KeyCommand> + '_ {
+        self.commands.try_iter()
+    }
+}
+
+/// The terminal settings of stdin before they were changed, restored on drop
+#[cfg(unix)]
+struct TerminalMode(libc::termios);
+
+#[cfg(unix)]
+impl TerminalMode {
+    fn noncanonical() -> Option<Self> {
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+            return None;
+        }
+
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return None;
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return None;
+        }
+        Some(Self(original))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+/// Read one byte from stdin, `None` at end of input or on error
+#[cfg(unix)]
+fn read_key() -> Option<u8> {
+    let mut key = 0u8;
+    let read = unsafe { libc::read(libc::STDIN_FILENO, &mut key as *mut u8 as *mut _, 1) };
+    (read == 1).then_some(key)
+}
+
+/// Interactive keys are only supported on Unix terminals
+#[cfg(not(unix))]
+struct TerminalMode;
+
+#[cfg(not(unix))]
+impl TerminalMode {
+    fn noncanonical() -> Option<Self> {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn read_key() -> Option<u8> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_map_to_commands_in_either_case() {
+        for (key, command) in [
+            (b'q', KeyCommand::Quit),
+            (b'Q', KeyCommand::Quit),
+            (b'r', KeyCommand::ResetStats),
+            (b'R', KeyCommand::ResetStats),
+            (b'p', KeyCommand::TogglePause),
+            (b'P', KeyCommand::TogglePause),
+            (b' ', KeyCommand::TogglePause),
+        ] {
+            assert_eq!(command_for_key(key), Some(command), "{:?}", key as char);
+        }
+
+        for key in [b'x', b'\n', 0x03, 0xff] {
+            assert_eq!(command_for_key(key), None, "{key:#x}");
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod axfr;
+pub mod capabilities;
+pub mod dig;
+pub mod duration;
+pub mod keys;
+pub mod resolve;
+pub mod sweep;
@@ -0,0 +1,124 @@
+use clap::Args;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+
+use pingoc::resolve::{
+    trace_hostname, AddressFamily, ResolutionStep, SearchList, Sources, DEFAULT_NDOTS,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Command-line arguments for the resolve subcommand
+#[derive(Args, Debug)]
+pub struct ResolveArgs {
+    /// Hostname to resolve, as ping would
+    host: String,
+
+    /// Show every step taken: what the system resolver returned, and each
+    /// server asked along with the records or referral it gave
+    #[arg(long)]
+    trace: bool,
+
+    /// Only resolve IPv4 addresses
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Only resolve IPv6 addresses
+    #[arg(short = '6', long)]
+    ipv6: bool,
+
+    /// Domains to qualify a short host with, as with ping --search
+    #[arg(long, value_name = "DOMAIN", value_delimiter = ',')]
+    search: Vec<String>,
+
+    /// Number of dots a host needs to be tried as given before the --search
+    /// domains
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_NDOTS, requires = "search")]
+    ndots: usize,
+}
+
+/// Resolve a host exactly like ping resolves its destination, optionally
+/// showing how
+pub fn resolve_handler(args: ResolveArgs) -> Result<()> {
+    let family = if args.ipv4 {
+        AddressFamily::V4
+    } else if args.ipv6 {
+        AddressFamily::V6
+    } else {
+        AddressFamily::Any
+    };
+    let search = SearchList::new(args.search).with_ndots(args.ndots);
+    let trace = trace_hostname(&args.host, family, &search, &Sources::default());
+    if args.trace {
+        trace.steps.iter().for_each(print_step);
+    }
+
+    let (ip, source, canonical_name) = trace.result?;
+    let canonical = canonical_name
+        .map(|name| format!(" (canonical {name})"))
+        .unwrap_or_default();
+    println!("{}{canonical} resolved to {ip} via {source}", args.host);
+    Ok(())
+}
+
+fn print_step(step: &ResolutionStep) {
+    match step {
+        ResolutionStep::Literal(ip) => println!(";; {ip} is an IP address"),
+        ResolutionStep::System(addrs) if addrs.is_empty() => {
+            println!(";; system resolver: no addresses")
+        }
+        ResolutionStep::System(addrs) => {
+            let addrs: Vec<String> = addrs.iter().map(IpAddr::to_string).collect();
+            println!(";; system resolver: {}", addrs.join(", "));
+        }
+        ResolutionStep::Response {
+            server,
+            query_name,
+            query_type,
+            response_code,
+            referral,
+            answers,
+        } => {
+            let server = SocketAddr::from(*server);
+            match referral {
+                Some((zone, nameservers)) if answers.is_empty() => println!(
+                    ";; {query_name}. {query_type} from {server} (recursive): referral to {zone}. via {}",
+                    nameservers.join(", ")
+                ),
+                _ => println!(
+                    ";; {query_name}. {query_type} from {server} (recursive): {response_code:?}, {} answers",
+                    answers.len()
+                ),
+            }
+            for answer in answers {
+                println!(";;   {answer}");
+            }
+        }
+        ResolutionStep::Iteration(step) => {
+            let server = format!("{} ({})", step.nameserver, step.address);
+            match &step.referral {
+                Some((zone, nameservers)) if step.answers == 0 => println!(
+                    ";; {}. {} from {server} (iterating): referral to {zone}. via {}",
+                    step.query_name,
+                    step.query_type,
+                    nameservers.join(", ")
+                ),
+                _ => println!(
+                    ";; {}. {} from {server} (iterating): {:?}, {} answers",
+                    step.query_name, step.query_type, step.response_code, step.answers
+                ),
+            }
+        }
+        ResolutionStep::Failed {
+            server,
+            query_type,
+            error,
+        } => {
+            let asked = match server {
+                Some(server) => format!("from {}", SocketAddr::from(*server)),
+                None => "by iterating from the root".to_string(),
+            };
+            println!(";; {query_type} {asked}: {error}");
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use clap::Args;
+use std::error::Error;
+use std::io;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use super::duration::parse_duration;
+use pingoc::icmp::packet::IcmpPacket;
+use pingoc::icmp::socket::IcmpSocket;
+use pingoc::ping::correlate::{MatchStrategy, ReplyCorrelator};
+use pingoc::ping::identifiers::IdentifierAllocator;
+use pingoc::ping::pacer::Pacer;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Payload size of each probe, the same as ping's default
+const PROBE_SIZE: usize = 56;
+
+/// Shortest prefix swept without --allow-large, 65534 hosts
+const MIN_PREFIX_LEN: u8 = 16;
+
+/// An IPv4 network in CIDR notation, e.g. `192.168.1.0/24`
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv4Cidr {
+    network: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let (addr, prefix_len) = value.split_once('/').unwrap_or((value, "32"));
+        let addr: Ipv4Addr = addr
+            .parse()
+            .map_err(|_| format!("invalid network address {addr:?}"))?;
+        let prefix_len: u8 = match prefix_len.parse() {
+            Ok(len) if len <= 32 => len,
+            _ => return Err(format!("invalid prefix length {prefix_len:?}")),
+        };
+
+        let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+        Ok(Self {
+            network: Ipv4Addr::from_bits(addr.to_bits() & mask),
+            prefix_len,
+        })
+    }
+}
+
+impl Ipv4Cidr {
+    /// The addresses of the network that can be assigned to hosts.
+    ///
+    /// The network and broadcast addresses are skipped, except for /31
+    /// point-to-point links (RFC 3021) and /32 single hosts where every
+    /// address is a host.
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let first = self.network.to_bits() as u64;
+        let size = 1u64 << (32 - self.prefix_len);
+        let (start, end) = if self.prefix_len >= 31 {
+            (first, first + size)
+        } else {
+            (first + 1, first + size - 1)
+        };
+
+        (start..end).map(|addr| Ipv4Addr::from_bits(addr as u32))
+    }
+
+    /// Number of addresses `hosts` yields
+    pub fn host_count(&self) -> u64 {
+        let size = 1u64 << (32 - self.prefix_len);
+        if self.prefix_len >= 31 {
+            size
+        } else {
+            size - 2
+        }
+    }
+}
+
+/// Command-line arguments for the sweep subcommand
+#[derive(Args, Debug)]
+pub struct SweepArgs {
+    /// Network to sweep, e.g. 192.168.1.0/24
+    network: Ipv4Cidr,
+
+    /// Number of probes sent to each host
+    #[arg(short, long, default_value_t = 2)]
+    count: u16,
+
+    /// Time to wait for replies after each round of probes, e.g. `300ms`
+    #[arg(short, long, default_value = "1", value_parser = parse_duration)]
+    timeout: Duration,
+
+    /// Maximum number of hosts probed at the same time
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u16).range(1..))]
+    parallel: u16,
+
+    /// Cap the rate of probes, in packets per second
+    #[arg(long, value_name = "PPS", value_parser = clap::value_parser!(u32).range(1..))]
+    pps: Option<u32>,
+
+    /// Allow sweeping a network larger than a /16
+    #[arg(long)]
+    allow_large: bool,
+}
+
+impl SweepArgs {
+    /// Refuse a network with a prefix shorter than `MIN_PREFIX_LEN` unless
+    /// --allow-large is set
+    fn check_size(&self) -> Result<()> {
+        if self.allow_large || self.network.prefix_len >= MIN_PREFIX_LEN {
+            return Ok(());
+        }
+
+        Err(format!(
+            "sweeping /{} means probing {} hosts; sweep a /{MIN_PREFIX_LEN} or smaller \
+             network or pass --allow-large",
+            self.network.prefix_len,
+            self.network.host_count()
+        )
+        .into())
+    }
+}
+
+pub fn sweep_handler(args: SweepArgs) -> Result<()> {
+    args.check_size()?;
+    let socket = IcmpSocket::new(args.timeout)?;
+    let pacer = args.pps.map(Pacer::new);
+
+    let mut allocator = IdentifierAllocator::for_process();
+    let mut live = Vec::new();
+    let mut hosts = args.network.hosts();
+    loop {
+        let batch: Vec<Ipv4Addr> = hosts.by_ref().take(args.parallel as usize).collect();
+        if batch.is_empty() {
+            break;
+        }
+        live.extend(probe_batch(
+            &socket,
+            &batch,
+            &mut allocator,
+            pacer.as_ref(),
+            &args,
+        )?);
+    }
+    live.sort();
+
+    for host in &live {
+        println!("{host}");
+    }
+    println!(
+        "{} of {} hosts are up",
+        live.len(),
+        args.network.host_count()
+    );
+
+    Ok(())
+}
+
+/// Probe a batch of hosts over the shared socket, returning those that replied
+fn probe_batch(
+    socket: &IcmpSocket,
+    batch: &[Ipv4Addr],
+    allocator: &mut IdentifierAllocator,
+    pacer: Option<&Pacer>,
+    args: &SweepArgs,
+) -> Result<Vec<Ipv4Addr>> {
+    let identifiers = batch
+        .iter()
+        .map(|_| allocator.allocate())
+        .collect::<std::result::Result<Vec<u16>, _>>()?;
+    let identifier = |host: usize| identifiers[host];
+
+    let mut correlator =
+        ReplyCorrelator::new().with_strategy(MatchStrategy::for_socket(socket.kind()));
+    for (i, host) in batch.iter().enumerate() {
+        correlator.register(identifier(i), i, (*host).into());
+    }
+
+    let mut alive = vec![false; batch.len()];
+    for sequence_no in 1..=args.count {
+        for (i, host) in batch.iter().enumerate() {
+            if alive[i] {
+                continue;
+            }
+            if let Some(pacer) = pacer {
+                pacer.acquire();
+            }
+            let mut packet = IcmpPacket::echo_request(identifier(i), sequence_no, PROBE_SIZE);
+            // Unreachable hosts within the range are expected, not fatal
+            if socket.send_to(&mut packet, *host).is_ok() {
+                correlator.record_request(identifier(i), sequence_no, Instant::now());
+            }
+        }
+
+        // Wait out the deadline however much else arrives meanwhile
+        let deadline = Instant::now() + args.timeout;
+        while correlator.outstanding() > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let (packet, source, _) = match socket.recv_timeout(remaining) {
+                Ok(Some(received)) => received,
+                Ok(None) => continue,
+                Err(err) if is_transient(&*err) => continue,
+                Err(_) => break,
+            };
+            if let Some(reply) = correlator.dispatch(&packet, source.into(), Instant::now()) {
+                alive[reply.host] = true;
+                correlator.unregister(identifier(reply.host));
+            }
+        }
+        correlator.expire();
+
+        if alive.iter().all(|&up| up) {
+            break;
+        }
+    }
+    for identifier in identifiers {
+        allocator.release(identifier);
+    }
+
+    Ok(batch
+        .iter()
+        .zip(alive)
+        .filter_map(|(host, up)| up.then_some(*host))
+        .collect())
+}
+
+/// Whether a receive error only concerns the message at hand, e.g. one that
+/// didn't parse or a wait a signal cut short, so receiving can go on
+fn is_transient(err: &(dyn Error + 'static)) -> bool {
+    err.downcast_ref::<io::Error>().is_none_or(|err| {
+        matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(network: &str) -> Vec<Ipv4Addr> {
+        network.parse::<Ipv4Cidr>().unwrap().hosts().collect()
+    }
+
+    #[test]
+    fn network_and_broadcast_addresses_are_skipped() {
+        let hosts = hosts("192.0.2.77/24");
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts[0], Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(hosts[253], Ipv4Addr::new(192, 0, 2, 254));
+    }
+
+    #[test]
+    fn every_address_of_a_point_to_point_link_or_single_host_is_a_host() {
+        assert_eq!(
+            hosts("192.0.2.5/31"),
+            [Ipv4Addr::new(192, 0, 2, 4), Ipv4Addr::new(192, 0, 2, 5)]
+        );
+        assert_eq!(hosts("192.0.2.5/32"), [Ipv4Addr::new(192, 0, 2, 5)]);
+        assert_eq!(hosts("192.0.2.5"), [Ipv4Addr::new(192, 0, 2, 5)]);
+    }
+
+    #[test]
+    fn host_count_matches_the_hosts_yielded() {
+        for network in ["192.0.2.0/24", "192.0.2.4/31", "192.0.2.5/32"] {
+            let cidr: Ipv4Cidr = network.parse().unwrap();
+            assert_eq!(cidr.host_count(), hosts(network).len() as u64, "{network}");
+        }
+        let everything: Ipv4Cidr = "0.0.0.0/0".parse().unwrap();
+        assert_eq!(everything.host_count(), (1 << 32) - 2);
+    }
+
+    #[test]
+    fn networks_larger_than_a_slash_16_need_allow_large() {
+        let args = |network: &str, allow_large| SweepArgs {
+            network: network.parse().unwrap(),
+            count: 2,
+            timeout: Duration::from_secs(1),
+            parallel: 64,
+            pps: None,
+            allow_large,
+        };
+
+        assert!(args("10.0.0.0/16", false).check_size().is_ok());
+        let error = args("10.0.0.0/8", false)
+            .check_size()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("sweeping /8 means probing 16777214 hosts"),
+            "{error}"
+        );
+        assert!(args("0.0.0.0/0", true).check_size().is_ok());
+    }
+}
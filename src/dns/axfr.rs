@@ -0,0 +1,84 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+
+use super::{
+    header::DnsResponseCode,
+    packet::DnsPacket,
+    query::DnsQueryType,
+    record::DnsRecord,
+    tcp::{read_message, write_message},
+};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Custom error type for zone transfers
+#[derive(Debug)]
+pub enum AxfrError {
+    /// The server refused or failed the transfer
+    Failed(DnsResponseCode),
+    /// The first record of the transfer was not the zone's SOA
+    MissingSoa,
+    /// The transfer went on past the record limit
+    RecordLimit(usize),
+}
+
+impl fmt::Display for AxfrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AxfrError::Failed(code) => write!(f, "Zone transfer failed with {:?}", code),
+            AxfrError::MissingSoa => write!(f, "Zone transfer did not start with a SOA record"),
+            AxfrError::RecordLimit(limit) => {
+                write!(f, "Zone transfer exceeded the limit of {} records", limit)
+            }
+        }
+    }
+}
+
+impl Error for AxfrError {}
+
+/// Transfer `zone` from `server` over TCP (RFC 5936), calling `on_record`
+/// for each record as it arrives and returning how many there were.
+///
+/// The transfer spans as many messages as the server likes and ends with a
+/// repeat of the SOA record it began with. Both SOA records are passed to
+/// `on_record`. More than `max_records` records abort the transfer.
+pub fn zone_transfer(
+    zone: &str,
+    server: (IpAddr, u16),
+    timeout: Duration,
+    max_records: usize,
+    mut on_record: impl FnMut(&DnsRecord),
+) -> Result<usize> {
+    let mut stream = TcpStream::connect_timeout(&server.into(), timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+
+    let mut query = DnsPacket::query(zone, DnsQueryType::AXFR);
+    query.header.recursion_desired = false;
+    write_message(&mut stream, &query)?;
+
+    let mut count = 0;
+    loop {
+        let response = read_message(&mut stream)?;
+        if response.header.response_code != DnsResponseCode::NoError {
+            return Err(Box::new(AxfrError::Failed(response.header.response_code)));
+        }
+
+        for record in &response.answers {
+            let is_soa = matches!(record, DnsRecord::SOA { .. });
+            if count == 0 && !is_soa {
+                return Err(Box::new(AxfrError::MissingSoa));
+            }
+            if count == max_records {
+                return Err(Box::new(AxfrError::RecordLimit(max_records)));
+            }
+
+            count += 1;
+            on_record(record);
+            if is_soa && count > 1 {
+                return Ok(count);
+            }
+        }
+    }
+}
@@ -7,6 +7,7 @@ pub enum PacketBufferError {
     EndOfBuffer,
     InvalidLabelLength,
     JumpLimitExceeded,
+    CharacterStringTooLong(usize),
     Utf8ConversionError(std::string::FromUtf8Error),
 }
 
@@ -21,6 +22,9 @@ impl fmt::Display for PacketBufferError {
             }
             PacketBufferError::InvalidLabelLength => write!(f, "Invalid label length in DNS name"),
             PacketBufferError::JumpLimitExceeded => write!(f, "Limit of DNS jumps exceeded"),
+            PacketBufferError::CharacterStringTooLong(len) => {
+                write!(f, "Character string of {} bytes exceeds 255 bytes", len)
+            }
             PacketBufferError::Utf8ConversionError(err) => {
                 write!(f, "UTF-8 conversion error: {}", err)
             }
@@ -32,24 +36,48 @@ impl std::error::Error for PacketBufferError {}
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+pub const DNS_BUFFER_SZ: usize = 512;
+
+/// Number of bytes `PacketBuffer::write_query_name` writes for `name`: a
+/// length byte per label plus the label, and the terminating zero byte
+pub fn query_name_len(name: &str) -> usize {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() {
+        return 1;
+    }
+    name.split('.').map(|label| 1 + label.len()).sum::<usize>() + 1
+}
+
 /// A Buffer to read and write various components of a DNS packet
 pub struct PacketBuffer {
-    pub buffer: [u8; 512],
+    pub buffer: Vec<u8>,
     pub pos: usize,
 }
 
+impl Default for PacketBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PacketBuffer {
-    /// Initialize an empty buffer
+    /// Initialize an empty buffer, large enough for a UDP message
     pub fn new() -> Self {
+        Self::with_size(DNS_BUFFER_SZ)
+    }
+
+    /// Initialize an empty buffer of `size` bytes, e.g. for a TCP message
+    pub fn with_size(size: usize) -> Self {
         Self {
-            buffer: [0; 512],
+            buffer: vec![0; size],
             pos: 0,
         }
     }
 
-    /// Set position in the buffer
+    /// Set position in the buffer. The end of the buffer is a valid
+    /// position, reached after reading its last byte.
     pub fn seek(&mut self, pos: usize) -> Result<()> {
-        if pos >= self.buffer.len() {
+        if pos > self.buffer.len() {
             return Err(Box::new(PacketBufferError::PositionOutOfBounds(pos)));
         }
         self.pos = pos;
@@ -167,6 +195,14 @@ impl PacketBuffer {
         Ok(result.join("."))
     }
 
+    /// Read a <character-string>: a length byte followed by up to 255 bytes
+    /// of text, decoded lossily as it need not be UTF-8
+    pub fn read_character_string(&mut self) -> Result<String> {
+        let len = self.read()? as usize;
+        let bytes = self.read_bytes(len)?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
     /// Write a byte to the buffer and increment position
     pub fn write(&mut self, value: u8) -> Result<()> {
         if self.pos >= self.buffer.len() {
@@ -209,20 +245,124 @@ impl PacketBuffer {
         Ok(())
     }
 
-    /// Write a DNS query name to the buffer
+    /// Overwrite the byte at `pos` without moving the position
+    pub fn set(&mut self, pos: usize, value: u8) -> Result<()> {
+        if pos >= self.buffer.len() {
+            return Err(Box::new(PacketBufferError::EndOfBuffer));
+        }
+        self.buffer[pos] = value;
+        Ok(())
+    }
+
+    /// Overwrite a 16-bit unsigned integer at `pos` without moving the position
+    pub fn set_u16(&mut self, pos: usize, value: u16) -> Result<()> {
+        self.set(pos, (value >> 8) as u8)?;
+        self.set(pos + 1, value as u8)
+    }
+
+    /// Write a 16-bit length followed by whatever `write` puts in the buffer,
+    /// the length being patched in once the contents are written. Used for
+    /// RDATA whose size is only known after encoding, e.g. names.
+    pub fn write_length_prefixed<F>(&mut self, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut PacketBuffer) -> Result<()>,
+    {
+        let length_pos = self.pos;
+        self.write_u16(0)?;
+        write(self)?;
+
+        let length = self.pos - length_pos - 2;
+        self.set_u16(length_pos, length as u16)
+    }
+
+    /// Write a DNS query name to the buffer.
+    /// A trailing dot is optional, so `example.com.` and `example.com` are
+    /// written identically, and the root (`""` or `"."`) is just the zero byte.
     pub fn write_query_name(&mut self, name: &str) -> Result<()> {
         let pos = self.pos;
-        for label in name.split('.') {
-            if label.len() > 63 {
-                self.pos = pos;
-                return Err(Box::new(PacketBufferError::InvalidLabelLength));
-            }
+        let name = name.strip_suffix('.').unwrap_or(name);
+
+        if !name.is_empty() {
+            for label in name.split('.') {
+                if label.is_empty() || label.len() > 63 {
+                    self.pos = pos;
+                    return Err(Box::new(PacketBufferError::InvalidLabelLength));
+                }
 
-            self.write(label.len() as u8)?;
-            for b in label.bytes() {
-                self.write(b)?;
+                self.write(label.len() as u8)?;
+                for b in label.bytes() {
+                    self.write(b)?;
+                }
             }
         }
         self.write(0)
     }
+
+    /// Write a <character-string>: a length byte followed by the text, which
+    /// must be at most 255 bytes
+    pub fn write_character_string(&mut self, text: &str) -> Result<()> {
+        let len = u8::try_from(text.len())
+            .map_err(|_| PacketBufferError::CharacterStringTooLong(text.len()))?;
+        self.write(len)?;
+        self.write_bytes(text.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `name` at the start of a buffer and return the bytes written
+    /// along with the name read back from them
+    fn round_trip(name: &str) -> (Vec<u8>, String) {
+        let mut buffer = PacketBuffer::new();
+        buffer.write_query_name(name).unwrap();
+        let written = buffer.buffer[..buffer.pos].to_vec();
+
+        buffer.seek(0).unwrap();
+        let read = buffer.read_query_name().unwrap();
+        assert_eq!(buffer.pos, written.len());
+        (written, read)
+    }
+
+    #[test]
+    fn fully_qualified_name_round_trips() {
+        let (written, read) = round_trip("example.com.");
+        assert_eq!(written, b"\x07example\x03com\x00");
+        assert_eq!(read, "example.com");
+    }
+
+    #[test]
+    fn relative_name_is_written_like_the_fully_qualified_one() {
+        let (written, read) = round_trip("example.com");
+        assert_eq!(written, b"\x07example\x03com\x00");
+        assert_eq!(read, "example.com");
+    }
+
+    #[test]
+    fn root_is_a_single_zero_byte() {
+        for root in ["", "."] {
+            let (written, read) = round_trip(root);
+            assert_eq!(written, [0]);
+            assert_eq!(read, "");
+        }
+    }
+
+    #[test]
+    fn empty_label_is_rejected() {
+        let mut buffer = PacketBuffer::new();
+        assert!(buffer.write_query_name("example..com").is_err());
+        assert_eq!(buffer.pos, 0);
+    }
+
+    #[test]
+    fn compressed_name_ending_the_buffer_is_read() {
+        // `com` followed by a pointer back to it as the last two bytes
+        let mut buffer = PacketBuffer::with_size(0);
+        buffer.buffer = b"\x03com\x00\xc0\x00".to_vec();
+        buffer.seek(5).unwrap();
+
+        assert_eq!(buffer.read_query_name().unwrap(), "com");
+        assert_eq!(buffer.pos, buffer.buffer.len());
+    }
 }
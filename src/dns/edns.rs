@@ -0,0 +1,107 @@
+use std::fmt;
+
+/// EDNS option code for Extended DNS Errors (RFC 8914)
+pub const EDNS_OPTION_EDE: u16 = 15;
+
+/// UDP payload size advertised in our OPT records
+pub const EDNS_UDP_PAYLOAD_SZ: u16 = 4096;
+
+/// Smallest UDP payload size a requestor may advertise, smaller values are
+/// treated as this (RFC 6891 section 6.2.5)
+pub const MIN_UDP_PAYLOAD_SZ: u16 = 512;
+
+/// The UDP payload size to use with a server: the lesser of what we
+/// advertise and what it advertised, but no less than 512 bytes
+pub fn negotiate_payload_size(ours: u16, theirs: u16) -> u16 {
+    ours.min(theirs).max(MIN_UDP_PAYLOAD_SZ)
+}
+
+/// An Extended DNS Error carried in an OPT record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedDnsError {
+    pub code: u16,
+    pub text: String,
+}
+
+impl ExtendedDnsError {
+    /// Human readable name of the INFO-CODE as registered by IANA
+    pub fn description(&self) -> &'static str {
+        match self.code {
+            0 => "Other Error",
+            1 => "Unsupported DNSKEY Algorithm",
+            2 => "Unsupported DS Digest Type",
+            3 => "Stale Answer",
+            4 => "Forged Answer",
+            5 => "DNSSEC Indeterminate",
+            6 => "DNSSEC Bogus",
+            7 => "Signature Expired",
+            8 => "Signature Not Yet Valid",
+            9 => "DNSKEY Missing",
+            10 => "RRSIGs Missing",
+            11 => "No Zone Key Bit Set",
+            12 => "NSEC Missing",
+            13 => "Cached Error",
+            14 => "Not Ready",
+            15 => "Blocked",
+            16 => "Censored",
+            17 => "Filtered",
+            18 => "Prohibited",
+            19 => "Stale NXDOMAIN Answer",
+            20 => "Not Authoritative",
+            21 => "Not Supported",
+            22 => "No Reachable Authority",
+            23 => "Network Error",
+            24 => "Invalid Data",
+            _ => "Unknown Error",
+        }
+    }
+}
+
+impl fmt::Display for ExtendedDnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (EDE {})", self.description(), self.code)?;
+        if !self.text.is_empty() {
+            write!(f, ": {}", self.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterate over the `(code, data)` pairs of an OPT record's RDATA.
+/// Parsing stops silently at the first option that overruns the data.
+pub fn options(data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    /*
+                   +0 (MSB)                            +1 (LSB)
+        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+     0: |                          OPTION-CODE                          |
+        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+     2: |                         OPTION-LENGTH                         |
+        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+     4: |                                                               |
+        /                          OPTION-DATA                          /
+        /                                                               /
+        +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+    */
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 4)?;
+        let code = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let value = data.get(pos + 4..pos + 4 + len)?;
+        pos += 4 + len;
+        Some((code, value))
+    })
+}
+
+/// Extract every Extended DNS Error option from an OPT record's RDATA
+pub fn extended_errors(data: &[u8]) -> Vec<ExtendedDnsError> {
+    options(data)
+        .filter(|(code, value)| *code == EDNS_OPTION_EDE && value.len() >= 2)
+        .map(|(_, value)| ExtendedDnsError {
+            code: u16::from_be_bytes([value[0], value[1]]),
+            text: String::from_utf8_lossy(&value[2..])
+                .trim_end_matches('\0')
+                .to_string(),
+        })
+        .collect()
+}
@@ -1,20 +1,49 @@
 use super::buffer::PacketBuffer;
 use std::error::Error;
+use std::fmt;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// Size of the header: ID, flags and the four section counts
+pub const DNS_HEADER_LEN: usize = 12;
+
+/// Response code (RCODE) of a DNS message. The header only carries the low
+/// four bits, codes from 16 up also need the extended RCODE of an OPT record.
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum DnsResponseCode {
-    NoError = 0,
-    FormErr = 1,
-    ServFail = 2,
-    NxDomain = 3,
-    NotImp = 4,
-    Refused = 5,
+    NoError,
+    FormErr,
+    ServFail,
+    NxDomain,
+    NotImp,
+    Refused,
+    /// A name exists that should not (RFC 2136)
+    YxDomain,
+    /// An RRset exists that should not (RFC 2136)
+    YxRrSet,
+    /// An RRset that should exist does not (RFC 2136)
+    NxRrSet,
+    /// The server is not authoritative for the zone (RFC 2136)
+    NotAuth,
+    /// A name is not within the zone (RFC 2136)
+    NotZone,
+    /// DSO-TYPE not implemented (RFC 8490)
+    DsoTypeNi,
+    /// Unsupported EDNS version (RFC 6891), only with an OPT record
+    BadVers,
+    /// Any other code, unassigned or not supported here
+    Unknown(u16),
 }
 
 impl DnsResponseCode {
+    /// The code carried in the four RCODE bits of the header
     pub fn from_u8(value: u8) -> DnsResponseCode {
+        Self::from_u16(value.into())
+    }
+
+    /// The code for a full, possibly extended, RCODE value
+    pub fn from_u16(value: u16) -> DnsResponseCode {
         match value {
             0 => DnsResponseCode::NoError,
             1 => DnsResponseCode::FormErr,
@@ -22,12 +51,69 @@ impl DnsResponseCode {
             3 => DnsResponseCode::NxDomain,
             4 => DnsResponseCode::NotImp,
             5 => DnsResponseCode::Refused,
-            _ => panic!("Invalid response code"),
+            6 => DnsResponseCode::YxDomain,
+            7 => DnsResponseCode::YxRrSet,
+            8 => DnsResponseCode::NxRrSet,
+            9 => DnsResponseCode::NotAuth,
+            10 => DnsResponseCode::NotZone,
+            11 => DnsResponseCode::DsoTypeNi,
+            16 => DnsResponseCode::BadVers,
+            other => DnsResponseCode::Unknown(other),
+        }
+    }
+
+    /// The full RCODE value, of which the header carries the low four bits
+    pub fn to_u16(self) -> u16 {
+        match self {
+            DnsResponseCode::NoError => 0,
+            DnsResponseCode::FormErr => 1,
+            DnsResponseCode::ServFail => 2,
+            DnsResponseCode::NxDomain => 3,
+            DnsResponseCode::NotImp => 4,
+            DnsResponseCode::Refused => 5,
+            DnsResponseCode::YxDomain => 6,
+            DnsResponseCode::YxRrSet => 7,
+            DnsResponseCode::NxRrSet => 8,
+            DnsResponseCode::NotAuth => 9,
+            DnsResponseCode::NotZone => 10,
+            DnsResponseCode::DsoTypeNi => 11,
+            DnsResponseCode::BadVers => 16,
+            DnsResponseCode::Unknown(value) => value,
+        }
+    }
+
+    /// Human readable meaning of the code
+    pub fn description(&self) -> &'static str {
+        match self {
+            DnsResponseCode::NoError => "No error",
+            DnsResponseCode::FormErr => "Format error",
+            DnsResponseCode::ServFail => "Server failure",
+            DnsResponseCode::NxDomain => "Non-existent domain",
+            DnsResponseCode::NotImp => "Not implemented",
+            DnsResponseCode::Refused => "Query refused",
+            DnsResponseCode::YxDomain => "Name exists when it should not",
+            DnsResponseCode::YxRrSet => "RR set exists when it should not",
+            DnsResponseCode::NxRrSet => "RR set that should exist does not",
+            DnsResponseCode::NotAuth => "Server not authoritative for zone",
+            DnsResponseCode::NotZone => "Name not contained in zone",
+            DnsResponseCode::DsoTypeNi => "DSO-TYPE not implemented",
+            DnsResponseCode::BadVers => "Bad OPT version",
+            DnsResponseCode::Unknown(_) => "Unknown response code",
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+impl fmt::Display for DnsResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsResponseCode::Unknown(value) => write!(f, "RCODE{}", value),
+            known => write!(f, "{} ({})", known.description(), known.to_u16()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsHeader {
     pub id: u16,
 
@@ -48,6 +134,12 @@ pub struct DnsHeader {
     pub additional_count: u16,
 }
 
+impl Default for DnsHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DnsHeader {
     /* Header section format
 
@@ -88,6 +180,25 @@ impl DnsHeader {
         }
     }
 
+    /// Sets the ID used to match responses to this query.
+    pub fn with_id(mut self, id: u16) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Sets whether the server is asked to resolve the query recursively.
+    pub fn with_recursion_desired(mut self, recursion_desired: bool) -> Self {
+        self.recursion_desired = recursion_desired;
+        self
+    }
+
+    /// Marks the header as a standard query (QR clear, opcode QUERY).
+    pub fn as_query(mut self) -> Self {
+        self.query_response = false;
+        self.opcode = 0;
+        self
+    }
+
     /// Reads a `DnsHeader` from a `PacketBuffer`.
     pub fn read(buffer: &mut PacketBuffer) -> Result<Self> {
         let mut header = DnsHeader::new();
@@ -144,10 +255,16 @@ impl DnsHeader {
             flags |= 1 << 7;
         }
         flags |= (self.reserved as u16) << 4;
-        flags |= self.response_code as u16;
+        // Bits above the low four go in the OPT record, if any
+        flags |= self.response_code.to_u16() & 0x0F;
         flags
     }
 
+    /// Size of the header on the wire, which is fixed
+    pub fn wire_len(&self) -> usize {
+        DNS_HEADER_LEN
+    }
+
     pub fn write(&self, buffer: &mut PacketBuffer) -> Result<()> {
         buffer.write_u16(self.id)?;
         buffer.write_u16(self.get_flags())?;
@@ -159,3 +276,33 @@ impl DnsHeader {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_codes_round_trip() {
+        for value in (0..=11).chain([16, 12, 23, 4095]) {
+            assert_eq!(DnsResponseCode::from_u16(value).to_u16(), value);
+        }
+        assert_eq!(DnsResponseCode::from_u8(9), DnsResponseCode::NotAuth);
+        assert_eq!(DnsResponseCode::from_u8(10), DnsResponseCode::NotZone);
+        assert_eq!(DnsResponseCode::from_u16(16), DnsResponseCode::BadVers);
+        assert_eq!(DnsResponseCode::from_u8(12), DnsResponseCode::Unknown(12));
+    }
+
+    #[test]
+    fn header_carries_the_low_four_bits_of_the_code() {
+        for value in 0..=15 {
+            let mut header = DnsHeader::new();
+            header.response_code = DnsResponseCode::from_u8(value);
+            let mut buffer = PacketBuffer::new();
+            header.write(&mut buffer).unwrap();
+
+            buffer.seek(0).unwrap();
+            let read = DnsHeader::read(&mut buffer).unwrap();
+            assert_eq!(read.response_code, header.response_code);
+        }
+    }
+}
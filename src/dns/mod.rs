@@ -1,7 +1,13 @@
+pub mod axfr;
 pub mod buffer;
+pub mod edns;
 pub mod header;
 pub mod packet;
 pub mod query;
 pub mod question;
 pub mod record;
+pub mod resolv_conf;
 pub mod resolve;
+#[cfg(feature = "server")]
+pub mod serve;
+pub mod tcp;
@@ -1,18 +1,76 @@
 use super::{
-    buffer::PacketBuffer, header::DnsHeader, query::DnsQueryType, question::DnsQuestion,
+    buffer::PacketBuffer,
+    edns::{self, ExtendedDnsError},
+    header::{DnsHeader, DnsResponseCode},
+    query::{DnsQueryClass, DnsQueryType},
+    question::DnsQuestion,
     record::DnsRecord,
 };
-use std::{error::Error, net::IpAddr};
+use std::{error::Error, fmt, net::IpAddr};
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// Longest chain of CNAMEs followed
+pub const MAX_CNAME_CHAIN: usize = 8;
+
+/// Smallest possible question: root name, type and class
+const MIN_QUESTION_LEN: usize = 1 + 2 + 2;
+/// Smallest possible record: root name, type, class, TTL and empty RDATA
+const MIN_RECORD_LEN: usize = 1 + 2 + 2 + 4 + 2;
+
+/// Custom error type for DNS packets
 #[derive(Debug)]
+pub enum DnsPacketError {
+    /// The header declares more questions or records than the remaining
+    /// bytes could possibly hold
+    InconsistentCounts { needed: usize, remaining: usize },
+}
+
+impl fmt::Display for DnsPacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsPacketError::InconsistentCounts { needed, remaining } => write!(
+                f,
+                "Header counts need at least {} bytes but only {} remain",
+                needed, remaining
+            ),
+        }
+    }
+}
+
+impl Error for DnsPacketError {}
+
+/// Fail with `DnsPacketError::InconsistentCounts` if fewer than `needed`
+/// bytes remain in `buffer`
+fn check_counts(buffer: &PacketBuffer, needed: usize) -> Result<()> {
+    let remaining = buffer.buffer.len().saturating_sub(buffer.pos);
+    if needed > remaining {
+        return Err(Box::new(DnsPacketError::InconsistentCounts {
+            needed,
+            remaining,
+        }));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsPacket {
     pub header: DnsHeader,
     pub questions: Vec<DnsQuestion>,
     pub answers: Vec<DnsRecord>,
     pub authorities: Vec<DnsRecord>,
     pub additional: Vec<DnsRecord>,
+    /// Set by `read` when the message ended partway through a record, e.g. a
+    /// datagram cut short. The records before it are kept and the header
+    /// counts are left as received.
+    pub incomplete: bool,
+}
+
+impl Default for DnsPacket {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DnsPacket {
@@ -37,28 +95,101 @@ impl DnsPacket {
             answers: Vec::new(),
             authorities: Vec::new(),
             additional: Vec::new(),
+            incomplete: false,
         }
     }
 
+    /// A standard query for `name` with recursion desired and a random ID
+    pub fn query(name: &str, query_type: DnsQueryType) -> Self {
+        let mut packet = Self::new();
+        packet.header = DnsHeader::new()
+            .as_query()
+            .with_id(rand::random())
+            .with_recursion_desired(true);
+        packet.header.question_count = 1;
+        packet
+            .questions
+            .push(DnsQuestion::new(name.to_string(), query_type));
+        packet
+    }
+
+    /// Read a packet, rejecting one whose header counts more questions or
+    /// records than its bytes could hold
     pub fn read(buffer: &mut PacketBuffer) -> Result<Self> {
+        Self::read_counts(buffer, false)
+    }
+
+    /// Read a packet, trying only as many records as the remaining bytes
+    /// could hold when the header counts more, so a datagram cut short
+    /// still yields its first records. The packet is flagged `incomplete`
+    /// if any were left out.
+    pub fn read_lenient(buffer: &mut PacketBuffer) -> Result<Self> {
+        Self::read_counts(buffer, true)
+    }
+
+    fn read_counts(buffer: &mut PacketBuffer, lenient: bool) -> Result<Self> {
         let header = DnsHeader::read(buffer)?;
+
+        // Reject a question count the bytes can't possibly hold up front,
+        // as nothing after the questions makes sense without them
+        check_counts(buffer, header.question_count as usize * MIN_QUESTION_LEN)?;
+
         let mut packet = DnsPacket::new();
         packet.header = header;
         for _ in 0..header.question_count {
             packet.questions.push(DnsQuestion::read(buffer)?);
         }
-        for _ in 0..header.answer_count {
-            packet.answers.push(DnsRecord::read(buffer)?);
-        }
-        for _ in 0..header.authority_count {
-            packet.authorities.push(DnsRecord::read(buffer)?);
+
+        let record_count = usize::from(header.answer_count)
+            + usize::from(header.authority_count)
+            + usize::from(header.additional_count);
+        if !lenient {
+            check_counts(buffer, record_count * MIN_RECORD_LEN)?;
         }
-        for _ in 0..header.additional_count {
-            packet.additional.push(DnsRecord::read(buffer)?);
+
+        // A record that fails to parse ends the packet, keeping what came
+        // before it, as complete earlier records are still useful
+        let sections = [
+            (header.answer_count, &mut packet.answers),
+            (header.authority_count, &mut packet.authorities),
+            (header.additional_count, &mut packet.additional),
+        ];
+        'sections: for (count, records) in sections {
+            let remaining = buffer.buffer.len().saturating_sub(buffer.pos);
+            let limit = usize::from(count).min(remaining / MIN_RECORD_LEN);
+            for _ in 0..limit {
+                match DnsRecord::read(buffer) {
+                    Ok(record) => records.push(record),
+                    Err(_) => {
+                        packet.incomplete = true;
+                        break 'sections;
+                    }
+                }
+            }
+            if limit < usize::from(count) {
+                packet.incomplete = true;
+                break;
+            }
         }
         Ok(packet)
     }
 
+    /// Size of the packet on the wire, without writing it, e.g. to check a
+    /// response still fits a UDP message before adding another record
+    pub fn wire_len(&self) -> usize {
+        self.header.wire_len()
+            + self
+                .questions
+                .iter()
+                .map(DnsQuestion::wire_len)
+                .sum::<usize>()
+            + [&self.answers, &self.authorities, &self.additional]
+                .into_iter()
+                .flatten()
+                .map(DnsRecord::wire_len)
+                .sum::<usize>()
+    }
+
     pub fn write(&self, buffer: &mut PacketBuffer) -> Result<()> {
         self.header.write(buffer)?;
         self.questions.iter().try_for_each(|q| q.write(buffer))?;
@@ -117,14 +248,579 @@ impl DnsPacket {
             .next()
     }
 
-    pub fn get_record(&self, query_type: DnsQueryType) -> Option<IpAddr> {
-        self.answers
+    /// MX records of the answer section as `(priority, host)`, most preferred first
+    pub fn mx_records_sorted(&self) -> Vec<(u16, &str)> {
+        let mut records: Vec<(u16, &str)> = self
+            .answers
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::MX { priority, host, .. } => Some((*priority, host.as_str())),
+                _ => None,
+            })
+            .collect();
+        records.sort_by_key(|(priority, _)| *priority);
+        records
+    }
+
+    /// Addresses for `host` given as glue in the additional section
+    pub fn glue_addresses(&self, host: &str) -> Vec<IpAddr> {
+        self.additional
             .iter()
-            .find(|record| record.matches_query_type(query_type))
-            .and_then(|record| match record {
+            .filter_map(|record| match record {
+                DnsRecord::A { domain, addr, .. } if domain == host => Some(IpAddr::V4(*addr)),
+                DnsRecord::AAAA { domain, addr, .. } if domain == host => Some(IpAddr::V6(*addr)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Extended DNS Errors attached to the OPT record of this packet, if any
+    pub fn extended_errors(&self) -> Vec<ExtendedDnsError> {
+        self.additional
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::OPT { options, .. } => Some(edns::extended_errors(options)),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// UDP payload size advertised by the OPT record of this packet, `None`
+    /// without EDNS
+    pub fn udp_payload_size(&self) -> Option<u16> {
+        self.additional.iter().find_map(|record| match record {
+            DnsRecord::OPT {
+                udp_payload_size, ..
+            } => Some(*udp_payload_size),
+            _ => None,
+        })
+    }
+
+    /// Full response code, combining the header RCODE with the upper bits
+    /// carried by the OPT record, if any
+    pub fn response_code(&self) -> DnsResponseCode {
+        let extended = self.additional.iter().find_map(|record| match record {
+            DnsRecord::OPT { extended_rcode, .. } => Some(*extended_rcode),
+            _ => None,
+        });
+        match extended {
+            Some(upper) if upper != 0 => DnsResponseCode::from_u16(
+                (u16::from(upper) << 4) | (self.header.response_code.to_u16() & 0x0F),
+            ),
+            _ => self.header.response_code,
+        }
+    }
+
+    /// Whether this response to a query asking for recursion came back
+    /// empty, not authoritative, from a server that doesn't offer recursion
+    /// (RA clear), which usually means an authoritative server was asked
+    /// about a name outside its zones
+    pub fn lacks_recursion(&self) -> bool {
+        self.header.recursion_desired
+            && !self.header.recursion_available
+            && !self.header.authoritative_answer
+            && self.answers.is_empty()
+    }
+
+    /// How long a negative answer (NXDOMAIN or no data) may be cached: the
+    /// lesser of the authority SOA's TTL and its minimum field (RFC 2308)
+    pub fn get_negative_ttl(&self) -> Option<u32> {
+        self.authorities.iter().find_map(|record| match record {
+            DnsRecord::SOA {
+                minimum_ttl, ttl, ..
+            } => Some((*minimum_ttl).min(*ttl)),
+            _ => None,
+        })
+    }
+
+    /// Follow CNAME records in the answer section from `name` to the name
+    /// they ultimately point at
+    pub fn canonical_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.cname_chain(name).last().copied().unwrap_or(name)
+    }
+
+    /// The targets of the CNAME records in the answer section followed from
+    /// `name`, in order. Empty if `name` has no CNAME in this packet.
+    pub fn cname_chain<'a>(&'a self, name: &'a str) -> Vec<&'a str> {
+        let mut chain = Vec::new();
+        let mut name = name;
+        // Bound the chain so that a CNAME loop can't spin forever
+        while chain.len() < MAX_CNAME_CHAIN {
+            let next = self.answers.iter().find_map(|record| match record {
+                DnsRecord::CNAME { domain, host, .. } if domain.eq_ignore_ascii_case(name) => {
+                    Some(host.as_str())
+                }
+                _ => None,
+            });
+            match next {
+                Some(host) => {
+                    chain.push(host);
+                    name = host;
+                }
+                None => break,
+            }
+        }
+        chain
+    }
+
+    /// Answers of `query_type` for `query_name`, after following CNAMEs
+    /// unless CNAMEs are what is asked for. Only records in the class of the
+    /// question are considered, so e.g. an IN record can't answer a CH query.
+    pub fn answers_for<'a>(
+        &'a self,
+        query_name: &'a str,
+        query_type: DnsQueryType,
+    ) -> impl Iterator<Item = &'a DnsRecord> {
+        let query_class = self.questions.first().map(|question| question.query_class);
+        self.answers_in_class(query_name, query_type, query_class)
+    }
+
+    /// Like `answers_for`, but in `query_class` rather than the class of the
+    /// question, or in any class if `None`
+    pub fn answers_in_class<'a>(
+        &'a self,
+        query_name: &'a str,
+        query_type: DnsQueryType,
+        query_class: Option<DnsQueryClass>,
+    ) -> impl Iterator<Item = &'a DnsRecord> {
+        let canonical_name = if query_type == DnsQueryType::CNAME {
+            query_name
+        } else {
+            self.canonical_name(query_name)
+        };
+        self.answers.iter().filter(move |record| {
+            record.matches_query_type(query_type)
+                && query_class.is_none_or(|class| record.matches_class(class))
+                && record
+                    .domain()
+                    .is_some_and(|domain| domain.eq_ignore_ascii_case(canonical_name))
+        })
+    }
+
+    /// The first A or AAAA address answering `query_name`
+    pub fn get_record(&self, query_name: &str, query_type: DnsQueryType) -> Option<IpAddr> {
+        self.get_records(query_name, query_type).into_iter().next()
+    }
+
+    /// Every A or AAAA address answering `query_name`, in the order the
+    /// server gave them
+    pub fn get_records(&self, query_name: &str, query_type: DnsQueryType) -> Vec<IpAddr> {
+        self.answers_for(query_name, query_type)
+            .filter_map(|record| match record {
                 DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
                 DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
                 _ => None,
             })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::header::DnsResponseCode;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn extended_errors_are_read_from_the_opt_record() {
+        // OPTION-CODE 15, OPTION-LENGTH 6, INFO-CODE 15 (Blocked) and a
+        // NUL-terminated EXTRA-TEXT
+        let options = vec![0, 15, 0, 6, 0, 15, b'a', b'd', b's', 0];
+
+        let mut packet = DnsPacket::new();
+        packet.header.additional_count = 1;
+        packet.additional.push(DnsRecord::OPT {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options,
+        });
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.pos = 0;
+
+        let packet = DnsPacket::read(&mut buffer).unwrap();
+        assert_eq!(
+            packet.extended_errors(),
+            vec![ExtendedDnsError {
+                code: 15,
+                text: "ads".to_string(),
+            }]
+        );
+        assert_eq!(
+            packet.extended_errors()[0].to_string(),
+            "Blocked (EDE 15): ads"
+        );
+    }
+
+    #[test]
+    fn mx_records_are_sorted_by_priority_with_their_glue() {
+        let mx = |priority, host: &str| DnsRecord::MX {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            priority,
+            host: host.to_string(),
+            ttl: 300,
+        };
+        let mut packet = DnsPacket::new();
+        packet.header.answer_count = 3;
+        packet.answers = vec![
+            mx(20, "backup.example.com"),
+            mx(5, "mx1.example.com"),
+            mx(10, "mx2.example.com"),
+        ];
+        packet.header.additional_count = 1;
+        packet.additional.push(DnsRecord::A {
+            domain: "mx1.example.com".to_string(),
+            class: DnsQueryClass::IN,
+            addr: "192.0.2.25".parse().unwrap(),
+            ttl: 300,
+        });
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.pos = 0;
+
+        let packet = DnsPacket::read(&mut buffer).unwrap();
+        assert_eq!(
+            packet.mx_records_sorted(),
+            vec![
+                (5, "mx1.example.com"),
+                (10, "mx2.example.com"),
+                (20, "backup.example.com"),
+            ]
+        );
+        assert_eq!(
+            packet.glue_addresses("mx1.example.com"),
+            vec!["192.0.2.25".parse::<IpAddr>().unwrap()]
+        );
+        assert!(packet.glue_addresses("mx2.example.com").is_empty());
+    }
+
+    #[test]
+    fn query_is_a_recursive_standard_query() {
+        let query = DnsPacket::query("example.com", DnsQueryType::AAAA);
+        let mut buffer = PacketBuffer::new();
+        query.write(&mut buffer).unwrap();
+        buffer.pos = 0;
+        let read = DnsPacket::read(&mut buffer).unwrap();
+
+        assert!(!read.header.query_response);
+        assert_eq!(read.header.opcode, 0);
+        assert!(read.header.recursion_desired);
+        assert_eq!(read.header.id, query.header.id);
+        assert_eq!(read.header.question_count, 1);
+        assert_eq!(read.questions.len(), 1);
+        assert_eq!(read.questions[0].name, "example.com");
+        assert_eq!(read.questions[0].query_type, DnsQueryType::AAAA);
+    }
+
+    #[test]
+    fn questions_beyond_the_bytes_present_are_rejected() {
+        let mut buffer = PacketBuffer::new();
+        DnsPacket::query("example.com", DnsQueryType::A)
+            .write(&mut buffer)
+            .unwrap();
+
+        // QDCOUNT claims 1000 questions, the bytes hold one
+        buffer.buffer[4..6].copy_from_slice(&1000u16.to_be_bytes());
+        buffer.buffer.truncate(buffer.pos);
+        buffer.pos = 0;
+        let err = DnsPacket::read(&mut buffer).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<DnsPacketError>(),
+            Some(DnsPacketError::InconsistentCounts { .. })
+        ));
+    }
+
+    #[test]
+    fn answer_counts_beyond_the_bytes_present_are_rejected() {
+        let answer = DnsRecord::A {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            addr: "192.0.2.1".parse().unwrap(),
+            ttl: 300,
+        };
+        let mut packet = DnsPacket::new();
+        packet.header.answer_count = 1;
+        packet.answers.push(answer.clone());
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        // ANCOUNT claims 1000 answers, the bytes hold one
+        buffer.buffer[6..8].copy_from_slice(&1000u16.to_be_bytes());
+        buffer.buffer.truncate(buffer.pos);
+        buffer.pos = 0;
+        let err = DnsPacket::read(&mut buffer).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DnsPacketError>(),
+            Some(DnsPacketError::InconsistentCounts { .. })
+        ));
+
+        // Read leniently, the counts only bound the records tried
+        buffer.pos = 0;
+        let read = DnsPacket::read_lenient(&mut buffer).unwrap();
+        assert!(read.incomplete);
+        assert_eq!(read.answers, [answer]);
+    }
+
+    #[test]
+    fn get_record_skips_answers_for_other_names() {
+        let a = |domain: &str, addr: &str| DnsRecord::A {
+            domain: domain.to_string(),
+            class: DnsQueryClass::IN,
+            addr: addr.parse().unwrap(),
+            ttl: 300,
+        };
+        let mut packet = DnsPacket::new();
+        packet.answers = vec![
+            a("unrelated.example.net", "192.0.2.9"),
+            DnsRecord::CNAME {
+                domain: "www.example.com".to_string(),
+                class: DnsQueryClass::IN,
+                host: "web.example.com".to_string(),
+                ttl: 300,
+            },
+            a("web.example.com", "192.0.2.1"),
+        ];
+
+        assert_eq!(
+            packet.get_record("www.example.com", DnsQueryType::A),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(
+            packet.get_record("unrelated.example.net", DnsQueryType::A),
+            Some("192.0.2.9".parse().unwrap())
+        );
+        assert_eq!(packet.get_record("example.org", DnsQueryType::A), None);
+    }
+
+    #[test]
+    fn only_answers_in_the_question_class_are_returned() {
+        let txt = |class: DnsQueryClass, text: &str| DnsRecord::TXT {
+            domain: "version.bind".to_string(),
+            class,
+            text: text.to_string(),
+            ttl: 0,
+        };
+        let mut packet = DnsPacket::new();
+        packet.questions.push(DnsQuestion::with_class(
+            "version.bind".to_string(),
+            DnsQueryType::TXT,
+            DnsQueryClass::CH,
+        ));
+        packet.answers = vec![
+            txt(DnsQueryClass::IN, "in"),
+            txt(DnsQueryClass::CH, "chaos"),
+        ];
+
+        let texts = |records: Vec<&DnsRecord>| -> Vec<String> {
+            records
+                .into_iter()
+                .filter_map(|record| match record {
+                    DnsRecord::TXT { text, .. } => Some(text.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(
+            texts(
+                packet
+                    .answers_for("version.bind", DnsQueryType::TXT)
+                    .collect()
+            ),
+            ["chaos"]
+        );
+        assert_eq!(
+            texts(
+                packet
+                    .answers_in_class("version.bind", DnsQueryType::TXT, None)
+                    .collect()
+            ),
+            ["in", "chaos"]
+        );
+    }
+
+    #[test]
+    fn negative_ttl_comes_from_the_authority_soa() {
+        let mut packet = DnsPacket::new();
+        packet.header.query_response = true;
+        packet.header.response_code = DnsResponseCode::NxDomain;
+        packet.header.question_count = 1;
+        packet.questions.push(DnsQuestion::new(
+            "nope.example.com".to_string(),
+            DnsQueryType::A,
+        ));
+        packet.header.authority_count = 1;
+        packet.authorities.push(DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            primary_ns: "ns1.example.com".to_string(),
+            mailbox: "hostmaster.example.com".to_string(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 900,
+            expire: 1209600,
+            minimum_ttl: 300,
+            ttl: 3600,
+        });
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.pos = 0;
+        let mut response = DnsPacket::read(&mut buffer).unwrap();
+
+        assert_eq!(response.header.response_code, DnsResponseCode::NxDomain);
+        assert_eq!(response.get_negative_ttl(), Some(300));
+
+        // The SOA's own TTL caps its minimum field
+        if let DnsRecord::SOA { ttl, .. } = &mut response.authorities[0] {
+            *ttl = 60;
+        }
+        assert_eq!(response.get_negative_ttl(), Some(60));
+
+        response.authorities.clear();
+        assert_eq!(response.get_negative_ttl(), None);
+    }
+
+    #[test]
+    fn records_before_a_truncated_answer_are_kept() {
+        let a = |addr: &str| DnsRecord::A {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            addr: addr.parse().unwrap(),
+            ttl: 300,
+        };
+        let mut packet = DnsPacket::new();
+        packet.header.question_count = 1;
+        packet
+            .questions
+            .push(DnsQuestion::new("example.com".to_string(), DnsQueryType::A));
+        packet.header.answer_count = 2;
+        packet.answers = vec![a("192.0.2.1"), a("192.0.2.2")];
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        // Cut the datagram off within the second answer's address
+        buffer.buffer.truncate(buffer.pos - 2);
+        buffer.pos = 0;
+        let read = DnsPacket::read(&mut buffer).unwrap();
+
+        assert!(read.incomplete);
+        assert_eq!(read.header.answer_count, 2);
+        assert_eq!(read.answers, [a("192.0.2.1")]);
+    }
+
+    #[test]
+    fn clipped_response_with_many_answers_keeps_its_first_records() {
+        let a = |last: u8| DnsRecord::A {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            addr: [192, 0, 2, last].into(),
+            ttl: 300,
+        };
+        let mut packet = DnsPacket::query("example.com", DnsQueryType::A);
+        packet.header.answer_count = 8;
+        packet.answers = (1..=8).map(a).collect();
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        // Cut the datagram off within the third answer, leaving fewer bytes
+        // than eight of even the smallest records would take
+        let answer_len = a(1).wire_len();
+        buffer
+            .buffer
+            .truncate(buffer.pos - 5 * answer_len - answer_len / 2);
+        buffer.pos = 0;
+        assert!(DnsPacket::read(&mut buffer).is_err());
+        buffer.pos = 0;
+        let read = DnsPacket::read_lenient(&mut buffer).unwrap();
+
+        assert!(read.incomplete);
+        assert_eq!(read.header.answer_count, 8);
+        assert_eq!(read.answers, [a(1), a(2)]);
+    }
+
+    #[test]
+    fn complete_packet_is_not_flagged_incomplete() {
+        let mut packet = DnsPacket::query("example.com", DnsQueryType::A);
+        packet.header.answer_count = 1;
+        packet.answers.push(DnsRecord::A {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            addr: "192.0.2.1".parse().unwrap(),
+            ttl: 300,
+        });
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        buffer.buffer.truncate(buffer.pos);
+        buffer.pos = 0;
+        let read = DnsPacket::read(&mut buffer).unwrap();
+
+        assert!(!read.incomplete);
+        assert_eq!(read.answers.len(), 1);
+    }
+
+    #[test]
+    fn wire_len_matches_the_bytes_written() {
+        let mut packet = DnsPacket::query("www.example.com", DnsQueryType::MX);
+        packet.header.answer_count = 1;
+        packet.answers.push(DnsRecord::MX {
+            domain: "www.example.com".to_string(),
+            class: DnsQueryClass::IN,
+            priority: 10,
+            host: "mx.example.com".to_string(),
+            ttl: 300,
+        });
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        assert_eq!(packet.header.wire_len(), 12);
+        assert_eq!(packet.questions[0].wire_len(), 17 + 4);
+        assert_eq!(packet.wire_len(), buffer.pos);
+    }
+
+    /// A response to an A query for www.example.com, read back from the wire
+    fn parsed_response() -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 0x1234;
+        packet.header.query_response = true;
+        packet.header.question_count = 1;
+        packet.header.answer_count = 1;
+        packet.questions.push(DnsQuestion::new(
+            "www.example.com".to_string(),
+            DnsQueryType::A,
+        ));
+        packet.answers.push(DnsRecord::A {
+            domain: "www.example.com".to_string(),
+            class: DnsQueryClass::IN,
+            addr: Ipv4Addr::new(192, 0, 2, 7),
+            ttl: 300,
+        });
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.pos = 0;
+        DnsPacket::read(&mut buffer).unwrap()
+    }
+
+    #[test]
+    fn cloned_packet_equals_the_parsed_one() {
+        let parsed = parsed_response();
+        let mut cloned = parsed.clone();
+        assert_eq!(cloned, parsed);
+
+        cloned.answers.clear();
+        assert_ne!(cloned, parsed);
+        assert_eq!(parsed.answers.len(), 1);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn serialized_packet_reads_back_the_same() {
+        let parsed = parsed_response();
+        let json = serde_json::to_string(&parsed).unwrap();
+        assert_eq!(serde_json::from_str::<DnsPacket>(&json).unwrap(), parsed);
     }
 }
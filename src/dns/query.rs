@@ -1,6 +1,10 @@
+use std::fmt;
+use std::str::FromStr;
+
 /// Represents DNS query types.
 #[repr(u16)]
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum DnsQueryType {
     /// A record maps a domain name to an IPv4 address.
     A = 1,
@@ -12,14 +16,22 @@ pub enum DnsQueryType {
     SOA = 6,
     /// PTR record maps an IP address to a domain name (reverse DNS).
     PTR = 12,
+    /// HINFO record describes the CPU and OS of a host.
+    HINFO = 13,
     /// MX record maps a domain name to a mail exchange server.
     MX = 15,
     /// TXT record provides arbitrary text for a domain name.
     TXT = 16,
+    /// RP record names the person responsible for a domain.
+    RP = 17,
     /// AAAA record maps a domain name to an IPv6 address.
     AAAA = 28,
     /// SRV record maps a domain name to a specific service.
     SRV = 33,
+    /// OPT pseudo-record carries EDNS(0) options.
+    OPT = 41,
+    /// AXFR requests a transfer of the whole zone, over TCP.
+    AXFR = 252,
     /// Unknown query type with a specific numeric value.
     UNKNOWN(u16),
 }
@@ -33,10 +45,14 @@ impl DnsQueryType {
             5 => Self::CNAME,
             6 => Self::SOA,
             12 => Self::PTR,
+            13 => Self::HINFO,
             15 => Self::MX,
             16 => Self::TXT,
+            17 => Self::RP,
             28 => Self::AAAA,
             33 => Self::SRV,
+            41 => Self::OPT,
+            252 => Self::AXFR,
             other => Self::UNKNOWN(other),
         }
     }
@@ -49,20 +65,66 @@ impl DnsQueryType {
             Self::CNAME => 5,
             Self::SOA => 6,
             Self::PTR => 12,
+            Self::HINFO => 13,
             Self::MX => 15,
             Self::TXT => 16,
+            Self::RP => 17,
             Self::AAAA => 28,
             Self::SRV => 33,
+            Self::OPT => 41,
+            Self::AXFR => 252,
             Self::UNKNOWN(value) => value,
         }
     }
 }
 
+impl fmt::Display for DnsQueryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UNKNOWN(value) => write!(f, "TYPE{}", value),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+impl FromStr for DnsQueryType {
+    type Err = String;
+
+    /// Parses a mnemonic such as `AAAA` (case-insensitive) or the generic
+    /// `TYPE<n>` notation of RFC 3597.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let upper = value.to_ascii_uppercase();
+        let query_type = match upper.as_str() {
+            "A" => Self::A,
+            "NS" => Self::NS,
+            "CNAME" => Self::CNAME,
+            "SOA" => Self::SOA,
+            "PTR" => Self::PTR,
+            "HINFO" => Self::HINFO,
+            "MX" => Self::MX,
+            "TXT" => Self::TXT,
+            "RP" => Self::RP,
+            "AAAA" => Self::AAAA,
+            "SRV" => Self::SRV,
+            "OPT" => Self::OPT,
+            "AXFR" => Self::AXFR,
+            other => other
+                .strip_prefix("TYPE")
+                .and_then(|n| n.parse().ok())
+                .map(Self::from_u16)
+                .ok_or_else(|| format!("Unknown query type {}", value))?,
+        };
+        Ok(query_type)
+    }
+}
+
 /// Represents DNS query classes.
 #[repr(u16)]
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum DnsQueryClass {
     /// Internet class (most common).
+    #[default]
     IN = 1,
     /// Chaos class (experimental).
     CH = 3,
@@ -109,3 +171,25 @@ impl DnsQueryClass {
         }
     }
 }
+
+impl fmt::Display for DnsQueryClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for DnsQueryClass {
+    type Err = String;
+
+    /// Parses a class mnemonic such as `IN` or `CH` (case-insensitive)
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_uppercase().as_str() {
+            "IN" => Ok(Self::IN),
+            "CH" | "CHAOS" => Ok(Self::CH),
+            "HS" | "HESIOD" => Ok(Self::HS),
+            "NONE" => Ok(Self::NONE),
+            "ANY" => Ok(Self::ANY),
+            _ => Err(format!("Unknown query class {}", value)),
+        }
+    }
+}
@@ -1,12 +1,13 @@
 use super::{
-    buffer::PacketBuffer,
+    buffer::{query_name_len, PacketBuffer},
     query::{DnsQueryClass, DnsQueryType},
 };
 use std::error::Error;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsQuestion {
     pub name: String,
     pub query_type: DnsQueryType,
@@ -29,10 +30,15 @@ impl DnsQuestion {
     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
     */
     pub fn new(name: String, query_type: DnsQueryType) -> Self {
+        Self::with_class(name, query_type, DnsQueryClass::IN)
+    }
+
+    /// A question in a class other than IN, e.g. CH for `version.bind`
+    pub fn with_class(name: String, query_type: DnsQueryType, query_class: DnsQueryClass) -> Self {
         Self {
             name,
             query_type,
-            query_class: DnsQueryClass::IN,
+            query_class,
         }
     }
 
@@ -45,6 +51,11 @@ impl DnsQuestion {
         Ok(question)
     }
 
+    /// Size of the question on the wire: the name, type and class
+    pub fn wire_len(&self) -> usize {
+        query_name_len(&self.name) + 4
+    }
+
     pub fn write(&self, buffer: &mut PacketBuffer) -> Result<()> {
         buffer.write_query_name(&self.name)?;
         buffer.write_u16(self.query_type.to_u16())?;
@@ -52,3 +63,26 @@ impl DnsQuestion {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chaos_class_question_is_serialized() {
+        let question = DnsQuestion::with_class(
+            "version.bind".to_string(),
+            DnsQueryType::TXT,
+            DnsQueryClass::CH,
+        );
+        let mut buffer = PacketBuffer::new();
+        question.write(&mut buffer).unwrap();
+        assert_eq!(
+            &buffer.buffer[..buffer.pos],
+            b"\x07version\x04bind\x00\x00\x10\x00\x03"
+        );
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsQuestion::read(&mut buffer).unwrap(), question);
+    }
+}
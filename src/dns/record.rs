@@ -1,33 +1,41 @@
-use super::query::DnsQueryClass;
-use super::{buffer::PacketBuffer, query::DnsQueryType};
+use super::{
+    buffer::{query_name_len, PacketBuffer},
+    query::{DnsQueryClass, DnsQueryType},
+};
 use std::error::Error;
+use std::fmt;
 use std::net::{Ipv4Addr, Ipv6Addr};
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum DnsRecord {
     /// A (Address) record maps a domain to an IPv4 address
     A {
         domain: String,
+        class: DnsQueryClass,
         addr: Ipv4Addr,
         ttl: u32,
     },
     /// NS (Name Server) record maps a domain to a name server
     NS {
         domain: String,
+        class: DnsQueryClass,
         host: String,
         ttl: u32,
     },
     /// CNAME (Canonical Name) record maps a domain to another domain
     CNAME {
         domain: String,
+        class: DnsQueryClass,
         host: String,
         ttl: u32,
     },
     /// SOA (Start of Authority) record provides administrative information
     SOA {
         domain: String,
+        class: DnsQueryClass,
         primary_ns: String,
         mailbox: String,
         serial: u32,
@@ -40,12 +48,22 @@ pub enum DnsRecord {
     /// PTR (Pointer) record maps an IP address to a domain name (reverse DNS)
     PTR {
         domain: String,
+        class: DnsQueryClass,
         host: String,
         ttl: u32,
     },
+    /// HINFO (Host Information) record describes the CPU and OS of a host
+    HINFO {
+        domain: String,
+        class: DnsQueryClass,
+        cpu: String,
+        os: String,
+        ttl: u32,
+    },
     /// MX (Mail Exchange) record maps a domain to a mail server
     MX {
         domain: String,
+        class: DnsQueryClass,
         priority: u16,
         host: String,
         ttl: u32,
@@ -53,27 +71,48 @@ pub enum DnsRecord {
     /// TXT (Text) record provides arbitrary human-readable text for a domain
     TXT {
         domain: String,
+        class: DnsQueryClass,
         text: String,
         ttl: u32,
     },
+    /// RP (Responsible Person) record gives the mailbox of the person
+    /// responsible for a domain and a domain with TXT records about them
+    RP {
+        domain: String,
+        class: DnsQueryClass,
+        mailbox: String,
+        txt_domain: String,
+        ttl: u32,
+    },
     /// AAAA (IPv6 Address) record maps a domain to an IPv6 address
     AAAA {
         domain: String,
+        class: DnsQueryClass,
         addr: std::net::Ipv6Addr,
         ttl: u32,
     },
     /// SRV (Service Locator) record maps a domain to a specific service
     SRV {
         domain: String,
+        class: DnsQueryClass,
         priority: u16,
         weight: u16,
         port: u16,
         target: String,
         ttl: u32,
     },
+    /// OPT pseudo-record carrying EDNS(0) parameters and options
+    OPT {
+        udp_payload_size: u16,
+        extended_rcode: u8,
+        version: u8,
+        flags: u16,
+        options: Vec<u8>,
+    },
     /// Represents an unknown record type
     UNKNOWN {
         domain: String,
+        class: DnsQueryClass,
         query_type: DnsQueryType,
         data: Vec<u8>,
         ttl: u32,
@@ -107,22 +146,38 @@ impl DnsRecord {
     pub fn read(buffer: &mut PacketBuffer) -> Result<DnsRecord> {
         let domain = buffer.read_query_name()?;
         let query_type = DnsQueryType::from_u16(buffer.read_u16()?);
-        let _query_class = DnsQueryClass::from_u16(buffer.read_u16()?);
+        let class_value = buffer.read_u16()?;
+        let class = DnsQueryClass::from_u16(class_value);
         let ttl = buffer.read_u32()?;
         let length = buffer.read_u16()?;
 
         match query_type {
             DnsQueryType::A => {
                 let addr = Ipv4Addr::from(buffer.read_u32()?);
-                Ok(DnsRecord::A { domain, addr, ttl })
+                Ok(DnsRecord::A {
+                    domain,
+                    class,
+                    addr,
+                    ttl,
+                })
             }
             DnsQueryType::NS => {
                 let host = buffer.read_query_name()?;
-                Ok(DnsRecord::NS { domain, host, ttl })
+                Ok(DnsRecord::NS {
+                    domain,
+                    class,
+                    host,
+                    ttl,
+                })
             }
             DnsQueryType::CNAME => {
                 let host = buffer.read_query_name()?;
-                Ok(DnsRecord::CNAME { domain, host, ttl })
+                Ok(DnsRecord::CNAME {
+                    domain,
+                    class,
+                    host,
+                    ttl,
+                })
             }
             DnsQueryType::SOA => {
                 let primary_ns = buffer.read_query_name()?;
@@ -134,6 +189,7 @@ impl DnsRecord {
                 let minimum_ttl = buffer.read_u32()?;
                 Ok(DnsRecord::SOA {
                     domain,
+                    class,
                     primary_ns,
                     mailbox,
                     serial,
@@ -146,13 +202,30 @@ impl DnsRecord {
             }
             DnsQueryType::PTR => {
                 let host = buffer.read_query_name()?;
-                Ok(DnsRecord::PTR { domain, host, ttl })
+                Ok(DnsRecord::PTR {
+                    domain,
+                    class,
+                    host,
+                    ttl,
+                })
+            }
+            DnsQueryType::HINFO => {
+                let cpu = buffer.read_character_string()?;
+                let os = buffer.read_character_string()?;
+                Ok(DnsRecord::HINFO {
+                    domain,
+                    class,
+                    cpu,
+                    os,
+                    ttl,
+                })
             }
             DnsQueryType::MX => {
                 let priority = buffer.read_u16()?;
                 let host = buffer.read_query_name()?;
                 Ok(DnsRecord::MX {
                     domain,
+                    class,
                     priority,
                     host,
                     ttl,
@@ -161,11 +234,32 @@ impl DnsRecord {
             DnsQueryType::TXT => {
                 let txt_data = buffer.read_bytes(length as usize)?;
                 let text = String::from_utf8_lossy(txt_data).into_owned();
-                Ok(DnsRecord::TXT { domain, text, ttl })
+                Ok(DnsRecord::TXT {
+                    domain,
+                    class,
+                    text,
+                    ttl,
+                })
+            }
+            DnsQueryType::RP => {
+                let mailbox = buffer.read_query_name()?;
+                let txt_domain = buffer.read_query_name()?;
+                Ok(DnsRecord::RP {
+                    domain,
+                    class,
+                    mailbox,
+                    txt_domain,
+                    ttl,
+                })
             }
             DnsQueryType::AAAA => {
                 let addr = Ipv6Addr::from(buffer.read_u128()?);
-                Ok(DnsRecord::AAAA { domain, addr, ttl })
+                Ok(DnsRecord::AAAA {
+                    domain,
+                    class,
+                    addr,
+                    ttl,
+                })
             }
             DnsQueryType::SRV => {
                 let priority = buffer.read_u16()?;
@@ -174,6 +268,7 @@ impl DnsRecord {
                 let target = buffer.read_query_name()?;
                 Ok(DnsRecord::SRV {
                     domain,
+                    class,
                     priority,
                     weight,
                     port,
@@ -181,10 +276,23 @@ impl DnsRecord {
                     ttl,
                 })
             }
+            DnsQueryType::OPT => {
+                // The CLASS field holds the requestor's UDP payload size and
+                // the TTL field holds the extended RCODE, version and flags.
+                let options = buffer.read_bytes(length as usize)?.to_vec();
+                Ok(DnsRecord::OPT {
+                    udp_payload_size: class_value,
+                    extended_rcode: (ttl >> 24) as u8,
+                    version: (ttl >> 16) as u8,
+                    flags: ttl as u16,
+                    options,
+                })
+            }
             _ => {
                 let data = buffer.read_bytes(length as usize)?.to_vec();
                 Ok(DnsRecord::UNKNOWN {
                     domain,
+                    class,
                     query_type,
                     data,
                     ttl,
@@ -196,44 +304,44 @@ impl DnsRecord {
         match self {
             DnsRecord::A {
                 ref domain,
+                class,
                 ref addr,
                 ttl,
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(DnsQueryType::A.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
                 buffer.write_u16(4)?;
                 buffer.write_u32(u32::from(*addr))?;
             }
             DnsRecord::NS {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(DnsQueryType::NS.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
-                let host_bytes = host.as_bytes();
-                buffer.write_u16(host_bytes.len() as u16)?;
-                buffer.write_bytes(host_bytes)?;
+                buffer.write_length_prefixed(|buffer| buffer.write_query_name(host))?;
             }
             DnsRecord::CNAME {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(DnsQueryType::CNAME.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
-                let host_bytes = host.as_bytes();
-                buffer.write_u16(host_bytes.len() as u16)?;
-                buffer.write_bytes(host_bytes)?;
+                buffer.write_length_prefixed(|buffer| buffer.write_query_name(host))?;
             }
             DnsRecord::SOA {
                 ref domain,
+                class,
                 ref primary_ns,
                 ref mailbox,
                 serial,
@@ -245,70 +353,107 @@ impl DnsRecord {
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(DnsQueryType::SOA.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
 
-                buffer.write_query_name(primary_ns)?;
-                buffer.write_query_name(mailbox)?;
-                buffer.write_u32(*serial)?;
-                buffer.write_u32(*refresh)?;
-                buffer.write_u32(*retry)?;
-                buffer.write_u32(*expire)?;
-                buffer.write_u32(*minimum_ttl)?;
+                buffer.write_length_prefixed(|buffer| {
+                    buffer.write_query_name(primary_ns)?;
+                    buffer.write_query_name(mailbox)?;
+                    buffer.write_u32(*serial)?;
+                    buffer.write_u32(*refresh)?;
+                    buffer.write_u32(*retry)?;
+                    buffer.write_u32(*expire)?;
+                    buffer.write_u32(*minimum_ttl)
+                })?;
             }
             DnsRecord::PTR {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(DnsQueryType::PTR.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
+                buffer.write_u32(*ttl)?;
+                buffer.write_length_prefixed(|buffer| buffer.write_query_name(host))?;
+            }
+            DnsRecord::HINFO {
+                ref domain,
+                class,
+                ref cpu,
+                ref os,
+                ttl,
+            } => {
+                buffer.write_query_name(domain)?;
+                buffer.write_u16(DnsQueryType::HINFO.to_u16())?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
-                buffer.write_u16(host.len() as u16)?;
-                buffer.write_bytes(host.as_bytes())?;
+                buffer.write_length_prefixed(|buffer| {
+                    buffer.write_character_string(cpu)?;
+                    buffer.write_character_string(os)
+                })?;
             }
             DnsRecord::MX {
                 ref domain,
+                class,
                 priority,
                 ref host,
                 ttl,
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(DnsQueryType::MX.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
-                buffer.write_u16(2)?;
-                buffer.write_u16(*priority)?;
-                buffer.write_query_name(host)?;
+                buffer.write_length_prefixed(|buffer| {
+                    buffer.write_u16(*priority)?;
+                    buffer.write_query_name(host)
+                })?;
             }
             DnsRecord::TXT {
                 ref domain,
+                class,
                 ref text,
                 ttl,
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(DnsQueryType::TXT.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
+                buffer.write_u32(*ttl)?;
+                buffer.write_length_prefixed(|buffer| buffer.write_bytes(text.as_bytes()))?;
+            }
+            DnsRecord::RP {
+                ref domain,
+                class,
+                ref mailbox,
+                ref txt_domain,
+                ttl,
+            } => {
+                buffer.write_query_name(domain)?;
+                buffer.write_u16(DnsQueryType::RP.to_u16())?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
-                let txt_bytes = text.as_bytes();
-                buffer.write_u16(txt_bytes.len() as u16)?;
-                buffer.write_bytes(txt_bytes)?;
+                buffer.write_length_prefixed(|buffer| {
+                    buffer.write_query_name(mailbox)?;
+                    buffer.write_query_name(txt_domain)
+                })?;
             }
             DnsRecord::AAAA {
                 ref domain,
+                class,
                 ref addr,
                 ttl,
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(DnsQueryType::AAAA.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
                 buffer.write_u16(16)?;
                 buffer.write_u128(u128::from(*addr))?;
             }
             DnsRecord::SRV {
                 ref domain,
+                class,
                 priority,
                 weight,
                 port,
@@ -317,31 +462,128 @@ impl DnsRecord {
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(DnsQueryType::SRV.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
-                buffer.write_u16(6)?;
-                buffer.write_u16(*priority)?;
-                buffer.write_u16(*weight)?;
-                buffer.write_u16(*port)?;
-                buffer.write_query_name(target)?;
+                buffer.write_length_prefixed(|buffer| {
+                    buffer.write_u16(*priority)?;
+                    buffer.write_u16(*weight)?;
+                    buffer.write_u16(*port)?;
+                    buffer.write_query_name(target)
+                })?;
+            }
+            DnsRecord::OPT {
+                udp_payload_size,
+                extended_rcode,
+                version,
+                flags,
+                ref options,
+            } => {
+                // OPT is always owned by the root domain
+                buffer.write(0)?;
+                buffer.write_u16(DnsQueryType::OPT.to_u16())?;
+                buffer.write_u16(*udp_payload_size)?;
+                buffer.write(*extended_rcode)?;
+                buffer.write(*version)?;
+                buffer.write_u16(*flags)?;
+                buffer.write_length_prefixed(|buffer| buffer.write_bytes(options))?;
             }
             DnsRecord::UNKNOWN {
                 ref domain,
+                class,
                 query_type,
                 ref data,
                 ttl,
             } => {
                 buffer.write_query_name(domain)?;
                 buffer.write_u16(query_type.to_u16())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_u16())?;
                 buffer.write_u32(*ttl)?;
-                buffer.write_u16(data.len() as u16)?;
-                buffer.write_bytes(data)?;
+                buffer.write_length_prefixed(|buffer| buffer.write_bytes(data))?;
             }
         }
         Ok(())
     }
 
+    /// Size of the record on the wire, as written by `write`, without
+    /// writing it. Names are counted uncompressed, as `write` leaves them.
+    pub fn wire_len(&self) -> usize {
+        // TYPE, CLASS, TTL and RDLENGTH
+        const FIXED_LEN: usize = 2 + 2 + 4 + 2;
+
+        let data_len = match self {
+            Self::A { .. } => 4,
+            Self::NS { host, .. } | Self::CNAME { host, .. } | Self::PTR { host, .. } => {
+                query_name_len(host)
+            }
+            Self::SOA {
+                primary_ns,
+                mailbox,
+                ..
+            } => query_name_len(primary_ns) + query_name_len(mailbox) + 5 * 4,
+            Self::HINFO { cpu, os, .. } => 1 + cpu.len() + 1 + os.len(),
+            Self::MX { host, .. } => 2 + query_name_len(host),
+            Self::TXT { text, .. } => text.len(),
+            Self::RP {
+                mailbox,
+                txt_domain,
+                ..
+            } => query_name_len(mailbox) + query_name_len(txt_domain),
+            Self::AAAA { .. } => 16,
+            Self::SRV { target, .. } => 3 * 2 + query_name_len(target),
+            Self::OPT { options, .. } => options.len(),
+            Self::UNKNOWN { data, .. } => data.len(),
+        };
+        let name_len = self.domain().map_or(1, query_name_len);
+
+        name_len + FIXED_LEN + data_len
+    }
+
+    /// The owner name of the record, `None` for the OPT pseudo-record
+    pub fn domain(&self) -> Option<&str> {
+        match self {
+            Self::A { domain, .. }
+            | Self::NS { domain, .. }
+            | Self::CNAME { domain, .. }
+            | Self::SOA { domain, .. }
+            | Self::PTR { domain, .. }
+            | Self::HINFO { domain, .. }
+            | Self::MX { domain, .. }
+            | Self::TXT { domain, .. }
+            | Self::RP { domain, .. }
+            | Self::AAAA { domain, .. }
+            | Self::SRV { domain, .. }
+            | Self::UNKNOWN { domain, .. } => Some(domain),
+            Self::OPT { .. } => None,
+        }
+    }
+
+    /// The class of the record, `None` for the OPT pseudo-record whose
+    /// CLASS field holds the UDP payload size instead
+    pub fn class(&self) -> Option<DnsQueryClass> {
+        match self {
+            Self::A { class, .. }
+            | Self::NS { class, .. }
+            | Self::CNAME { class, .. }
+            | Self::SOA { class, .. }
+            | Self::PTR { class, .. }
+            | Self::HINFO { class, .. }
+            | Self::MX { class, .. }
+            | Self::TXT { class, .. }
+            | Self::RP { class, .. }
+            | Self::AAAA { class, .. }
+            | Self::SRV { class, .. }
+            | Self::UNKNOWN { class, .. } => Some(*class),
+            Self::OPT { .. } => None,
+        }
+    }
+
+    /// Whether the record answers a question of `class`, ANY matching
+    /// records of every class
+    pub fn matches_class(&self, class: DnsQueryClass) -> bool {
+        self.class()
+            .is_some_and(|record_class| class == DnsQueryClass::ANY || record_class == class)
+    }
+
     pub fn matches_query_type(&self, query_type: DnsQueryType) -> bool {
         match (self, query_type) {
             (Self::A { .. }, DnsQueryType::A) => true,
@@ -349,10 +591,13 @@ impl DnsRecord {
             (Self::CNAME { .. }, DnsQueryType::CNAME) => true,
             (Self::SOA { .. }, DnsQueryType::SOA) => true,
             (Self::PTR { .. }, DnsQueryType::PTR) => true,
+            (Self::HINFO { .. }, DnsQueryType::HINFO) => true,
             (Self::MX { .. }, DnsQueryType::MX) => true,
             (Self::TXT { .. }, DnsQueryType::TXT) => true,
+            (Self::RP { .. }, DnsQueryType::RP) => true,
             (Self::AAAA { .. }, DnsQueryType::AAAA) => true,
             (Self::SRV { .. }, DnsQueryType::SRV) => true,
+            (Self::OPT { .. }, DnsQueryType::OPT) => true,
             (
                 Self::UNKNOWN {
                     query_type: record_query_type,
@@ -364,3 +609,357 @@ impl DnsRecord {
         }
     }
 }
+
+impl fmt::Display for DnsRecord {
+    /// Formats the record in zone file presentation format
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsRecord::A { domain, class, addr, ttl } => write!(f, "{domain}.\t{ttl}\t{class}\tA\t{addr}"),
+            DnsRecord::NS { domain, class, host, ttl } => {
+                write!(f, "{domain}.\t{ttl}\t{class}\tNS\t{host}.")
+            }
+            DnsRecord::CNAME { domain, class, host, ttl } => {
+                write!(f, "{domain}.\t{ttl}\t{class}\tCNAME\t{host}.")
+            }
+            DnsRecord::SOA {
+                domain,
+                class,
+                primary_ns,
+                mailbox,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum_ttl,
+                ttl,
+            } => write!(
+                f,
+                "{domain}.\t{ttl}\t{class}\tSOA\t{primary_ns}. {mailbox}. {serial} {refresh} {retry} {expire} {minimum_ttl}"
+            ),
+            DnsRecord::PTR { domain, class, host, ttl } => {
+                write!(f, "{domain}.\t{ttl}\t{class}\tPTR\t{host}.")
+            }
+            DnsRecord::HINFO {
+                domain,
+                class,
+                cpu,
+                os,
+                ttl,
+            } => write!(f, "{domain}.\t{ttl}\t{class}\tHINFO\t{cpu:?} {os:?}"),
+            DnsRecord::MX {
+                domain,
+                class,
+                priority,
+                host,
+                ttl,
+            } => write!(f, "{domain}.\t{ttl}\t{class}\tMX\t{priority} {host}."),
+            DnsRecord::TXT { domain, class, text, ttl } => {
+                write!(f, "{domain}.\t{ttl}\t{class}\tTXT\t{text:?}")
+            }
+            DnsRecord::RP {
+                domain,
+                class,
+                mailbox,
+                txt_domain,
+                ttl,
+            } => write!(f, "{domain}.\t{ttl}\t{class}\tRP\t{mailbox}. {txt_domain}."),
+            DnsRecord::AAAA { domain, class, addr, ttl } => {
+                write!(f, "{domain}.\t{ttl}\t{class}\tAAAA\t{addr}")
+            }
+            DnsRecord::SRV {
+                domain,
+                class,
+                priority,
+                weight,
+                port,
+                target,
+                ttl,
+            } => write!(
+                f,
+                "{domain}.\t{ttl}\t{class}\tSRV\t{priority} {weight} {port} {target}."
+            ),
+            DnsRecord::OPT {
+                udp_payload_size,
+                version,
+                flags,
+                ..
+            } => write!(
+                f,
+                "; EDNS: version {version}, flags {flags:#06x}, udp {udp_payload_size}"
+            ),
+            DnsRecord::UNKNOWN {
+                domain,
+                class,
+                query_type,
+                data,
+                ttl,
+            } => {
+                // RFC 3597 generic encoding
+                write!(f, "{domain}.\t{ttl}\t{class}\t{query_type}\t\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                    data.iter().try_for_each(|b| write!(f, "{b:02x}"))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `record`, check its RDLENGTH covers exactly the RDATA written
+    /// after it, and read it back
+    fn round_trip(record: &DnsRecord) -> DnsRecord {
+        let mut buffer = PacketBuffer::new();
+        record.write(&mut buffer).unwrap();
+        let written = buffer.pos;
+
+        buffer.seek(0).unwrap();
+        buffer.read_query_name().unwrap();
+        let rdata_start = buffer.pos + 10;
+        buffer.seek(buffer.pos + 8).unwrap();
+        assert_eq!(buffer.read_u16().unwrap() as usize, written - rdata_start);
+
+        buffer.seek(0).unwrap();
+        let read = DnsRecord::read(&mut buffer).unwrap();
+        assert_eq!(buffer.pos, written);
+        read
+    }
+
+    #[test]
+    fn ptr_round_trips() {
+        let record = DnsRecord::PTR {
+            domain: "1.2.0.192.in-addr.arpa".to_string(),
+            class: DnsQueryClass::IN,
+            host: "host.example.com".to_string(),
+            ttl: 3600,
+        };
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn ns_and_cname_round_trip() {
+        let ns = DnsRecord::NS {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            host: "ns1.example.net".to_string(),
+            ttl: 86400,
+        };
+        assert_eq!(round_trip(&ns), ns);
+
+        let cname = DnsRecord::CNAME {
+            domain: "www.example.com".to_string(),
+            class: DnsQueryClass::IN,
+            host: "example.com".to_string(),
+            ttl: 300,
+        };
+        assert_eq!(round_trip(&cname), cname);
+    }
+
+    #[test]
+    fn soa_round_trips() {
+        let record = DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            primary_ns: "ns1.example.com".to_string(),
+            mailbox: "hostmaster.example.com".to_string(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum_ttl: 300,
+            ttl: 3600,
+        };
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn record_after_a_soa_reads_back() {
+        let soa = DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            primary_ns: "ns1.example.com".to_string(),
+            mailbox: "hostmaster.example.com".to_string(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum_ttl: 300,
+            ttl: 3600,
+        };
+        let a = DnsRecord::A {
+            domain: "ns1.example.com".to_string(),
+            class: DnsQueryClass::IN,
+            addr: "192.0.2.53".parse().unwrap(),
+            ttl: 3600,
+        };
+        let mut buffer = PacketBuffer::new();
+        soa.write(&mut buffer).unwrap();
+        a.write(&mut buffer).unwrap();
+        let written = buffer.pos;
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), soa);
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), a);
+        assert_eq!(buffer.pos, written);
+    }
+
+    #[test]
+    fn mx_round_trips() {
+        let record = DnsRecord::MX {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            priority: 10,
+            host: "mail.example.com".to_string(),
+            ttl: 300,
+        };
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn srv_round_trips() {
+        let record = DnsRecord::SRV {
+            domain: "_sip._udp.example.com".to_string(),
+            class: DnsQueryClass::IN,
+            priority: 10,
+            weight: 60,
+            port: 5060,
+            target: "sip.example.com".to_string(),
+            ttl: 300,
+        };
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn hinfo_round_trips() {
+        let hinfo = DnsRecord::HINFO {
+            domain: "host.example.com".to_string(),
+            class: DnsQueryClass::IN,
+            cpu: "x86_64".to_string(),
+            os: "Linux".to_string(),
+            ttl: 3600,
+        };
+        assert_eq!(round_trip(&hinfo), hinfo);
+    }
+
+    #[test]
+    fn rp_round_trips() {
+        let rp = DnsRecord::RP {
+            domain: "example.com".to_string(),
+            class: DnsQueryClass::IN,
+            mailbox: "admin.example.com".to_string(),
+            txt_domain: "contact.example.com".to_string(),
+            ttl: 3600,
+        };
+        assert_eq!(round_trip(&rp), rp);
+    }
+
+    #[test]
+    fn wire_len_matches_the_bytes_written() {
+        let domain = || "www.example.com".to_string();
+        let host = || "host.example.net".to_string();
+        let records = vec![
+            DnsRecord::A {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: 300,
+            },
+            DnsRecord::NS {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                host: host(),
+                ttl: 300,
+            },
+            DnsRecord::CNAME {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                host: host(),
+                ttl: 300,
+            },
+            DnsRecord::SOA {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                primary_ns: host(),
+                mailbox: "hostmaster.example.com".to_string(),
+                serial: 1,
+                refresh: 2,
+                retry: 3,
+                expire: 4,
+                minimum_ttl: 5,
+                ttl: 300,
+            },
+            DnsRecord::PTR {
+                domain: "1.2.0.192.in-addr.arpa".to_string(),
+                class: DnsQueryClass::IN,
+                host: host(),
+                ttl: 300,
+            },
+            DnsRecord::HINFO {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                cpu: "x86_64".to_string(),
+                os: "Linux".to_string(),
+                ttl: 300,
+            },
+            DnsRecord::MX {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                priority: 10,
+                host: host(),
+                ttl: 300,
+            },
+            DnsRecord::TXT {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                text: "v=spf1 -all ".repeat(30),
+                ttl: 300,
+            },
+            DnsRecord::RP {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                mailbox: "admin.example.com".to_string(),
+                txt_domain: host(),
+                ttl: 300,
+            },
+            DnsRecord::AAAA {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                addr: "2001:db8::1".parse().unwrap(),
+                ttl: 300,
+            },
+            DnsRecord::SRV {
+                domain: "_sip._tcp.example.com".to_string(),
+                class: DnsQueryClass::IN,
+                priority: 10,
+                weight: 5,
+                port: 5060,
+                target: host(),
+                ttl: 300,
+            },
+            DnsRecord::OPT {
+                udp_payload_size: 1232,
+                extended_rcode: 0,
+                version: 0,
+                flags: 0,
+                options: vec![0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8],
+            },
+            DnsRecord::UNKNOWN {
+                domain: domain(),
+                class: DnsQueryClass::IN,
+                query_type: DnsQueryType::UNKNOWN(99),
+                data: vec![1, 2, 3],
+                ttl: 300,
+            },
+        ];
+
+        for record in records {
+            let mut buffer = PacketBuffer::new();
+            record.write(&mut buffer).unwrap();
+            assert_eq!(record.wire_len(), buffer.pos, "{record:?}");
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Where the system resolver is configured
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// The parts of a resolv.conf(5) our resolver follows: the nameservers, the
+/// search list and `options ndots`. Everything else is ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvConf {
+    /// Nameservers in the order listed
+    pub nameservers: Vec<IpAddr>,
+    /// Domains of the last `search` or `domain` line
+    pub search: Vec<String>,
+    /// Value of `options ndots:n`, if given
+    pub ndots: Option<usize>,
+}
+
+impl ResolvConf {
+    /// Parse the contents of a resolv.conf. Lines that don't parse, such as
+    /// a nameserver that isn't an IP address, are skipped like the system
+    /// resolver skips them.
+    pub fn parse(text: &str) -> Self {
+        let mut conf = Self::default();
+        for line in text.lines() {
+            let line = line.trim_start();
+            if line.starts_with(['#', ';']) {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    // The interface a link-local nameserver may name is dropped
+                    let address = words.next().map(|address| match address.split_once('%') {
+                        Some((address, _)) => address,
+                        None => address,
+                    });
+                    if let Some(Ok(address)) = address.map(str::parse) {
+                        conf.nameservers.push(address);
+                    }
+                }
+                // Whichever of `search` and `domain` comes last wins
+                Some("search") | Some("domain") => {
+                    conf.search = words.map(str::to_string).collect();
+                }
+                Some("options") => {
+                    for option in words {
+                        if let Some(Ok(ndots)) = option.strip_prefix("ndots:").map(str::parse) {
+                            conf.ndots = Some(ndots);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        conf
+    }
+
+    /// Read and parse the resolv.conf at `path`, `None` if it is missing or
+    /// can't be read
+    pub fn read(path: impl AsRef<Path>) -> Option<Self> {
+        fs::read_to_string(path).ok().map(|text| Self::parse(&text))
+    }
+
+    /// The system's configuration, from `RESOLV_CONF_PATH`
+    pub fn system() -> Option<Self> {
+        Self::read(RESOLV_CONF_PATH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn nameservers_search_list_and_ndots_are_parsed() {
+        let conf = ResolvConf::parse(
+            "# Generated by NetworkManager\n\
+             domain example.org\n\
+             search corp.example.com example.com\n\
+             nameserver 192.0.2.53\n\
+             ; a comment\n\
+             nameserver fe80::53%eth0\n\
+             nameserver not-an-address\n\
+             nameserver 198.51.100.53\n\
+             options rotate ndots:2 timeout:1\n",
+        );
+
+        assert_eq!(
+            conf.nameservers,
+            [
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53)),
+                IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0x53)),
+                IpAddr::V4(Ipv4Addr::new(198, 51, 100, 53)),
+            ]
+        );
+        assert_eq!(conf.search, ["corp.example.com", "example.com"]);
+        assert_eq!(conf.ndots, Some(2));
+    }
+
+    #[test]
+    fn missing_file_reads_as_none() {
+        assert_eq!(ResolvConf::read("/nonexistent/resolv.conf"), None);
+    }
+}
@@ -1,44 +1,376 @@
 use crate::dns::header::DnsResponseCode;
 
-use super::{buffer::PacketBuffer, packet::DnsPacket, query::DnsQueryType, question::DnsQuestion};
+use super::{
+    buffer::{PacketBuffer, DNS_BUFFER_SZ},
+    edns::EDNS_UDP_PAYLOAD_SZ,
+    packet::DnsPacket,
+    query::{DnsQueryClass, DnsQueryType},
+    record::DnsRecord,
+    tcp::{read_message, write_message},
+};
 use std::error::Error;
-use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use std::{fmt, io};
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// a.root-servers.net, where recursive lookups start
+pub const ROOT_SERVER: Ipv4Addr = Ipv4Addr::new(198, 41, 0, 4);
+
+/// Name of `ROOT_SERVER`
+pub const ROOT_SERVER_NAME: &str = "a.root-servers.net";
+
+/// The IPv4 addresses of the root servers, a to m
+const ROOT_SERVERS: [(&str, Ipv4Addr); 13] = [
+    (ROOT_SERVER_NAME, ROOT_SERVER),
+    ("b.root-servers.net", Ipv4Addr::new(170, 247, 170, 2)),
+    ("c.root-servers.net", Ipv4Addr::new(192, 33, 4, 12)),
+    ("d.root-servers.net", Ipv4Addr::new(199, 7, 91, 13)),
+    ("e.root-servers.net", Ipv4Addr::new(192, 203, 230, 10)),
+    ("f.root-servers.net", Ipv4Addr::new(192, 5, 5, 241)),
+    ("g.root-servers.net", Ipv4Addr::new(192, 112, 36, 4)),
+    ("h.root-servers.net", Ipv4Addr::new(198, 97, 190, 53)),
+    ("i.root-servers.net", Ipv4Addr::new(192, 36, 148, 17)),
+    ("j.root-servers.net", Ipv4Addr::new(192, 58, 128, 30)),
+    ("k.root-servers.net", Ipv4Addr::new(193, 0, 14, 129)),
+    ("l.root-servers.net", Ipv4Addr::new(199, 7, 83, 42)),
+    ("m.root-servers.net", Ipv4Addr::new(202, 12, 27, 33)),
+];
+
+/// How long `recursive_lookup` waits for each server asked
+pub const ITERATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server to start iterating from, see `recursive_lookup_from`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootHint {
+    pub name: String,
+    pub address: IpAddr,
+    /// Port of the hint, and of every server asked when iterating from it
+    pub port: u16,
+}
+
+impl RootHint {
+    pub fn new(name: impl Into<String>, address: IpAddr) -> Self {
+        Self {
+            name: name.into(),
+            address,
+            port: 53,
+        }
+    }
+
+    /// Ask the hint, and every server it leads to, on `port` rather than 53
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// The root servers, a.root-servers.net first
+    pub fn root_servers() -> Vec<Self> {
+        ROOT_SERVERS
+            .iter()
+            .map(|&(name, address)| Self::new(name, IpAddr::V4(address)))
+            .collect()
+    }
+}
+
+impl From<IpAddr> for RootHint {
+    /// A hint known only by its address, e.g. a private root
+    fn from(address: IpAddr) -> Self {
+        Self::new(address.to_string(), address)
+    }
+}
+
+/// Custom error type for DNS lookups
+#[derive(Debug)]
+pub enum LookupError {
+    /// The response did not echo the exact casing of the 0x20-encoded query name
+    CaseMismatch { sent: String, received: String },
+    /// The source port asked for is already bound by another socket
+    SourcePortInUse(u16),
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::CaseMismatch { sent, received } => write!(
+                f,
+                "Response question {} does not match query {}, possibly spoofed",
+                received, sent
+            ),
+            LookupError::SourcePortInUse(port) => write!(
+                f,
+                "Source port {} is already in use, pick another or leave it unset",
+                port
+            ),
+        }
+    }
+}
+
+impl Error for LookupError {}
+
+/// Options controlling how a single query is sent
+#[derive(Debug, Clone)]
+pub struct LookupOptions {
+    /// Randomize the case of the query name (0x20 encoding) and require the
+    /// response to echo it back exactly
+    pub randomize_case: bool,
+    /// Class of the question, IN unless querying e.g. CH `version.bind`
+    pub query_class: DnsQueryClass,
+    /// Set the RD bit, asking the server to recurse. Wanted from recursive
+    /// resolvers, but off when iterating through authoritative servers.
+    pub recursion_desired: bool,
+    /// Advertise EDNS(0) with an OPT record
+    pub edns: bool,
+    /// UDP payload size to advertise with EDNS(0), and the largest response
+    /// accepted
+    pub udp_payload_size: u16,
+    /// How long to wait for the response, or forever if `None`
+    pub timeout: Option<Duration>,
+    /// Local port to send the query from, e.g. to test firewall rules keyed
+    /// on it, or an ephemeral port if `None`. Only applies over UDP.
+    pub source_port: Option<u16>,
+}
+
+impl Default for LookupOptions {
+    fn default() -> Self {
+        Self {
+            randomize_case: false,
+            query_class: DnsQueryClass::default(),
+            recursion_desired: true,
+            edns: true,
+            udp_payload_size: EDNS_UDP_PAYLOAD_SZ,
+            timeout: None,
+            source_port: None,
+        }
+    }
+}
+
 pub fn lookup(domain: &str, query_type: DnsQueryType, server: (IpAddr, u16)) -> Result<DnsPacket> {
-    let socket = UdpSocket::bind(("0.0.0.0", 43210))?;
-    let mut packet = DnsPacket::new();
+    lookup_with_options(domain, query_type, server, &LookupOptions::default())
+}
 
-    packet.header.id = 1234;
-    packet.header.question_count = 1;
-    packet
-        .questions
-        .push(DnsQuestion::new(domain.into(), query_type));
+pub fn lookup_with_options(
+    domain: &str,
+    query_type: DnsQueryType,
+    server: (IpAddr, u16),
+    options: &LookupOptions,
+) -> Result<DnsPacket> {
+    let socket = bind_source(server.0, options.source_port)?;
+    socket.set_read_timeout(options.timeout)?;
+    let (query_name, packet) = build_query(domain, query_type, options);
 
     let mut buffer = PacketBuffer::new();
     packet.write(&mut buffer)?;
 
     socket.send_to(&buffer.buffer[..buffer.pos], server)?;
 
-    let mut buffer = PacketBuffer::new();
-    socket.recv_from(&mut buffer.buffer)?;
+    // Without EDNS servers stick to the 512 bytes of RFC 1035
+    let mut buffer = if options.edns {
+        PacketBuffer::with_size(DNS_BUFFER_SZ.max(options.udp_payload_size.into()))
+    } else {
+        PacketBuffer::new()
+    };
+    let (received, _) = socket.recv_from(&mut buffer.buffer)?;
+    // Only parse what arrived, so a datagram cut short ends the packet
+    // rather than reading on into the zeroed rest of the buffer, keeping
+    // the records before the cut
+    buffer.buffer.truncate(received);
 
-    DnsPacket::read(&mut buffer)
+    let response = DnsPacket::read_lenient(&mut buffer)?;
+    check_case(&query_name, response, options)
 }
 
-pub fn recursive_lookup(query_name: &str, query_type: DnsQueryType) -> Result<DnsPacket> {
-    let mut nameserver = IpAddr::V4(Ipv4Addr::new(198, 41, 0, 4));
-    loop {
-        if cfg!(debug_assertions) {
-            println!(
-                "Attempting lookup of {query_name}::{query_type:?} with nameserver {nameserver:?}"
-            );
+/// Like `lookup_with_options`, but over TCP (RFC 7766), e.g. to retry a
+/// query whose response over UDP came back truncated
+pub fn lookup_tcp_with_options(
+    domain: &str,
+    query_type: DnsQueryType,
+    server: (IpAddr, u16),
+    options: &LookupOptions,
+) -> Result<DnsPacket> {
+    let mut stream = match options.timeout {
+        Some(timeout) => TcpStream::connect_timeout(&server.into(), timeout)?,
+        None => TcpStream::connect(server)?,
+    };
+    stream.set_read_timeout(options.timeout)?;
+    let (query_name, packet) = build_query(domain, query_type, options);
+
+    write_message(&mut stream, &packet)?;
+    let response = read_message(&mut stream)?;
+    check_case(&query_name, response, options)
+}
+
+/// The query for `domain` as `options` describe it, along with the name
+/// actually asked for, whose case may have been randomized
+fn build_query(
+    domain: &str,
+    query_type: DnsQueryType,
+    options: &LookupOptions,
+) -> (String, DnsPacket) {
+    let query_name = if options.randomize_case {
+        randomize_case(domain)
+    } else {
+        domain.to_string()
+    };
+
+    let mut packet = DnsPacket::query(&query_name, query_type);
+    packet.header.recursion_desired = options.recursion_desired;
+    packet.questions[0].query_class = options.query_class;
+
+    // Advertise EDNS(0) so that servers may attach Extended DNS Errors
+    if options.edns {
+        packet.header.additional_count = 1;
+        packet.additional.push(DnsRecord::OPT {
+            udp_payload_size: options.udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        });
+    }
+
+    (query_name, packet)
+}
+
+/// Reject a response to a 0x20-encoded query that doesn't echo the exact
+/// case of `query_name`
+fn check_case(query_name: &str, response: DnsPacket, options: &LookupOptions) -> Result<DnsPacket> {
+    if options.randomize_case {
+        let received = response.questions.first().map(|q| q.name.as_str());
+        if received != Some(query_name) {
+            return Err(Box::new(LookupError::CaseMismatch {
+                sent: query_name.to_string(),
+                received: received.unwrap_or_default().to_string(),
+            }));
+        }
+    }
+
+    Ok(response)
+}
+
+/// Bind a socket of the family of `server` to send a query from, on
+/// `source_port` or an ephemeral port
+fn bind_source(server: IpAddr, source_port: Option<u16>) -> Result<UdpSocket> {
+    let local = match server {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    match (
+        UdpSocket::bind((local, source_port.unwrap_or(0))),
+        source_port,
+    ) {
+        (Ok(socket), _) => Ok(socket),
+        (Err(err), Some(port)) if err.kind() == io::ErrorKind::AddrInUse => {
+            Err(Box::new(LookupError::SourcePortInUse(port)))
         }
+        (Err(err), _) => Err(Box::new(err)),
+    }
+}
 
-        let server = (nameserver, 53);
-        let response = lookup(query_name, query_type, server)?;
+/// Flip the case of each ASCII letter at random (draft-vixie-dnsext-dns0x20)
+pub fn randomize_case(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphabetic() && rand::random::<bool>() {
+                if c.is_ascii_uppercase() {
+                    c.to_ascii_lowercase()
+                } else {
+                    c.to_ascii_uppercase()
+                }
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// One query made while iterating from the root, see `recursive_lookup_traced`
+#[derive(Debug, Clone)]
+pub struct TraceStep {
+    /// Name of the server asked
+    pub nameserver: String,
+    /// Address of the server asked
+    pub address: IpAddr,
+    pub query_name: String,
+    pub query_type: DnsQueryType,
+    /// The zone the server referred to and the nameservers it named for it,
+    /// if it gave a referral
+    pub referral: Option<(String, Vec<String>)>,
+    pub response_code: DnsResponseCode,
+    /// Number of records in the answer section
+    pub answers: usize,
+    /// How long the server took to respond
+    pub elapsed: Duration,
+    /// Whether the response came over TCP, having been truncated over UDP
+    pub over_tcp: bool,
+}
 
+pub fn recursive_lookup(query_name: &str, query_type: DnsQueryType) -> Result<DnsPacket> {
+    recursive_lookup_traced(query_name, query_type, &mut |_| {})
+}
+
+/// Like `recursive_lookup`, passing a `TraceStep` to `trace` for every
+/// server asked, including those asked for the addresses of nameservers
+/// given without glue
+pub fn recursive_lookup_traced(
+    query_name: &str,
+    query_type: DnsQueryType,
+    trace: &mut dyn FnMut(&TraceStep),
+) -> Result<DnsPacket> {
+    let root_hints = RootHint::root_servers();
+    recursive_lookup_from(
+        query_name,
+        query_type,
+        &root_hints,
+        Some(ITERATION_TIMEOUT),
+        trace,
+    )
+}
+
+/// Like `recursive_lookup_traced`, starting from the first of `root_hints`
+/// that responds within `timeout`, e.g. a private root for split-horizon
+/// testing. Every server asked on the way is given `timeout` to respond.
+pub fn recursive_lookup_from(
+    query_name: &str,
+    query_type: DnsQueryType,
+    root_hints: &[RootHint],
+    timeout: Option<Duration>,
+    trace: &mut dyn FnMut(&TraceStep),
+) -> Result<DnsPacket> {
+    // We do the recursion ourselves, the servers asked are authoritative
+    let options = LookupOptions {
+        recursion_desired: false,
+        timeout,
+        ..Default::default()
+    };
+
+    // Start from the first root hint that answers at all
+    let mut root_err = None;
+    let mut start = None;
+    for hint in root_hints {
+        let nameserver = (hint.name.clone(), hint.address);
+        match ask_traced(
+            query_name,
+            query_type,
+            &nameserver,
+            hint.port,
+            &options,
+            trace,
+        ) {
+            Ok(response) => {
+                start = Some((hint.port, response));
+                break;
+            }
+            Err(err) => root_err = Some(err),
+        }
+    }
+    let (port, mut response) = match start {
+        Some(start) => start,
+        None => return Err(root_err.unwrap_or_else(|| "No root hints to start from".into())),
+    };
+
+    loop {
         if (!response.answers.is_empty()
             && response.header.response_code == DnsResponseCode::NoError)
             || response.header.response_code == DnsResponseCode::NxDomain
@@ -46,8 +378,14 @@ pub fn recursive_lookup(query_name: &str, query_type: DnsQueryType) -> Result<Dn
             return Ok(response);
         }
 
-        if let Some(new_nameserver) = response.get_resolved_nameserver(query_name, query_type) {
-            nameserver = new_nameserver;
+        let nameservers: Vec<(&str, &str)> = response.get_nameservers(query_name).collect();
+        if let Some(address) = response.get_resolved_nameserver(query_name, query_type) {
+            let name = nameservers
+                .iter()
+                .find(|(_, host)| response.glue_addresses(host).contains(&address))
+                .map_or_else(|| address.to_string(), |(_, host)| host.to_string());
+            let nameserver = (name, address);
+            response = ask_traced(query_name, query_type, &nameserver, port, &options, trace)?;
             continue;
         }
 
@@ -56,10 +394,337 @@ pub fn recursive_lookup(query_name: &str, query_type: DnsQueryType) -> Result<Dn
             None => return Ok(response),
         };
 
-        let recursive_response = recursive_lookup(new_nameserver, query_type)?;
-        match recursive_response.get_record(query_type) {
-            Some(ns) => nameserver = ns,
+        let recursive_response =
+            recursive_lookup_from(new_nameserver, query_type, root_hints, timeout, trace)?;
+        let nameserver = match recursive_response.get_record(new_nameserver, query_type) {
+            Some(address) => (new_nameserver.to_string(), address),
             None => return Ok(response),
         };
+        response = ask_traced(query_name, query_type, &nameserver, port, &options, trace)?;
+    }
+}
+
+/// Ask `nameserver` on `port`, again over TCP if the response is
+/// truncated, and pass the `TraceStep` to `trace`
+fn ask_traced(
+    query_name: &str,
+    query_type: DnsQueryType,
+    nameserver: &(String, IpAddr),
+    port: u16,
+    options: &LookupOptions,
+    trace: &mut dyn FnMut(&TraceStep),
+) -> Result<DnsPacket> {
+    let started_at = Instant::now();
+    let server = (nameserver.1, port);
+    let mut response = lookup_with_options(query_name, query_type, server, options)?;
+    // A truncated referral may be missing nameservers or their glue, so
+    // ask again over TCP before following it
+    let over_tcp = response.header.truncated_message;
+    if over_tcp {
+        response = lookup_tcp_with_options(query_name, query_type, server, options)?;
+    }
+
+    let nameservers: Vec<(&str, &str)> = response.get_nameservers(query_name).collect();
+    let referral = nameservers.first().map(|(zone, _)| {
+        let hosts = nameservers.iter().map(|(_, host)| host.to_string());
+        (zone.to_string(), hosts.collect())
+    });
+    trace(&TraceStep {
+        nameserver: nameserver.0.clone(),
+        address: nameserver.1,
+        query_name: query_name.to_string(),
+        query_type,
+        referral,
+        response_code: response.header.response_code,
+        answers: response.answers.len(),
+        elapsed: started_at.elapsed(),
+        over_tcp,
+    });
+    Ok(response)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::dns::question::DnsQuestion;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+
+    /// Answer `count` queries on a free local port with whatever `respond`
+    /// makes of each, returning the address to send them to
+    pub(crate) fn serve(
+        count: usize,
+        mut respond: impl FnMut(DnsPacket) -> DnsPacket + Send + 'static,
+    ) -> ((IpAddr, u16), JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            for _ in 0..count {
+                let mut buffer = PacketBuffer::new();
+                let (_, peer) = socket.recv_from(&mut buffer.buffer).unwrap();
+                let response = respond(DnsPacket::read(&mut buffer).unwrap());
+                let mut buffer = PacketBuffer::new();
+                response.write(&mut buffer).unwrap();
+                socket.send_to(&buffer.buffer[..buffer.pos], peer).unwrap();
+            }
+        });
+        ((address.ip(), address.port()), handle)
+    }
+
+    /// An empty response to `query`, repeating its question
+    pub(crate) fn response_to(query: &DnsPacket) -> DnsPacket {
+        let question = &query.questions[0];
+        let mut response = DnsPacket::new();
+        response.header.id = query.header.id;
+        response.header.query_response = true;
+        response.header.question_count = 1;
+        response
+            .questions
+            .push(DnsQuestion::new(question.name.clone(), question.query_type));
+        response
+    }
+
+    #[test]
+    fn randomized_case_echoed_back_is_accepted() {
+        let asked = Arc::new(Mutex::new(String::new()));
+        let server_asked = Arc::clone(&asked);
+        let (server, handle) = serve(1, move |query| {
+            *server_asked.lock().unwrap() = query.questions[0].name.clone();
+            response_to(&query)
+        });
+
+        let options = LookupOptions {
+            randomize_case: true,
+            ..Default::default()
+        };
+        let response =
+            lookup_with_options("www.example.com", DnsQueryType::A, server, &options).unwrap();
+        handle.join().unwrap();
+
+        let asked = asked.lock().unwrap();
+        assert!(asked.eq_ignore_ascii_case("www.example.com"));
+        assert_eq!(response.questions[0].name, *asked);
+    }
+
+    #[test]
+    fn randomized_case_not_echoed_back_is_rejected() {
+        let (server, handle) = serve(1, |query| {
+            let mut response = response_to(&query);
+            let name = &mut response.questions[0].name;
+            *name = name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_uppercase() {
+                        c.to_ascii_lowercase()
+                    } else {
+                        c.to_ascii_uppercase()
+                    }
+                })
+                .collect();
+            response
+        });
+
+        let options = LookupOptions {
+            randomize_case: true,
+            ..Default::default()
+        };
+        let err =
+            lookup_with_options("www.example.com", DnsQueryType::A, server, &options).unwrap_err();
+        handle.join().unwrap();
+
+        assert!(matches!(
+            err.downcast_ref::<LookupError>(),
+            Some(LookupError::CaseMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn recursion_is_desired_from_stub_lookups_only() {
+        let desired = Arc::new(Mutex::new(Vec::new()));
+        let server_desired = Arc::clone(&desired);
+        let (server, handle) = serve(2, move |query| {
+            server_desired
+                .lock()
+                .unwrap()
+                .push(query.header.recursion_desired);
+            let mut response = response_to(&query);
+            response.header.answer_count = 1;
+            response.answers.push(DnsRecord::A {
+                domain: query.questions[0].name.clone(),
+                class: DnsQueryClass::IN,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: 300,
+            });
+            response
+        });
+
+        lookup("www.example.com", DnsQueryType::A, server).unwrap();
+        recursive_lookup_from(
+            "www.example.com",
+            DnsQueryType::A,
+            &[RootHint::from(server.0).with_port(server.1)],
+            Some(Duration::from_secs(1)),
+            &mut |_| {},
+        )
+        .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(*desired.lock().unwrap(), vec![true, false]);
+    }
+
+    #[test]
+    fn unreachable_root_hint_falls_over_to_the_next() {
+        // Nothing listens on the port of a socket that was just closed
+        let unreachable = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (server, handle) = serve(1, |query| {
+            let mut response = response_to(&query);
+            response.header.answer_count = 1;
+            response.answers.push(DnsRecord::A {
+                domain: query.questions[0].name.clone(),
+                class: DnsQueryClass::IN,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: 300,
+            });
+            response
+        });
+
+        let root_hints = [
+            RootHint::new("unreachable.example", unreachable.ip()).with_port(unreachable.port()),
+            RootHint::from(server.0).with_port(server.1),
+        ];
+        let mut asked = Vec::new();
+        let response = recursive_lookup_from(
+            "www.example.com",
+            DnsQueryType::A,
+            &root_hints,
+            Some(Duration::from_millis(200)),
+            &mut |step| asked.push(step.nameserver.clone()),
+        )
+        .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(asked, [server.0.to_string()]);
+        assert_eq!(
+            response.get_record("www.example.com", DnsQueryType::A),
+            Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+        );
+    }
+
+    #[test]
+    fn truncated_referral_is_asked_again_over_tcp() {
+        let mut asked = 0;
+        let (server, handle) = serve(2, move |query| {
+            asked += 1;
+            let mut response = response_to(&query);
+            if asked == 1 {
+                // Too big for UDP, so only over TCP does the referral come
+                response.header.truncated_message = true;
+            } else {
+                response.header.answer_count = 1;
+                response.answers.push(DnsRecord::A {
+                    domain: query.questions[0].name.clone(),
+                    class: DnsQueryClass::IN,
+                    addr: Ipv4Addr::new(192, 0, 2, 1),
+                    ttl: 300,
+                });
+            }
+            response
+        });
+        let listener = TcpListener::bind(server).unwrap();
+        let tcp = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = read_message(&mut stream).unwrap();
+            let mut response = response_to(&query);
+            response.header.authority_count = 1;
+            response.authorities.push(DnsRecord::NS {
+                domain: "example.com".to_string(),
+                class: DnsQueryClass::IN,
+                host: "ns.example.com".to_string(),
+                ttl: 300,
+            });
+            response.header.additional_count = 1;
+            response.additional.push(DnsRecord::A {
+                domain: "ns.example.com".to_string(),
+                class: DnsQueryClass::IN,
+                addr: Ipv4Addr::LOCALHOST,
+                ttl: 300,
+            });
+            write_message(&mut stream, &response).unwrap();
+        });
+
+        let mut steps = Vec::new();
+        let response = recursive_lookup_from(
+            "www.example.com",
+            DnsQueryType::A,
+            &[RootHint::from(server.0).with_port(server.1)],
+            Some(Duration::from_secs(1)),
+            &mut |step| steps.push(step.clone()),
+        )
+        .unwrap();
+        handle.join().unwrap();
+        tcp.join().unwrap();
+
+        // The referral followed is the one that came over TCP
+        assert_eq!(steps.len(), 2);
+        assert!(steps[0].over_tcp);
+        assert_eq!(
+            steps[0].referral,
+            Some((
+                "example.com".to_string(),
+                vec!["ns.example.com".to_string()]
+            ))
+        );
+        assert_eq!(steps[1].nameserver, "ns.example.com");
+        assert!(!steps[1].over_tcp);
+        assert_eq!(
+            response.get_record("www.example.com", DnsQueryType::A),
+            Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+        );
+    }
+
+    #[test]
+    fn query_is_sent_from_the_requested_source_port() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buffer = PacketBuffer::new();
+            let (_, peer) = server.recv_from(&mut buffer.buffer).unwrap();
+            let response = response_to(&DnsPacket::read(&mut buffer).unwrap());
+            let mut buffer = PacketBuffer::new();
+            response.write(&mut buffer).unwrap();
+            server.send_to(&buffer.buffer[..buffer.pos], peer).unwrap();
+            peer.port()
+        });
+
+        // A port free a moment ago, held by nothing else in these tests
+        let source_port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let options = LookupOptions {
+            source_port: Some(source_port),
+            ..Default::default()
+        };
+        lookup_with_options(
+            "example.com",
+            DnsQueryType::A,
+            (address.ip(), address.port()),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(handle.join().unwrap(), source_port);
+
+        let taken = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let err = bind_source(address.ip(), Some(port)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            LookupError::SourcePortInUse(port).to_string()
+        );
     }
 }
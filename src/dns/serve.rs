@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use super::{
+    buffer::{PacketBuffer, DNS_BUFFER_SZ},
+    header::DnsResponseCode,
+    packet::DnsPacket,
+    query::DnsQueryType,
+    question::DnsQuestion,
+    record::DnsRecord,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Records served by a `DnsServer`, keyed by owner name and type. Names are
+/// matched case-insensitively and without a trailing dot.
+pub type Zone = HashMap<(String, DnsQueryType), Vec<DnsRecord>>;
+
+/// A minimal authoritative DNS server answering queries over UDP from a
+/// static zone, for testing resolvers against known data.
+///
+/// Every answer is authoritative: records of the queried name and type are
+/// returned, NOERROR with no answers if the name has records of other types
+/// only, and NXDOMAIN if it has none at all. There is no recursion, no
+/// referrals and no CNAME chasing.
+#[derive(Debug)]
+pub struct DnsServer {
+    socket: UdpSocket,
+    zone: Zone,
+}
+
+impl DnsServer {
+    /// Bind to `address`, e.g. `127.0.0.1:0` for a free port, serving `zone`
+    pub fn bind(address: impl ToSocketAddrs, zone: Zone) -> io::Result<Self> {
+        let zone = zone
+            .into_iter()
+            .map(|((name, query_type), records)| ((normalize(&name), query_type), records))
+            .collect();
+
+        Ok(Self {
+            socket: UdpSocket::bind(address)?,
+            zone,
+        })
+    }
+
+    /// The address the server listens on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Build the response to `query` from the zone
+    pub fn answer(&self, query: &DnsPacket) -> DnsPacket {
+        let mut response = DnsPacket::new();
+        response.header.id = query.header.id;
+        response.header.query_response = true;
+        response.header.opcode = query.header.opcode;
+        response.header.authoritative_answer = true;
+        response.header.recursion_desired = query.header.recursion_desired;
+
+        let Some(question) = query.questions.first() else {
+            response.header.response_code = DnsResponseCode::FormErr;
+            return response;
+        };
+
+        let name = normalize(&question.name);
+        match self.zone.get(&(name.clone(), question.query_type)) {
+            Some(records) => response.answers.extend(
+                records
+                    .iter()
+                    .filter(|record| record.matches_class(question.query_class))
+                    .cloned(),
+            ),
+            None if !self.zone.keys().any(|(owner, _)| *owner == name) => {
+                response.header.response_code = DnsResponseCode::NxDomain;
+            }
+            None => {}
+        }
+
+        response.header.question_count = 1;
+        response.questions.push(DnsQuestion::with_class(
+            question.name.clone(),
+            question.query_type,
+            question.query_class,
+        ));
+        response.header.answer_count = response.answers.len() as u16;
+
+        // Without EDNS a UDP response must fit 512 bytes, so have the client
+        // retry over TCP rather than send a partial answer
+        if response.wire_len() > DNS_BUFFER_SZ {
+            response.answers.clear();
+            response.header.answer_count = 0;
+            response.header.truncated_message = true;
+        }
+        response
+    }
+
+    /// Answer a single query
+    pub fn serve_one(&self) -> Result<()> {
+        let mut buffer = PacketBuffer::new();
+        let (received, client) = self.socket.recv_from(&mut buffer.buffer)?;
+        buffer.buffer.truncate(received);
+
+        let query = DnsPacket::read(&mut buffer)?;
+        let response = self.answer(&query);
+
+        let mut buffer = PacketBuffer::new();
+        response.write(&mut buffer)?;
+        self.socket.send_to(&buffer.buffer[..buffer.pos], client)?;
+        Ok(())
+    }
+
+    /// Answer queries until the socket fails, skipping queries that fail
+    /// to parse
+    pub fn serve(&self) -> Result<()> {
+        loop {
+            match self.serve_one() {
+                Err(err) if err.is::<io::Error>() => return Err(err),
+                _ => continue,
+            }
+        }
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::query::DnsQueryClass;
+    use crate::resolve::Resolver;
+    use std::net::Ipv4Addr;
+    use std::thread;
+
+    #[test]
+    fn resolver_gets_the_served_a_record() {
+        let mut zone = Zone::new();
+        zone.insert(
+            ("host.example.test".to_string(), DnsQueryType::A),
+            vec![DnsRecord::A {
+                domain: "host.example.test".to_string(),
+                class: DnsQueryClass::IN,
+                addr: Ipv4Addr::new(192, 0, 2, 10),
+                ttl: 300,
+            }],
+        );
+        let server = DnsServer::bind("127.0.0.1:0", zone).unwrap();
+        let address = server.local_addr().unwrap();
+        let serving = thread::spawn(move || server.serve_one().is_ok());
+
+        let mut resolver = Resolver::new().with_servers(vec![(address.ip(), address.port())]);
+        let addr = resolver.resolve_v4("host.example.test").unwrap();
+        assert!(serving.join().unwrap());
+
+        assert_eq!(addr, Ipv4Addr::new(192, 0, 2, 10));
+    }
+}
@@ -0,0 +1,40 @@
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use super::{buffer::PacketBuffer, packet::DnsPacket};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Largest message a TCP frame can carry
+pub const DNS_TCP_MESSAGE_SZ: usize = u16::MAX as usize;
+
+/* Over TCP every message is prefixed with its length (RFC 1035 4.2.2)
+
++---------------------+
+|   Length (16 bits)  |
++---------------------+
+|       Message       |
++---------------------+
+*/
+
+/// Write `packet` to `stream` as one length-prefixed message
+pub fn write_message(stream: &mut TcpStream, packet: &DnsPacket) -> Result<()> {
+    let mut buffer = PacketBuffer::with_size(DNS_TCP_MESSAGE_SZ);
+    packet.write(&mut buffer)?;
+
+    let len = buffer.pos as u16;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(&buffer.buffer[..buffer.pos])?;
+    Ok(())
+}
+
+/// Read one length-prefixed message from `stream`
+pub fn read_message(stream: &mut TcpStream) -> Result<DnsPacket> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+
+    let mut buffer = PacketBuffer::with_size(u16::from_be_bytes(len) as usize);
+    stream.read_exact(&mut buffer.buffer)?;
+    DnsPacket::read(&mut buffer)
+}
@@ -34,6 +34,12 @@ pub struct PacketBuffer {
     pub pos: usize,
 }
 
+impl Default for PacketBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PacketBuffer {
     /// Initialize an empty buffer
     pub fn new() -> Self {
@@ -146,3 +152,31 @@ impl From<&[u8]> for PacketBuffer {
         }
     }
 }
+
+/// Format `bytes` as a hex dump, 16 bytes to a line: the offset, the bytes
+/// in hex in two groups of eight and the printable ones as ASCII, e.g.
+///
+/// ```text
+/// 0000  08 00 f7 fe 00 01 00 01  68 69                    |........hi|
+/// ```
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            if i == 8 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{byte:02x} "));
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+        dump.push_str(&format!("{:04x}  {hex:<49} |{ascii}|\n", line * 16));
+    }
+    dump
+}
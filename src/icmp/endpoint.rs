@@ -0,0 +1,266 @@
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+#[cfg(any(test, feature = "testing"))]
+use super::ip::IPV4_HEADER_LEN;
+#[cfg(any(test, feature = "testing"))]
+use super::mock::MockTransport;
+use super::packet::{IcmpPacket, ICMP_HEADER_LEN};
+use super::socket::{HeaderIncludedTransport, IcmpSocket, IcmpSocketKind, RecvInfo};
+use super::socket6::{Icmp6Socket, IPV6_HEADER_LEN};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// An ICMP socket of either address family, so callers can ping an `IpAddr`
+/// without branching on the family themselves
+pub enum IcmpEndpoint {
+    V4(IcmpSocket),
+    V6(Icmp6Socket),
+    /// IPv4 over a raw socket where we build the IP header ourselves
+    V4HeaderIncluded(IcmpSocket<HeaderIncludedTransport>),
+    /// IPv4 over a scripted transport, for testing without a network
+    #[cfg(any(test, feature = "testing"))]
+    Mock(IcmpSocket<MockTransport>),
+}
+
+/// TTL a scripted socket claims its packets go out with, Linux's default
+#[cfg(any(test, feature = "testing"))]
+const MOCK_TTL: u32 = 64;
+
+impl IcmpEndpoint {
+    /// Open a socket of the family of `ip` and point it at `ip`
+    pub fn connect(ip: IpAddr, timeout: Duration) -> Result<Self> {
+        let mut endpoint = Self::new(ip, timeout)?;
+        endpoint.set_destination(ip)?;
+        Ok(endpoint)
+    }
+
+    /// Open a socket of the family of `ip`, without setting a destination
+    pub fn new(ip: IpAddr, timeout: Duration) -> Result<Self> {
+        Ok(match ip {
+            IpAddr::V4(_) => Self::V4(IcmpSocket::new(timeout)?),
+            IpAddr::V6(_) => Self::V6(Icmp6Socket::new(timeout)?),
+        })
+    }
+
+    /// Open a raw IPv4 socket that sends our own IP headers, see
+    /// `IcmpSocket::new_header_included`
+    pub fn new_header_included(timeout: Duration) -> Result<Self> {
+        Ok(Self::V4HeaderIncluded(IcmpSocket::new_header_included(
+            timeout,
+        )?))
+    }
+
+    /// Set the destination of subsequent `send` calls. `ip` must be of the
+    /// same family as the socket.
+    pub fn set_destination(&mut self, ip: IpAddr) -> Result<()> {
+        self.set_scoped_destination(ip, 0)
+    }
+
+    /// Like `set_destination`, reaching an IPv6 `ip` through the interface
+    /// with index `scope_id`, see `Icmp6Socket::connect_scoped`. The scope
+    /// is ignored for IPv4.
+    pub fn set_scoped_destination(&mut self, ip: IpAddr, scope_id: u32) -> Result<()> {
+        match (self, ip) {
+            (Self::V4(socket), IpAddr::V4(ip)) => socket.connect(ip)?,
+            (Self::V6(socket), IpAddr::V6(ip)) => socket.connect_scoped(ip, scope_id)?,
+            (Self::V4HeaderIncluded(socket), IpAddr::V4(ip)) => socket.connect(ip)?,
+            // Scripted replies come from wherever they were scripted to
+            #[cfg(any(test, feature = "testing"))]
+            (Self::Mock(_), IpAddr::V4(_)) => {}
+            (_, ip) => {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{ip} is of the wrong address family for this socket"),
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// The kind of socket that was opened. Sockets building their own IP
+    /// header, and scripted ones, send identifiers untouched like raw ones.
+    pub fn kind(&self) -> IcmpSocketKind {
+        match self {
+            Self::V4(socket) => socket.kind(),
+            Self::V6(socket) => socket.kind(),
+            Self::V4HeaderIncluded(_) => IcmpSocketKind::Raw,
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(_) => IcmpSocketKind::Raw,
+        }
+    }
+
+    pub fn send(&self, packet: &mut IcmpPacket) -> Result<usize> {
+        match self {
+            Self::V4(socket) => socket.send(packet),
+            Self::V6(socket) => socket.send(packet),
+            Self::V4HeaderIncluded(socket) => socket.send(packet),
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(socket) => socket.send(packet),
+        }
+    }
+
+    /// Send a packet to `ip`, regardless of the destination set. `ip` must be
+    /// of the same family as the socket.
+    pub fn send_to(&self, packet: &mut IcmpPacket, ip: IpAddr) -> Result<usize> {
+        match (self, ip) {
+            (Self::V4(socket), IpAddr::V4(ip)) => socket.send_to(packet, ip),
+            (Self::V6(socket), IpAddr::V6(ip)) => socket.send_to(packet, ip),
+            (Self::V4HeaderIncluded(socket), IpAddr::V4(ip)) => socket.send_to(packet, ip),
+            #[cfg(any(test, feature = "testing"))]
+            (Self::Mock(socket), IpAddr::V4(ip)) => socket.send_to(packet, ip),
+            (_, ip) => Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{ip} is of the wrong address family for this socket"),
+            ))),
+        }
+    }
+
+    pub fn recv(&self) -> Result<IcmpPacket> {
+        self.recv_from().map(|(packet, _)| packet)
+    }
+
+    /// Receive a packet along with the address it was sent from
+    pub fn recv_from(&self) -> Result<(IcmpPacket, IpAddr)> {
+        match self {
+            Self::V4(socket) => socket
+                .recv_from()
+                .map(|(packet, source)| (packet, source.into())),
+            Self::V6(socket) => socket
+                .recv_from()
+                .map(|(packet, source)| (packet, source.into())),
+            Self::V4HeaderIncluded(socket) => socket
+                .recv_from()
+                .map(|(packet, source)| (packet, source.into())),
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(socket) => socket
+                .recv_from()
+                .map(|(packet, source)| (packet, source.into())),
+        }
+    }
+
+    /// Like `recv_from`, also returning the TTL (IPv4) or hop limit (IPv6)
+    /// the packet arrived with, if the kernel reported it
+    pub fn recv_with_ttl(&self) -> Result<(IcmpPacket, IpAddr, Option<u8>)> {
+        self.recv_with_info()
+            .map(|(packet, source, info)| (packet, source, info.ttl))
+    }
+
+    /// Like `recv_with_ttl`, also returning the kernel receive time of the
+    /// packet, if the kernel reported it
+    pub fn recv_with_info(&self) -> Result<(IcmpPacket, IpAddr, RecvInfo)> {
+        match self {
+            Self::V4(socket) => socket
+                .recv_with_info()
+                .map(|(packet, source, info)| (packet, source.into(), info)),
+            Self::V6(socket) => socket
+                .recv_with_info()
+                .map(|(packet, source, info)| (packet, source.into(), info)),
+            Self::V4HeaderIncluded(socket) => socket
+                .recv_with_info()
+                .map(|(packet, source, info)| (packet, source.into(), info)),
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(socket) => socket
+                .recv_with_info()
+                .map(|(packet, source, info)| (packet, source.into(), info)),
+        }
+    }
+
+    /// Wait up to `timeout` for a packet, see `IcmpSocket::recv_timeout`
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<(IcmpPacket, IpAddr, RecvInfo)>> {
+        let received = match self {
+            Self::V4(socket) => socket
+                .recv_timeout(timeout)?
+                .map(|(packet, source, info)| (packet, source.into(), info)),
+            Self::V6(socket) => socket
+                .recv_timeout(timeout)?
+                .map(|(packet, source, info)| (packet, source.into(), info)),
+            Self::V4HeaderIncluded(socket) => socket
+                .recv_timeout(timeout)?
+                .map(|(packet, source, info)| (packet, source.into(), info)),
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(socket) => socket
+                .recv_timeout(timeout)?
+                .map(|(packet, source, info)| (packet, source.into(), info)),
+        };
+        Ok(received)
+    }
+
+    /// TTL (IPv4) or hop limit (IPv6) of outgoing packets
+    pub fn get_ttl(&self) -> Result<u32> {
+        match self {
+            Self::V4(socket) => socket.get_ttl(),
+            Self::V6(socket) => socket.get_hop_limit(),
+            Self::V4HeaderIncluded(socket) => socket.get_ttl(),
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(_) => Ok(MOCK_TTL),
+        }
+    }
+
+    /// Set the TTL (IPv4) or hop limit (IPv6) of outgoing packets
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        match self {
+            Self::V4(socket) => socket.set_ttl(ttl),
+            Self::V6(socket) => socket.set_hop_limit(ttl),
+            Self::V4HeaderIncluded(socket) => socket.set_ttl(ttl),
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(_) => Ok(()),
+        }
+    }
+
+    /// Size of the IP and ICMP headers preceding the payload of the packets
+    /// sent, including IPv4 options but not IPv6 extension headers
+    pub fn header_len(&self) -> usize {
+        match self {
+            Self::V4(socket) => socket.header_len(),
+            Self::V6(_) => IPV6_HEADER_LEN + ICMP_HEADER_LEN,
+            Self::V4HeaderIncluded(socket) => {
+                socket.transport().header().header_len() + ICMP_HEADER_LEN
+            }
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(_) => IPV4_HEADER_LEN + ICMP_HEADER_LEN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn either_family_is_reached_through_the_one_type() {
+        let mut mock = IcmpEndpoint::Mock(IcmpSocket::with_transport(
+            MockTransport::new(),
+            Ipv4Addr::LOCALHOST,
+        ));
+        assert_eq!(mock.header_len(), IPV4_HEADER_LEN + ICMP_HEADER_LEN);
+        mock.set_destination(Ipv4Addr::LOCALHOST.into()).unwrap();
+        assert!(mock.set_destination(Ipv6Addr::LOCALHOST.into()).is_err());
+
+        // Real sockets need ping_group_range to cover us or CAP_NET_RAW, and
+        // IPv6 a host that has it, so each family is skipped if it can't be
+        // opened
+        let timeout = Duration::from_secs(1);
+        if let Ok(mut v4) = IcmpEndpoint::new(Ipv4Addr::LOCALHOST.into(), timeout) {
+            assert!(matches!(v4, IcmpEndpoint::V4(_)));
+            v4.set_destination(Ipv4Addr::LOCALHOST.into()).unwrap();
+            assert_eq!(v4.header_len(), IPV4_HEADER_LEN + ICMP_HEADER_LEN);
+        }
+
+        if let Ok(mut v6) = IcmpEndpoint::new(Ipv6Addr::LOCALHOST.into(), timeout) {
+            assert!(matches!(v6, IcmpEndpoint::V6(_)));
+            v6.set_destination(Ipv6Addr::LOCALHOST.into()).unwrap();
+            assert_eq!(v6.header_len(), IPV6_HEADER_LEN + ICMP_HEADER_LEN);
+            v6.set_ttl(7).unwrap();
+            assert_eq!(v6.get_ttl().unwrap(), 7);
+
+            // Each reaches addresses of its own family only
+            assert!(v6.set_destination(Ipv4Addr::LOCALHOST.into()).is_err());
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use super::buffer::PacketBuffer;
+use super::ip::{Ipv4Header, IPPROTO_ICMP};
+use super::packet::{IcmpPacket, ICMP_HEADER_LEN};
+use super::transport::PacketTransport;
+use super::types::{IcmpContentType, IcmpType};
+
+/// A scripted reception: raw bytes or an error, delivered after a delay
+#[derive(Debug)]
+enum Scripted {
+    Packet {
+        delay: Duration,
+        bytes: Vec<u8>,
+        source: Ipv4Addr,
+    },
+    Error {
+        delay: Duration,
+        kind: io::ErrorKind,
+    },
+}
+
+impl Scripted {
+    /// How long the reception is still to take
+    fn delay_mut(&mut self) -> &mut Duration {
+        match self {
+            Self::Packet { delay, .. } | Self::Error { delay, .. } => delay,
+        }
+    }
+}
+
+/// A transport replaying scripted replies instead of touching the network.
+///
+/// Receptions are handed out in the order they were scripted, each after
+/// sleeping for its delay, less any time `wait_readable` spent waiting for
+/// it. Once the script runs out, receiving fails with `WouldBlock` just like
+/// a socket whose receive timeout expired. Everything sent is recorded and
+/// can be inspected with `sent`, apart from sends scripted to fail with
+/// `fail_send`.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    script: Mutex<VecDeque<Scripted>>,
+    send_failures: Mutex<VecDeque<io::ErrorKind>>,
+    sent: Mutex<Vec<(Vec<u8>, Ipv4Addr)>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver `packet` from `source` after `delay`
+    pub fn reply_after(self, delay: Duration, source: Ipv4Addr, mut packet: IcmpPacket) -> Self {
+        let mut buffer = PacketBuffer::new();
+        packet
+            .write(&mut buffer)
+            .expect("scripted packet should encode");
+        self.bytes_after(delay, source, buffer.buffer)
+    }
+
+    /// Deliver the raw `bytes` from `source` after `delay`, e.g. a capture
+    /// read from a fixture file
+    pub fn bytes_after(self, delay: Duration, source: Ipv4Addr, bytes: Vec<u8>) -> Self {
+        self.push(Scripted::Packet {
+            delay,
+            bytes,
+            source,
+        })
+    }
+
+    /// Fail the next reception with `kind` after `delay`
+    pub fn error_after(self, delay: Duration, kind: io::ErrorKind) -> Self {
+        self.push(Scripted::Error { delay, kind })
+    }
+
+    /// Fail the next send not already scripted to fail with `kind`, e.g.
+    /// `WouldBlock` to act like a socket whose send buffer is full
+    pub fn fail_send(self, kind: io::ErrorKind) -> Self {
+        self.send_failures.lock().unwrap().push_back(kind);
+        self
+    }
+
+    /// Everything sent so far along with its destination
+    pub fn sent(&self) -> Vec<(Vec<u8>, Ipv4Addr)> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    fn push(self, scripted: Scripted) -> Self {
+        self.script.lock().unwrap().push_back(scripted);
+        self
+    }
+}
+
+impl PacketTransport for MockTransport {
+    fn send_to(&self, bytes: &[u8], destination: Ipv4Addr) -> io::Result<usize> {
+        if let Some(kind) = self.send_failures.lock().unwrap().pop_front() {
+            return Err(io::Error::from(kind));
+        }
+        self.sent
+            .lock()
+            .unwrap()
+            .push((bytes.to_vec(), destination));
+        Ok(bytes.len())
+    }
+
+    fn recv_from(&self) -> io::Result<(Vec<u8>, Ipv4Addr)> {
+        let next = self.script.lock().unwrap().pop_front();
+        match next {
+            Some(Scripted::Packet {
+                delay,
+                bytes,
+                source,
+            }) => {
+                thread::sleep(delay);
+                Ok((bytes, source))
+            }
+            Some(Scripted::Error { delay, kind }) => {
+                thread::sleep(delay);
+                Err(io::Error::from(kind))
+            }
+            None => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+        }
+    }
+
+    /// Wait for the next reception only as long as `timeout`, taking the time
+    /// waited off its delay. Once the script has run out a reception is
+    /// reported available, so that receiving fails with `WouldBlock`.
+    fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        let mut script = self.script.lock().unwrap();
+        let Some(delay) = script.front_mut().map(Scripted::delay_mut) else {
+            return Ok(true);
+        };
+        if *delay <= timeout {
+            return Ok(true);
+        }
+        *delay -= timeout;
+        drop(script);
+        thread::sleep(timeout);
+        Ok(false)
+    }
+}
+
+/// The identifier `IdentifierAllocator::for_process` hands out first, so
+/// that of the first host of a `Pinger`
+pub fn process_identifier() -> u16 {
+    std::process::id() as u16
+}
+
+/// An echo reply to request `sequence_no` carrying `identifier`, with the
+/// 56 byte payload of a default request
+pub fn echo_reply(identifier: u16, sequence_no: u16) -> IcmpPacket {
+    IcmpPacket {
+        msg_type: IcmpType::EchoReply,
+        ..IcmpPacket::echo_request(identifier, sequence_no, 56)
+    }
+}
+
+/// A router's host unreachable about request `sequence_no` carrying
+/// `identifier` to `destination`, quoting the request's headers
+pub fn unreachable_reply(identifier: u16, sequence_no: u16, destination: Ipv4Addr) -> IcmpPacket {
+    let mut quoted = PacketBuffer::new();
+    Ipv4Header::new(
+        Ipv4Addr::UNSPECIFIED,
+        destination,
+        IPPROTO_ICMP,
+        ICMP_HEADER_LEN,
+    )
+    .write(&mut quoted)
+    .expect("quoted header should encode");
+    IcmpPacket::echo_request(identifier, sequence_no, 0)
+        .write(&mut quoted)
+        .expect("quoted request should encode");
+    IcmpPacket {
+        msg_type: IcmpType::DestinationUnreachable,
+        msg_code: 1,
+        content: IcmpContentType::DestinationUnreachable { unused: 0 },
+        payload: quoted.buffer,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icmp::socket::IcmpSocket;
+    use std::time::Instant;
+
+    fn error_kind(err: &(dyn std::error::Error + 'static)) -> Option<io::ErrorKind> {
+        err.downcast_ref::<io::Error>().map(io::Error::kind)
+    }
+
+    #[test]
+    fn scripted_receptions_are_replayed_in_order() {
+        let host = Ipv4Addr::new(192, 0, 2, 1);
+        let reply = echo_reply(7, 1);
+        let transport = MockTransport::new()
+            .reply_after(Duration::from_millis(20), host, reply)
+            .error_after(Duration::ZERO, io::ErrorKind::ConnectionRefused);
+        let socket = IcmpSocket::with_transport(transport, host);
+
+        let sent_at = Instant::now();
+        socket
+            .send(&mut IcmpPacket::echo_request(7, 1, 56))
+            .unwrap();
+        let (packet, source) = socket.recv_from().unwrap();
+        assert!(sent_at.elapsed() >= Duration::from_millis(20));
+        assert_eq!(source, host);
+        assert!(matches!(
+            packet.content,
+            IcmpContentType::Echo {
+                id: 7,
+                sequence_no: 1
+            }
+        ));
+        assert_eq!(socket.transport().sent().len(), 1);
+        assert_eq!(socket.transport().sent()[0].1, host);
+
+        let err = socket.recv().unwrap_err();
+        assert_eq!(error_kind(&*err), Some(io::ErrorKind::ConnectionRefused));
+        // The script has run out, which looks like a receive timeout
+        let err = socket.recv().unwrap_err();
+        assert_eq!(error_kind(&*err), Some(io::ErrorKind::WouldBlock));
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let host = Ipv4Addr::new(192, 0, 2, 1);
+        let reply = echo_reply(7, 1);
+        // A full send buffer, then a signal interrupting the receive
+        let transport = MockTransport::new()
+            .fail_send(io::ErrorKind::WouldBlock)
+            .error_after(Duration::ZERO, io::ErrorKind::Interrupted)
+            .reply_after(Duration::ZERO, host, reply);
+        let socket = IcmpSocket::with_transport(transport, host);
+
+        socket
+            .send(&mut IcmpPacket::echo_request(7, 1, 56))
+            .unwrap();
+        assert_eq!(socket.transport().sent().len(), 1);
+        let (_, source) = socket.recv_from().unwrap();
+        assert_eq!(source, host);
+    }
+
+    #[test]
+    fn transient_failures_are_reported_without_retries() {
+        let host = Ipv4Addr::new(192, 0, 2, 1);
+        let transport = MockTransport::new().fail_send(io::ErrorKind::WouldBlock);
+        let mut socket = IcmpSocket::with_transport(transport, host);
+        socket.set_transient_retries(0);
+
+        let err = socket
+            .send(&mut IcmpPacket::echo_request(7, 1, 56))
+            .unwrap_err();
+        assert_eq!(error_kind(&*err), Some(io::ErrorKind::WouldBlock));
+        assert!(socket.transport().sent().is_empty());
+    }
+}
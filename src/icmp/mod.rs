@@ -2,3 +2,9 @@ pub mod packet;
 pub mod types;
 pub mod socket;
 pub mod buffer;
+pub mod transport;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
+pub mod socket6;
+pub mod endpoint;
+pub mod ip;
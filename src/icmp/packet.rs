@@ -1,8 +1,14 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
 use super::buffer::PacketBuffer;
-use super::types::{IcmpContentType, IcmpType};
+use super::types::{self, IcmpContentType, IcmpType};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+/// Length of the ICMP header preceding the payload: type, code, checksum
+/// and the 4 bytes of echo id and sequence number
+pub const ICMP_HEADER_LEN: usize = 8;
+
 #[derive(Clone, Debug)]
 pub struct IcmpPacket {
     pub msg_type: IcmpType,
@@ -29,19 +35,121 @@ impl Default for IcmpPacket {
 
 impl IcmpPacket {
     pub fn echo_request(id: u16, sequence_no: u16, packet_size: usize) -> Self {
+        Self::echo_request_with_code(id, sequence_no, packet_size, 0)
+    }
+
+    /// Build an echo request carrying a nonstandard message code.
+    ///
+    /// Advanced: RFC 792 mandates code 0 for echo, other values are only useful
+    /// for testing how hosts treat malformed requests.
+    pub fn echo_request_with_code(
+        id: u16,
+        sequence_no: u16,
+        packet_size: usize,
+        msg_code: u8,
+    ) -> Self {
         Self {
+            msg_code,
             content: IcmpContentType::Echo { id, sequence_no },
             payload: vec![0; packet_size],
             ..Default::default()
         }
     }
 
+    /// Build an address mask request (RFC 950), asking the host for the
+    /// subnet mask of the interface it arrives on
+    pub fn address_mask_request(id: u16, sequence_no: u16) -> Self {
+        Self {
+            msg_type: IcmpType::AddressMaskRequest,
+            content: IcmpContentType::AddressMask {
+                id,
+                sequence_no,
+                mask: Ipv4Addr::UNSPECIFIED,
+            },
+            payload: Vec::new(),
+            ..Default::default()
+        }
+    }
+
+    /// The MTU reported by a "fragmentation needed" destination unreachable
+    /// message, `None` for any other message
+    pub fn next_hop_mtu(&self) -> Option<u16> {
+        self.content.next_hop_mtu(self.msg_code)
+    }
+
+    /// Standard description of the message type and code, see
+    /// `types::describe`
+    pub fn description(&self) -> String {
+        types::describe(self.msg_type, self.msg_code)
+    }
+
+    /// Whether this message reports an error with a packet we sent, which it
+    /// quotes in its payload
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self.msg_type,
+            IcmpType::DestinationUnreachable
+                | IcmpType::SourceQuench
+                | IcmpType::Redirect
+                | IcmpType::TimeExceeded
+                | IcmpType::ParameterProblem
+        )
+    }
+
+    /// The identifier and sequence number of the echo request an error
+    /// message quotes, skipping the IPv4 or IPv6 header it starts with.
+    /// `None` for other messages, or if what's quoted isn't an echo request.
+    pub fn quoted_echo(&self) -> Option<(u16, u16)> {
+        if !self.is_error() {
+            return None;
+        }
+
+        let header_len = match self.payload.first()? >> 4 {
+            4 => (self.payload[0] & 0x0F) as usize * 4,
+            6 => 40,
+            _ => return None,
+        };
+        let quoted = self.payload.get(header_len..header_len + 8)?;
+        // Echo request is type 8 in ICMP and 128 in ICMPv6
+        if !matches!(quoted[0], 8 | 128) {
+            return None;
+        }
+        Some((
+            u16::from_be_bytes([quoted[4], quoted[5]]),
+            u16::from_be_bytes([quoted[6], quoted[7]]),
+        ))
+    }
+
+    /// The destination of the packet an error message quotes, read from the
+    /// IPv4 or IPv6 header it starts with. `None` for other messages.
+    pub fn quoted_destination(&self) -> Option<IpAddr> {
+        if !self.is_error() {
+            return None;
+        }
+
+        match self.payload.first()? >> 4 {
+            4 => {
+                let octets: [u8; 4] = self.payload.get(16..20)?.try_into().ok()?;
+                Some(Ipv4Addr::from(octets).into())
+            }
+            6 => {
+                let octets: [u8; 16] = self.payload.get(24..40)?.try_into().ok()?;
+                Some(Ipv6Addr::from(octets).into())
+            }
+            _ => None,
+        }
+    }
+
     pub fn write(&mut self, buffer: &mut PacketBuffer) -> Result<()> {
         buffer.write(self.msg_type.to_u8()).unwrap();
         buffer.write(self.msg_code).unwrap();
 
-        buffer.write_u16(self.checksum)?;
+        // The checksum is computed with the checksum field zeroed
+        buffer.write_u16(0)?;
         buffer.write_u32(self.content.to_u32())?;
+        if let IcmpContentType::AddressMask { mask, .. } = self.content {
+            buffer.write_u32(mask.to_bits())?;
+        }
         buffer.write_bytes(&self.payload)?;
 
         self.checksum = self.calculate_checksum(buffer);
@@ -60,7 +168,9 @@ impl IcmpPacket {
             acc.wrapping_add(word as u32)
         });
 
-        let sum = (sum & 0xFFFFF) * (sum >> 16);
+        // Fold the carries back into the low 16 bits
+        let sum = (sum & 0xFFFF) + (sum >> 16);
+        let sum = (sum & 0xFFFF) + (sum >> 16);
         !(sum as u16)
     }
 
@@ -74,6 +184,12 @@ impl IcmpPacket {
 
         let content = buffer.read_u32()?;
         packet.content = IcmpContentType::new(packet.msg_type, content);
+        // The mask of an address mask message is the word after the header
+        if let IcmpContentType::AddressMask { mask, .. } = &mut packet.content {
+            if buffer.buffer.len() - buffer.pos >= 4 {
+                *mask = Ipv4Addr::from(buffer.read_u32()?);
+            }
+        }
 
         packet.payload = buffer
             .read_bytes(buffer.buffer.len() - buffer.pos)?
@@ -82,3 +198,71 @@ impl IcmpPacket {
         Ok(packet)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_request_code_round_trips() {
+        let mut packet = IcmpPacket::echo_request_with_code(7, 9, 8, 42);
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        let read = IcmpPacket::read(&mut buffer).unwrap();
+        assert!(matches!(read.msg_type, IcmpType::EchoRequest));
+        assert_eq!(read.msg_code, 42);
+        assert!(matches!(
+            read.content,
+            IcmpContentType::Echo {
+                id: 7,
+                sequence_no: 9
+            }
+        ));
+        assert_eq!(read.payload, vec![0; 8]);
+    }
+
+    #[test]
+    fn address_mask_reply_round_trips() {
+        let mut packet = IcmpPacket {
+            msg_type: IcmpType::AddressMaskReply,
+            ..IcmpPacket::address_mask_request(7, 3)
+        };
+        packet.content = IcmpContentType::AddressMask {
+            id: 7,
+            sequence_no: 3,
+            mask: Ipv4Addr::new(255, 255, 255, 0),
+        };
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        assert_eq!(buffer.buffer.len(), 12);
+
+        buffer.seek(0).unwrap();
+        let read = IcmpPacket::read(&mut buffer).unwrap();
+        assert!(matches!(read.msg_type, IcmpType::AddressMaskReply));
+        assert!(matches!(
+            read.content,
+            IcmpContentType::AddressMask {
+                id: 7,
+                sequence_no: 3,
+                mask
+            } if mask == Ipv4Addr::new(255, 255, 255, 0)
+        ));
+        assert!(read.payload.is_empty());
+    }
+
+    #[test]
+    fn fragmentation_needed_carries_the_next_hop_mtu() {
+        // Type 3 (destination unreachable), code 4, MTU 1400 in the low half
+        // of the second word, then the start of the quoted datagram
+        let bytes = [3, 4, 0, 0, 0, 0, 0x05, 0x78, 0x45, 0, 0, 84];
+        let packet = IcmpPacket::read(&mut PacketBuffer::from(&bytes[..])).unwrap();
+        assert_eq!(packet.next_hop_mtu(), Some(1400));
+
+        // Host unreachable leaves the word unused
+        let bytes = [3, 1, 0, 0, 0, 0, 0x05, 0x78, 0x45, 0, 0, 84];
+        let packet = IcmpPacket::read(&mut PacketBuffer::from(&bytes[..])).unwrap();
+        assert_eq!(packet.next_hop_mtu(), None);
+    }
+}
@@ -1,157 +1,879 @@
-use libc::{sockaddr_in, socket, AF_INET, IPPROTO_ICMP, IP_RECVTTL, SOCK_DGRAM};
-use std::net::Ipv4Addr;
-use std::{io, mem};
+use libc::{socket, AF_INET, IPPROTO_ICMP, IP_RECVTTL, SOCK_DGRAM, SOCK_RAW};
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{fmt, io, mem};
 
 use super::buffer::PacketBuffer;
-use super::packet::IcmpPacket;
+use super::ip::{Ipv4Header, IPPROTO_ICMP as IP_PROTOCOL_ICMP, IPV4_HEADER_LEN};
+use super::packet::{IcmpPacket, ICMP_HEADER_LEN};
+use super::transport::{
+    is_transient_recv_error, is_transient_send_error, retry_transient, PacketTransport,
+};
+pub use super::transport::{
+    HeaderIncludedTransport, IcmpSocketKind, RecvInfo, SocketTransport, DEFAULT_TRANSIENT_RETRIES,
+};
+
+/// Custom error type for the IcmpSocket
+#[derive(Debug)]
+pub enum IcmpSocketError {
+    /// Neither an unprivileged nor a raw ICMP socket could be opened
+    PermissionDenied(io::Error),
+    /// A raw socket was required but could not be opened
+    RawSocketDenied(io::Error),
+    /// The kernel refused a packet of this many bytes as too large (EMSGSIZE)
+    PacketTooLarge(usize),
+    /// Only part of the packet was sent
+    ShortWrite { sent: usize, len: usize },
+    /// Binding to a network interface was not permitted
+    DeviceBindDenied { device: String, err: io::Error },
+    /// Setting a source route was not permitted
+    SourceRouteDenied(io::Error),
+    /// A message larger than the `capacity` of the receive buffer arrived
+    /// and was cut short, `len` bytes long if the kernel said so
+    Truncated { len: Option<usize>, capacity: usize },
+}
+
+impl fmt::Display for IcmpSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IcmpSocketError::PermissionDenied(err) => write!(
+                f,
+                "Not permitted to open an ICMP socket ({}).\n\
+                 Either allow unprivileged ICMP sockets for your group, e.g.\n    \
+                 sysctl -w net.ipv4.ping_group_range=\"0 2147483647\"\n\
+                 or grant the binary raw socket access, e.g.\n    \
+                 setcap cap_net_raw+ep $(which pingoc)",
+                err
+            ),
+            IcmpSocketError::RawSocketDenied(err) => write!(
+                f,
+                "Not permitted to open a raw ICMP socket ({}).\n\
+                 Building IP headers needs root or the CAP_NET_RAW capability, e.g.\n    \
+                 setcap cap_net_raw+ep $(which pingoc)",
+                err
+            ),
+            IcmpSocketError::PacketTooLarge(len) => write!(
+                f,
+                "A {} byte packet is too large to send, try a smaller --packet-size",
+                len
+            ),
+            IcmpSocketError::ShortWrite { sent, len } => write!(
+                f,
+                "Only {} of {} bytes were sent, try a smaller --packet-size",
+                sent, len
+            ),
+            IcmpSocketError::DeviceBindDenied { device, err } => write!(
+                f,
+                "Not permitted to bind to interface {} ({}).\n\
+                 Sending out of a given interface needs root or the CAP_NET_RAW capability, e.g.\n    \
+                 setcap cap_net_raw+ep $(which pingoc)",
+                device, err
+            ),
+            IcmpSocketError::SourceRouteDenied(err) => write!(
+                f,
+                "Not permitted to set a source route ({}).\n\
+                 Source routing needs root or the CAP_NET_RAW capability, e.g.\n    \
+                 setcap cap_net_raw+ep $(which pingoc)",
+                err
+            ),
+            IcmpSocketError::Truncated {
+                len: Some(len),
+                capacity,
+            } => write!(
+                f,
+                "Received a {} byte packet, more than the {} byte receive buffer holds",
+                len, capacity
+            ),
+            IcmpSocketError::Truncated {
+                len: None,
+                capacity,
+            } => write!(
+                f,
+                "Received a packet larger than the {} byte receive buffer holds",
+                capacity
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IcmpSocketError {}
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-const ICMP_RECV_BUFFER_SZ: usize = 2000;
+/// Sends and receives ICMP packets through a `PacketTransport`, by default a
+/// kernel ICMP socket
+pub struct IcmpSocket<T: PacketTransport = SocketTransport> {
+    transport: T,
+    destination: Ipv4Addr,
+    /// Retries of a send or receive failing with a transient error
+    transient_retries: u32,
+}
 
-pub struct IcmpSocket {
-    socket: i32,
-    address: sockaddr_in,
+/// Whether `err` means the process lacks the privilege to open the socket
+fn is_permission_error(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EACCES) | Some(libc::EPERM))
 }
 
-impl IcmpSocket {
-    pub fn new(timeout: usize) -> Result<Self> {
-        let socket = unsafe { socket(AF_INET, SOCK_DGRAM, IPPROTO_ICMP) };
-        if socket < 0 {
-            return Err(Box::new(io::Error::last_os_error()));
+/// Check the outcome of sending a `len` byte packet, turning EMSGSIZE and
+/// short writes into descriptive errors
+pub(super) fn check_sent(result: io::Result<usize>, len: usize) -> Result<usize> {
+    match result {
+        Ok(sent) if sent < len => Err(Box::new(IcmpSocketError::ShortWrite { sent, len })),
+        Ok(sent) => Ok(sent),
+        Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) => {
+            Err(Box::new(IcmpSocketError::PacketTooLarge(len)))
         }
+        Err(err) => Err(Box::new(err)),
+    }
+}
 
+/// Check the outcome of setting an option that needs root or
+/// `CAP_NET_RAW`, turning a lack of privilege into the error `denied` makes
+/// of it
+fn check_privileged(
+    result: io::Result<()>,
+    denied: impl FnOnce(io::Error) -> IcmpSocketError,
+) -> Result<()> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) if is_permission_error(&err) => Err(Box::new(denied(err))),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+pub(super) fn open(
+    domain: libc::c_int,
+    socket_type: libc::c_int,
+    protocol: libc::c_int,
+) -> io::Result<i32> {
+    let socket = unsafe { socket(domain, socket_type, protocol) };
+    if socket < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// Close a socket opened by `open`. Negative descriptors, which `open`
+/// never returns, are ignored.
+pub(super) fn close(socket: i32) {
+    if socket >= 0 {
+        unsafe { libc::close(socket) };
+    }
+}
+
+/// Open an ICMP socket of the given family, preferring an unprivileged
+/// datagram socket and falling back to a raw socket if that is not permitted
+pub(super) fn open_with_fallback(
+    domain: libc::c_int,
+    protocol: libc::c_int,
+) -> Result<(i32, IcmpSocketKind)> {
+    match open(domain, SOCK_DGRAM, protocol) {
+        Ok(socket) => Ok((socket, IcmpSocketKind::Datagram)),
+        Err(err) if is_permission_error(&err) => match open(domain, SOCK_RAW, protocol) {
+            Ok(socket) => Ok((socket, IcmpSocketKind::Raw)),
+            Err(raw_err) if is_permission_error(&raw_err) => {
+                Err(Box::new(IcmpSocketError::PermissionDenied(err)))
+            }
+            Err(raw_err) => Err(Box::new(raw_err)),
+        },
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Convert a timeout to a `timeval` with microsecond precision. A zero
+/// timeout would make the socket block forever, so it is rounded up to 1us.
+fn to_timeval(timeout: Duration) -> libc::timeval {
+    let timeout = timeout.max(Duration::from_micros(1));
+    libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+    }
+}
+
+/// Set both the receive and send timeout of a socket
+pub(super) fn set_timeout(socket: i32, timeout: Duration) -> io::Result<()> {
+    let timeout_tval = to_timeval(timeout);
+
+    for name in [libc::SO_RCVTIMEO, libc::SO_SNDTIMEO] {
         let result = unsafe {
             libc::setsockopt(
                 socket,
-                libc::IPPROTO_IP,
-                IP_RECVTTL,
-                &(1 as libc::c_int) as *const libc::c_int as *const libc::c_void,
-                mem::size_of::<libc::c_int>() as u32,
+                libc::SOL_SOCKET,
+                name,
+                &timeout_tval as *const _ as *const libc::c_void,
+                mem::size_of_val(&timeout_tval) as u32,
             )
         };
+
         if result < 0 {
-            return Err(Box::new(io::Error::last_os_error()));
+            return Err(io::Error::last_os_error());
         }
+    }
+
+    Ok(())
+}
+
+pub(super) fn set_option(
+    socket: i32,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(super) fn get_option(
+    socket: i32,
+    level: libc::c_int,
+    name: libc::c_int,
+) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
 
-        let mut address: sockaddr_in = unsafe { std::mem::zeroed() };
-        address.sin_family = AF_INET as u16;
-        address.sin_port = 0;
+    let result = unsafe {
+        libc::getsockopt(
+            socket,
+            level,
+            name,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
 
-        let icmp_socket = Self { socket, address };
-        icmp_socket.set_timeout(timeout)?;
-        Ok(icmp_socket)
+    if result < 0 {
+        return Err(io::Error::last_os_error());
     }
+    Ok(value)
+}
+
+impl IcmpSocket {
+    /// Open an ICMP socket, preferring an unprivileged datagram socket and
+    /// falling back to a raw socket if that is not permitted.
+    pub fn new(timeout: Duration) -> Result<Self> {
+        let (socket, kind) = open_with_fallback(AF_INET, IPPROTO_ICMP)?;
+        // Owned from here on, so the socket is closed if setting it up fails
+        let transport = SocketTransport { socket, kind };
 
-    fn set_timeout(&self, timeout: usize) -> Result<()> {
-        let mut timeout_tval: libc::timeval = unsafe { std::mem::zeroed() };
-        timeout_tval.tv_sec = timeout as i64;
-        timeout_tval.tv_usec = 0;
+        set_option(socket, libc::IPPROTO_IP, IP_RECVTTL, 1)?;
+        set_option(socket, libc::SOL_SOCKET, libc::SO_TIMESTAMP, 1)?;
+        set_timeout(socket, timeout)?;
+        Ok(Self::with_transport(transport, Ipv4Addr::UNSPECIFIED))
+    }
 
+    /// Check whether this process may open an ICMP socket of `kind`, without
+    /// keeping it open
+    pub fn probe(kind: IcmpSocketKind) -> io::Result<()> {
+        let socket_type = match kind {
+            IcmpSocketKind::Datagram => SOCK_DGRAM,
+            IcmpSocketKind::Raw => SOCK_RAW,
+        };
+        let socket = open(AF_INET, socket_type, IPPROTO_ICMP)?;
+        close(socket);
+        Ok(())
+    }
+
+    /// The kind of socket that was opened
+    pub fn kind(&self) -> IcmpSocketKind {
+        self.transport.kind
+    }
+
+    /// Set the destination of subsequent `send` calls.
+    ///
+    /// The ICMP socket itself is deliberately left unconnected so that a single
+    /// socket can address several hosts (see `send_to`). Instead the target is
+    /// validated up front with `validate_destination`.
+    ///
+    /// With `set_broadcast` enabled the route check is skipped, so that
+    /// broadcast addresses can be pinged.
+    pub fn connect(&mut self, ip: Ipv4Addr) -> io::Result<()> {
+        if !self.is_broadcast()? {
+            Self::validate_destination(ip)?;
+        } else if ip.octets()[0] == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{ip} is not a valid ping destination"),
+            ));
+        }
+        self.destination = ip;
+        Ok(())
+    }
+
+    /// Allow sending to broadcast addresses (`SO_BROADCAST`).
+    ///
+    /// Note that many hosts ignore broadcast echo requests (on Linux see
+    /// `net.ipv4.icmp_echo_ignore_broadcasts`), so silence is not proof that
+    /// nothing is up.
+    pub fn set_broadcast(&self, enable: bool) -> io::Result<()> {
+        set_option(
+            self.transport.socket,
+            libc::SOL_SOCKET,
+            libc::SO_BROADCAST,
+            enable as libc::c_int,
+        )
+    }
+
+    /// Whether sending to broadcast addresses is allowed
+    pub fn is_broadcast(&self) -> io::Result<bool> {
+        Ok(get_option(self.transport.socket, libc::SOL_SOCKET, libc::SO_BROADCAST)? != 0)
+    }
+
+    /// Check that `ip` can be pinged: the broadcast address and those of
+    /// "this network" (0.0.0.0/8, only ever a source) are rejected, and the
+    /// kernel is asked for a route to the host by connecting a throwaway UDP
+    /// socket, so that unroutable destinations fail here (e.g. with
+    /// `ENETUNREACH`) rather than on the first send.
+    pub fn validate_destination(ip: Ipv4Addr) -> io::Result<()> {
+        if ip.octets()[0] == 0 || ip.is_broadcast() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{ip} is not a valid ping destination"),
+            ));
+        }
+
+        // Connecting a UDP socket sends nothing but performs a route lookup
+        UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?.connect((ip, 1))?;
+        Ok(())
+    }
+
+    /// Send out of the network interface named `device` only
+    /// (`SO_BINDTODEVICE`), regardless of the routing table. Requires root or
+    /// `CAP_NET_RAW`.
+    pub fn bind_to_device(&self, device: &str) -> Result<()> {
         let result = unsafe {
             libc::setsockopt(
-                self.socket,
+                self.transport.socket,
                 libc::SOL_SOCKET,
-                libc::SO_RCVTIMEO,
-                &timeout_tval as *const _ as *const libc::c_void,
-                mem::size_of_val(&timeout_tval) as u32,
+                libc::SO_BINDTODEVICE,
+                device.as_ptr() as *const libc::c_void,
+                device.len() as libc::socklen_t,
             )
         };
 
-        if result < 0 {
-            return Err(Box::new(io::Error::last_os_error()));
-        }
+        let result = if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+        check_privileged(result, |err| IcmpSocketError::DeviceBindDenied {
+            device: device.to_string(),
+            err,
+        })
+    }
 
+    /// Route packets through `hops` in order on their way to the destination,
+    /// with a loose source route option (RFC 791) in the IP header. Requires
+    /// root or `CAP_NET_RAW`.
+    pub fn set_loose_source_route(&self, hops: &[Ipv4Addr]) -> Result<()> {
+        let options = loose_source_route(hops)?;
         let result = unsafe {
             libc::setsockopt(
-                self.socket,
-                libc::SOL_SOCKET,
-                libc::SO_SNDTIMEO,
-                &timeout_tval as *const _ as *const libc::c_void,
-                mem::size_of_val(&timeout_tval) as u32,
+                self.transport.socket,
+                libc::IPPROTO_IP,
+                libc::IP_OPTIONS,
+                options.as_ptr() as *const libc::c_void,
+                options.len() as libc::socklen_t,
+            )
+        };
+
+        let result = if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+        check_privileged(result, IcmpSocketError::SourceRouteDenied)
+    }
+
+    /// Size of the IP and ICMP headers preceding the payload of the packets
+    /// sent, including the IP options set with `set_loose_source_route`
+    pub fn header_len(&self) -> usize {
+        headers_len(self.ip_options_len().unwrap_or(0))
+    }
+
+    /// Length of the IP options the kernel adds to every packet sent, as
+    /// set through `IP_OPTIONS`
+    fn ip_options_len(&self) -> io::Result<usize> {
+        // The options area of an IPv4 header is at most 40 bytes
+        let mut options = [0u8; 40];
+        let mut len = options.len() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                self.transport.socket,
+                libc::IPPROTO_IP,
+                libc::IP_OPTIONS,
+                options.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
             )
         };
 
         if result < 0 {
-            return Err(Box::new(io::Error::last_os_error()));
+            return Err(io::Error::last_os_error());
         }
+        Ok(len as usize)
+    }
 
+    /// Send every packet by way of `gateway`: out of the interface facing it,
+    /// with a loose source route naming it as the first hop.
+    ///
+    /// Both require root or `CAP_NET_RAW`. Hosts drop
+    /// source routed packets unless `net.ipv4.conf.*.accept_source_route` is
+    /// set, and so do many routers, so this is meant for lab networks.
+    pub fn set_gateway(&self, gateway: Ipv4Addr) -> Result<()> {
+        self.bind_to_device(&interface_towards(gateway)?)?;
+        self.set_loose_source_route(&[gateway])?;
         Ok(())
     }
 
+    pub fn get_ttl(&self) -> Result<u32> {
+        Ok(get_option(self.transport.socket, libc::IPPROTO_IP, libc::IP_TTL)? as u32)
+    }
+
+    /// Set the TTL of outgoing packets
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        set_option(
+            self.transport.socket,
+            libc::IPPROTO_IP,
+            libc::IP_TTL,
+            ttl as libc::c_int,
+        )
+    }
+}
+
+/// Size of the IP and ICMP headers preceding the payload of a packet sent
+/// with `options_len` bytes of IP options
+fn headers_len(options_len: usize) -> usize {
+    IPV4_HEADER_LEN + options_len + ICMP_HEADER_LEN
+}
+
+/// Encode a loose source route through `hops` for `IP_OPTIONS`. Linux takes
+/// the first hop as the address to send to and fills the destination into
+/// the last slot, which is left empty here. A leading NOP pads the option to
+/// a whole number of 32-bit words.
+fn loose_source_route(hops: &[Ipv4Addr]) -> io::Result<Vec<u8>> {
+    // The options area holds 40 bytes: NOP, type, length, pointer and nine
+    // addresses, one of which is the destination
+    if hops.is_empty() || hops.len() > 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a source route takes 1 to 8 hops, not {}", hops.len()),
+        ));
+    }
+
+    let len = 3 + 4 * (hops.len() + 1);
+    let mut options = vec![libc::IPOPT_NOP, libc::IPOPT_LSRR, len as u8, 4];
+    for hop in hops {
+        options.extend(hop.octets());
+    }
+    options.extend(Ipv4Addr::UNSPECIFIED.octets());
+    Ok(options)
+}
+
+/// Name of the network interface packets to `ip` leave through, found by
+/// asking the kernel for the source address of a route to it
+pub fn interface_towards(ip: Ipv4Addr) -> io::Result<String> {
+    let probe = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    probe.connect((ip, 1))?;
+    let IpAddr::V4(source) = probe.local_addr()?.ip() else {
+        unreachable!("an IPv4 socket has an IPv4 address")
+    };
+
+    let mut addresses: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addresses) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut device = None;
+    let mut entry = addresses;
+    while !entry.is_null() && device.is_none() {
+        let ifaddr = unsafe { &*entry };
+        let address = ifaddr.ifa_addr;
+        if !address.is_null() && unsafe { (*address).sa_family } as libc::c_int == AF_INET {
+            let address = unsafe { &*(address as *const libc::sockaddr_in) };
+            if u32::from_be(address.sin_addr.s_addr) == source.to_bits() {
+                let name = unsafe { std::ffi::CStr::from_ptr(ifaddr.ifa_name) };
+                device = Some(name.to_string_lossy().into_owned());
+            }
+        }
+        entry = ifaddr.ifa_next;
+    }
+    unsafe { libc::freeifaddrs(addresses) };
+
+    device.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no interface has {source}, the source address towards {ip}"),
+        )
+    })
+}
+
+impl IcmpSocket<HeaderIncludedTransport> {
+    /// Open a raw ICMP socket with `IP_HDRINCL`, so that every packet goes
+    /// out with an IPv4 header built from `transport().header()` rather than
+    /// by the kernel. Requires root or `CAP_NET_RAW`.
+    pub fn new_header_included(timeout: Duration) -> Result<Self> {
+        let socket = match open(AF_INET, SOCK_RAW, IPPROTO_ICMP) {
+            Ok(socket) => socket,
+            Err(err) if is_permission_error(&err) => {
+                return Err(Box::new(IcmpSocketError::RawSocketDenied(err)))
+            }
+            Err(err) => return Err(Box::new(err)),
+        };
+        let header = Ipv4Header::new(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            IP_PROTOCOL_ICMP,
+            0,
+        );
+        let transport = HeaderIncludedTransport {
+            socket,
+            header: Mutex::new(header),
+        };
+
+        set_option(socket, libc::IPPROTO_IP, libc::IP_HDRINCL, 1)?;
+        set_option(socket, libc::IPPROTO_IP, IP_RECVTTL, 1)?;
+        set_option(socket, libc::SOL_SOCKET, libc::SO_TIMESTAMP, 1)?;
+        set_timeout(socket, timeout)?;
+        Ok(Self::with_transport(transport, Ipv4Addr::UNSPECIFIED))
+    }
+
+    /// Set the destination of subsequent `send` calls, see
+    /// `IcmpSocket::validate_destination`
     pub fn connect(&mut self, ip: Ipv4Addr) -> io::Result<()> {
-        self.address.sin_addr.s_addr = ip.to_bits().to_be();
+        IcmpSocket::validate_destination(ip)?;
+        self.destination = ip;
         Ok(())
     }
 
+    /// TTL written into the IP header
+    pub fn get_ttl(&self) -> Result<u32> {
+        Ok(self.transport.header().ttl as u32)
+    }
+
+    /// Set the TTL written into the IP header
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        let ttl = u8::try_from(ttl).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("TTL {ttl} does not fit the IP header"),
+            )
+        })?;
+        let mut header = self.transport.header();
+        header.ttl = ttl;
+        self.transport.set_header(header);
+        Ok(())
+    }
+}
+
+impl<T: PacketTransport> IcmpSocket<T> {
+    /// Wrap an arbitrary transport, e.g. a `MockTransport` in tests. Packets
+    /// are sent to `destination` unless `send_to` is used.
+    pub fn with_transport(transport: T, destination: Ipv4Addr) -> Self {
+        Self {
+            transport,
+            destination,
+            transient_retries: DEFAULT_TRANSIENT_RETRIES,
+        }
+    }
+
+    /// How many times to retry a send that failed because the socket buffer
+    /// was full or a signal interrupted it, or a receive that a signal
+    /// interrupted, before reporting the error. 0 disables retrying.
+    pub fn set_transient_retries(&mut self, retries: u32) {
+        self.transient_retries = retries;
+    }
+
+    /// The transport packets go through
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Send a packet to the address set by `connect`
     pub fn send(&self, packet: &mut IcmpPacket) -> Result<usize> {
+        self.send_to(packet, self.destination)
+    }
+
+    /// Send a packet to `ip`, regardless of the address set by `connect`
+    pub fn send_to(&self, packet: &mut IcmpPacket, ip: Ipv4Addr) -> Result<usize> {
         let mut buffer = PacketBuffer::new();
         packet.write(&mut buffer)?;
 
         let buffer_bytes = buffer.get_bytes(0, buffer.buffer.len())?;
+        check_sent(self.send_bytes(buffer_bytes, ip), buffer_bytes.len())
+    }
 
-        let result = unsafe {
-            libc::sendto(
-                self.socket,
-                buffer_bytes.as_ptr() as *const libc::c_void,
-                buffer_bytes.len(),
-                0,
-                &self.address as *const _ as *const libc::sockaddr,
-                mem::size_of::<sockaddr_in>() as libc::socklen_t,
-            )
-        };
+    /// Send encoded bytes through the transport, retrying transient failures
+    fn send_bytes(&self, bytes: &[u8], ip: Ipv4Addr) -> io::Result<usize> {
+        retry_transient(self.transient_retries, is_transient_send_error, || {
+            self.transport.send_to(bytes, ip)
+        })
+    }
 
-        if result == -1 {
-            Err(Box::new(io::Error::last_os_error()))
-        } else {
-            Ok(result as usize)
-        }
+    /// Receive from the transport, retrying transient failures
+    fn recv_bytes(&self) -> io::Result<(Vec<u8>, Ipv4Addr, RecvInfo)> {
+        retry_transient(self.transient_retries, is_transient_recv_error, || {
+            self.transport.recv_with_info()
+        })
     }
 
     pub fn recv(&self) -> Result<IcmpPacket> {
-        let buffer = [0; ICMP_RECV_BUFFER_SZ];
-        let mut address: sockaddr_in = unsafe { std::mem::zeroed() };
-        let mut address_len = mem::size_of::<sockaddr_in>() as libc::socklen_t;
-
-        let num_bytes = unsafe {
-            libc::recvfrom(
-                self.socket,
-                buffer.as_ptr() as *mut libc::c_void,
-                ICMP_RECV_BUFFER_SZ,
-                0,
-                &mut address as *mut _ as *mut libc::sockaddr,
-                &mut address_len,
-            )
-        };
+        self.recv_from().map(|(packet, _)| packet)
+    }
 
-        if num_bytes < 0 {
-            return Err(Box::new(io::Error::last_os_error()));
+    /// Receive a packet along with the address it was sent from
+    pub fn recv_from(&self) -> Result<(IcmpPacket, Ipv4Addr)> {
+        self.recv_with_info()
+            .map(|(packet, source, _)| (packet, source))
+    }
+
+    /// Like `recv_from`, also returning the TTL the packet arrived with if
+    /// the transport reported it
+    pub fn recv_with_ttl(&self) -> Result<(IcmpPacket, Ipv4Addr, Option<u8>)> {
+        self.recv_with_info()
+            .map(|(packet, source, info)| (packet, source, info.ttl))
+    }
+
+    /// Like `recv_from`, also returning the TTL and kernel receive time of
+    /// the packet if the transport reported them
+    pub fn recv_with_info(&self) -> Result<(IcmpPacket, Ipv4Addr, RecvInfo)> {
+        let (bytes, source, info) = self.recv_bytes()?;
+        let mut packet_buffer = PacketBuffer::from(bytes.as_slice());
+
+        Ok((IcmpPacket::read(&mut packet_buffer)?, source, info))
+    }
+
+    /// Send `packet` to `ip` exactly as given, returning the bytes put on the
+    /// wire. Only the checksum is filled in.
+    ///
+    /// Advanced: meant for protocol testing, e.g. sweeping identifiers and
+    /// sequence numbers outside a `Pinger`. Datagram sockets still have the
+    /// kernel rewrite the identifier of echo requests to the socket's port.
+    pub fn send_raw(&self, mut packet: IcmpPacket, ip: Ipv4Addr) -> Result<Vec<u8>> {
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer)?;
+
+        let buffer_bytes = buffer.get_bytes(0, buffer.buffer.len())?;
+        check_sent(self.send_bytes(buffer_bytes, ip), buffer_bytes.len())?;
+        Ok(buffer_bytes.to_vec())
+    }
+
+    /// Receive any ICMP message, returning the raw bytes of the message
+    /// without IP header, the packet parsed from them, or `None` if they
+    /// don't parse, and the address it came from.
+    ///
+    /// Advanced: the counterpart of `send_raw`, for looking at replies as
+    /// they arrived rather than as pingoc interprets them.
+    pub fn recv_raw(&self) -> Result<(Option<IcmpPacket>, Vec<u8>, Ipv4Addr)> {
+        let (bytes, source, _) = self.recv_bytes()?;
+        let packet = IcmpPacket::read(&mut PacketBuffer::from(bytes.as_slice())).ok();
+
+        Ok((packet, bytes, source))
+    }
+
+    /// Wait up to `timeout` for a packet, independently of the socket's read
+    /// timeout, returning `None` if none arrived in time. Lets callers wake
+    /// up exactly when the next send is due rather than a whole read
+    /// timeout later. Returns what `recv_with_info` does otherwise.
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<(IcmpPacket, Ipv4Addr, RecvInfo)>> {
+        if !self.transport.wait_readable(timeout)? {
+            return Ok(None);
         }
+        self.recv_with_info().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icmp::mock::MockTransport;
+    use std::io::Read;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::time::Instant;
 
-        let recv_sz = num_bytes as usize;
-        let mut packet_buffer = PacketBuffer::from(&buffer[..recv_sz]);
+    #[test]
+    fn eacces_is_explained() {
+        let err = io::Error::from_raw_os_error(libc::EACCES);
+        assert!(is_permission_error(&err));
+        assert!(!is_permission_error(&io::Error::from_raw_os_error(
+            libc::EINVAL
+        )));
 
-        IcmpPacket::read(&mut packet_buffer)
+        let message = IcmpSocketError::PermissionDenied(err).to_string();
+        assert!(message.starts_with("Not permitted to open an ICMP socket"));
+        assert!(message.contains("net.ipv4.ping_group_range"));
+        assert!(message.contains("setcap cap_net_raw+ep"));
     }
 
-    pub fn get_ttl(&self) -> Result<u32> {
-        let mut ttl: u32 = 0;
-        let mut len: u32 = mem::size_of::<u32>() as u32;
+    #[test]
+    fn destinations_that_are_never_routed_are_rejected() {
+        for ip in [
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::new(0, 1, 2, 3),
+            Ipv4Addr::BROADCAST,
+        ] {
+            let err = IcmpSocket::validate_destination(ip).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{ip}");
+        }
+        IcmpSocket::validate_destination(Ipv4Addr::LOCALHOST).unwrap();
+    }
 
-        let result = unsafe {
-            libc::getsockopt(
-                self.socket,
-                libc::IPPROTO_IP,
-                libc::IP_TTL,
-                &mut ttl as *mut u32 as *mut libc::c_void,
-                &mut len,
-            )
+    #[test]
+    fn destinations_without_a_route_are_rejected() {
+        // TEST-NET-2 is never routed on the internet, but a default route
+        // still covers it, so this only bites where there is none or it is
+        // blackholed, e.g. `ip route add blackhole 198.51.100.0/24`
+        let ip = Ipv4Addr::new(198, 51, 100, 1);
+        let Err(err) = IcmpSocket::validate_destination(ip) else {
+            return;
         };
+        // Blackhole routes fail with EINVAL, unreachable and prohibit routes
+        // with EHOSTUNREACH and EACCES
+        assert!(
+            matches!(
+                err.raw_os_error(),
+                Some(libc::ENETUNREACH | libc::EHOSTUNREACH | libc::EINVAL | libc::EACCES)
+            ),
+            "{err}"
+        );
+    }
 
-        if result < 0 {
-            return Err(Box::new(io::Error::last_os_error()));
+    #[test]
+    fn timeout_is_kept_to_the_microsecond() {
+        let timeval = to_timeval(Duration::from_secs_f64(0.25));
+        assert_eq!((timeval.tv_sec, timeval.tv_usec), (0, 250_000));
+
+        let timeval = to_timeval(Duration::from_micros(1_500_001));
+        assert_eq!((timeval.tv_sec, timeval.tv_usec), (1, 500_001));
+
+        // Zero would mean blocking forever
+        let timeval = to_timeval(Duration::ZERO);
+        assert_eq!((timeval.tv_sec, timeval.tv_usec), (0, 1));
+    }
+
+    #[test]
+    fn oversized_and_partly_sent_packets_are_explained() {
+        let err =
+            check_sent(Err(io::Error::from_raw_os_error(libc::EMSGSIZE)), 65_000).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IcmpSocketError>(),
+            Some(IcmpSocketError::PacketTooLarge(65_000))
+        ));
+        assert!(err.to_string().contains("try a smaller --packet-size"));
+
+        let err = check_sent(Ok(32), 64).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IcmpSocketError>(),
+            Some(IcmpSocketError::ShortWrite { sent: 32, len: 64 })
+        ));
+
+        assert_eq!(check_sent(Ok(64), 64).unwrap(), 64);
+        let err = check_sent(Err(io::Error::from_raw_os_error(libc::ENETUNREACH)), 64).unwrap_err();
+        assert!(err.downcast_ref::<io::Error>().is_some());
+    }
+
+    #[test]
+    fn recv_timeout_returns_once_nothing_arrived() {
+        let host = Ipv4Addr::new(192, 0, 2, 1);
+        let reply = IcmpPacket::echo_request(7, 1, 56);
+        let transport = MockTransport::new().reply_after(Duration::from_millis(300), host, reply);
+        let socket = IcmpSocket::with_transport(transport, host);
+
+        // The reply is due later than the wait asked for, which should win
+        let started_at = Instant::now();
+        let received = socket.recv_timeout(Duration::from_millis(50)).unwrap();
+        let waited = started_at.elapsed();
+        assert!(received.is_none());
+        assert!(waited < Duration::from_millis(250), "waited {waited:?}");
+
+        // Waiting again picks the reply up when it is due, not a whole
+        // delay later
+        let (_, source, _) = socket
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap()
+            .unwrap();
+        let waited = started_at.elapsed();
+        assert_eq!(source, host);
+        assert!(
+            waited >= Duration::from_millis(300) && waited < Duration::from_millis(550),
+            "waited {waited:?}"
+        );
+    }
+
+    #[test]
+    fn dropped_sockets_give_their_descriptors_back() {
+        // Any descriptor will do, and one end of a pair tells when the other
+        // was closed without needing the privilege to open ICMP sockets
+        for _ in 0..1000 {
+            let (ours, mut peer) = UnixStream::pair().unwrap();
+            let transport = SocketTransport {
+                socket: ours.into_raw_fd(),
+                kind: IcmpSocketKind::Datagram,
+            };
+            drop(IcmpSocket::with_transport(transport, Ipv4Addr::LOCALHOST));
+
+            // A leaked descriptor would keep the pair open and the read waiting
+            peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+            assert_eq!(peer.read(&mut [0; 1]).unwrap(), 0);
         }
+    }
+
+    #[test]
+    fn options_needing_privilege_explain_eperm() {
+        let denied = |err| IcmpSocketError::DeviceBindDenied {
+            device: "eth1".to_string(),
+            err,
+        };
+        let err =
+            check_privileged(Err(io::Error::from_raw_os_error(libc::EPERM)), denied).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IcmpSocketError>(),
+            Some(IcmpSocketError::DeviceBindDenied { device, .. }) if device == "eth1"
+        ));
+        assert!(err.to_string().contains("CAP_NET_RAW"));
+
+        let err = check_privileged(
+            Err(io::Error::from_raw_os_error(libc::EPERM)),
+            IcmpSocketError::SourceRouteDenied,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IcmpSocketError>(),
+            Some(IcmpSocketError::SourceRouteDenied(_))
+        ));
+
+        // Other failures are passed on as they are
+        let err = check_privileged(
+            Err(io::Error::from_raw_os_error(libc::ENODEV)),
+            IcmpSocketError::SourceRouteDenied,
+        )
+        .unwrap_err();
+        assert!(err.downcast_ref::<io::Error>().is_some());
+    }
+
+    #[test]
+    fn header_len_counts_ip_options() {
+        assert_eq!(headers_len(0), IPV4_HEADER_LEN + ICMP_HEADER_LEN);
 
-        Ok(ttl)
+        let route = loose_source_route(&[Ipv4Addr::new(192, 0, 2, 1)]).unwrap();
+        assert_eq!(
+            headers_len(route.len()),
+            IPV4_HEADER_LEN + 12 + ICMP_HEADER_LEN
+        );
     }
 }
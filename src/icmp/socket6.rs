@@ -0,0 +1,310 @@
+use libc::{sockaddr_in6, AF_INET6, IPPROTO_ICMPV6};
+use std::ffi::CString;
+use std::net::{Ipv6Addr, SocketAddrV6, UdpSocket};
+use std::time::Duration;
+use std::{io, mem};
+
+use super::buffer::PacketBuffer;
+use super::packet::IcmpPacket;
+use super::socket::{
+    check_sent, close, get_option, open_with_fallback, set_option, set_timeout, IcmpSocketKind,
+};
+use super::transport::{
+    control_timestamp, control_value, is_transient_recv_error, is_transient_send_error,
+    poll_readable, received_len, retry_transient, RecvInfo, CONTROL_BUFFER_SZ,
+    DEFAULT_TRANSIENT_RETRIES,
+};
+use super::types::IcmpType;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const ICMP_RECV_BUFFER_SZ: usize = 2000;
+
+/// Length of the fixed IPv6 header, without extension headers
+pub const IPV6_HEADER_LEN: usize = 40;
+
+/// Map an ICMPv6 message type (RFC 4443) to its ICMPv4 counterpart, so that
+/// packets of both families share `IcmpPacket`
+fn type_from_v6(icmp6_type: u8) -> Option<IcmpType> {
+    match icmp6_type {
+        1 => Some(IcmpType::DestinationUnreachable),
+        3 => Some(IcmpType::TimeExceeded),
+        4 => Some(IcmpType::ParameterProblem),
+        128 => Some(IcmpType::EchoRequest),
+        129 => Some(IcmpType::EchoReply),
+        _ => None,
+    }
+}
+
+/// Map an ICMPv6 destination unreachable code (RFC 4443) to the closest
+/// ICMPv4 code, so that it is described the same way
+fn unreachable_code_from_v6(code: u8) -> u8 {
+    match code {
+        // No route to destination, beyond scope of source address
+        0 | 2 => 0,
+        // Address unreachable
+        3 => 1,
+        // Port unreachable
+        4 => 3,
+        // Administratively prohibited, failed policy, reject route and
+        // anything newer
+        _ => 13,
+    }
+}
+
+/// Map an ICMPv4 message type to the ICMPv6 type sent on the wire
+fn type_to_v6(icmp_type: IcmpType) -> Option<u8> {
+    match icmp_type {
+        IcmpType::DestinationUnreachable => Some(1),
+        IcmpType::TimeExceeded => Some(3),
+        IcmpType::ParameterProblem => Some(4),
+        IcmpType::EchoRequest => Some(128),
+        IcmpType::EchoReply => Some(129),
+        _ => None,
+    }
+}
+
+/// An ICMPv6 socket.
+///
+/// Packets are built and parsed as `IcmpPacket`s with their ICMPv4 types,
+/// which are translated to and from ICMPv6 types on the wire. The kernel
+/// fills in the ICMPv6 checksum, which covers a pseudo-header we don't see.
+/// The socket is closed when this is dropped.
+pub struct Icmp6Socket {
+    socket: i32,
+    kind: IcmpSocketKind,
+    destination: Ipv6Addr,
+    /// Interface index link-local destinations are reached through
+    scope_id: u32,
+    /// Retries of a send or receive failing with a transient error
+    transient_retries: u32,
+}
+
+impl Icmp6Socket {
+    /// Open an ICMPv6 socket, preferring an unprivileged datagram socket and
+    /// falling back to a raw socket if that is not permitted.
+    pub fn new(timeout: Duration) -> Result<Self> {
+        let (socket, kind) = open_with_fallback(AF_INET6, IPPROTO_ICMPV6)?;
+        // Owned from here on, so the socket is closed if setting it up fails
+        let icmp6 = Self {
+            socket,
+            kind,
+            destination: Ipv6Addr::UNSPECIFIED,
+            scope_id: 0,
+            transient_retries: DEFAULT_TRANSIENT_RETRIES,
+        };
+
+        set_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+        set_option(socket, libc::SOL_SOCKET, libc::SO_TIMESTAMP, 1)?;
+        set_timeout(socket, timeout)?;
+        Ok(icmp6)
+    }
+
+    /// The kind of socket that was opened
+    pub fn kind(&self) -> IcmpSocketKind {
+        self.kind
+    }
+
+    /// How many times to retry a transient failure, see
+    /// `IcmpSocket::set_transient_retries`
+    pub fn set_transient_retries(&mut self, retries: u32) {
+        self.transient_retries = retries;
+    }
+
+    /// Set the destination of subsequent `send` calls, after checking there
+    /// is a route to it
+    pub fn connect(&mut self, ip: Ipv6Addr) -> io::Result<()> {
+        self.connect_scoped(ip, 0)
+    }
+
+    /// Like `connect`, reaching `ip` through the interface with index
+    /// `scope_id`. Link-local addresses are ambiguous without one, so they
+    /// are refused with a `scope_id` of 0.
+    pub fn connect_scoped(&mut self, ip: Ipv6Addr, scope_id: u32) -> io::Result<()> {
+        if ip.is_unspecified() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{ip} is not a valid ping destination"),
+            ));
+        }
+        if ip.is_unicast_link_local() && scope_id == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "link-local address {ip} needs a zone naming the interface, e.g. {ip}%eth0"
+                ),
+            ));
+        }
+
+        // Connecting a UDP socket sends nothing but performs a route lookup
+        UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
+            .connect(SocketAddrV6::new(ip, 1, 0, scope_id))?;
+        self.destination = ip;
+        self.scope_id = scope_id;
+        Ok(())
+    }
+
+    /// Send a packet to the address set by `connect`
+    pub fn send(&self, packet: &mut IcmpPacket) -> Result<usize> {
+        self.send_to(packet, self.destination)
+    }
+
+    /// Send a packet to `ip`, regardless of the address set by `connect`
+    pub fn send_to(&self, packet: &mut IcmpPacket, ip: Ipv6Addr) -> Result<usize> {
+        let icmp6_type = type_to_v6(packet.msg_type).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} has no ICMPv6 equivalent", packet.msg_type),
+            )
+        })?;
+
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer)?;
+        buffer.buffer[0] = icmp6_type;
+
+        let mut address: sockaddr_in6 = unsafe { mem::zeroed() };
+        address.sin6_family = AF_INET6 as u16;
+        address.sin6_addr.s6_addr = ip.octets();
+        if ip.is_unicast_link_local() {
+            address.sin6_scope_id = self.scope_id;
+        }
+
+        let result = retry_transient(self.transient_retries, is_transient_send_error, || {
+            let result = unsafe {
+                libc::sendto(
+                    self.socket,
+                    buffer.buffer.as_ptr() as *const libc::c_void,
+                    buffer.buffer.len(),
+                    0,
+                    &address as *const _ as *const libc::sockaddr,
+                    mem::size_of::<sockaddr_in6>() as libc::socklen_t,
+                )
+            };
+            if result == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(result as usize)
+            }
+        });
+        check_sent(result, buffer.buffer.len())
+    }
+
+    pub fn recv(&self) -> Result<IcmpPacket> {
+        self.recv_from().map(|(packet, _)| packet)
+    }
+
+    /// Receive a packet along with the address it was sent from. ICMPv6
+    /// messages without an ICMPv4 counterpart, such as neighbour discovery
+    /// seen by raw sockets, are skipped.
+    pub fn recv_from(&self) -> Result<(IcmpPacket, Ipv6Addr)> {
+        self.recv_with_info()
+            .map(|(packet, source, _)| (packet, source))
+    }
+
+    /// Like `recv_from`, also returning the hop limit the packet arrived with
+    /// if the kernel reported it
+    pub fn recv_with_hop_limit(&self) -> Result<(IcmpPacket, Ipv6Addr, Option<u8>)> {
+        self.recv_with_info()
+            .map(|(packet, source, info)| (packet, source, info.ttl))
+    }
+
+    /// Like `recv_from`, also returning the hop limit and kernel receive time
+    /// of the packet if the kernel reported them
+    pub fn recv_with_info(&self) -> Result<(IcmpPacket, Ipv6Addr, RecvInfo)> {
+        loop {
+            let mut buffer = [0u8; ICMP_RECV_BUFFER_SZ];
+            let mut control = [0u64; CONTROL_BUFFER_SZ];
+            let mut address: sockaddr_in6 = unsafe { mem::zeroed() };
+            let mut iov = libc::iovec {
+                iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.len(),
+            };
+
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_name = &mut address as *mut _ as *mut libc::c_void;
+            msg.msg_namelen = mem::size_of::<sockaddr_in6>() as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = mem::size_of_val(&control);
+
+            let num_bytes =
+                retry_transient(self.transient_retries, is_transient_recv_error, || {
+                    let num_bytes =
+                        unsafe { libc::recvmsg(self.socket, &mut msg, libc::MSG_TRUNC) };
+                    received_len(num_bytes, &msg, buffer.len())
+                })?;
+
+            // Unlike IPv4, raw ICMPv6 sockets never deliver the IP header
+            let Some(icmp_type) = buffer.first().copied().and_then(type_from_v6) else {
+                continue;
+            };
+            buffer[0] = icmp_type.to_u8();
+            if matches!(icmp_type, IcmpType::DestinationUnreachable) && num_bytes > 1 {
+                buffer[1] = unreachable_code_from_v6(buffer[1]);
+            }
+
+            let info = RecvInfo {
+                ttl: control_value::<libc::c_int>(&msg, libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT)
+                    .and_then(|hop_limit| u8::try_from(hop_limit).ok()),
+                timestamp: control_timestamp(&msg),
+                ip_header_len: None,
+            };
+            let mut packet_buffer = PacketBuffer::from(&buffer[..num_bytes]);
+            let source = Ipv6Addr::from(address.sin6_addr.s6_addr);
+            return Ok((IcmpPacket::read(&mut packet_buffer)?, source, info));
+        }
+    }
+
+    /// Wait up to `timeout` for a packet, see `IcmpSocket::recv_timeout`
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<(IcmpPacket, Ipv6Addr, RecvInfo)>> {
+        if !poll_readable(self.socket, timeout)? {
+            return Ok(None);
+        }
+        self.recv_with_info().map(Some)
+    }
+
+    /// Hop limit of outgoing unicast packets
+    pub fn get_hop_limit(&self) -> Result<u32> {
+        Ok(get_option(self.socket, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS)? as u32)
+    }
+
+    /// Set the hop limit of outgoing unicast packets
+    pub fn set_hop_limit(&self, hop_limit: u32) -> io::Result<()> {
+        set_option(
+            self.socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_UNICAST_HOPS,
+            hop_limit as libc::c_int,
+        )
+    }
+}
+
+impl Drop for Icmp6Socket {
+    fn drop(&mut self) {
+        close(self.socket);
+    }
+}
+
+/// The index of the interface a zone such as the `eth0` of `fe80::1%eth0`
+/// names. Numeric zones are taken to be indices already.
+pub fn interface_index(zone: &str) -> io::Result<u32> {
+    if let Ok(index) = zone.parse() {
+        return Ok(index);
+    }
+
+    let unknown = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no network interface named {zone:?}"),
+        )
+    };
+    let name = CString::new(zone).map_err(|_| unknown())?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(unknown()),
+        index => Ok(index),
+    }
+}
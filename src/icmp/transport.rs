@@ -0,0 +1,470 @@
+use libc::sockaddr_in;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use std::{io, mem};
+
+use super::buffer::PacketBuffer;
+use super::ip::{Ipv4Header, IPV4_HEADER_LEN};
+use super::socket::{close, IcmpSocketError};
+
+const ICMP_RECV_BUFFER_SZ: usize = 2000;
+
+/// How many times a send or receive failing with a transient error is
+/// retried before giving up, unless configured otherwise
+pub const DEFAULT_TRANSIENT_RETRIES: u32 = 3;
+
+/// Room for the TTL and timestamp control messages, in `u64`s so the buffer
+/// is aligned for `cmsghdr`
+pub(super) const CONTROL_BUFFER_SZ: usize = 16;
+
+/// What the kernel reported about a received message besides its contents
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecvInfo {
+    /// TTL (IPv4) or hop limit (IPv6) the message arrived with
+    pub ttl: Option<u8>,
+    /// When the kernel received the message, from `SO_TIMESTAMP`
+    pub timestamp: Option<SystemTime>,
+    /// Length of the IP header the message arrived with, including any
+    /// options, when the socket delivers it (raw IPv4 sockets only)
+    pub ip_header_len: Option<usize>,
+}
+
+/// Moves encoded ICMP messages to and from the network.
+///
+/// `IcmpSocket` does all of its I/O through this trait, so that a scripted
+/// transport can stand in for the network (see `MockTransport` behind the
+/// `testing` feature).
+pub trait PacketTransport {
+    /// Send an encoded ICMP message to `destination`, returning the number of
+    /// bytes written
+    fn send_to(&self, bytes: &[u8], destination: Ipv4Addr) -> io::Result<usize>;
+
+    /// Receive one ICMP message, without any IP header, along with the
+    /// address it came from
+    fn recv_from(&self) -> io::Result<(Vec<u8>, Ipv4Addr)>;
+
+    /// Like `recv_from`, also returning the TTL and receive time the kernel
+    /// reported, if known
+    fn recv_with_info(&self) -> io::Result<(Vec<u8>, Ipv4Addr, RecvInfo)> {
+        self.recv_from()
+            .map(|(bytes, source)| (bytes, source, RecvInfo::default()))
+    }
+
+    /// Wait up to `timeout` for a message to become available, returning
+    /// whether one has. Transports that can't wait report one as available
+    /// and leave the waiting to `recv_from`.
+    fn wait_readable(&self, _timeout: Duration) -> io::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// The kind of socket backing an `IcmpSocket`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcmpSocketKind {
+    /// Unprivileged `SOCK_DGRAM` ICMP socket, gated by `net.ipv4.ping_group_range`
+    Datagram,
+    /// `SOCK_RAW` socket, requiring root or `CAP_NET_RAW`. Received packets
+    /// carry the IP header, which is stripped before parsing.
+    Raw,
+}
+
+/// Transport over a kernel ICMP socket, which it closes when dropped.
+///
+/// The descriptor is owned by exactly one transport, so it isn't `Clone`;
+/// sharing one would need a `dup`ed descriptor of its own.
+#[derive(Debug)]
+pub struct SocketTransport {
+    pub(super) socket: i32,
+    pub(super) kind: IcmpSocketKind,
+}
+
+impl PacketTransport for SocketTransport {
+    fn send_to(&self, bytes: &[u8], destination: Ipv4Addr) -> io::Result<usize> {
+        send_to_socket(self.socket, bytes, destination)
+    }
+
+    fn recv_from(&self) -> io::Result<(Vec<u8>, Ipv4Addr)> {
+        self.recv_with_info()
+            .map(|(bytes, source, _)| (bytes, source))
+    }
+
+    fn recv_with_info(&self) -> io::Result<(Vec<u8>, Ipv4Addr, RecvInfo)> {
+        recv_from_socket(self.socket, self.kind == IcmpSocketKind::Raw)
+    }
+
+    fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        poll_readable(self.socket, timeout)
+    }
+}
+
+impl Drop for SocketTransport {
+    fn drop(&mut self) {
+        close(self.socket);
+    }
+}
+
+/// Transport over a raw socket with `IP_HDRINCL` set, prefixing every
+/// message with an IPv4 header of our own making.
+///
+/// The header is built from a template whose TTL, TOS, flags and options are
+/// sent as is; the length, destination and checksum are filled in per
+/// packet, and the identification increments with every packet.
+#[derive(Debug)]
+pub struct HeaderIncludedTransport {
+    pub(super) socket: i32,
+    pub(super) header: Mutex<Ipv4Header>,
+}
+
+impl HeaderIncludedTransport {
+    /// The header template
+    pub fn header(&self) -> Ipv4Header {
+        self.header.lock().unwrap().clone()
+    }
+
+    /// Replace the header template
+    pub fn set_header(&self, header: Ipv4Header) {
+        *self.header.lock().unwrap() = header;
+    }
+}
+
+impl Drop for HeaderIncludedTransport {
+    fn drop(&mut self) {
+        close(self.socket);
+    }
+}
+
+impl PacketTransport for HeaderIncludedTransport {
+    fn send_to(&self, bytes: &[u8], destination: Ipv4Addr) -> io::Result<usize> {
+        let mut header = {
+            let mut template = self.header.lock().unwrap();
+            template.identification = template.identification.wrapping_add(1);
+            template.clone()
+        };
+        header.destination = destination;
+        header.total_length = (header.header_len() + bytes.len()) as u16;
+
+        let mut buffer = PacketBuffer::new();
+        header
+            .write(&mut buffer)
+            .and_then(|_| buffer.write_bytes(bytes))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+
+        // Report only the ICMP bytes sent, as the other transports do
+        let sent = send_to_socket(self.socket, &buffer.buffer, destination)?;
+        Ok(sent.saturating_sub(header.header_len()))
+    }
+
+    fn recv_from(&self) -> io::Result<(Vec<u8>, Ipv4Addr)> {
+        self.recv_with_info()
+            .map(|(bytes, source, _)| (bytes, source))
+    }
+
+    fn recv_with_info(&self) -> io::Result<(Vec<u8>, Ipv4Addr, RecvInfo)> {
+        recv_from_socket(self.socket, true)
+    }
+
+    fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        poll_readable(self.socket, timeout)
+    }
+}
+
+fn send_to_socket(socket: i32, bytes: &[u8], destination: Ipv4Addr) -> io::Result<usize> {
+    let mut address: sockaddr_in = unsafe { mem::zeroed() };
+    address.sin_family = libc::AF_INET as u16;
+    address.sin_port = 0;
+    address.sin_addr.s_addr = destination.to_bits().to_be();
+
+    let result = unsafe {
+        libc::sendto(
+            socket,
+            bytes.as_ptr() as *const libc::c_void,
+            bytes.len(),
+            0,
+            &address as *const _ as *const libc::sockaddr,
+            mem::size_of::<sockaddr_in>() as libc::socklen_t,
+        )
+    };
+
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Whether a failed send is worth retrying: the socket buffer was full
+/// (EAGAIN, ENOBUFS), as happens under load, or a signal interrupted it
+pub(super) fn is_transient_send_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    ) || err.raw_os_error() == Some(libc::ENOBUFS)
+}
+
+/// Whether a failed receive is worth retrying. Only an interrupting signal
+/// is: with `SO_RCVTIMEO` set, EAGAIN means the receive timed out.
+pub(super) fn is_transient_recv_error(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Interrupted
+}
+
+/// Run `operation`, retrying it up to `retries` times with a yield in
+/// between for as long as it fails with an error `is_transient` accepts.
+/// The last error is returned once the retries run out.
+pub(super) fn retry_transient<T>(
+    retries: u32,
+    is_transient: fn(&io::Error) -> bool,
+    mut operation: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                attempt += 1;
+                std::thread::yield_now();
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Wait with `poll(2)` up to `timeout` for `socket` to become readable. A
+/// signal interrupting the wait counts as nothing arriving, so that callers
+/// get to check for e.g. Ctrl-C.
+pub(super) fn poll_readable(socket: i32, timeout: Duration) -> io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: socket,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // Round up, so a wait of under a millisecond doesn't turn into a spin
+    let timeout_ms = timeout
+        .as_micros()
+        .div_ceil(1000)
+        .min(libc::c_int::MAX as u128);
+
+    match unsafe { libc::poll(&mut fd, 1, timeout_ms as libc::c_int) } {
+        -1 => {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(err)
+            }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Receive one message, stripping the IP header raw sockets deliver. The
+/// TTL comes from the `IP_TTL` control message enabled by `IP_RECVTTL`, or
+/// failing that from the IP header if there is one, and the receive time
+/// from the `SCM_TIMESTAMP` control message enabled by `SO_TIMESTAMP`.
+fn recv_from_socket(socket: i32, has_ip_header: bool) -> io::Result<(Vec<u8>, Ipv4Addr, RecvInfo)> {
+    let mut buffer = [0u8; ICMP_RECV_BUFFER_SZ];
+    let mut control = [0u64; CONTROL_BUFFER_SZ];
+    let mut address: sockaddr_in = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut address as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<sockaddr_in>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control);
+
+    let num_bytes = unsafe { libc::recvmsg(socket, &mut msg, libc::MSG_TRUNC) };
+    let recv_sz = received_len(num_bytes, &msg, buffer.len())?;
+    let (start, header_ttl) = if has_ip_header && recv_sz >= IPV4_HEADER_LEN {
+        // Skip the IP header, whose length is given in 32-bit words
+        (
+            ((buffer[0] & 0x0F) as usize * 4).min(recv_sz),
+            Some(buffer[8]),
+        )
+    } else {
+        (0, None)
+    };
+    let ttl = control_value::<libc::c_int>(&msg, libc::IPPROTO_IP, libc::IP_TTL)
+        .and_then(|ttl| u8::try_from(ttl).ok())
+        .or(header_ttl);
+    let source = Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr));
+    let info = RecvInfo {
+        ttl,
+        timestamp: control_timestamp(&msg),
+        ip_header_len: header_ttl.map(|_| start),
+    };
+
+    Ok((buffer[start..recv_sz].to_vec(), source, info))
+}
+
+/// The length of the message `recvmsg` returned `num_bytes` for, having been
+/// passed `MSG_TRUNC` so that's the full length even when it didn't fit the
+/// `capacity` bytes of the buffer. A message cut short is reported rather
+/// than parsed, also when only `msg_flags` says so.
+pub(super) fn received_len(
+    num_bytes: isize,
+    msg: &libc::msghdr,
+    capacity: usize,
+) -> io::Result<usize> {
+    if num_bytes < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let len = num_bytes as usize;
+    if len > capacity || msg.msg_flags & libc::MSG_TRUNC != 0 {
+        // Some kernels set the flag but still return the truncated length
+        let len = (len > capacity).then_some(len);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            IcmpSocketError::Truncated { len, capacity },
+        ));
+    }
+    Ok(len)
+}
+
+/// Find the control message of `level` and `kind` in a message received with
+/// `recvmsg` and read the value it carries
+pub(super) fn control_value<T: Copy>(
+    msg: &libc::msghdr,
+    level: libc::c_int,
+    kind: libc::c_int,
+) -> Option<T> {
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == level && header.cmsg_type == kind {
+            if (header.cmsg_len as usize)
+                < unsafe { libc::CMSG_LEN(mem::size_of::<T>() as u32) } as usize
+            {
+                return None;
+            }
+            let data = unsafe { libc::CMSG_DATA(cmsg) } as *const T;
+            return Some(unsafe { data.read_unaligned() });
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(msg, cmsg) };
+    }
+    None
+}
+
+/// The kernel receive time carried by an `SCM_TIMESTAMP` control message
+pub(super) fn control_timestamp(msg: &libc::msghdr) -> Option<SystemTime> {
+    let time = control_value::<libc::timeval>(msg, libc::SOL_SOCKET, libc::SCM_TIMESTAMP)?;
+    let since_epoch = Duration::new(
+        u64::try_from(time.tv_sec).ok()?,
+        u32::try_from(time.tv_usec).ok()?.checked_mul(1000)?,
+    );
+    SystemTime::UNIX_EPOCH.checked_add(since_epoch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `msghdr` whose control buffer `control` holds a single control
+    /// message of `level` and `kind` carrying `value`
+    fn control_message<T>(
+        control: &mut [u64; CONTROL_BUFFER_SZ],
+        level: libc::c_int,
+        kind: libc::c_int,
+        value: T,
+    ) -> libc::msghdr {
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<T>() as u32) } as _;
+
+        let cmsg = unsafe { &mut *libc::CMSG_FIRSTHDR(&msg) };
+        cmsg.cmsg_level = level;
+        cmsg.cmsg_type = kind;
+        cmsg.cmsg_len = unsafe { libc::CMSG_LEN(mem::size_of::<T>() as u32) } as _;
+        let data = unsafe { libc::CMSG_DATA(cmsg) } as *mut T;
+        unsafe { data.write_unaligned(value) };
+        msg
+    }
+
+    #[test]
+    fn ttl_and_hop_limit_are_read_from_their_control_messages() {
+        let mut control = [0; CONTROL_BUFFER_SZ];
+        let msg = control_message(&mut control, libc::IPPROTO_IP, libc::IP_TTL, 54);
+        assert_eq!(
+            control_value::<libc::c_int>(&msg, libc::IPPROTO_IP, libc::IP_TTL),
+            Some(54)
+        );
+
+        let mut control = [0; CONTROL_BUFFER_SZ];
+        let msg = control_message(&mut control, libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT, 57);
+        assert_eq!(
+            control_value::<libc::c_int>(&msg, libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT),
+            Some(57)
+        );
+    }
+
+    #[test]
+    fn nothing_is_read_without_the_control_message() {
+        let msg: libc::msghdr = unsafe { mem::zeroed() };
+        assert_eq!(
+            control_value::<libc::c_int>(&msg, libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT),
+            None
+        );
+
+        let mut control = [0; CONTROL_BUFFER_SZ];
+        let msg = control_message(&mut control, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, 57);
+        assert_eq!(
+            control_value::<libc::c_int>(&msg, libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT),
+            None
+        );
+    }
+
+    #[test]
+    fn receive_time_is_read_from_the_timestamp_control_message() {
+        let time = libc::timeval {
+            tv_sec: 1_700_000_000,
+            tv_usec: 250_000,
+        };
+        let mut control = [0; CONTROL_BUFFER_SZ];
+        let msg = control_message(&mut control, libc::SOL_SOCKET, libc::SCM_TIMESTAMP, time);
+        assert_eq!(
+            control_timestamp(&msg),
+            Some(SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000))
+        );
+
+        let msg: libc::msghdr = unsafe { mem::zeroed() };
+        assert_eq!(control_timestamp(&msg), None);
+    }
+
+    /// The `len` and `capacity` of the `Truncated` error `result` holds
+    fn truncation(result: io::Result<usize>) -> (Option<usize>, usize) {
+        let err = result.unwrap_err();
+        match err.get_ref().and_then(|err| err.downcast_ref()) {
+            Some(IcmpSocketError::Truncated { len, capacity }) => (*len, *capacity),
+            _ => panic!("expected a truncation error, got {err:?}"),
+        }
+    }
+
+    #[test]
+    fn message_longer_than_the_buffer_is_truncated() {
+        let msg: libc::msghdr = unsafe { mem::zeroed() };
+        assert_eq!(
+            truncation(received_len(2000, &msg, 1500)),
+            (Some(2000), 1500)
+        );
+    }
+
+    #[test]
+    fn truncation_flag_alone_is_reported() {
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_flags = libc::MSG_TRUNC;
+        assert_eq!(truncation(received_len(1500, &msg, 1500)), (None, 1500));
+    }
+
+    #[test]
+    fn message_that_fits_is_received_whole() {
+        let msg: libc::msghdr = unsafe { mem::zeroed() };
+        assert_eq!(received_len(1500, &msg, 1500).unwrap(), 1500);
+        assert_eq!(received_len(64, &msg, 1500).unwrap(), 64);
+    }
+}
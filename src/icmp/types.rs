@@ -1,19 +1,25 @@
 use std::net::Ipv4Addr;
 
+/// Type of an ICMP message. Convert to and from the type number with
+/// `to_u8`/`from_u8` or `u8::from`/`IcmpType::from`, as `Unknown` keeps the
+/// number and rules out `as u8` casts.
 #[derive(Copy, Clone, Debug)]
 pub enum IcmpType {
-    EchoReply = 0,
-    DestinationUnreachable = 3,
-    SourceQuench = 4,
-    Redirect = 5,
-    EchoRequest = 8,
-    TimeExceeded = 11,
-    ParameterProblem = 12,
-    TimestampRequest = 13,
-    TimestampReply = 14,
-    InformationRequest = 15,
-    InformationReply = 16,
-    Unknown,
+    EchoReply,
+    DestinationUnreachable,
+    SourceQuench,
+    Redirect,
+    EchoRequest,
+    TimeExceeded,
+    ParameterProblem,
+    TimestampRequest,
+    TimestampReply,
+    InformationRequest,
+    InformationReply,
+    AddressMaskRequest,
+    AddressMaskReply,
+    /// Any other type, keeping its number so it is written back unchanged
+    Unknown(u8),
 }
 
 impl IcmpType {
@@ -30,7 +36,9 @@ impl IcmpType {
             Self::TimestampReply => 14,
             Self::InformationRequest => 15,
             Self::InformationReply => 16,
-            Self::Unknown => 17,
+            Self::AddressMaskRequest => 17,
+            Self::AddressMaskReply => 18,
+            Self::Unknown(icmp_type) => *icmp_type,
         }
     }
 
@@ -47,11 +55,87 @@ impl IcmpType {
             14 => Self::TimestampReply,
             15 => Self::InformationRequest,
             16 => Self::InformationReply,
-            _ => Self::Unknown,
+            17 => Self::AddressMaskRequest,
+            18 => Self::AddressMaskReply,
+            other => Self::Unknown(other),
         }
     }
 }
 
+impl From<IcmpType> for u8 {
+    fn from(icmp_type: IcmpType) -> Self {
+        icmp_type.to_u8()
+    }
+}
+
+impl From<u8> for IcmpType {
+    fn from(icmp_type: u8) -> Self {
+        Self::from_u8(icmp_type)
+    }
+}
+
+/// Destination unreachable code for "fragmentation needed and DF set"
+pub const FRAGMENTATION_NEEDED: u8 = 4;
+
+/// Standard description of a message of `msg_type` with code `msg_code`
+/// (RFC 792, RFC 1812), e.g. "Destination Port Unreachable" for type 3 code
+/// 3. Codes a type doesn't define are reported as bad codes.
+pub fn describe(msg_type: IcmpType, msg_code: u8) -> String {
+    let description = match (msg_type, msg_code) {
+        (IcmpType::EchoReply, 0) => "Echo Reply",
+        (IcmpType::DestinationUnreachable, code) => match code {
+            0 => "Destination Net Unreachable",
+            1 => "Destination Host Unreachable",
+            2 => "Destination Protocol Unreachable",
+            3 => "Destination Port Unreachable",
+            FRAGMENTATION_NEEDED => "Fragmentation Needed and DF Set",
+            5 => "Source Route Failed",
+            6 => "Destination Net Unknown",
+            7 => "Destination Host Unknown",
+            8 => "Source Host Isolated",
+            9 => "Destination Net Prohibited",
+            10 => "Destination Host Prohibited",
+            11 => "Destination Net Unreachable for Type of Service",
+            12 => "Destination Host Unreachable for Type of Service",
+            13 => "Packet Filtered",
+            14 => "Precedence Violation",
+            15 => "Precedence Cutoff",
+            code => return format!("Destination Unreachable, Bad Code: {code}"),
+        },
+        (IcmpType::SourceQuench, 0) => "Source Quench",
+        (IcmpType::Redirect, code) => match code {
+            0 => "Redirect Network",
+            1 => "Redirect Host",
+            2 => "Redirect Type of Service and Network",
+            3 => "Redirect Type of Service and Host",
+            code => return format!("Redirect, Bad Code: {code}"),
+        },
+        (IcmpType::EchoRequest, 0) => "Echo Request",
+        (IcmpType::TimeExceeded, code) => match code {
+            0 => "Time to live exceeded",
+            1 => "Fragment reassembly time exceeded",
+            code => return format!("Time exceeded, Bad Code: {code}"),
+        },
+        (IcmpType::ParameterProblem, code) => match code {
+            0 => "Parameter problem",
+            1 => "Parameter problem: missing required option",
+            2 => "Parameter problem: bad length",
+            code => return format!("Parameter problem, Bad Code: {code}"),
+        },
+        (IcmpType::TimestampRequest, 0) => "Timestamp Request",
+        (IcmpType::TimestampReply, 0) => "Timestamp Reply",
+        (IcmpType::InformationRequest, 0) => "Information Request",
+        (IcmpType::InformationReply, 0) => "Information Reply",
+        (IcmpType::AddressMaskRequest, 0) => "Address Mask Request",
+        (IcmpType::AddressMaskReply, 0) => "Address Mask Reply",
+        (IcmpType::Unknown(icmp_type), _) => {
+            return format!("Unknown ICMP type {icmp_type}, code {msg_code}")
+        }
+        (msg_type, code) => return format!("{msg_type:?}, Bad Code: {code}"),
+    };
+    description.to_string()
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum IcmpContentType {
     Echo {
@@ -82,6 +166,17 @@ pub enum IcmpContentType {
         id: u16,
         sequence_no: u16,
     },
+    /// Address mask request or reply (RFC 950). The mask follows the header
+    /// rather than being part of it, see `IcmpPacket::read`.
+    AddressMask {
+        id: u16,
+        sequence_no: u16,
+        mask: Ipv4Addr,
+    },
+    /// The rest of the header of a message of unknown type, kept as is
+    Unknown {
+        content: u32,
+    },
 }
 
 impl IcmpContentType {
@@ -109,17 +204,39 @@ impl IcmpContentType {
                 id: (content >> 16) as u16,
                 sequence_no: content as u16,
             },
-            _ => unimplemented!(),
+            IcmpType::AddressMaskRequest | IcmpType::AddressMaskReply => Self::AddressMask {
+                id: (content >> 16) as u16,
+                sequence_no: content as u16,
+                mask: Ipv4Addr::UNSPECIFIED,
+            },
+            IcmpType::Unknown(_) => Self::Unknown { content },
+        }
+    }
+
+    /// The next-hop MTU of a destination unreachable message (RFC 1191).
+    /// Only meaningful for code `FRAGMENTATION_NEEDED`, where the router puts
+    /// the MTU in the low 16 bits of the otherwise unused word.
+    pub fn next_hop_mtu(&self, msg_code: u8) -> Option<u16> {
+        match *self {
+            Self::DestinationUnreachable { unused } if msg_code == FRAGMENTATION_NEEDED => {
+                Some(unused as u16)
+            }
+            _ => None,
         }
     }
+
     pub fn to_u32(&self) -> u32 {
         match *self {
             Self::Echo { id, sequence_no }
             | Self::Timestamp { id, sequence_no }
-            | Self::Information { id, sequence_no } => ((id as u32) << 16) | sequence_no as u32,
+            | Self::Information { id, sequence_no }
+            | Self::AddressMask {
+                id, sequence_no, ..
+            } => ((id as u32) << 16) | sequence_no as u32,
             Self::DestinationUnreachable { unused }
             | Self::TimeExceeded { unused }
             | Self::SourceQuench { unused } => unused,
+            Self::Unknown { content } => content,
             Self::Redirect { gateway_address } => gateway_address.to_bits(),
             Self::ParameterProblem { pointer, unused } => {
                 ((pointer as u32) << 24) | (unused & 0xFFFFFF)
@@ -127,3 +244,50 @@ impl IcmpContentType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_type_number_round_trips() {
+        for icmp_type in 0..=u8::MAX {
+            assert_eq!(IcmpType::from_u8(icmp_type).to_u8(), icmp_type);
+            assert_eq!(u8::from(IcmpType::from(icmp_type)), icmp_type);
+        }
+        assert!(matches!(IcmpType::from_u8(42), IcmpType::Unknown(42)));
+    }
+
+    #[test]
+    fn error_codes_are_described() {
+        assert_eq!(
+            describe(IcmpType::DestinationUnreachable, 1),
+            "Destination Host Unreachable"
+        );
+        assert_eq!(
+            describe(IcmpType::DestinationUnreachable, 3),
+            "Destination Port Unreachable"
+        );
+        assert_eq!(
+            describe(IcmpType::DestinationUnreachable, FRAGMENTATION_NEEDED),
+            "Fragmentation Needed and DF Set"
+        );
+        assert_eq!(describe(IcmpType::TimeExceeded, 0), "Time to live exceeded");
+        assert_eq!(
+            describe(IcmpType::TimeExceeded, 1),
+            "Fragment reassembly time exceeded"
+        );
+    }
+
+    #[test]
+    fn undefined_codes_are_reported_as_bad() {
+        assert_eq!(
+            describe(IcmpType::DestinationUnreachable, 42),
+            "Destination Unreachable, Bad Code: 42"
+        );
+        assert_eq!(
+            describe(IcmpType::TimeExceeded, 2),
+            "Time exceeded, Bad Code: 2"
+        );
+    }
+}
@@ -1,3 +1,4 @@
 pub mod dns;
 pub mod icmp;
+pub mod ping;
 pub mod resolve;
@@ -1,55 +1,576 @@
-use clap::Parser;
-use pingoc::resolve::resolve_hostname;
+mod cli;
+
+use clap::{Args, Parser, Subcommand};
+use cli::axfr::{axfr_handler, AxfrArgs};
+use cli::capabilities::capabilities_handler;
+use cli::dig::{dig_handler, DigArgs};
+use cli::duration::parse_duration;
+use cli::keys::{KeyCommand, KeyCommands};
+use cli::resolve::{resolve_handler, ResolveArgs};
+use cli::sweep::{sweep_handler, SweepArgs};
+use pingoc::resolve::{
+    host_from_url, resolve_hostname_searched, split_zone, AddressFamily, AddressSelection,
+    ResolutionSource, Resolver, SearchList, DEFAULT_NDOTS,
+};
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::error::Error;
+use std::fmt;
+use std::io::{self, IsTerminal};
 use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
-use pingoc::icmp::packet::IcmpPacket;
-use pingoc::icmp::socket::IcmpSocket;
-use pingoc::icmp::types::IcmpContentType;
+use pingoc::icmp::buffer::{hexdump, PacketBuffer};
+use pingoc::icmp::endpoint::IcmpEndpoint;
+use pingoc::icmp::ip::IPV4_HEADER_LEN;
+use pingoc::icmp::packet::{IcmpPacket, ICMP_HEADER_LEN};
+use pingoc::icmp::socket::{HeaderIncludedTransport, IcmpSocket};
+use pingoc::icmp::socket6::interface_index;
+use pingoc::icmp::types::{IcmpContentType, IcmpType};
+use pingoc::ping::compare::comparison_table;
+use pingoc::ping::correlate::{MatchStrategy, PayloadMismatch, SourceCheck};
+use pingoc::ping::history::{self, History, HistoryEntry};
+use pingoc::ping::identifiers::IdentifierAllocator;
+#[cfg(feature = "metrics")]
+use pingoc::ping::metrics::{Metrics, MetricsServer};
+use pingoc::ping::output::{line_prefix, paint, ColorChoice, CsvLog, LineStyle, TimestampFormat};
+use pingoc::ping::pacer::Pacer;
+use pingoc::ping::pinger::{Pinger, ProbeEvent, ProbeReply, ProbeSocket};
+use pingoc::ping::snapshot::SnapshotTimer;
+use pingoc::ping::stats::{PingSummary, ResponderTally};
+use pingoc::ping::tag::{self, TagMismatch};
+use pingoc::ping::timestamp;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
-struct PingStats {
-    packets_sent: usize,
-    packets_recv: usize,
-    bytes_sent: f32,
-    bytes_recv: f32,
+/// Ping hosts over ICMP and inspect their DNS records
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    ping: PingArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Query DNS records of a name
+    Dig(DigArgs),
+    /// Transfer a whole DNS zone from a server over TCP
+    Axfr(AxfrArgs),
+    /// Find the live hosts of a network by pinging every address in it
+    Sweep(SweepArgs),
+    /// Resolve a host the way ping does, with --trace showing every step
+    Resolve(ResolveArgs),
+    /// Report what this build supports and what the environment allows
+    Capabilities,
 }
 
 /// Command-line arguments for pingoc
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[derive(Args, Debug)]
 struct PingArgs {
-    /// Ping destination (hostname or IP)
-    destination: String,
+    /// Ping destinations (hostnames, IPs or URLs), pinged together over one socket
+    #[arg(required = true)]
+    destinations: Vec<String>,
 
     /// Number of ping requests to send
     #[arg(short, long)]
-    count: Option<usize>,
+    count: Option<u64>,
 
-    /// Suppress output, only show summary
-    #[arg(short, long)]
+    /// Suppress per-reply lines, still showing the banner and summary
+    #[arg(short, long, conflicts_with = "verbose")]
     quiet: bool,
 
-    /// Set the timeout for each ping request in seconds
-    #[arg(short, long, default_value_t = 1)]
-    timeout: usize,
+    /// Suppress everything but the summary, including the banner and errors
+    #[arg(long, conflicts_with = "verbose")]
+    silent: bool,
+
+    /// How long to wait for each reply before counting it as lost, e.g. `250ms`
+    /// (seconds if no unit). This is per probe, see --deadline for the whole run.
+    #[arg(short, long, default_value = "1", value_parser = parse_duration)]
+    timeout: Duration,
+
+    /// Stop after this much time in total, regardless of how many were sent
+    #[arg(short = 'w', long, value_parser = parse_duration)]
+    deadline: Option<Duration>,
 
     /// Ping with a specific packet size (in bytes)
     #[arg(short, long, default_value_t = 56)]
     packet_size: usize,
 
-    /// Set the interval between pings in seconds
-    #[arg(short, long, default_value_t = 0.5)]
-    interval: f64,
+    /// ASCII tag to put in every payload after the timestamp, cut short to
+    /// fit --packet-size, so captures can be filtered by it. Replies are
+    /// checked to echo it back.
+    #[arg(long, value_parser = parse_tag)]
+    tag: Option<String>,
+
+    /// Set the interval between pings, e.g. `500ms`, `2s` or `1m` (seconds if no unit)
+    #[arg(short, long, default_value = "0.5", value_parser = parse_duration)]
+    interval: Duration,
+
+    /// Allow an --interval shorter than 10ms without capping the rate with
+    /// --pps, at the risk of flooding the network
+    #[arg(long)]
+    allow_fast_interval: bool,
+
+    /// Send each request as soon as the previous reply arrives or times out,
+    /// instead of waiting a fixed --interval
+    #[arg(short = 'A', long, conflicts_with = "interval")]
+    adaptive: bool,
+
+    /// Print the statistics so far every this often while pinging, e.g. `1m`,
+    /// without resetting them
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        conflicts_with = "json_summary_only"
+    )]
+    stats_interval: Option<Duration>,
+
+    /// Only resolve and ping IPv4 addresses
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Only resolve and ping IPv6 addresses
+    #[arg(short = '6', long, conflicts_with_all = ["broadcast", "raw", "compare"])]
+    ipv6: bool,
 
     /// Enable verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    /// Resend a timed out request up to this many times before counting it as lost
+    #[arg(short, long, default_value_t = 0)]
+    retries: usize,
+
+    /// Only print the final summary, as a single JSON object
+    #[arg(long)]
+    json_summary_only: bool,
+
+    /// Prefix each reply and timeout line with the current time (`-D`, `-D=iso`)
+    #[arg(
+        short = 'D',
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "unix"
+    )]
+    timestamp_lines: Option<TimestampFormat>,
+
+    /// Cap the rate of echo requests across all destinations, in packets per second
+    #[arg(long, value_name = "PPS", value_parser = clap::value_parser!(u32).range(1..))]
+    pps: Option<u32>,
+
+    /// Allow pinging a broadcast address and list every host that answers.
+    /// Many hosts ignore broadcast pings, so not every live host will show up.
+    #[arg(short, long)]
+    broadcast: bool,
+
+    /// Send over a raw socket with IP_HDRINCL, building the IPv4 header
+    /// ourselves. Requires root or CAP_NET_RAW.
+    #[arg(long, conflicts_with = "broadcast")]
+    raw: bool,
+
+    /// Append one row per probe to this CSV file, alongside the usual output
+    #[arg(long, value_name = "PATH")]
+    csv: Option<PathBuf>,
+
+    /// Append a summary of every run to this file, one JSON object per host
+    /// and line, to follow a link's health across runs
+    #[arg(long, value_name = "PATH")]
+    history: Option<PathBuf>,
+
+    /// Instead of pinging, print the loss and round trip times of the last
+    /// N runs (7 by default) of each destination recorded in --history
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "7",
+        requires = "history"
+    )]
+    history_summary: Option<usize>,
+
+    /// Serve live metrics of every destination in the Prometheus format at
+    /// http://ADDR/metrics, e.g. 127.0.0.1:9101
+    #[cfg(feature = "metrics")]
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Ping this host alongside the destination and finish with a side by
+    /// side comparison of their loss and round trip times
+    #[arg(long, value_name = "HOST", conflicts_with_all = ["broadcast", "raw"])]
+    compare: Option<String>,
+
+    /// Send every request by way of this IPv4 gateway, out of the interface
+    /// facing it and with a loose source route naming it as the first hop.
+    /// Needs root or CAP_NET_RAW, and hosts and routers that drop source
+    /// routed packets won't answer.
+    #[arg(long, value_name = "IP", conflicts_with_all = ["broadcast", "raw", "ipv6", "compare"])]
+    gateway: Option<Ipv4Addr>,
+
+    /// Stop early, still printing statistics, once N requests in a row were
+    /// answered with destination unreachable
+    #[arg(long, value_name = "N", conflicts_with_all = ["broadcast", "compare"])]
+    stop_on_unreachable: Option<u32>,
+
+    /// Re-resolve the destination over DNS before every request and rotate
+    /// through its addresses: round-robin (the default), random or first.
+    /// Useful to reach each backend of a load balanced name in turn.
+    #[arg(
+        long,
+        value_name = "STRATEGY",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "round-robin",
+        conflicts_with_all = ["broadcast", "compare"]
+    )]
+    rotate: Option<AddressSelection>,
+
+    /// Domains to qualify a short destination with, tried in order before
+    /// the name as given, like the search list of resolv.conf. Repeat or
+    /// separate with commas.
+    #[arg(long, value_name = "DOMAIN", value_delimiter = ',')]
+    search: Vec<String>,
+
+    /// Number of dots a destination needs to be tried as given before the
+    /// --search domains
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_NDOTS, requires = "search")]
+    ndots: usize,
+
+    /// What to do with replies from another address than the destination,
+    /// as with anycast or NAT: count them separately but accept them
+    /// (lenient) or reject them as not answering the request (strict)
+    #[arg(long, value_name = "MODE", default_value_t = SourceCheck::default())]
+    source_check: SourceCheck,
+
+    /// Color reply, timeout and error lines: auto, always or never
+    #[arg(long, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Print `seq N missing` when a reply arrives while earlier sequences are
+    /// still unanswered
+    #[arg(long)]
+    show_gaps: bool,
+
+    /// Accept single-key commands while pinging from a terminal: q quits,
+    /// r resets the statistics and p pauses or resumes sending
+    #[arg(long)]
+    interactive: bool,
+
+    /// Ask the destination for its subnet mask with address mask requests
+    /// (RFC 950) instead of pinging it. Few hosts still answer these. Needs
+    /// root or CAP_NET_RAW.
+    #[arg(long, conflicts_with_all = ["ipv6", "broadcast", "compare", "gateway", "dry_run"])]
+    mask: bool,
+
+    /// Resolve the destinations and build the echo requests, printing each
+    /// as a hex dump instead of sending it. Needs no privileges or network
+    /// beyond name resolution. Datagram sockets would have the kernel
+    /// rewrite the identifier. Payloads of 8 bytes or more start with the
+    /// send timestamp, so their bytes differ from run to run.
+    #[arg(long, conflicts_with_all = ["ipv6", "raw", "gateway", "interactive"])]
+    dry_run: bool,
+
+    /// Advanced: ICMP code to put in echo requests (RFC 792 requires 0).
+    /// Linux unprivileged ICMP sockets refuse nonzero codes with EINVAL.
+    #[arg(long, hide = true, default_value_t = 0)]
+    icmp_code: u8,
+}
+
+/// Shortest --interval allowed without --pps or --allow-fast-interval
+const MIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often keys are checked for while --interactive pinging is paused
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl PingArgs {
+    /// Refuse an --interval that would send faster than `MIN_INTERVAL` allows,
+    /// unless the rate is capped by --pps or --allow-fast-interval is set
+    fn check_send_rate(&self) -> Result<()> {
+        let max_pps = Duration::from_secs(1).as_nanos() / MIN_INTERVAL.as_nanos();
+        let capped = self.pps.is_some_and(|pps| u128::from(pps) <= max_pps);
+        if self.adaptive || self.allow_fast_interval || capped || self.interval >= MIN_INTERVAL {
+            return Ok(());
+        }
+
+        Err(format!(
+            "--interval {:?} would send more than {max_pps} packets/s; use an interval of at \
+             least {MIN_INTERVAL:?}, cap the rate with --pps or pass --allow-fast-interval",
+            self.interval
+        )
+        .into())
+    }
+
+    /// Whether per-reply lines should be printed
+    fn print_replies(&self) -> bool {
+        !self.quiet && !self.silent && !self.json_summary_only
+    }
+
+    /// Whether the opening `Pingoc: ...` line should be printed
+    fn print_banner(&self) -> bool {
+        !self.silent && !self.json_summary_only
+    }
+
+    /// Whether timeout, error and status lines should be printed
+    fn print_errors(&self) -> bool {
+        !self.silent
+    }
+
+    /// The opening `Pingoc: ...` line for `destination`, resolved to `ip`
+    /// as `resolution` says, giving the payload of the requests and their
+    /// size on the wire behind `header_len` bytes of IP and ICMP headers. A
+    /// canonical name other than the destination is shown, so CNAME
+    /// indirection isn't hidden. Verbose mode adds the resolution time and
+    /// source, telling slow DNS apart from a slow network.
+    fn banner(
+        &self,
+        destination: &str,
+        ip: IpAddr,
+        header_len: usize,
+        resolution: Resolution,
+    ) -> String {
+        let canonical = resolution
+            .canonical_name
+            .map(|name| format!(" (canonical {name})"))
+            .unwrap_or_default();
+        let payload_len = self.echo_request(0, 0).payload.len();
+        let mut banner = format!(
+            "Pingoc: {}{} ({}) with {}({}) bytes of data.",
+            destination,
+            canonical,
+            ip,
+            payload_len,
+            payload_len + header_len
+        );
+        if self.verbose {
+            banner.push_str(&format!(
+                " (resolved in {:.3} ms via {})",
+                resolution.elapsed.as_secs_f64() * 1000.0,
+                resolution.source
+            ));
+        }
+        banner
+    }
+
+    /// The domains short destinations are qualified with
+    fn search_list(&self) -> SearchList {
+        SearchList::new(self.search.clone()).with_ndots(self.ndots)
+    }
+
+    /// The address family destinations are resolved in. --broadcast, --raw
+    /// and --gateway only work over IPv4, so they imply -4.
+    fn family(&self) -> AddressFamily {
+        if self.ipv4 || self.broadcast || self.raw || self.gateway.is_some() {
+            AddressFamily::V4
+        } else if self.ipv6 {
+            AddressFamily::V6
+        } else {
+            AddressFamily::Any
+        }
+    }
+
+    /// Whether the --deadline of a run that began at `started_at` has passed
+    fn deadline_passed(&self, started_at: Instant) -> bool {
+        self.deadline
+            .is_some_and(|deadline| started_at.elapsed() >= deadline)
+    }
+
+    /// `line` as written to a stream, colored as --color says given whether
+    /// the stream `is_terminal`
+    fn render_line(&self, style: LineStyle, line: &str, is_terminal: bool) -> String {
+        paint(line, style, self.color.enabled(is_terminal))
+    }
+
+    /// Print a reply line to stdout, colored unless disabled
+    fn print_line(&self, style: LineStyle, line: String) {
+        println!(
+            "{}",
+            self.render_line(style, &line, io::stdout().is_terminal())
+        );
+    }
+
+    /// Print a timeout or error line to stderr, colored unless disabled
+    fn eprint_line(&self, style: LineStyle, line: String) {
+        if !self.print_errors() {
+            return;
+        }
+        eprintln!(
+            "{}",
+            self.render_line(style, &line, io::stderr().is_terminal())
+        );
+    }
+
+    /// Wait before the next round of requests, calling `snapshot` for every
+    /// --stats-interval snapshot that falls due meanwhile. In --adaptive mode
+    /// the wait for replies paces the run, so there is no extra wait.
+    fn wait_interval(&self, snapshots: &mut Option<SnapshotTimer>, mut snapshot: impl FnMut()) {
+        let wait = if self.adaptive {
+            Duration::ZERO
+        } else {
+            self.interval
+        };
+        let until = Instant::now() + wait;
+
+        loop {
+            let now = Instant::now();
+            if snapshots.as_mut().is_some_and(|timer| timer.poll(now)) {
+                snapshot();
+            }
+            if now >= until {
+                return;
+            }
+            let wake = snapshots
+                .as_ref()
+                .map_or(until, |timer| timer.next_due().min(until));
+            thread::sleep(wake.saturating_duration_since(now));
+        }
+    }
+
+    /// Act on the keys pressed since the last call: quitting sets `interrupt`
+    /// and resetting calls `reset`. Returns whether to hold off sending,
+    /// because pinging is paused or about to stop.
+    fn apply_keys(
+        &self,
+        keys: Option<&KeyCommands>,
+        interrupt: &AtomicBool,
+        paused: &mut bool,
+        mut reset: impl FnMut(),
+    ) -> bool {
+        let Some(keys) = keys else {
+            return false;
+        };
+
+        for command in keys.pending() {
+            let status = match command {
+                KeyCommand::Quit => {
+                    interrupt.store(true, Ordering::SeqCst);
+                    continue;
+                }
+                KeyCommand::ResetStats => {
+                    reset();
+                    "statistics reset"
+                }
+                KeyCommand::TogglePause => {
+                    *paused = !*paused;
+                    if *paused {
+                        "paused, press p to resume"
+                    } else {
+                        "resumed"
+                    }
+                }
+            };
+            if self.print_errors() {
+                eprintln!("{}{status}", self.prefix());
+            }
+        }
+
+        *paused || interrupt.load(Ordering::SeqCst)
+    }
+
+    /// Print the rate requests were actually sent at, for --adaptive runs
+    fn print_packet_rate(&self, packets_sent: u64, started_at: Instant) {
+        if !self.adaptive || self.json_summary_only {
+            return;
+        }
+        let elapsed = started_at.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            println!(
+                "adaptive: {:.1} packets/s effective rate",
+                packets_sent as f64 / elapsed
+            );
+        }
+    }
+
+    /// Print the sequences `missing` from `host`, if --show-gaps is set
+    fn print_gaps(&self, host: impl fmt::Display, missing: &[u16]) {
+        if !self.show_gaps || !self.print_replies() {
+            return;
+        }
+        for sequence_no in missing {
+            self.eprint_line(
+                LineStyle::Timeout,
+                format!("{}seq {sequence_no} missing from {host}", self.prefix()),
+            );
+        }
+    }
+
+    /// Have `pinger` account for its probe events in the metrics served on
+    /// --metrics-addr, if given, from a background thread
+    fn serve_metrics<S: ProbeSocket>(&self, pinger: Pinger<S>) -> Result<Pinger<S>> {
+        #[cfg(feature = "metrics")]
+        if let Some(address) = self.metrics_addr {
+            let metrics = Arc::new(Metrics::new());
+            MetricsServer::bind(address, Arc::clone(&metrics))?.spawn();
+            return Ok(pinger.on_event(move |event| metrics.record(&event)));
+        }
+        Ok(pinger)
+    }
+
+    /// Append the summaries of this run to the --history file, if any. Hosts
+    /// that failed to resolve weren't pinged and are left out.
+    fn record_history(&self, summaries: &[PingSummary]) -> Result<()> {
+        let Some(path) = &self.history else {
+            return Ok(());
+        };
+        let finished_at = SystemTime::now();
+        let entries: Vec<HistoryEntry> = summaries
+            .iter()
+            .filter(|summary| summary.resolve_error.is_none())
+            .map(|summary| HistoryEntry::new(summary, finished_at))
+            .collect();
+        history::append(path, &entries)?;
+        Ok(())
+    }
+
+    /// An echo request with the --packet-size, --icmp-code and --tag asked
+    /// for, not yet timestamped
+    fn echo_request(&self, id: u16, sequence_no: u16) -> IcmpPacket {
+        let mut packet =
+            IcmpPacket::echo_request_with_code(id, sequence_no, self.packet_size, self.icmp_code);
+        if let Some(tag) = &self.tag {
+            tag::write(&mut packet, tag.as_bytes());
+        }
+        packet
+    }
+
+    /// `echo_request`, timestamped when its payload has room, along with
+    /// whether it was, as the `Pinger` sending them builds them
+    fn stamped_echo_request(&self, id: u16, sequence_no: u16) -> (IcmpPacket, bool) {
+        let mut packet = self.echo_request(id, sequence_no);
+        let stamped = timestamp::stamp(&mut packet);
+        (packet, stamped)
+    }
+
+    /// The bytes of the request --dry-run prints for `sequence_no`, as they
+    /// would go on the wire after the IP header
+    fn dry_run_bytes(&self, id: u16, sequence_no: u16) -> Result<Vec<u8>> {
+        let (mut packet, _) = self.stamped_echo_request(id, sequence_no);
+        let mut buffer = PacketBuffer::new();
+        packet.write(&mut buffer)?;
+        Ok(buffer.buffer)
+    }
+
+    /// Prefix for reply and timeout lines
+    fn prefix(&self) -> String {
+        line_prefix(self.timestamp_lines)
+    }
 }
 
 /// Configure keyboard interrupt handling
@@ -65,102 +586,1547 @@ fn setup_interrupt_handler() -> Arc<AtomicBool> {
     interrupt
 }
 
-fn send_ping(
-    socket: &mut IcmpSocket,
-    ip: Ipv4Addr,
-    id: u16,
-    packet_size: usize,
-    quiet: bool,
-) -> Result<Option<usize>> {
-    let mut packet = IcmpPacket::echo_request(id, 0, packet_size);
-    socket.send(&mut packet)?;
-
-    match socket.recv() {
-        Ok(received_packet) => {
-            let num_bytes = received_packet.payload.len();
-            let icmp_seq = match received_packet.content {
-                IcmpContentType::Echo { id: _, sequence_no } => sequence_no,
-                _ => 1,
-            };
-            let ttl = socket.get_ttl()?;
+/// Prints the reply, timeout and error lines of a run as the probe events
+/// of its `Pinger` come in
+struct ProbePrinter {
+    args: Rc<PingArgs>,
+    /// Bytes of IP and ICMP headers the requests go out behind
+    header_len: usize,
+    /// How reply lines name the TTL, `hlim` for the hop limit of IPv6
+    ttl_label: &'static str,
+    /// TTL the requests go out with, shown for replies whose own the
+    /// socket doesn't report
+    outgoing_ttl: Option<u32>,
+    /// Whether every request given up on gets a line, not only when verbose
+    show_timeouts: bool,
+    /// Hosts a reply from another address was already noted for
+    foreign_noted: HashSet<IpAddr>,
+}
 
-            if !quiet {
-                println!("{num_bytes} bytes from {ip}: icmp_seq={icmp_seq} ttl={ttl}");
+impl ProbePrinter {
+    fn print(&mut self, event: &ProbeEvent) {
+        let args = Rc::clone(&self.args);
+        let prefix = args.prefix();
+        match event {
+            ProbeEvent::Reply(reply) => {
+                if reply.source != reply.host && !args.broadcast {
+                    self.note_foreign_reply(reply.host, reply.source);
+                }
+                self.print_reply(reply);
             }
+            // Every host answering a broadcast gets its line
+            ProbeEvent::Duplicate(reply) if args.broadcast => self.print_reply(reply),
+            ProbeEvent::Rejected { host, source, .. } => self.note_foreign_reply(*host, *source),
+            ProbeEvent::Resent {
+                host,
+                sequence_no,
+                attempt,
+            } if args.verbose => args.eprint_line(
+                LineStyle::Timeout,
+                format!(
+                    "{prefix}Retrying icmp_seq={sequence_no} to {host} ({}/{})",
+                    attempt - 1,
+                    args.retries
+                ),
+            ),
+            ProbeEvent::Missing { host, sequence_nos } => args.print_gaps(host, sequence_nos),
+            ProbeEvent::Timeout { host, sequence_no } if self.show_timeouts => args.eprint_line(
+                LineStyle::Timeout,
+                format!("{prefix}No reply from {host} for icmp_seq={sequence_no}"),
+            ),
+            ProbeEvent::IcmpError {
+                sequence_no,
+                source,
+                description,
+                ..
+            } => args.eprint_line(
+                LineStyle::Error,
+                format!("{prefix}From {source} icmp_seq={sequence_no}: {description}"),
+            ),
+            ProbeEvent::NotSent { host, message, .. } => args.eprint_line(
+                LineStyle::Error,
+                format!("{prefix}Error sending packet to {host}: {message}"),
+            ),
+            ProbeEvent::Error { message } => args.eprint_line(
+                LineStyle::Error,
+                format!("{prefix}Error receiving packet: {message}"),
+            ),
+            _ => {}
+        }
+    }
 
-            Ok(Some(num_bytes))
+    fn print_reply(&self, reply: &ProbeReply) {
+        if !self.args.print_replies() {
+            return;
         }
-        Err(e) => {
-            eprintln!("Error receiving packet: {e}");
-            Ok(None)
+        let ttl = reply
+            .ttl
+            .map(u32::from)
+            .or(self.outgoing_ttl)
+            .map_or("?".to_string(), |ttl| ttl.to_string());
+        self.args.print_line(
+            LineStyle::Reply,
+            format!(
+                "{}{} bytes from {}: icmp_seq={} {}={ttl} time={:.3} ms{}{}{}",
+                self.args.prefix(),
+                reply.num_bytes,
+                reply.source,
+                reply.sequence_no,
+                self.ttl_label,
+                reply.rtt.as_secs_f64() * 1000.0,
+                ip_header_note(reply.ip_header_len, self.header_len, reply.num_bytes),
+                corruption_note(reply.corruption),
+                tag_note(reply.tag_mismatch.as_ref())
+            ),
+        );
+    }
+
+    /// Note the first reply from `source` rather than the `host` pinged
+    fn note_foreign_reply(&mut self, host: IpAddr, source: IpAddr) {
+        if !self.args.print_replies() || !self.foreign_noted.insert(host) {
+            return;
         }
+        let handling = match self.args.source_check {
+            SourceCheck::Lenient => "counting such replies separately",
+            SourceCheck::Strict => "ignoring such replies",
+        };
+        self.args.eprint_line(
+            LineStyle::Timeout,
+            format!(
+                "{}Reply from {source} (expected {host}), {handling}",
+                self.args.prefix()
+            ),
+        );
     }
 }
 
-fn ping_handler(args: PingArgs) -> Result<()> {
-    let ip = match resolve_hostname(&args.destination) {
-        Some(IpAddr::V4(v4)) => v4,
-        _ => return Err("Failed to resolve hostname".into()),
+/// A hook appending one row to `csv` per request answered or given up on,
+/// keeping the first error writing it in `error`. Replies to a broadcast
+/// are logged under the host that sent them.
+fn csv_hook(
+    csv: CsvLog,
+    broadcast: bool,
+    outgoing_ttl: Option<u32>,
+    error: Rc<RefCell<Option<io::Error>>>,
+) -> impl FnMut(ProbeEvent) {
+    move |event| {
+        let reply_row = |reply: ProbeReply| {
+            let target = if broadcast { reply.source } else { reply.host };
+            let ttl = reply.ttl.map(u32::from).or(outgoing_ttl);
+            (
+                target,
+                reply.sequence_no,
+                Some(reply.rtt),
+                ttl,
+                LineStyle::Reply,
+            )
+        };
+        let (target, sequence_no, rtt, ttl, status) = match event {
+            ProbeEvent::Reply(reply) => reply_row(reply),
+            ProbeEvent::Duplicate(reply) if broadcast => reply_row(reply),
+            ProbeEvent::Timeout { host, sequence_no } => {
+                (host, sequence_no, None, None, LineStyle::Timeout)
+            }
+            ProbeEvent::IcmpError {
+                host, sequence_no, ..
+            }
+            | ProbeEvent::NotSent {
+                host, sequence_no, ..
+            } => (host, sequence_no, None, None, LineStyle::Error),
+            _ => return,
+        };
+        if error.borrow().is_some() {
+            return;
+        }
+        if let Err(err) = csv.record(target, sequence_no, rtt, ttl, status) {
+            *error.borrow_mut() = Some(err);
+        }
+    }
+}
+
+/// A run pinging one or more destinations through a `Pinger`, whose hooks
+/// print its lines, log it to --csv and serve its --metrics-addr
+struct PingRun {
+    args: Rc<PingArgs>,
+    pinger: Pinger<IcmpEndpoint>,
+    /// Each destination pinged, as named on the command line
+    destinations: Vec<String>,
+    /// Hosts that answered a --broadcast
+    responders: Rc<RefCell<ResponderTally>>,
+    /// The first error writing to --csv, which ends the run
+    csv_error: Rc<RefCell<Option<io::Error>>>,
+}
+
+impl PingRun {
+    /// Ping `hosts`, each named as on the command line and its address, over
+    /// `socket`
+    fn new(args: &Rc<PingArgs>, socket: IcmpEndpoint, hosts: &[(&str, IpAddr)]) -> Result<Self> {
+        if args.verbose && !timestamp::fits(args.packet_size) {
+            args.eprint_line(
+                LineStyle::Error,
+                format!(
+                    "Payload of {} bytes is too small for the {} byte timestamp, timing replies externally",
+                    args.packet_size,
+                    timestamp::TIMESTAMP_LEN
+                ),
+            );
+        }
+        let outgoing_ttl = match socket {
+            IcmpEndpoint::V6(_) => None,
+            _ => Some(socket.get_ttl()?),
+        };
+        let mut printer = ProbePrinter {
+            args: Rc::clone(args),
+            header_len: socket.header_len(),
+            ttl_label: match socket {
+                IcmpEndpoint::V6(_) => "hlim",
+                _ => "ttl",
+            },
+            outgoing_ttl,
+            show_timeouts: args.verbose || hosts.len() == 1,
+            foreign_noted: HashSet::new(),
+        };
+
+        let strategy = MatchStrategy::for_socket(socket.kind());
+        let addresses: Vec<IpAddr> = hosts.iter().map(|(_, ip)| *ip).collect();
+        let mut pinger = Pinger::with_socket(socket, &addresses)?
+            .with_match_strategy(strategy)
+            .with_source_check(args.source_check)
+            .with_packet_size(args.packet_size)
+            .with_icmp_code(args.icmp_code)
+            .on_event(move |event| printer.print(&event));
+        if let Some(tag) = &args.tag {
+            pinger = pinger.with_tag(tag.as_bytes());
+        }
+        if let Some(pps) = args.pps {
+            pinger = pinger.with_pacer(Pacer::new(pps));
+        }
+        pinger = pinger.with_retries(args.retries);
+        if let Some(limit) = args.stop_on_unreachable {
+            pinger = pinger.with_stop_on_unreachable(limit);
+        }
+
+        let responders = Rc::new(RefCell::new(ResponderTally::new()));
+        if args.broadcast {
+            let tally = Rc::clone(&responders);
+            pinger = pinger.with_broadcast().on_event(move |event| {
+                if let ProbeEvent::Reply(reply) | ProbeEvent::Duplicate(reply) = event {
+                    tally.borrow_mut().record(reply.source);
+                }
+            });
+        }
+        let csv_error = Rc::new(RefCell::new(None));
+        if let Some(path) = &args.csv {
+            let csv = CsvLog::open(path)?;
+            pinger = pinger.on_event(csv_hook(
+                csv,
+                args.broadcast,
+                outgoing_ttl,
+                Rc::clone(&csv_error),
+            ));
+        }
+        pinger = args.serve_metrics(pinger)?;
+
+        Ok(Self {
+            args: Rc::clone(args),
+            pinger,
+            destinations: hosts.iter().map(|(name, _)| name.to_string()).collect(),
+            responders,
+            csv_error,
+        })
+    }
+
+    /// Ping until --count, --deadline, an interrupt or --stop-on-unreachable
+    /// ends the run, calling `between_rounds` once the replies to each round
+    /// are in. Returns when the run started.
+    fn run(
+        &mut self,
+        interrupt: &AtomicBool,
+        mut between_rounds: impl FnMut(&mut Pinger<IcmpEndpoint>) -> Result<()>,
+    ) -> Result<Instant> {
+        let args = Rc::clone(&self.args);
+        let started_at = Instant::now();
+        let mut snapshots = args
+            .stats_interval
+            .map(|interval| SnapshotTimer::new(interval, started_at));
+        let mut remaining_count = args.count;
+        let keys = args.interactive.then(KeyCommands::start).flatten();
+        let mut paused = false;
+
+        while !interrupt.load(Ordering::SeqCst)
+            && remaining_count.is_none_or(|cnt| cnt > 0)
+            && !args.deadline_passed(started_at)
+        {
+            if args.apply_keys(keys.as_ref(), interrupt, &mut paused, || {
+                self.pinger.reset_stats();
+                *self.responders.borrow_mut() = ResponderTally::new();
+            }) {
+                thread::sleep(PAUSED_POLL_INTERVAL);
+                continue;
+            }
+
+            self.pinger.send_round()?;
+            loop {
+                self.collect_round(interrupt, &mut snapshots);
+                // Requests with retries left go out again under the same sequence
+                if self.pinger.expire()? == 0 || interrupt.load(Ordering::SeqCst) {
+                    break;
+                }
+            }
+            if let Some(err) = self.csv_error.borrow_mut().take() {
+                return Err(err.into());
+            }
+            if let (Some(host), Some(limit)) = (self.pinger.unreachable(), args.stop_on_unreachable)
+            {
+                args.eprint_line(
+                    LineStyle::Error,
+                    format!(
+                        "{}Stopping: {} was unreachable {limit} times in a row",
+                        args.prefix(),
+                        self.destinations[host]
+                    ),
+                );
+                break;
+            }
+            between_rounds(&mut self.pinger)?;
+
+            remaining_count = remaining_count.map(|cnt| cnt - 1);
+            args.wait_interval(&mut snapshots, || self.print_stats());
+        }
+
+        Ok(started_at)
+    }
+
+    /// Collect the replies to the requests in flight for up to --timeout,
+    /// printing every --stats-interval snapshot falling due meanwhile
+    fn collect_round(&mut self, interrupt: &AtomicBool, snapshots: &mut Option<SnapshotTimer>) {
+        let deadline = Instant::now() + self.args.timeout;
+        // Any number of hosts may answer a broadcast, so listen throughout
+        while (self.args.broadcast || self.pinger.outstanding() > 0)
+            && !interrupt.load(Ordering::SeqCst)
+        {
+            let now = Instant::now();
+            if snapshots.as_mut().is_some_and(|timer| timer.poll(now)) {
+                self.print_stats();
+            }
+
+            // Wait no longer than the deadline or the next snapshot, rather
+            // than a whole read timeout
+            let remaining = deadline.saturating_duration_since(now);
+            if remaining.is_zero() {
+                break;
+            }
+            let wait = snapshots
+                .as_ref()
+                .map_or(remaining, |timer| remaining.min(timer.until_due(now)));
+            self.pinger.collect(wait);
+            // Collecting only stops short once nothing is outstanding or
+            // receiving failed
+            if now.elapsed() < wait {
+                break;
+            }
+        }
+    }
+
+    /// Statistics so far of every destination, in the order given
+    fn summaries(&self) -> Vec<PingSummary> {
+        self.destinations
+            .iter()
+            .enumerate()
+            .map(|(host, destination)| self.pinger.stats(host).summary(destination))
+            .collect()
+    }
+
+    fn print_stats(&self) {
+        self.summaries().iter().for_each(print_ping_stats);
+    }
+}
+
+/// Note on a reply line when the reply's IP header, which only raw IPv4
+/// sockets show, differs in length from the one sent behind `header_len`
+/// bytes of headers, e.g. because a router added options. Gives the true
+/// size of the reply of `payload_len` bytes on the wire.
+fn ip_header_note(ip_header_len: Option<usize>, header_len: usize, payload_len: usize) -> String {
+    match ip_header_len {
+        Some(ip_header_len) if ip_header_len + ICMP_HEADER_LEN != header_len => format!(
+            " ({ip_header_len} byte IP header, {} bytes on the wire)",
+            ip_header_len + ICMP_HEADER_LEN + payload_len
+        ),
+        _ => String::new(),
+    }
+}
+
+/// What to append to a reply line when the reply echoed a payload other than
+/// the one sent
+fn corruption_note(corruption: Option<PayloadMismatch>) -> String {
+    corruption.map_or(String::new(), |mismatch| {
+        format!(" (payload corrupted: {mismatch})")
+    })
+}
+
+/// Parse a --tag, which must be nonempty ASCII to be filtered on in a
+/// capture
+fn parse_tag(value: &str) -> std::result::Result<String, String> {
+    if value.is_empty() || !value.is_ascii() {
+        return Err("expected a nonempty ASCII string".to_string());
+    }
+    Ok(value.to_string())
+}
+
+/// Note on a reply line that the reply didn't echo the --tag
+fn tag_note(mismatch: Option<&TagMismatch>) -> String {
+    mismatch.map_or(String::new(), |mismatch| format!(" ({mismatch})"))
+}
+
+/// How a destination was resolved, as shown in its banner
+struct Resolution {
+    elapsed: Duration,
+    source: ResolutionSource,
+    /// Where the CNAMEs of the destination led, if anywhere
+    canonical_name: Option<String>,
+}
+
+/// Resolve `destination` to an address of `family`, also returning how that
+/// went
+fn resolve_destination(
+    destination: &str,
+    family: AddressFamily,
+    search: &SearchList,
+) -> Result<(IpAddr, Resolution)> {
+    let started_at = Instant::now();
+    let (ip, source, canonical_name) = resolve_hostname_searched(destination, family, search)?;
+    let resolution = Resolution {
+        elapsed: started_at.elapsed(),
+        source,
+        canonical_name,
+    };
+    Ok((ip, resolution))
+}
+
+/// Resolve `host` afresh for --rotate and point `socket` at the address
+/// picked, through the interface with index `scope_id` for IPv6. Keeps
+/// `current` if the lookup fails, so a DNS hiccup doesn't end the run.
+fn rotate_destination(
+    resolver: &mut Resolver,
+    host: &str,
+    current: IpAddr,
+    scope_id: u32,
+    socket: &mut IcmpEndpoint,
+) -> Result<IpAddr> {
+    // The socket only reaches addresses of the family it was opened for
+    let next = match resolver.resolve_in(host, AddressFamily::of(current)) {
+        Ok(next) => next,
+        Err(_) => return Ok(current),
     };
+    if next != current {
+        socket.set_scoped_destination(next, scope_id)?;
+    }
+    Ok(next)
+}
+
+fn resolve_ipv4(destination: &str, search: &SearchList) -> Result<(Ipv4Addr, Resolution)> {
+    match resolve_destination(destination, AddressFamily::V4, search)? {
+        (IpAddr::V4(v4), resolution) => Ok((v4, resolution)),
+        (IpAddr::V6(_), _) => unreachable!("resolved in IPv4 only"),
+    }
+}
+
+/// Build the requests --dry-run would send to each destination, --count of
+/// them or one, and print them rather than opening a socket
+fn dry_run_handler(args: PingArgs) -> Result<()> {
+    let count = args.count.unwrap_or(1);
+    let mut allocator = IdentifierAllocator::for_process();
+
+    for destination in args.destinations.iter().chain(&args.compare) {
+        let destination = host_from_url(destination);
+        let (ip, resolution) = resolve_ipv4(destination, &args.search_list())?;
+        IcmpSocket::validate_destination(ip)?;
+        let id = allocator.allocate()?;
+        if args.print_banner() {
+            println!(
+                "{}",
+                args.banner(
+                    destination,
+                    IpAddr::V4(ip),
+                    IPV4_HEADER_LEN + ICMP_HEADER_LEN,
+                    resolution
+                )
+            );
+        }
 
-    let mut socket = IcmpSocket::new(args.timeout)?;
+        for n in 1..=count {
+            // The sequence number wraps around after 65535 requests
+            let sequence_no = n as u16;
+            let bytes = args.dry_run_bytes(id, sequence_no)?;
+            let wrapped = if u64::from(sequence_no) == n {
+                String::new()
+            } else {
+                format!(" (request {n})")
+            };
+
+            println!(
+                "{}icmp_seq={sequence_no}{wrapped} id={id}: {} bytes to {ip}, not sent",
+                args.prefix(),
+                bytes.len()
+            );
+            print!("{}", hexdump(&bytes));
+        }
+    }
+
+    Ok(())
+}
+
+/// Send --count address mask requests (one by default) to the destination
+/// and print the masks that come back
+fn mask_handler(args: PingArgs) -> Result<()> {
+    if args.destinations.len() > 1 {
+        return Err("--mask takes a single destination".into());
+    }
+    let destination = host_from_url(&args.destinations[0]);
+    let (ip, _) = resolve_ipv4(destination, &args.search_list())?;
+
+    // Unprivileged ICMP sockets only send echo requests
+    let mut socket = IcmpSocket::new_header_included(args.timeout)?;
     socket.connect(ip)?;
     let interrupt = setup_interrupt_handler();
+    if args.print_banner() {
+        println!("Pingoc: asking {destination} ({ip}) for its address mask");
+    }
 
-    let mut stats = PingStats {
-        packets_sent: 0,
-        packets_recv: 0,
-        bytes_sent: 0.0,
-        bytes_recv: 0.0,
-    };
+    let id = std::process::id() as u16;
+    let count = args.count.unwrap_or(1);
+    let mut sent = 0;
+    let mut answered = 0;
+    let mut failure = None;
+    for n in 1..=count {
+        if interrupt.load(Ordering::SeqCst) {
+            break;
+        }
+        let sequence_no = n as u16;
+        let sent_at = Instant::now();
+        if let Err(err) = socket.send(&mut IcmpPacket::address_mask_request(id, sequence_no)) {
+            failure = Some(err);
+            break;
+        }
+        sent += 1;
 
-    println!(
-        "Pingoc: {} ({}) with {}({}) bytes of data.",
-        args.destination,
-        ip,
-        args.packet_size,
-        args.packet_size + 28
-    );
+        match wait_for_mask(&socket, ip, id, sequence_no, args.timeout)? {
+            Some(mask) => {
+                answered += 1;
+                let prefix_len = mask.to_bits().leading_ones();
+                // Only a contiguous mask has a prefix length
+                let cidr = if mask.to_bits().count_ones() == prefix_len {
+                    format!(" (/{prefix_len})")
+                } else {
+                    String::new()
+                };
+                args.print_line(
+                    LineStyle::Reply,
+                    format!(
+                        "{}Address mask from {ip}: {mask}{cidr} icmp_seq={sequence_no} time={:.3} ms",
+                        args.prefix(),
+                        sent_at.elapsed().as_secs_f64() * 1000.0
+                    ),
+                );
+            }
+            None => args.eprint_line(
+                LineStyle::Timeout,
+                format!(
+                    "{}No address mask reply from {ip} for icmp_seq={sequence_no}",
+                    args.prefix()
+                ),
+            ),
+        }
 
-    let mut id = 1;
-    let mut remaining_count = args.count;
+        if n < count {
+            thread::sleep(args.interval);
+        }
+    }
 
-    // Ping loop
-    while !interrupt.load(Ordering::SeqCst) && remaining_count.map_or(true, |cnt| cnt > 0) {
-        stats.packets_sent += 1;
-        stats.bytes_sent += args.packet_size as f32;
+    println!("--- {destination} address mask statistics ---");
+    println!("{sent} requests sent, {answered} answered");
+    failure.map_or(Ok(()), Err)
+}
 
-        if let Some(recv_bytes) = send_ping(&mut socket, ip, id, args.packet_size, args.quiet)? {
-            stats.packets_recv += 1;
-            stats.bytes_recv += recv_bytes as f32;
+/// Wait up to `timeout` for the address mask reply from `ip` to request
+/// `sequence_no`, skipping any other message the raw socket sees
+fn wait_for_mask(
+    socket: &IcmpSocket<HeaderIncludedTransport>,
+    ip: Ipv4Addr,
+    id: u16,
+    sequence_no: u16,
+    timeout: Duration,
+) -> Result<Option<Ipv4Addr>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
         }
+        let Some((packet, source, _)) = socket.recv_timeout(remaining)? else {
+            return Ok(None);
+        };
+        if let (
+            IcmpType::AddressMaskReply,
+            IcmpContentType::AddressMask {
+                id: reply_id,
+                sequence_no: reply_seq,
+                mask,
+            },
+        ) = (packet.msg_type, packet.content)
+        {
+            if source == ip && reply_id == id && reply_seq == sequence_no {
+                return Ok(Some(mask));
+            }
+        }
+    }
+}
 
-        id += 1;
-
-        remaining_count = remaining_count.map(|cnt| cnt - 1);
-        thread::sleep(Duration::from_secs_f64(args.interval));
+/// Print the trend of the last --history-summary runs of each destination
+/// recorded in the --history file
+fn history_summary_handler(args: PingArgs, runs: usize) -> Result<()> {
+    let path = args
+        .history
+        .as_deref()
+        .expect("--history-summary requires --history");
+    let history = History::read(path)?;
+    if history.skipped > 0 {
+        args.eprint_line(
+            LineStyle::Error,
+            format!(
+                "Skipped {} unreadable lines of {}",
+                history.skipped,
+                path.display()
+            ),
+        );
     }
 
-    print_ping_stats(&args.destination, &stats);
+    for destination in args.destinations.iter().chain(&args.compare) {
+        let destination = host_from_url(destination);
+        let trend = history.trend(destination, runs);
+        if trend.recent.is_empty() {
+            println!("--- {destination} history: no runs recorded ---");
+            continue;
+        }
+
+        println!(
+            "--- {destination} history, last {} of {} runs ---",
+            trend.recent.len(),
+            trend.total_runs
+        );
+        let losses: Vec<String> = trend
+            .losses()
+            .iter()
+            .map(|loss| format!("{loss:.1}%"))
+            .collect();
+        println!("loss: {}", losses.join(", "));
+        let rtts: Vec<String> = trend
+            .recent
+            .iter()
+            .map(|entry| {
+                entry
+                    .avg_rtt_ms
+                    .map_or("-".to_string(), |avg| format!("{avg:.3}"))
+            })
+            .collect();
+        println!("avg rtt: {} ms", rtts.join(", "));
+        if let Some(loss) = trend.overall_loss() {
+            let rtt = trend
+                .overall_avg_rtt_ms()
+                .map_or(String::new(), |avg| format!(", avg rtt {avg:.3} ms"));
+            println!("overall: {loss:.1}% loss{rtt}");
+        }
+    }
 
     Ok(())
 }
 
-fn print_ping_stats(hostname: &str, stats: &PingStats) {
-    println!("--- {hostname} ping statistics ---");
-    let packet_loss = if stats.bytes_sent > 0.0 {
-        100.0 - (stats.bytes_recv / stats.bytes_sent * 100.0)
+fn ping_handler(args: PingArgs) -> Result<()> {
+    if let Some(runs) = args.history_summary {
+        return history_summary_handler(args, runs);
+    }
+    args.check_send_rate()?;
+    if args.dry_run {
+        return dry_run_handler(args);
+    }
+    if args.mask {
+        return mask_handler(args);
+    }
+    if args.compare.is_some() {
+        if args.destinations.len() > 1 {
+            return Err("--compare takes a single destination".into());
+        }
+        return multi_ping_handler(args);
+    }
+    if args.destinations.len() > 1 {
+        if args.broadcast {
+            return Err("--broadcast takes a single destination".into());
+        }
+        if args.raw {
+            return Err("--raw takes a single destination".into());
+        }
+        if args.ipv6 {
+            return Err("Pinging several destinations only supports IPv4".into());
+        }
+        if args.gateway.is_some() {
+            return Err("--gateway takes a single destination".into());
+        }
+        if args.stop_on_unreachable.is_some() {
+            return Err("--stop-on-unreachable takes a single destination".into());
+        }
+        if args.rotate.is_some() {
+            return Err("--rotate takes a single destination".into());
+        }
+        return multi_ping_handler(args);
+    }
+
+    let args = Rc::new(args);
+    let destination = host_from_url(&args.destinations[0]);
+    // A link-local IPv6 destination names the interface to use as its zone
+    let (host, zone) = split_zone(destination);
+    let scope_id = zone.map(interface_index).transpose()?.unwrap_or(0);
+    // A literal address has nothing to rotate through
+    let mut rotation = args
+        .rotate
+        .filter(|_| host.parse::<IpAddr>().is_err())
+        .map(|selection| {
+            let resolver = Resolver::new().with_selection(selection);
+            // Without --search, the system's search list applies
+            if args.search.is_empty() {
+                resolver
+            } else {
+                resolver.with_search(args.search_list())
+            }
+        });
+    let (mut ip, resolution) = match &mut rotation {
+        Some(resolver) => {
+            let started_at = Instant::now();
+            let ip = resolver.resolve_in(host, args.family())?;
+            let resolution = Resolution {
+                elapsed: started_at.elapsed(),
+                source: ResolutionSource::Dns,
+                canonical_name: resolver.canonical_name(host).map(str::to_string),
+            };
+            (ip, resolution)
+        }
+        None => resolve_destination(host, args.family(), &args.search_list())?,
+    };
+
+    let mut socket = match ip {
+        IpAddr::V4(_) if args.raw => IcmpEndpoint::new_header_included(args.timeout)?,
+        IpAddr::V6(_) if args.raw => return Err("--raw only applies to IPv4".into()),
+        _ => IcmpEndpoint::new(ip, args.timeout)?,
+    };
+    if args.broadcast {
+        match &socket {
+            IcmpEndpoint::V4(socket) => socket.set_broadcast(true)?,
+            IcmpEndpoint::V6(_) => return Err("--broadcast only applies to IPv4".into()),
+            IcmpEndpoint::V4HeaderIncluded(_) => unreachable!("--raw conflicts with --broadcast"),
+            #[cfg(feature = "testing")]
+            IcmpEndpoint::Mock(_) => unreachable!("only tests script sockets"),
+        }
+    }
+    if let Some(gateway) = args.gateway {
+        match &socket {
+            IcmpEndpoint::V4(socket) => socket.set_gateway(gateway)?,
+            _ => unreachable!("--gateway implies -4 and conflicts with --raw"),
+        }
+    }
+    socket.set_scoped_destination(ip, scope_id)?;
+    let interrupt = setup_interrupt_handler();
+
+    if args.print_banner() {
+        println!(
+            "{}",
+            args.banner(destination, ip, socket.header_len(), resolution)
+        );
+    }
+    // The identifier stays fixed for the run while the sequence number
+    // increments, wrapping around after 65535 requests.
+    let mut run = PingRun::new(&args, socket, &[(destination, ip)])?;
+    let started_at = run.run(&interrupt, |pinger| {
+        if let Some(resolver) = &mut rotation {
+            let next = rotate_destination(resolver, host, ip, scope_id, pinger.socket_mut())?;
+            if next != ip {
+                pinger.set_address(0, next);
+                ip = next;
+            }
+        }
+        Ok(())
+    })?;
+
+    let summary = run.pinger.stats(0).summary(destination);
+    args.record_history(std::slice::from_ref(&summary))?;
+    if args.json_summary_only {
+        println!("{}", serde_json::to_string(&summary)?);
     } else {
-        0.0
+        print_ping_stats(&summary);
+        args.print_packet_rate(summary.packets_sent, started_at);
+        if args.broadcast {
+            print_responders(&run.responders.borrow());
+        }
+    }
+
+    Ok(())
+}
+
+/// The destinations of a multi-host run, resolved
+struct ResolvedHosts<'a> {
+    /// Each destination that resolved, with its address
+    hosts: Vec<(&'a str, IpAddr)>,
+    /// How each of `hosts` was resolved
+    resolutions: Vec<Resolution>,
+    /// Summaries of the destinations that failed to resolve or can't be
+    /// pinged, by their position on the command line, so one stale name
+    /// doesn't abort a sweep of the others
+    unresolved: Vec<(usize, PingSummary)>,
+}
+
+/// Resolve every destination of a multi-host run, failing only if none
+/// resolve to an address that can be pinged or if a --compare is missing a
+/// side
+fn resolve_hosts(args: &PingArgs) -> Result<ResolvedHosts<'_>> {
+    let mut resolved = ResolvedHosts {
+        hosts: Vec::new(),
+        resolutions: Vec::new(),
+        unresolved: Vec::new(),
     };
+    for (position, destination) in args.destinations.iter().chain(&args.compare).enumerate() {
+        let destination = host_from_url(destination);
+        // An address no ping can reach, e.g. a broadcast one, is set aside
+        // like a name that didn't resolve
+        let resolved_ip = resolve_ipv4(destination, &args.search_list()).and_then(|resolved| {
+            IcmpSocket::validate_destination(resolved.0)?;
+            Ok(resolved)
+        });
+        let (ip, resolution) = match resolved_ip {
+            Ok(resolved) => resolved,
+            // A comparison is meaningless with only one side
+            Err(err) if args.compare.is_none() => {
+                args.eprint_line(LineStyle::Error, format!("{}{err}", args.prefix()));
+                let summary = PingSummary::unresolved(destination, err);
+                resolved.unresolved.push((position, summary));
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        resolved.hosts.push((destination, ip.into()));
+        resolved.resolutions.push(resolution);
+    }
+    if resolved.hosts.is_empty() {
+        return Err("None of the destinations could be resolved".into());
+    }
+    Ok(resolved)
+}
+
+/// Ping several hosts from a single socket, attributing each reply to its
+/// host by echo identifier. Also backs --compare, which adds its host to
+/// the destination.
+fn multi_ping_handler(args: PingArgs) -> Result<()> {
+    let args = Rc::new(args);
+    let ResolvedHosts {
+        hosts,
+        resolutions,
+        unresolved,
+    } = resolve_hosts(&args)?;
+
+    let socket = IcmpEndpoint::V4(IcmpSocket::new(args.timeout)?);
+    let interrupt = setup_interrupt_handler();
+    if args.print_banner() {
+        for ((destination, ip), resolution) in hosts.iter().zip(resolutions) {
+            println!(
+                "{}",
+                args.banner(destination, *ip, socket.header_len(), resolution)
+            );
+        }
+    }
+
+    let mut run = PingRun::new(&args, socket, &hosts)?;
+    let started_at = run.run(&interrupt, |_| Ok(()))?;
+
+    let mut summaries = run.summaries();
+    for (position, summary) in unresolved {
+        summaries.insert(position, summary);
+    }
+    args.record_history(&summaries)?;
+
+    if args.json_summary_only {
+        println!("{}", serde_json::to_string(&summaries)?);
+    } else {
+        summaries.iter().for_each(print_ping_stats);
+        print_sweep_outcome(&summaries);
+        // Not counted in the statistics above, but worth a look when debugging
+        if args.verbose && run.pinger.duplicates() + run.pinger.orphans() > 0 {
+            println!(
+                "ignored {} duplicate and {} unsolicited replies",
+                run.pinger.duplicates(),
+                run.pinger.orphans()
+            );
+        }
+        let packets_sent = summaries.iter().map(|summary| summary.packets_sent).sum();
+        args.print_packet_rate(packets_sent, started_at);
+        if let (Some(_), [first, second]) = (&args.compare, summaries.as_slice()) {
+            print!("{}", comparison_table(first, second));
+        }
+    }
+
+    Ok(())
+}
+
+/// Print how many of the hosts of a multi-host run replied, were unreachable
+/// or failed to resolve, unless all of them replied
+fn print_sweep_outcome(summaries: &[PingSummary]) {
+    let unresolved = summaries
+        .iter()
+        .filter(|summary| summary.resolve_error.is_some())
+        .count();
+    let unreachable = summaries
+        .iter()
+        .filter(|summary| summary.resolve_error.is_none() && summary.packets_recv == 0)
+        .count();
+    if unresolved + unreachable > 0 {
+        println!(
+            "{} of {} hosts replied, {unreachable} unreachable, {unresolved} failed to resolve",
+            summaries.len() - unresolved - unreachable,
+            summaries.len()
+        );
+    }
+}
 
+fn print_ping_stats(summary: &PingSummary) {
+    println!("--- {} ping statistics ---", summary.host);
+    if let Some(err) = &summary.resolve_error {
+        println!("not pinged: {err}");
+        return;
+    }
     println!(
         "{} bytes transmitted, {} received, {:.1}% packet loss",
-        stats.bytes_sent as usize, stats.bytes_recv as usize, packet_loss
+        summary.bytes_sent, summary.bytes_recv, summary.packet_loss
     );
+
+    if let Some(rtt) = summary.rtt {
+        println!(
+            "rtt min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms",
+            rtt.min_ms, rtt.avg_ms, rtt.max_ms, rtt.mdev_ms
+        );
+        if summary.packets_recv > 1 {
+            println!("jitter = {:.2} ms", rtt.jitter_ms);
+        }
+    }
+
+    if let Some(breakdown) = outcome_breakdown(summary) {
+        println!("{breakdown}");
+    }
+
+    if summary.packets_corrupted > 0 {
+        println!(
+            "{} of {} replies echoed a corrupted payload",
+            summary.packets_corrupted, summary.packets_recv
+        );
+    }
+
+    if summary.packets_mistagged > 0 {
+        println!(
+            "{} of {} replies came back without the tag sent",
+            summary.packets_mistagged, summary.packets_recv
+        );
+    }
+
+    if summary.foreign_replies > 0 {
+        println!(
+            "{} replies came from another address than the one pinged",
+            summary.foreign_replies
+        );
+    }
+
+    if summary.packets_retried > 0 {
+        println!(
+            "{} of {} replies received only after retrying",
+            summary.packets_retried, summary.packets_recv
+        );
+    }
+
+    if summary.sequence_gaps > 0 {
+        println!(
+            "{} sequence numbers went missing before a later one was answered",
+            summary.sequence_gaps
+        );
+    }
+
+    // A single TTL is the normal case and already shown on every reply line
+    if summary.ttls.len() > 1 {
+        let ttls: Vec<String> = summary
+            .ttls
+            .iter()
+            .map(|count| format!("{} ({} pkts)", count.ttl, count.packets))
+            .collect();
+        println!("TTLs observed: {}", ttls.join(", "));
+    }
 }
 
-fn main() -> Result<()> {
-    let args = PingArgs::parse();
-    ping_handler(args)
+/// Why the requests not answered weren't, e.g. "7 received, 2 timed out, 1
+/// unreachable", unless all were answered
+fn outcome_breakdown(summary: &PingSummary) -> Option<String> {
+    if summary.packets_recv >= summary.packets_sent {
+        return None;
+    }
+    let mut outcomes = vec![format!("{} received", summary.packets_recv)];
+    if summary.timeouts > 0 {
+        outcomes.push(format!("{} timed out", summary.timeouts));
+    }
+    for (kind, count) in &summary.errors {
+        outcomes.push(format!("{count} {kind}"));
+    }
+    if summary.sent_failures > 0 {
+        outcomes.push(format!("{} not sent", summary.sent_failures));
+    }
+    Some(outcomes.join(", "))
+}
+
+fn print_responders(tally: &ResponderTally) {
+    println!("{} distinct hosts responded", tally.distinct());
+    for (responder, replies) in tally.iter() {
+        println!("  {responder}: {replies} replies");
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Dig(args)) => dig_handler(args),
+        Some(Command::Axfr(args)) => axfr_handler(args),
+        Some(Command::Sweep(args)) => sweep_handler(args),
+        Some(Command::Resolve(args)) => resolve_handler(args),
+        Some(Command::Capabilities) => capabilities_handler(),
+        None => ping_handler(cli.ping),
+    };
+
+    if let Err(e) = result {
+        eprintln!("pingoc: {e}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingoc::icmp::ip::checksum;
+    use pingoc::icmp::mock::{echo_reply, process_identifier, unreachable_reply, MockTransport};
+    use pingoc::icmp::socket6::IPV6_HEADER_LEN;
+    use pingoc::ping::stats::PingStats;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn unanswered_requests_are_broken_down_by_outcome() {
+        let mut stats = PingStats::new();
+        for _ in 0..12 {
+            stats.record_sent(56);
+        }
+        for _ in 0..7 {
+            stats.record_reply(56, Duration::from_millis(1), false);
+        }
+        stats.record_timeouts(2);
+        stats.record_error("unreachable");
+        stats.record_error("time exceeded");
+        stats.record_error("unreachable");
+        stats.record_sent_failure();
+
+        let summary = stats.summary("example.com");
+        assert_eq!(summary.timeouts, 2);
+        assert_eq!(
+            summary.errors,
+            BTreeMap::from([
+                ("time exceeded".to_string(), 1),
+                ("unreachable".to_string(), 2)
+            ])
+        );
+        assert_eq!(summary.sent_failures, 1);
+        assert_eq!(
+            outcome_breakdown(&summary).as_deref(),
+            Some("7 received, 2 timed out, 1 time exceeded, 2 unreachable, 1 not sent")
+        );
+    }
+
+    #[test]
+    fn fully_answered_run_has_no_breakdown() {
+        let mut stats = PingStats::new();
+        stats.record_sent(56);
+        stats.record_reply(56, Duration::from_millis(1), false);
+        assert_eq!(outcome_breakdown(&stats.summary("example.com")), None);
+    }
+
+    /// Parse the ping of 192.0.2.1 with `args`
+    fn parse_ping(args: &[&str]) -> PingArgs {
+        Cli::try_parse_from(["pingoc"].iter().chain(args).chain(["192.0.2.1"].iter()))
+            .unwrap()
+            .ping
+    }
+
+    /// Parse the silent ping of 192.0.2.1 with `args`
+    fn ping_args(args: &[&str]) -> PingArgs {
+        parse_ping(&[&["--silent"], args].concat())
+    }
+
+    #[test]
+    fn requests_are_stamped_when_their_payload_has_room() {
+        for size in [0, 7] {
+            let args = ping_args(&["--packet-size", &size.to_string()]);
+            let (packet, stamped) = args.stamped_echo_request(1, 1);
+            assert!(!stamped);
+            assert_eq!(packet.payload, vec![0; size]);
+        }
+
+        let (packet, stamped) = ping_args(&[]).stamped_echo_request(1, 1);
+        assert!(stamped);
+        assert_eq!(packet.payload.len(), 56);
+        assert!(timestamp::sent_at(&packet).is_some());
+    }
+
+    #[test]
+    fn banner_shows_the_canonical_name_followed() {
+        let resolution = Resolution {
+            elapsed: Duration::ZERO,
+            source: ResolutionSource::Dns,
+            canonical_name: Some("cdn.example.net".to_string()),
+        };
+        assert_eq!(
+            ping_args(&[]).banner(
+                "www.example.com",
+                Ipv4Addr::new(192, 0, 2, 7).into(),
+                IPV4_HEADER_LEN + ICMP_HEADER_LEN,
+                resolution
+            ),
+            "Pingoc: www.example.com (canonical cdn.example.net) (192.0.2.7) with 56(84) bytes of data."
+        );
+    }
+
+    /// Run the ping of `args` to 192.0.2.1 over a socket replaying
+    /// `transport` the way `ping_handler` does, also passing every probe
+    /// event to `hook`. Returns the run once over.
+    fn run_scripted(
+        args: &[&str],
+        transport: MockTransport,
+        hook: impl FnMut(ProbeEvent) + 'static,
+    ) -> PingRun {
+        run_scripted_hosts(args, transport, &["192.0.2.1"], hook)
+    }
+
+    /// Like `run_scripted`, pinging each of `hosts` over the one socket
+    fn run_scripted_hosts(
+        args: &[&str],
+        transport: MockTransport,
+        hosts: &[&str],
+        hook: impl FnMut(ProbeEvent) + 'static,
+    ) -> PingRun {
+        let args = Rc::new(ping_args(args));
+        let hosts: Vec<(&str, IpAddr)> = hosts
+            .iter()
+            .map(|&host| (host, host.parse().unwrap()))
+            .collect();
+        let socket = IcmpEndpoint::Mock(IcmpSocket::with_transport(
+            transport,
+            Ipv4Addr::new(192, 0, 2, 1),
+        ));
+        let run = PingRun::new(&args, socket, &hosts).unwrap();
+        let mut run = PingRun {
+            pinger: run.pinger.on_event(hook),
+            ..run
+        };
+        run.run(&AtomicBool::new(false), |_| Ok(())).unwrap();
+        run
+    }
+
+    /// Number of requests a scripted run sent, retries included
+    fn requests_sent(run: &PingRun) -> usize {
+        let IcmpEndpoint::Mock(socket) = run.pinger.socket() else {
+            unreachable!()
+        };
+        socket.transport().sent().len()
+    }
+
+    #[test]
+    fn timed_out_request_is_retried() {
+        let transport = MockTransport::new().reply_after(
+            Duration::from_millis(80),
+            Ipv4Addr::new(192, 0, 2, 1),
+            echo_reply(process_identifier(), 1),
+        );
+        let run = run_scripted(
+            &["--count", "1", "--retries", "1", "--timeout", "50ms"],
+            transport,
+            |_| {},
+        );
+
+        assert_eq!(requests_sent(&run), 2);
+        let summary = &run.summaries()[0];
+        assert_eq!(summary.packets_recv, 1);
+        assert_eq!(summary.packets_retried, 1);
+        // Both transmissions count, as one request
+        assert_eq!(summary.packets_sent, 1);
+        assert_eq!(summary.bytes_sent, 2 * 56);
+    }
+
+    #[test]
+    fn requests_to_every_host_are_retried() {
+        let second = IcmpPacket {
+            content: IcmpContentType::Echo {
+                id: process_identifier() + 1,
+                sequence_no: 1,
+            },
+            ..echo_reply(process_identifier(), 1)
+        };
+        // The first host answers at once, the second only the resent request
+        let transport = MockTransport::new()
+            .reply_after(
+                Duration::ZERO,
+                Ipv4Addr::new(192, 0, 2, 1),
+                echo_reply(process_identifier(), 1),
+            )
+            .reply_after(
+                Duration::from_millis(80),
+                Ipv4Addr::new(192, 0, 2, 2),
+                second,
+            );
+        let run = run_scripted_hosts(
+            &["--count", "1", "--retries", "1", "--timeout", "50ms"],
+            transport,
+            &["192.0.2.1", "192.0.2.2"],
+            |_| {},
+        );
+
+        assert_eq!(requests_sent(&run), 3);
+        let summaries = run.summaries();
+        assert_eq!(summaries[0].packets_recv, 1);
+        assert_eq!(summaries[0].packets_retried, 0);
+        assert_eq!(summaries[1].packets_recv, 1);
+        assert_eq!(summaries[1].packets_retried, 1);
+    }
+
+    #[test]
+    fn request_is_lost_once_every_attempt_times_out() {
+        let run = run_scripted(
+            &["--count", "1", "--retries", "2"],
+            MockTransport::new(),
+            |_| {},
+        );
+
+        assert_eq!(requests_sent(&run), 3);
+        let summary = &run.summaries()[0];
+        assert_eq!(summary.timeouts, 1);
+        assert_eq!(summary.bytes_sent, 3 * 56);
+    }
+
+    #[test]
+    fn run_stops_once_enough_requests_in_a_row_are_unreachable() {
+        let router = Ipv4Addr::new(198, 51, 100, 1);
+        let host = Ipv4Addr::new(192, 0, 2, 1);
+        // A reply breaks the streak the first two requests started
+        let transport =
+            (1..=10).fold(
+                MockTransport::new(),
+                |transport, sequence_no| match sequence_no {
+                    3 => transport.reply_after(
+                        Duration::ZERO,
+                        host,
+                        echo_reply(process_identifier(), 3),
+                    ),
+                    _ => transport.reply_after(
+                        Duration::ZERO,
+                        router,
+                        unreachable_reply(process_identifier(), sequence_no, host),
+                    ),
+                },
+            );
+        let run = run_scripted(
+            &[
+                "--count",
+                "10",
+                "--interval",
+                "10ms",
+                "--stop-on-unreachable",
+                "3",
+            ],
+            transport,
+            |_| {},
+        );
+
+        assert_eq!(requests_sent(&run), 6);
+        let summary = &run.summaries()[0];
+        assert_eq!(summary.packets_recv, 1);
+        assert_eq!(summary.errors["unreachable"], 5);
+    }
+
+    #[test]
+    fn hosts_failing_to_resolve_are_set_aside() {
+        let args =
+            Cli::try_parse_from(["pingoc", "--silent", "127.0.0.1", "bad..name", "127.0.0.2"])
+                .unwrap()
+                .ping;
+        let resolved = resolve_hosts(&args).unwrap();
+
+        assert_eq!(
+            resolved.hosts,
+            [
+                ("127.0.0.1", Ipv4Addr::new(127, 0, 0, 1).into()),
+                ("127.0.0.2", Ipv4Addr::new(127, 0, 0, 2).into())
+            ]
+        );
+        assert_eq!(resolved.resolutions.len(), 2);
+        assert_eq!(resolved.unresolved.len(), 1);
+        let (position, summary) = &resolved.unresolved[0];
+        assert_eq!(*position, 1);
+        assert_eq!(summary.host, "bad..name");
+        assert!(summary.resolve_error.is_some());
+    }
+
+    #[test]
+    fn hosts_that_cannot_be_pinged_are_set_aside() {
+        let args = Cli::try_parse_from([
+            "pingoc",
+            "--silent",
+            "255.255.255.255",
+            "127.0.0.1",
+            "0.0.0.0",
+        ])
+        .unwrap()
+        .ping;
+        let resolved = resolve_hosts(&args).unwrap();
+
+        assert_eq!(resolved.hosts.len(), 1);
+        assert_eq!(resolved.hosts[0].1, Ipv4Addr::LOCALHOST);
+        let set_aside: Vec<_> = resolved
+            .unresolved
+            .iter()
+            .map(|(position, summary)| (*position, summary.host.as_str()))
+            .collect();
+        assert_eq!(set_aside, [(0, "255.255.255.255"), (2, "0.0.0.0")]);
+        assert!(resolved
+            .unresolved
+            .iter()
+            .all(|(_, summary)| summary.resolve_error.is_some()));
+    }
+
+    #[test]
+    fn run_fails_when_no_host_resolves() {
+        let args = Cli::try_parse_from(["pingoc", "--silent", "bad..name", "worse..name"])
+            .unwrap()
+            .ping;
+        assert!(resolve_hosts(&args).is_err());
+    }
+
+    #[test]
+    fn timeout_takes_fractional_seconds() {
+        assert_eq!(
+            ping_args(&["--timeout", "0.25"]).timeout,
+            Duration::from_millis(250)
+        );
+        assert_eq!(ping_args(&[]).timeout, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn gateway_takes_an_ipv4_address_and_implies_ipv4() {
+        let args = ping_args(&["--gateway", "192.0.2.254"]);
+        assert_eq!(args.gateway, Some(Ipv4Addr::new(192, 0, 2, 254)));
+        assert_eq!(args.family(), AddressFamily::V4);
+
+        for rejected in [
+            &["--gateway", "2001:db8::1"][..],
+            &["--gateway", "gateway.example.com"],
+            &["--gateway", "192.0.2.254", "-6"],
+            &["--gateway", "192.0.2.254", "--broadcast"],
+        ] {
+            let parsed = Cli::try_parse_from(
+                ["pingoc"]
+                    .iter()
+                    .chain(rejected)
+                    .chain(["192.0.2.1"].iter()),
+            );
+            assert!(parsed.is_err(), "{rejected:?}");
+        }
+    }
+
+    #[test]
+    fn color_never_leaves_lines_plain() {
+        let line = "64 bytes from 192.0.2.1: icmp_seq=1 ttl=64 time=0.042 ms";
+        let never = parse_ping(&["--color", "never"]);
+        for style in [LineStyle::Reply, LineStyle::Timeout, LineStyle::Error] {
+            assert_eq!(never.render_line(style, line, true), line);
+        }
+
+        let always = parse_ping(&["--color", "always"]).render_line(LineStyle::Reply, line, false);
+        assert_eq!(always, format!("\x1b[32m{line}\x1b[0m"));
+    }
+
+    #[test]
+    fn each_verbosity_level_prints_less() {
+        // What is printed: the banner, reply lines and error lines
+        let printed = |args: &[&str]| {
+            let args = parse_ping(args);
+            (
+                args.print_banner(),
+                args.print_replies(),
+                args.print_errors(),
+            )
+        };
+        assert_eq!(printed(&[]), (true, true, true));
+        assert_eq!(printed(&["--verbose"]), (true, true, true));
+        assert_eq!(printed(&["--quiet"]), (true, false, true));
+        assert_eq!(printed(&["--silent"]), (false, false, false));
+        assert_eq!(printed(&["--json-summary-only"]), (false, false, true));
+
+        for contradictory in [["--verbose", "--quiet"], ["--verbose", "--silent"]] {
+            let parsed = Cli::try_parse_from(
+                ["pingoc"]
+                    .iter()
+                    .chain(&contradictory)
+                    .chain(["192.0.2.1"].iter()),
+            );
+            assert!(parsed.is_err(), "{contradictory:?}");
+        }
+    }
+
+    #[test]
+    fn verbose_banner_tells_how_long_resolving_took() {
+        let resolution = || Resolution {
+            elapsed: Duration::from_micros(12_345),
+            source: ResolutionSource::Dns,
+            canonical_name: None,
+        };
+        let banner = |args: &[&str]| {
+            parse_ping(args).banner(
+                "example.com",
+                Ipv4Addr::new(192, 0, 2, 1).into(),
+                IPV4_HEADER_LEN + ICMP_HEADER_LEN,
+                resolution(),
+            )
+        };
+
+        assert_eq!(
+            banner(&["--verbose"]),
+            format!(
+                "Pingoc: example.com (192.0.2.1) with 56(84) bytes of data. (resolved in 12.345 ms via {})",
+                ResolutionSource::Dns
+            )
+        );
+        assert!(!banner(&[]).contains("resolved in"));
+    }
+
+    #[test]
+    fn banner_counts_the_headers_of_the_family_pinged() {
+        let args = parse_ping(&[]);
+        let resolution = || Resolution {
+            elapsed: Duration::ZERO,
+            source: ResolutionSource::Literal,
+            canonical_name: None,
+        };
+
+        let v4 = args.banner(
+            "192.0.2.1",
+            Ipv4Addr::new(192, 0, 2, 1).into(),
+            IPV4_HEADER_LEN + ICMP_HEADER_LEN,
+            resolution(),
+        );
+        assert!(v4.ends_with("with 56(84) bytes of data."), "{v4}");
+
+        let v6 = args.banner(
+            "2001:db8::1",
+            "2001:db8::1".parse().unwrap(),
+            IPV6_HEADER_LEN + ICMP_HEADER_LEN,
+            resolution(),
+        );
+        assert!(v6.ends_with("with 56(104) bytes of data."), "{v6}");
+
+        // A loose source route through one gateway adds 12 bytes of options
+        let routed = args.banner(
+            "192.0.2.1",
+            Ipv4Addr::new(192, 0, 2, 1).into(),
+            IPV4_HEADER_LEN + 12 + ICMP_HEADER_LEN,
+            resolution(),
+        );
+        assert!(routed.ends_with("with 56(96) bytes of data."), "{routed}");
+    }
+
+    #[test]
+    fn dry_run_emits_the_request_as_it_would_be_sent() {
+        let bytes = ping_args(&["--dry-run", "--packet-size", "4", "--tag", "ab"])
+            .dry_run_bytes(0x1234, 7)
+            .unwrap();
+        assert_eq!(bytes[..2], [8, 0]);
+        assert_eq!(bytes[4..], [0x12, 0x34, 0, 7, b'a', b'b', 0, 0]);
+        assert_eq!(checksum(&bytes), 0);
+
+        let stamped = ping_args(&["--dry-run"]).dry_run_bytes(1, 1).unwrap();
+        assert_eq!(stamped.len(), ICMP_HEADER_LEN + 56);
+        assert_ne!(stamped[8..16], [0; 8]);
+        assert_eq!(stamped[16..], [0; 48]);
+        assert_eq!(checksum(&stamped), 0);
+    }
+
+    #[test]
+    fn zero_interval_is_refused_unless_the_rate_is_capped() {
+        let error = parse_ping(&["--interval", "0"])
+            .check_send_rate()
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("--interval 0ns would send more than 100 packets/s"));
+        assert!(error.contains("--pps") && error.contains("--allow-fast-interval"));
+
+        for allowed in [
+            &["--interval", "0", "--allow-fast-interval"][..],
+            &["--interval", "0", "--pps", "100"],
+            &["--interval", "10ms"],
+            &["--adaptive"],
+        ] {
+            assert!(parse_ping(allowed).check_send_rate().is_ok(), "{allowed:?}");
+        }
+        assert!(parse_ping(&["--interval", "0", "--pps", "101"])
+            .check_send_rate()
+            .is_err());
+    }
+
+    /// How long after each request of a run the next one was sent, with the
+    /// reply to request n arriving `delays[n - 1]` after it was sent
+    fn send_gaps(args: &[&str], delays: &[Duration]) -> Vec<Duration> {
+        let host = Ipv4Addr::new(192, 0, 2, 1);
+        let transport = delays.iter().zip(1..).fold(
+            MockTransport::new(),
+            |transport, (&delay, sequence_no)| {
+                transport.reply_after(delay, host, echo_reply(process_identifier(), sequence_no))
+            },
+        );
+        let count = delays.len().to_string();
+        let sent_at = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&sent_at);
+        run_scripted(
+            &[args, &["--count", &count]].concat(),
+            transport,
+            move |event| {
+                if let ProbeEvent::Sent { .. } = event {
+                    seen.borrow_mut().push(Instant::now());
+                }
+            },
+        );
+
+        // When the request after the last would have been sent
+        let mut sent_at = sent_at.take();
+        sent_at.push(Instant::now());
+        sent_at.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+
+    #[test]
+    fn adaptive_requests_follow_the_replies() {
+        let delays = [40, 80, 20].map(Duration::from_millis);
+        let slack = Duration::from_millis(100);
+
+        let adaptive = send_gaps(&["--adaptive"], &delays);
+        for (gap, delay) in adaptive.iter().zip(delays) {
+            assert!(*gap >= delay && *gap < delay + slack, "{adaptive:?}");
+        }
+
+        let interval = Duration::from_millis(200);
+        let fixed = send_gaps(&["--interval", "200ms"], &delays);
+        for (gap, delay) in fixed.iter().zip(delays) {
+            assert!(*gap >= delay + interval, "{fixed:?}");
+        }
+    }
 }
@@ -0,0 +1,144 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+
+use super::stats::{PingSummary, RttSummary};
+
+/// Picks one value out of the round trip statistics
+type RttMetric = fn(&RttSummary) -> f64;
+
+/// Round trip time rows of the table
+const RTT_ROWS: [(&str, RttMetric); 5] = [
+    ("rtt min", |rtt| rtt.min_ms),
+    ("rtt avg", |rtt| rtt.avg_ms),
+    ("rtt max", |rtt| rtt.max_ms),
+    ("rtt mdev", |rtt| rtt.mdev_ms),
+    ("jitter", |rtt| rtt.jitter_ms),
+];
+
+/// Side by side table of the loss and round trip times of two hosts, with the
+/// better host named on each row (lower is better for every metric).
+///
+/// A host that never replied has no round trip times, so the other host wins
+/// those rows by default; if neither replied there is no winner.
+pub fn comparison_table(first: &PingSummary, second: &PingSummary) -> String {
+    let width = first.host.len().max(second.host.len()).max(10) + 2;
+    let mut table = String::new();
+
+    let _ = writeln!(table, "--- comparison ---");
+    let _ = writeln!(
+        table,
+        "{:<12}{:<width$}{:<width$}better",
+        "", first.host, second.host
+    );
+
+    let mut write_row = |label: &str, a: Option<f64>, b: Option<f64>, unit: &str| {
+        let better = match better(a, b) {
+            Some(Ordering::Less) => first.host.as_str(),
+            Some(Ordering::Greater) => second.host.as_str(),
+            Some(Ordering::Equal) => "tie",
+            None => "-",
+        };
+        let format = |value: Option<f64>| match value {
+            Some(value) if unit == "%" => format!("{value:.1}%"),
+            Some(value) => format!("{value:.3} {unit}"),
+            None => "-".to_string(),
+        };
+        let _ = writeln!(
+            table,
+            "{:<12}{:<width$}{:<width$}{}",
+            label,
+            format(a),
+            format(b),
+            better
+        );
+    };
+
+    write_row(
+        "loss",
+        Some(first.packet_loss),
+        Some(second.packet_loss),
+        "%",
+    );
+    for (label, metric) in RTT_ROWS {
+        write_row(
+            label,
+            first.rtt.as_ref().map(metric),
+            second.rtt.as_ref().map(metric),
+            "ms",
+        );
+    }
+
+    table
+}
+
+/// Which value is better, lower being better and a missing value losing to
+/// any other. `None` if both are missing.
+fn better(a: Option<f64>, b: Option<f64>) -> Option<Ordering> {
+    match (a, b) {
+        (Some(a), Some(b)) => a.partial_cmp(&b),
+        (Some(_), None) => Some(Ordering::Less),
+        (None, Some(_)) => Some(Ordering::Greater),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::stats::PingStats;
+
+    fn summary(host: &str, packet_loss: f64, rtt: Option<RttSummary>) -> PingSummary {
+        PingSummary {
+            packet_loss,
+            rtt,
+            ..PingStats::new().summary(host)
+        }
+    }
+
+    fn rtt(min_ms: f64, avg_ms: f64, max_ms: f64, mdev_ms: f64, jitter_ms: f64) -> RttSummary {
+        RttSummary {
+            min_ms,
+            avg_ms,
+            max_ms,
+            mdev_ms,
+            jitter_ms,
+        }
+    }
+
+    #[test]
+    fn table_names_the_better_host_on_each_row() {
+        let alpha = summary("alpha", 0.0, Some(rtt(1.0, 2.0, 3.0, 0.5, 0.25)));
+        let beta = summary("beta", 25.0, Some(rtt(0.5, 2.0, 4.0, 1.0, 0.1)));
+
+        assert_eq!(
+            comparison_table(&alpha, &beta),
+            "--- comparison ---\n\
+             \x20           alpha       beta        better\n\
+             loss        0.0%        25.0%       alpha\n\
+             rtt min     1.000 ms    0.500 ms    beta\n\
+             rtt avg     2.000 ms    2.000 ms    tie\n\
+             rtt max     3.000 ms    4.000 ms    alpha\n\
+             rtt mdev    0.500 ms    1.000 ms    alpha\n\
+             jitter      0.250 ms    0.100 ms    beta\n"
+        );
+    }
+
+    #[test]
+    fn host_that_never_replied_loses_every_rtt_row() {
+        let alpha = summary("alpha", 100.0, None);
+        let beta = summary("beta", 0.0, Some(rtt(1.0, 1.0, 1.0, 0.0, 0.0)));
+
+        let table = comparison_table(&alpha, &beta);
+        let rows: Vec<_> = table.lines().skip(2).collect();
+        assert_eq!(rows[0], "loss        100.0%      0.0%        beta");
+        assert_eq!(rows[1], "rtt min     -           1.000 ms    beta");
+        assert!(rows.iter().all(|row| row.ends_with("beta")), "{table}");
+
+        let silent = summary("beta", 100.0, None);
+        let table = comparison_table(&alpha, &silent);
+        assert!(
+            table.lines().skip(3).all(|row| row.ends_with(" -")),
+            "{table}"
+        );
+    }
+}
@@ -0,0 +1,699 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::icmp::packet::IcmpPacket;
+use crate::icmp::socket::IcmpSocketKind;
+use crate::icmp::types::{IcmpContentType, IcmpType};
+
+/// A host registered with the correlator and its unanswered requests
+#[derive(Debug)]
+struct ActiveHost {
+    index: usize,
+    address: IpAddr,
+    outstanding: HashMap<u16, SentRequest>,
+    /// Sequences already answered and when they were sent, to recognise
+    /// duplicate replies and time them
+    answered: HashMap<u16, Instant>,
+}
+
+impl ActiveHost {
+    /// Whether `sequence_no` was sent to this host and is outstanding or
+    /// was answered
+    fn knows(&self, sequence_no: u16) -> bool {
+        self.outstanding.contains_key(&sequence_no) || self.answered.contains_key(&sequence_no)
+    }
+}
+
+/// An outstanding request: when it was sent, and what its payload was if
+/// the reply should be checked against it
+#[derive(Debug)]
+struct SentRequest {
+    sent_at: Instant,
+    payload: Option<Vec<u8>>,
+}
+
+/// An echo reply matched to the request that caused it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchedReply {
+    /// Index of the host as given to `register`
+    pub host: usize,
+    pub sequence_no: u16,
+    pub rtt: Duration,
+    /// How the echoed payload differs from the one sent, if it was recorded
+    /// with `record_request_with_payload` and came back altered
+    pub corruption: Option<PayloadMismatch>,
+    /// The address the reply came from, if it isn't the host's, see
+    /// `SourceCheck::Lenient`
+    pub foreign_source: Option<IpAddr>,
+}
+
+/// How the payload echoed in a reply differs from the one sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadMismatch {
+    /// Number of bytes that differ, counting any missing or extra bytes
+    pub differing: usize,
+    /// Offset of the first differing byte
+    pub first_offset: usize,
+}
+
+impl PayloadMismatch {
+    /// Compare an echoed payload to the one sent, `None` if they're equal
+    pub fn compare(sent: &[u8], echoed: &[u8]) -> Option<Self> {
+        let pairs = || sent.iter().zip(echoed);
+        let common = pairs().count();
+        let missing_or_extra = sent.len().max(echoed.len()) - common;
+        let first_offset = pairs()
+            .position(|(a, b)| a != b)
+            .or((missing_or_extra > 0).then_some(common));
+        let differing = pairs().filter(|(a, b)| a != b).count() + missing_or_extra;
+
+        first_offset.map(|first_offset| Self {
+            differing,
+            first_offset,
+        })
+    }
+}
+
+impl fmt::Display for PayloadMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes differ from offset {}",
+            self.differing, self.first_offset
+        )
+    }
+}
+
+/// What a received packet turned out to be, see `ReplyCorrelator::classify`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Received {
+    /// The first reply to an outstanding request
+    Reply(MatchedReply),
+    /// Another reply to a request that was already answered, `rtt` after
+    /// the request was sent
+    Duplicate {
+        host: usize,
+        sequence_no: u16,
+        rtt: Duration,
+    },
+    /// A reply to an outstanding request that came from another address
+    /// than the host's, rejected by `SourceCheck::Strict`. The request is
+    /// still waiting for its reply.
+    ForeignSource {
+        host: usize,
+        sequence_no: u16,
+        source: IpAddr,
+    },
+    /// An ICMP error, e.g. destination unreachable from the router at
+    /// `source`, about an outstanding request. The request is answered, so
+    /// a reply arriving after all is a duplicate.
+    Error {
+        host: usize,
+        sequence_no: u16,
+        source: IpAddr,
+    },
+    /// An echo reply, or an error about an echo request, that matches no
+    /// request of ours, e.g. one answering a request long since expired
+    Orphan,
+    /// Anything else, e.g. our own requests seen on a raw socket or errors
+    /// about requests already answered
+    Unrelated,
+}
+
+/// How echo replies are matched to the requests they answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchStrategy {
+    /// Match on identifier, sequence number and source address
+    #[default]
+    Identifier,
+    /// Match on source address and sequence number, ignoring the identifier
+    Sequence,
+}
+
+impl MatchStrategy {
+    /// The strategy that works for a socket of `kind`.
+    ///
+    /// Linux datagram ICMP sockets don't send the identifier we put in an
+    /// echo request: the kernel overwrites it with the socket's own
+    /// identifier (its "port"), so replies come back carrying that one and
+    /// only the sequence number and source can be relied on. Raw sockets
+    /// send the identifier untouched.
+    pub fn for_socket(kind: IcmpSocketKind) -> Self {
+        match kind {
+            IcmpSocketKind::Datagram => Self::Sequence,
+            IcmpSocketKind::Raw => Self::Identifier,
+        }
+    }
+}
+
+/// What to do with a reply to one of our requests that comes from another
+/// address than the one the request was sent to, as happens with anycast
+/// destinations or behind NAT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SourceCheck {
+    /// Accept the reply, noting where it came from
+    #[default]
+    Lenient,
+    /// Reject the reply as not answering the request
+    Strict,
+}
+
+impl FromStr for SourceCheck {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "lenient" => Ok(Self::Lenient),
+            "strict" => Ok(Self::Strict),
+            other => Err(format!(
+                "unknown source check {other:?}, expected lenient or strict"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SourceCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lenient => write!(f, "lenient"),
+            Self::Strict => write!(f, "strict"),
+        }
+    }
+}
+
+/// Attributes echo replies arriving on a shared socket to the host whose
+/// request they answer.
+///
+/// Every host pinged through the socket is given its own echo identifier, and
+/// a reply is only accepted when its (identifier, sequence, source address)
+/// tuple matches an outstanding request. With `MatchStrategy::Sequence` the
+/// identifier is ignored and replies are attributed by source address.
+///
+/// A reply from another address than its host's is still matched if only
+/// one host could have been sent its request, and then accepted or rejected
+/// as `with_source_check` says.
+#[derive(Debug, Default)]
+pub struct ReplyCorrelator {
+    hosts: HashMap<u16, ActiveHost>,
+    strategy: MatchStrategy,
+    source_check: SourceCheck,
+    duplicates: u64,
+    orphans: u64,
+    foreign_replies: u64,
+}
+
+impl ReplyCorrelator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match replies with `strategy` rather than by identifier
+    pub fn with_strategy(mut self, strategy: MatchStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Accept or reject replies from another address than their host's
+    pub fn with_source_check(mut self, source_check: SourceCheck) -> Self {
+        self.source_check = source_check;
+        self
+    }
+
+    /// Start accepting replies carrying `identifier` from `address`
+    pub fn register(&mut self, identifier: u16, host: usize, address: IpAddr) {
+        self.hosts.insert(
+            identifier,
+            ActiveHost {
+                index: host,
+                address,
+                outstanding: HashMap::new(),
+                answered: HashMap::new(),
+            },
+        );
+    }
+
+    /// Stop accepting replies carrying `identifier`
+    pub fn unregister(&mut self, identifier: u16) {
+        self.hosts.remove(&identifier);
+    }
+
+    /// Remember that a request was sent so its reply can be matched
+    pub fn record_request(&mut self, identifier: u16, sequence_no: u16, sent_at: Instant) {
+        self.insert_request(
+            identifier,
+            sequence_no,
+            SentRequest {
+                sent_at,
+                payload: None,
+            },
+        );
+    }
+
+    /// Like `record_request`, also keeping the `payload` sent so that the
+    /// one echoed in the reply can be checked against it
+    pub fn record_request_with_payload(
+        &mut self,
+        identifier: u16,
+        sequence_no: u16,
+        sent_at: Instant,
+        payload: &[u8],
+    ) {
+        self.insert_request(
+            identifier,
+            sequence_no,
+            SentRequest {
+                sent_at,
+                payload: Some(payload.to_vec()),
+            },
+        );
+    }
+
+    fn insert_request(&mut self, identifier: u16, sequence_no: u16, request: SentRequest) {
+        if let Some(host) = self.hosts.get_mut(&identifier) {
+            // The sequence number wrapped around since it was last answered
+            host.answered.remove(&sequence_no);
+            host.outstanding.insert(sequence_no, request);
+        }
+    }
+
+    /// Match a received packet against the outstanding requests.
+    /// Returns `None` for anything that isn't the reply to one of our requests.
+    pub fn dispatch(
+        &mut self,
+        packet: &IcmpPacket,
+        source: IpAddr,
+        received_at: Instant,
+    ) -> Option<MatchedReply> {
+        match self.classify(packet, source, received_at) {
+            Received::Reply(reply) => Some(reply),
+            _ => None,
+        }
+    }
+
+    /// Like `dispatch`, but tells errors, duplicates, orphans and replies
+    /// from foreign sources apart from unrelated packets, and counts the
+    /// last three for `duplicates`, `orphans` and `foreign_replies`. Only a
+    /// `Received::Reply` should be counted as a reply in the statistics.
+    pub fn classify(
+        &mut self,
+        packet: &IcmpPacket,
+        source: IpAddr,
+        received_at: Instant,
+    ) -> Received {
+        let received = self.match_packet(packet, source, received_at);
+        match received {
+            Received::Duplicate { .. } => self.duplicates += 1,
+            Received::Orphan => self.orphans += 1,
+            Received::ForeignSource { .. } => self.foreign_replies += 1,
+            Received::Reply(MatchedReply {
+                foreign_source: Some(_),
+                ..
+            }) => self.foreign_replies += 1,
+            _ => {}
+        }
+        received
+    }
+
+    fn match_packet(
+        &mut self,
+        packet: &IcmpPacket,
+        source: IpAddr,
+        received_at: Instant,
+    ) -> Received {
+        if packet.is_error() {
+            return self.match_error(packet, source);
+        }
+        if !matches!(packet.msg_type, IcmpType::EchoReply) {
+            return Received::Unrelated;
+        }
+        let IcmpContentType::Echo { id, sequence_no } = packet.content else {
+            return Received::Unrelated;
+        };
+
+        if !self.is_ours(id) {
+            return Received::Unrelated;
+        }
+        let (key, foreign_source) = match self.find_key(id, Some(source), sequence_no) {
+            Some(key) => (key, None),
+            None => match self.sole_host(id, sequence_no) {
+                Some(key) => (key, Some(source)),
+                None => return Received::Orphan,
+            },
+        };
+        let host = self.hosts.get_mut(&key).expect("found among the hosts");
+        if foreign_source.is_some() && self.source_check == SourceCheck::Strict {
+            return Received::ForeignSource {
+                host: host.index,
+                sequence_no,
+                source,
+            };
+        }
+        match host.outstanding.remove(&sequence_no) {
+            Some(request) => {
+                host.answered.insert(sequence_no, request.sent_at);
+                Received::Reply(MatchedReply {
+                    host: host.index,
+                    sequence_no,
+                    rtt: received_at.saturating_duration_since(request.sent_at),
+                    corruption: request
+                        .payload
+                        .and_then(|sent| PayloadMismatch::compare(&sent, &packet.payload)),
+                    foreign_source,
+                })
+            }
+            None => Received::Duplicate {
+                host: host.index,
+                sequence_no,
+                rtt: received_at.saturating_duration_since(host.answered[&sequence_no]),
+            },
+        }
+    }
+
+    /// Match an ICMP error from `source` to the request it quotes. Errors
+    /// come from whichever router gave up, so only the quoted request can
+    /// tell whose it was: its destination, unless a source route rewrote it,
+    /// and its identifier and sequence number.
+    fn match_error(&mut self, packet: &IcmpPacket, source: IpAddr) -> Received {
+        let Some((id, sequence_no)) = packet.quoted_echo() else {
+            return Received::Unrelated;
+        };
+        if !self.is_ours(id) {
+            return Received::Unrelated;
+        }
+        let key = packet
+            .quoted_destination()
+            .and_then(|destination| self.find_key(id, Some(destination), sequence_no))
+            .or_else(|| self.find_key(id, None, sequence_no));
+        let Some(host) = key.and_then(|key| self.hosts.get_mut(&key)) else {
+            return Received::Orphan;
+        };
+        match host.outstanding.remove(&sequence_no) {
+            Some(request) => {
+                host.answered.insert(sequence_no, request.sent_at);
+                Received::Error {
+                    host: host.index,
+                    sequence_no,
+                    source,
+                }
+            }
+            None => Received::Unrelated,
+        }
+    }
+
+    /// The key of the host that was sent `sequence_no` under `id`, or with
+    /// `MatchStrategy::Sequence` under any identifier, preferring one still
+    /// waiting for its reply. Replies must also come from the host's
+    /// address, given as `source`.
+    fn find_key(&self, id: u16, source: Option<IpAddr>, sequence_no: u16) -> Option<u16> {
+        let from_host = |host: &ActiveHost| source.is_none_or(|source| host.address == source);
+        match self.strategy {
+            MatchStrategy::Identifier => self
+                .hosts
+                .get(&id)
+                .filter(|host| from_host(host) && host.knows(sequence_no))
+                .map(|_| id),
+            MatchStrategy::Sequence => {
+                let find = |sent: fn(&ActiveHost, u16) -> bool| {
+                    self.hosts
+                        .iter()
+                        .find(|(_, host)| from_host(host) && sent(host, sequence_no))
+                        .map(|(key, _)| *key)
+                };
+                find(|host, seq| host.outstanding.contains_key(&seq))
+                    .or_else(|| find(|host, seq| host.answered.contains_key(&seq)))
+            }
+        }
+    }
+
+    /// The key of the only host that can have been sent `sequence_no` under
+    /// `id`, for a reply from an address that is no host's. With
+    /// `MatchStrategy::Sequence` every host may have been sent it, and the
+    /// reply can only be attributed when a single one is still waiting for
+    /// it, or failing that a single one was answered.
+    fn sole_host(&self, id: u16, sequence_no: u16) -> Option<u16> {
+        if self.strategy == MatchStrategy::Identifier {
+            return self.find_key(id, None, sequence_no);
+        }
+
+        let sole = |sent: fn(&ActiveHost, u16) -> bool| {
+            let mut keys = self
+                .hosts
+                .iter()
+                .filter(|(_, host)| sent(host, sequence_no))
+                .map(|(key, _)| *key);
+            let key = keys.next()?;
+            Some(keys.next().is_none().then_some(key))
+        };
+        match sole(|host, seq| host.outstanding.contains_key(&seq)) {
+            Some(key) => key,
+            None => sole(|host, seq| host.answered.contains_key(&seq)).flatten(),
+        }
+    }
+
+    /// Whether a packet carrying `id` can be meant for us at all. Datagram
+    /// sockets only ever see replies to their own requests, but raw sockets
+    /// also see those of other processes.
+    fn is_ours(&self, id: u16) -> bool {
+        self.strategy == MatchStrategy::Sequence || self.hosts.contains_key(&id)
+    }
+
+    /// Number of duplicate replies seen by `classify`
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Number of orphaned replies and errors seen by `classify`
+    pub fn orphans(&self) -> u64 {
+        self.orphans
+    }
+
+    /// Number of replies from another address than their host's seen by
+    /// `classify`, whether accepted or rejected
+    pub fn foreign_replies(&self) -> u64 {
+        self.foreign_replies
+    }
+
+    /// Number of requests still waiting for a reply
+    pub fn outstanding(&self) -> usize {
+        self.hosts.values().map(|host| host.outstanding.len()).sum()
+    }
+
+    /// Forget all outstanding requests, returning the sequence numbers each
+    /// host lost, in order
+    pub fn expire(&mut self) -> Vec<(usize, Vec<u16>)> {
+        self.hosts
+            .values_mut()
+            .filter(|host| !host.outstanding.is_empty())
+            .map(|host| {
+                let mut lost: Vec<u16> = host.outstanding.drain().map(|(seq, _)| seq).collect();
+                lost.sort_unstable();
+                (host.index, lost)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::icmp::mock::{echo_reply, unreachable_reply};
+    use std::net::Ipv4Addr;
+
+    const FIRST: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const SECOND: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn replies_are_attributed_by_identifier() {
+        let mut correlator = ReplyCorrelator::new();
+        correlator.register(100, 0, FIRST);
+        correlator.register(200, 1, SECOND);
+        let sent_at = Instant::now();
+        correlator.record_request(100, 1, sent_at);
+        correlator.record_request(200, 1, sent_at);
+        let received_at = sent_at + Duration::from_millis(5);
+
+        // The second host answers first
+        let matched = correlator.dispatch(&echo_reply(200, 1), SECOND, received_at);
+        assert_eq!(
+            matched,
+            Some(MatchedReply {
+                host: 1,
+                sequence_no: 1,
+                rtt: Duration::from_millis(5),
+                corruption: None,
+                foreign_source: None,
+            })
+        );
+        assert_eq!(correlator.outstanding(), 1);
+
+        let matched = correlator.dispatch(&echo_reply(100, 1), FIRST, received_at);
+        assert_eq!(matched.map(|reply| reply.host), Some(0));
+        assert_eq!(correlator.outstanding(), 0);
+    }
+
+    #[test]
+    fn unknown_or_repeated_replies_are_ignored() {
+        let mut correlator = ReplyCorrelator::new();
+        correlator.register(100, 0, FIRST);
+        correlator.register(200, 1, SECOND);
+        let now = Instant::now();
+        correlator.record_request(100, 1, now);
+
+        assert_eq!(correlator.dispatch(&echo_reply(300, 1), FIRST, now), None);
+        assert!(correlator
+            .dispatch(&echo_reply(100, 1), FIRST, now)
+            .is_some());
+        assert_eq!(correlator.dispatch(&echo_reply(100, 1), FIRST, now), None);
+        assert_eq!(correlator.expire(), Vec::new());
+    }
+
+    #[test]
+    fn reply_from_another_address_is_noted_when_lenient() {
+        let mut correlator = ReplyCorrelator::new();
+        correlator.register(100, 0, FIRST);
+        let now = Instant::now();
+        correlator.record_request(100, 1, now);
+
+        let matched = correlator
+            .dispatch(&echo_reply(100, 1), SECOND, now)
+            .unwrap();
+        assert_eq!(matched.host, 0);
+        assert_eq!(matched.foreign_source, Some(SECOND));
+        assert_eq!(correlator.foreign_replies(), 1);
+        assert_eq!(correlator.outstanding(), 0);
+    }
+
+    #[test]
+    fn reply_from_another_address_is_rejected_when_strict() {
+        let mut correlator = ReplyCorrelator::new().with_source_check(SourceCheck::Strict);
+        correlator.register(100, 0, FIRST);
+        let now = Instant::now();
+        correlator.record_request(100, 1, now);
+
+        assert_eq!(
+            correlator.classify(&echo_reply(100, 1), SECOND, now),
+            Received::ForeignSource {
+                host: 0,
+                sequence_no: 1,
+                source: SECOND
+            }
+        );
+        assert_eq!(correlator.foreign_replies(), 1);
+        // The request is still waiting for its real reply
+        let matched = correlator
+            .dispatch(&echo_reply(100, 1), FIRST, now)
+            .unwrap();
+        assert_eq!(matched.foreign_source, None);
+    }
+
+    #[test]
+    fn each_strategy_matches_the_socket_kind_it_is_for() {
+        assert_eq!(
+            MatchStrategy::for_socket(IcmpSocketKind::Raw),
+            MatchStrategy::Identifier
+        );
+        assert_eq!(
+            MatchStrategy::for_socket(IcmpSocketKind::Datagram),
+            MatchStrategy::Sequence
+        );
+    }
+
+    #[test]
+    fn rewritten_identifiers_only_match_by_sequence() {
+        let now = Instant::now();
+        let correlator = |strategy| {
+            let mut correlator = ReplyCorrelator::new().with_strategy(strategy);
+            correlator.register(100, 0, FIRST);
+            correlator.register(200, 1, SECOND);
+            correlator.record_request(100, 1, now);
+            correlator.record_request(200, 1, now);
+            correlator
+        };
+        // The kernel put its own identifier in the request, and the reply
+        // carries it back
+        let rewritten = echo_reply(4242, 1);
+
+        let mut by_identifier = correlator(MatchStrategy::Identifier);
+        assert_eq!(by_identifier.dispatch(&rewritten, SECOND, now), None);
+
+        let mut by_sequence = correlator(MatchStrategy::Sequence);
+        let matched = by_sequence.dispatch(&rewritten, SECOND, now);
+        assert_eq!(matched.map(|reply| reply.host), Some(1));
+        // A sequence already answered, or never sent, still matches nothing
+        assert_eq!(by_sequence.dispatch(&rewritten, SECOND, now), None);
+        assert_eq!(by_sequence.dispatch(&echo_reply(4242, 2), FIRST, now), None);
+        assert_eq!(by_sequence.outstanding(), 1);
+    }
+
+    #[test]
+    fn expiring_names_the_sequences_each_host_lost() {
+        let mut correlator = ReplyCorrelator::new();
+        correlator.register(100, 0, FIRST);
+        correlator.register(200, 1, SECOND);
+        let now = Instant::now();
+        for sequence_no in [3, 1, 2] {
+            correlator.record_request(100, sequence_no, now);
+        }
+        correlator.record_request(200, 1, now);
+        assert!(correlator
+            .dispatch(&echo_reply(100, 2), FIRST, now)
+            .is_some());
+        assert!(correlator
+            .dispatch(&echo_reply(200, 1), SECOND, now)
+            .is_some());
+
+        assert_eq!(correlator.expire(), vec![(0, vec![1, 3])]);
+        assert_eq!(correlator.outstanding(), 0);
+    }
+
+    #[test]
+    fn errors_are_attributed_by_the_request_they_quote() {
+        let router = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+        // Both hosts were sent sequence 1 under an identifier the kernel
+        // rewrote, so only the destination quoted tells them apart
+        let mut correlator = ReplyCorrelator::new().with_strategy(MatchStrategy::Sequence);
+        correlator.register(100, 0, FIRST);
+        correlator.register(200, 1, SECOND);
+        let now = Instant::now();
+        correlator.record_request(100, 1, now);
+        correlator.record_request(200, 1, now);
+
+        let error = unreachable_reply(4242, 1, Ipv4Addr::new(192, 0, 2, 2));
+        assert_eq!(
+            correlator.classify(&error, router, now),
+            Received::Error {
+                host: 1,
+                sequence_no: 1,
+                source: router
+            }
+        );
+        assert_eq!(correlator.outstanding(), 1);
+
+        // The request is answered: a reply after all is a duplicate, and the
+        // same error again is nothing new
+        let late = now + Duration::from_millis(5);
+        assert_eq!(
+            correlator.classify(&echo_reply(4242, 1), SECOND, late),
+            Received::Duplicate {
+                host: 1,
+                sequence_no: 1,
+                rtt: Duration::from_millis(5)
+            }
+        );
+        assert_eq!(
+            correlator.classify(&error, router, now),
+            Received::Unrelated
+        );
+
+        let never_sent = unreachable_reply(4242, 9, Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(
+            correlator.classify(&never_sent, router, now),
+            Received::Orphan
+        );
+        assert_eq!(correlator.orphans(), 1);
+    }
+}
@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::stats::PingSummary;
+
+/// The outcome of pinging one host in one run, as kept in a history file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// When the run ended, in seconds since the unix epoch
+    pub finished_at: u64,
+    pub host: String,
+    pub packets_sent: u64,
+    pub packets_recv: u64,
+    /// Percentage of requests that went unanswered
+    pub packet_loss: f64,
+    /// Average round trip time, `None` if nothing replied
+    pub avg_rtt_ms: Option<f64>,
+}
+
+impl HistoryEntry {
+    /// The entry for the run summarized by `summary`, ended at `finished_at`
+    pub fn new(summary: &PingSummary, finished_at: SystemTime) -> Self {
+        Self {
+            finished_at: finished_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            host: summary.host.clone(),
+            packets_sent: summary.packets_sent,
+            packets_recv: summary.packets_recv,
+            packet_loss: summary.packet_loss,
+            avg_rtt_ms: summary.rtt.map(|rtt| rtt.avg_ms),
+        }
+    }
+}
+
+/// Append `entries` to the history file at `path`, one JSON object per line,
+/// creating it if needed
+pub fn append(path: &Path, entries: &[HistoryEntry]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for entry in entries {
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    }
+    file.flush()
+}
+
+/// The runs recorded in a history file
+#[derive(Debug, Default)]
+pub struct History {
+    /// Entries in the order they were recorded
+    pub entries: Vec<HistoryEntry>,
+    /// Lines that didn't parse as an entry and were left out
+    pub skipped: usize,
+}
+
+impl History {
+    /// Read the history file at `path`. A missing file is an empty history,
+    /// and lines that don't parse, e.g. one cut short by a crash, are
+    /// skipped and counted rather than failing the whole read.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+
+        let mut history = Self::default();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => history.entries.push(entry),
+                Err(_) => history.skipped += 1,
+            }
+        }
+        Ok(history)
+    }
+
+    /// The trend of the last `runs` runs recorded for `host`
+    pub fn trend(&self, host: &str, runs: usize) -> Trend {
+        let entries: Vec<&HistoryEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.host == host)
+            .collect();
+        Trend {
+            total_runs: entries.len(),
+            recent: entries[entries.len().saturating_sub(runs)..]
+                .iter()
+                .map(|entry| (*entry).clone())
+                .collect(),
+        }
+    }
+}
+
+/// The recent runs of one host, oldest first
+#[derive(Debug, Clone)]
+pub struct Trend {
+    /// Number of runs recorded for the host in all
+    pub total_runs: usize,
+    pub recent: Vec<HistoryEntry>,
+}
+
+impl Trend {
+    /// Packet loss of every recent run
+    pub fn losses(&self) -> Vec<f64> {
+        self.recent.iter().map(|entry| entry.packet_loss).collect()
+    }
+
+    /// Packet loss over all the recent runs together, `None` if none sent
+    /// anything
+    pub fn overall_loss(&self) -> Option<f64> {
+        let sent: u64 = self.recent.iter().map(|entry| entry.packets_sent).sum();
+        let recv: u64 = self.recent.iter().map(|entry| entry.packets_recv).sum();
+        (sent > 0).then(|| (sent - recv.min(sent)) as f64 / sent as f64 * 100.0)
+    }
+
+    /// Average round trip time over all the recent replies, weighting each
+    /// run by how many replies it got
+    pub fn overall_avg_rtt_ms(&self) -> Option<f64> {
+        let (total, replies) = self
+            .recent
+            .iter()
+            .filter_map(|entry| entry.avg_rtt_ms.map(|avg| (avg, entry.packets_recv)))
+            .fold((0.0, 0), |(total, replies), (avg, recv)| {
+                (total + avg * recv as f64, replies + recv)
+            });
+        (replies > 0).then(|| total / replies as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn entry(host: &str, packets_recv: u64, avg_rtt_ms: Option<f64>) -> HistoryEntry {
+        HistoryEntry {
+            finished_at: 1_700_000_000,
+            host: host.to_string(),
+            packets_sent: 10,
+            packets_recv,
+            packet_loss: (10 - packets_recv) as f64 * 10.0,
+            avg_rtt_ms,
+        }
+    }
+
+    #[test]
+    fn entries_round_trip_and_give_a_trend() {
+        let path = std::env::temp_dir().join(format!("pingoc-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let runs = [
+            entry("example.com", 10, Some(20.0)),
+            entry("example.org", 0, None),
+            entry("example.com", 8, Some(30.0)),
+            entry("example.com", 10, Some(10.0)),
+        ];
+        append(&path, &runs[..2]).unwrap();
+        append(&path, &runs[2..]).unwrap();
+        // A line cut short, as by a crash mid-write
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"finished_at\":17")
+            .unwrap();
+
+        let history = History::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(history.entries, runs);
+        assert_eq!(history.skipped, 1);
+
+        let trend = history.trend("example.com", 2);
+        assert_eq!(trend.total_runs, 3);
+        assert_eq!(trend.losses(), [20.0, 0.0]);
+        assert_eq!(trend.overall_loss(), Some(10.0));
+        // (8 * 30 + 10 * 10) / 18 replies
+        let avg = trend.overall_avg_rtt_ms().unwrap();
+        assert!((avg - 340.0 / 18.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn missing_file_is_an_empty_history() {
+        let history = History::read(Path::new("/nonexistent/pingoc-history")).unwrap();
+        assert!(history.entries.is_empty());
+        assert_eq!(history.trend("example.com", 7).overall_loss(), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use std::fmt;
+
+/// Number of distinct ICMP echo identifiers
+const IDENTIFIER_SPACE: u32 = 1 << 16;
+
+/// Error returned when every identifier is already in use
+#[derive(Debug)]
+pub struct IdentifiersExhausted;
+
+impl fmt::Display for IdentifiersExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "All {} ICMP identifiers are in use, ping fewer hosts at once",
+            IDENTIFIER_SPACE
+        )
+    }
+}
+
+impl std::error::Error for IdentifiersExhausted {}
+
+/// Hands out a distinct echo identifier to every host pinged over one socket,
+/// so replies can be attributed to the host whose request they answer.
+///
+/// Identifiers are counted up from a base, the first host getting the base
+/// itself. Released identifiers are handed out again before new ones.
+#[derive(Debug, Clone)]
+pub struct IdentifierAllocator {
+    base: u16,
+    /// Offset from `base` of the next identifier never handed out
+    fresh: u32,
+    released: Vec<u16>,
+    in_use: HashSet<u16>,
+}
+
+impl IdentifierAllocator {
+    /// Allocate identifiers counting up from `base`
+    pub fn new(base: u16) -> Self {
+        Self {
+            base,
+            fresh: 0,
+            released: Vec::new(),
+            in_use: HashSet::new(),
+        }
+    }
+
+    /// Allocate identifiers counting up from one derived from the process
+    /// id, so concurrent pingoc processes are unlikely to collide
+    pub fn for_process() -> Self {
+        Self::new(std::process::id() as u16)
+    }
+
+    /// An identifier no other host holds
+    pub fn allocate(&mut self) -> Result<u16, IdentifiersExhausted> {
+        let identifier = match self.released.pop() {
+            Some(identifier) => identifier,
+            None if self.fresh < IDENTIFIER_SPACE => {
+                self.fresh += 1;
+                self.base.wrapping_add((self.fresh - 1) as u16)
+            }
+            None => return Err(IdentifiersExhausted),
+        };
+        self.in_use.insert(identifier);
+        Ok(identifier)
+    }
+
+    /// Hand `identifier` back once its host is done. Returns `false` if it
+    /// wasn't allocated.
+    pub fn release(&mut self, identifier: u16) -> bool {
+        let allocated = self.in_use.remove(&identifier);
+        if allocated {
+            self.released.push(identifier);
+        }
+        allocated
+    }
+
+    /// Number of identifiers currently allocated
+    pub fn in_use(&self) -> usize {
+        self.in_use.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers_are_unique_until_released() {
+        let mut allocator = IdentifierAllocator::new(u16::MAX - 1);
+        let identifiers: Vec<u16> = (0..4).map(|_| allocator.allocate().unwrap()).collect();
+        assert_eq!(identifiers, [u16::MAX - 1, u16::MAX, 0, 1]);
+        assert_eq!(allocator.in_use(), 4);
+
+        assert!(allocator.release(u16::MAX));
+        assert!(!allocator.release(u16::MAX));
+        assert_eq!(allocator.allocate().unwrap(), u16::MAX);
+        assert_eq!(allocator.allocate().unwrap(), 2);
+    }
+
+    #[test]
+    fn every_identifier_can_be_allocated_once() {
+        let mut allocator = IdentifierAllocator::new(1000);
+        for _ in 0..IDENTIFIER_SPACE {
+            allocator.allocate().unwrap();
+        }
+        assert!(allocator.allocate().is_err());
+
+        allocator.release(1000);
+        assert_eq!(allocator.allocate().unwrap(), 1000);
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::pinger::ProbeEvent;
+
+/// How long a scrape may take to send its request before it is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request line and headers read before giving up on a scrape
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// Counters and gauges of one target
+#[derive(Debug, Default, Clone, Copy)]
+struct TargetMetrics {
+    sent: u64,
+    received: u64,
+    lost: u64,
+    /// Requests answered with an ICMP error rather than a reply
+    icmp_errors: u64,
+    errors: u64,
+    last_rtt: Option<Duration>,
+}
+
+impl TargetMetrics {
+    /// Share of the probes that got an answer or were given up on that got
+    /// no reply, whether lost or answered with an ICMP error. Probes still
+    /// in flight don't count, so the ratio doesn't dip between a send and
+    /// its reply.
+    fn loss_ratio(&self) -> f64 {
+        let unanswered = self.lost + self.icmp_errors;
+        match self.received + unanswered {
+            0 => 0.0,
+            settled => unanswered as f64 / settled as f64,
+        }
+    }
+}
+
+/// Live per-target probe metrics, rendered in the Prometheus text exposition
+/// format. Shared between whatever pings, e.g. through a `Pinger` hook fed
+/// to `record`, and the `MetricsServer` scraping them.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    targets: Mutex<BTreeMap<String, TargetMetrics>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account for a probe event of a `Pinger`, labelled with its host's
+    /// address
+    pub fn record(&self, event: &ProbeEvent) {
+        match event {
+            ProbeEvent::Sent { host, .. } => self.record_sent(&host.to_string()),
+            ProbeEvent::Reply(reply) => self.record_reply(&reply.host.to_string(), reply.rtt),
+            ProbeEvent::Timeout { host, .. } => self.record_lost(&host.to_string()),
+            ProbeEvent::IcmpError { host, .. } => self.record_icmp_error(&host.to_string()),
+            ProbeEvent::NotSent { host, .. } => self.record_error(&host.to_string()),
+            ProbeEvent::Resent { .. }
+            | ProbeEvent::Duplicate(_)
+            | ProbeEvent::Rejected { .. }
+            | ProbeEvent::Missing { .. }
+            | ProbeEvent::Error { .. } => {}
+        }
+    }
+
+    /// Account for an echo request sent to `target`
+    fn record_sent(&self, target: &str) {
+        self.update(target, |metrics| metrics.sent += 1);
+    }
+
+    /// Account for a reply from `target` after `rtt`
+    fn record_reply(&self, target: &str, rtt: Duration) {
+        self.update(target, |metrics| {
+            metrics.received += 1;
+            metrics.last_rtt = Some(rtt);
+        });
+    }
+
+    /// Account for a request to `target` given up on
+    fn record_lost(&self, target: &str) {
+        self.update(target, |metrics| metrics.lost += 1);
+    }
+
+    /// Account for a request to `target` answered with an ICMP error
+    fn record_icmp_error(&self, target: &str) {
+        self.update(target, |metrics| metrics.icmp_errors += 1);
+    }
+
+    /// Account for a request to `target` that couldn't be sent
+    fn record_error(&self, target: &str) {
+        self.update(target, |metrics| metrics.errors += 1);
+    }
+
+    fn update(&self, target: &str, update: impl FnOnce(&mut TargetMetrics)) {
+        let mut targets = self.targets.lock().unwrap();
+        update(targets.entry(target.to_string()).or_default());
+    }
+
+    /// Every metric of every target in the Prometheus text format
+    pub fn render(&self) -> String {
+        let targets = self.targets.lock().unwrap().clone();
+        let mut text = String::new();
+        let mut family =
+            |name: &str, kind: &str, help: &str, value: fn(&TargetMetrics) -> Option<f64>| {
+                let _ = writeln!(text, "# HELP {name} {help}");
+                let _ = writeln!(text, "# TYPE {name} {kind}");
+                for (target, metrics) in &targets {
+                    if let Some(value) = value(metrics) {
+                        let _ = writeln!(
+                            text,
+                            "{name}{{target=\"{}\"}} {value}",
+                            escape_label(target)
+                        );
+                    }
+                }
+            };
+
+        family(
+            "pingoc_packets_sent_total",
+            "counter",
+            "Echo requests sent.",
+            |metrics| Some(metrics.sent as f64),
+        );
+        family(
+            "pingoc_packets_received_total",
+            "counter",
+            "Echo replies received.",
+            |metrics| Some(metrics.received as f64),
+        );
+        family(
+            "pingoc_packets_lost_total",
+            "counter",
+            "Echo requests given up on without a reply.",
+            |metrics| Some(metrics.lost as f64),
+        );
+        family(
+            "pingoc_icmp_errors_total",
+            "counter",
+            "Echo requests answered with an ICMP error, e.g. destination unreachable.",
+            |metrics| Some(metrics.icmp_errors as f64),
+        );
+        family(
+            "pingoc_send_errors_total",
+            "counter",
+            "Echo requests that could not be sent.",
+            |metrics| Some(metrics.errors as f64),
+        );
+        family(
+            "pingoc_packet_loss_ratio",
+            "gauge",
+            "Share of the answered or given up requests that got no reply.",
+            |metrics| Some(metrics.loss_ratio()),
+        );
+        family(
+            "pingoc_rtt_seconds",
+            "gauge",
+            "Round trip time of the latest reply.",
+            |metrics| metrics.last_rtt.map(|rtt| rtt.as_secs_f64()),
+        );
+        text
+    }
+}
+
+/// A label value with backslashes, quotes and newlines escaped
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A minimal HTTP server answering `GET /metrics` with the current
+/// `Metrics`, one connection at a time, and with an error to anything else
+#[derive(Debug)]
+pub struct MetricsServer {
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsServer {
+    /// Bind to `address`, e.g. `127.0.0.1:0` for a free port, serving
+    /// `metrics`
+    pub fn bind(address: impl ToSocketAddrs, metrics: Arc<Metrics>) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            metrics,
+        })
+    }
+
+    /// The address the server listens on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answer a single scrape
+    pub fn serve_one(&self) -> io::Result<()> {
+        let (stream, _) = self.listener.accept()?;
+        self.answer(stream)
+    }
+
+    /// Answer scrapes until the listener fails, skipping connections that
+    /// fail midway
+    pub fn serve(&self) -> io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            let _ = self.answer(stream);
+        }
+    }
+
+    /// Serve from a background thread for the rest of the process
+    pub fn spawn(self) -> thread::JoinHandle<io::Result<()>> {
+        thread::spawn(move || self.serve())
+    }
+
+    fn answer(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?).take(MAX_REQUEST_LEN as u64);
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // The headers tell nothing we need, but are read so that closing
+        // the connection doesn't reset it under the client
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.metrics.render()),
+            (Some("GET"), Some(_)) => ("404 Not Found", "Not found\n".to_string()),
+            _ => (
+                "405 Method Not Allowed",
+                "Only GET is supported\n".to_string(),
+            ),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::pinger::ProbeReply;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    /// Send `request` to a server answering a single scrape of `metrics`,
+    /// returning the whole response
+    fn scrape(metrics: Arc<Metrics>, request: &str) -> String {
+        let server = MetricsServer::bind("127.0.0.1:0", metrics).unwrap();
+        let address = server.local_addr().unwrap();
+        let serving = thread::spawn(move || server.serve_one());
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        serving.join().unwrap().unwrap();
+        response
+    }
+
+    #[test]
+    fn probe_events_are_scraped_from_metrics() {
+        let host = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let metrics = Arc::new(Metrics::new());
+        for sequence_no in 1..=3 {
+            metrics.record(&ProbeEvent::Sent { host, sequence_no });
+        }
+        metrics.record(&ProbeEvent::Reply(ProbeReply {
+            host,
+            source: host,
+            sequence_no: 1,
+            num_bytes: 56,
+            rtt: Duration::from_millis(250),
+            ttl: Some(64),
+            ip_header_len: None,
+            corruption: None,
+            tag_mismatch: None,
+            attempts: 1,
+        }));
+        metrics.record(&ProbeEvent::Timeout {
+            host,
+            sequence_no: 3,
+        });
+        metrics.record(&ProbeEvent::IcmpError {
+            host,
+            sequence_no: 2,
+            source: IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1)),
+            kind: "unreachable".to_string(),
+            description: "Destination Host Unreachable".to_string(),
+        });
+
+        let response = scrape(metrics, "GET /metrics HTTP/1.1\r\nHost: pingoc\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        for line in [
+            "pingoc_packets_sent_total{target=\"192.0.2.1\"} 3",
+            "pingoc_packets_received_total{target=\"192.0.2.1\"} 1",
+            "pingoc_packets_lost_total{target=\"192.0.2.1\"} 1",
+            "pingoc_icmp_errors_total{target=\"192.0.2.1\"} 1",
+            "pingoc_send_errors_total{target=\"192.0.2.1\"} 0",
+            "pingoc_rtt_seconds{target=\"192.0.2.1\"} 0.25",
+        ] {
+            assert!(body.lines().any(|got| got == line), "{line} in {body}");
+        }
+        // Two of the three requests settled got no reply
+        let ratio = body
+            .lines()
+            .find_map(|line| line.strip_prefix("pingoc_packet_loss_ratio{target=\"192.0.2.1\"} "))
+            .unwrap();
+        assert!(
+            (ratio.parse::<f64>().unwrap() - 2.0 / 3.0).abs() < 1e-9,
+            "{ratio}"
+        );
+    }
+
+    #[test]
+    fn other_paths_are_not_found() {
+        let response = scrape(Arc::new(Metrics::new()), "GET / HTTP/1.1\r\n\r\n");
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{response}"
+        );
+    }
+}
@@ -0,0 +1,14 @@
+pub mod compare;
+pub mod correlate;
+#[cfg(feature = "json")]
+pub mod history;
+pub mod identifiers;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod output;
+pub mod pacer;
+pub mod pinger;
+pub mod snapshot;
+pub mod stats;
+pub mod tag;
+pub mod timestamp;
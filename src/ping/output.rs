@@ -0,0 +1,273 @@
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How to render the timestamp prefixed to each output line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// Seconds since the unix epoch, e.g. `[1700000000.123456]`
+    Unix,
+    /// ISO-8601 in UTC, e.g. `[2023-11-14T22:13:20.123456Z]`
+    Iso,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "unix" => Ok(Self::Unix),
+            "iso" | "iso8601" => Ok(Self::Iso),
+            other => Err(format!(
+                "unknown timestamp format {other:?}, expected unix or iso"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TimestampFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unix => write!(f, "unix"),
+            Self::Iso => write!(f, "iso"),
+        }
+    }
+}
+
+/// Whether to color output lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            other => Err(format!(
+                "unknown color choice {other:?}, expected auto, always or never"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Always => write!(f, "always"),
+            Self::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Whether to color output going to a stream, `is_terminal` telling if
+    /// that stream is a terminal
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => is_terminal && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+        }
+    }
+}
+
+/// Kind of output line, deciding its color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineStyle {
+    Reply,
+    Timeout,
+    Error,
+}
+
+/// Wrap `line` in the ANSI color of `style` if `enabled`. The text itself
+/// is left untouched, so stripping the escapes gives back the plain line.
+pub fn paint(line: &str, style: LineStyle, enabled: bool) -> String {
+    if !enabled {
+        return line.to_string();
+    }
+
+    let code = match style {
+        LineStyle::Reply => "32",
+        LineStyle::Timeout => "33",
+        LineStyle::Error => "31",
+    };
+    format!("\x1b[{code}m{line}\x1b[0m")
+}
+
+/// Format `time` for use as a line prefix, without the surrounding brackets
+pub fn format_timestamp(format: TimestampFormat, time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let micros = since_epoch.subsec_micros();
+
+    match format {
+        TimestampFormat::Unix => format!("{secs}.{micros:06}"),
+        TimestampFormat::Iso => {
+            let (year, month, day) = civil_from_days((secs / 86400) as i64);
+            let secs_of_day = secs % 86400;
+            format!(
+                "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{micros:06}Z",
+                secs_of_day / 3600,
+                secs_of_day / 60 % 60,
+                secs_of_day % 60
+            )
+        }
+    }
+}
+
+/// Prefix for an output line, empty when timestamps are disabled
+pub fn line_prefix(format: Option<TimestampFormat>) -> String {
+    match format {
+        Some(format) => format!("[{}] ", format_timestamp(format, SystemTime::now())),
+        None => String::new(),
+    }
+}
+
+/// Header row of the per-probe CSV log
+pub const CSV_HEADER: &str = "timestamp,target,seq,rtt_ms,ttl,status";
+
+/// Per-probe log in CSV, one row per probe as it completes.
+///
+/// Rows are appended, with the header only written to a new or empty file,
+/// and flushed one by one so that an interrupted run still leaves a usable
+/// file.
+#[derive(Debug)]
+pub struct CsvLog {
+    file: Mutex<File>,
+}
+
+impl CsvLog {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{CSV_HEADER}")?;
+        }
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append the row of one probe. `rtt` and `ttl` are left empty for lost
+    /// probes.
+    pub fn record(
+        &self,
+        target: IpAddr,
+        sequence_no: u16,
+        rtt: Option<Duration>,
+        ttl: Option<u32>,
+        status: LineStyle,
+    ) -> io::Result<()> {
+        let row = csv_row(SystemTime::now(), target, sequence_no, rtt, ttl, status);
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{row}")?;
+        file.flush()
+    }
+}
+
+/// Format one row of the CSV log, with the time in ISO-8601
+pub fn csv_row(
+    time: SystemTime,
+    target: IpAddr,
+    sequence_no: u16,
+    rtt: Option<Duration>,
+    ttl: Option<u32>,
+    status: LineStyle,
+) -> String {
+    let rtt = rtt.map_or(String::new(), |rtt| {
+        format!("{:.3}", rtt.as_secs_f64() * 1000.0)
+    });
+    let ttl = ttl.map_or(String::new(), |ttl| ttl.to_string());
+    let status = match status {
+        LineStyle::Reply => "reply",
+        LineStyle::Timeout => "timeout",
+        LineStyle::Error => "error",
+    };
+    format!(
+        "{},{target},{sequence_no},{rtt},{ttl},{status}",
+        format_timestamp(TimestampFormat::Iso, time)
+    )
+}
+
+/// Convert days since the unix epoch into a (year, month, day) date in the
+/// proleptic Gregorian calendar (Howard Hinnant's `civil_from_days`)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::net::Ipv4Addr;
+
+    const TARGET: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    #[test]
+    fn csv_rows_leave_lost_probes_without_rtt_or_ttl() {
+        let time = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        assert_eq!(
+            csv_row(
+                time,
+                TARGET,
+                7,
+                Some(Duration::from_micros(12_345)),
+                Some(64),
+                LineStyle::Reply
+            ),
+            "2023-11-14T22:13:20.123456Z,192.0.2.1,7,12.345,64,reply"
+        );
+        assert_eq!(
+            csv_row(time, TARGET, 8, None, None, LineStyle::Timeout),
+            "2023-11-14T22:13:20.123456Z,192.0.2.1,8,,,timeout"
+        );
+    }
+
+    #[test]
+    fn csv_log_writes_the_header_once() {
+        let path = env::temp_dir().join(format!("pingoc-csv-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        CsvLog::open(&path)
+            .unwrap()
+            .record(TARGET, 0, None, None, LineStyle::Timeout)
+            .unwrap();
+        CsvLog::open(&path)
+            .unwrap()
+            .record(TARGET, 1, None, None, LineStyle::Error)
+            .unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].ends_with(",192.0.2.1,0,,,timeout"));
+        assert!(lines[2].ends_with(",192.0.2.1,1,,,error"));
+    }
+}
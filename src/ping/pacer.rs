@@ -0,0 +1,104 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token bucket capping how many packets are sent per second.
+///
+/// Tokens accrue at the configured rate up to a burst of `burst` tokens, and
+/// each packet consumes one. The bucket is behind a mutex so a single pacer
+/// can be shared by everything sending, making the cap global rather than per
+/// host.
+#[derive(Debug)]
+pub struct Pacer {
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Pacer {
+    /// Allow `packets_per_second` packets per second, without bursts
+    pub fn new(packets_per_second: u32) -> Self {
+        Self::with_burst(packets_per_second, 1)
+    }
+
+    /// Allow `packets_per_second` packets per second on average, letting up to
+    /// `burst` packets go out back to back after an idle period
+    pub fn with_burst(packets_per_second: u32, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+        Self {
+            rate: packets_per_second.max(1) as f64,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token if one is available
+    pub fn try_acquire(&self) -> bool {
+        self.take().is_none()
+    }
+
+    /// Block until a token is available and take it
+    pub fn acquire(&self) {
+        while let Some(wait) = self.take() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Take a token, or return how long until the next one accrues
+    fn take(&self) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_most_the_rate_is_allowed_over_a_second() {
+        let pacer = Pacer::with_burst(50, 5);
+        let started_at = Instant::now();
+        let mut acquired = 0;
+        while started_at.elapsed() < Duration::from_secs(1) {
+            if pacer.try_acquire() {
+                acquired += 1;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // The full bucket lets the first burst through at once
+        assert!(acquired <= 50 + 5, "{acquired} tokens in a second");
+        assert!(acquired >= 45, "only {acquired} tokens in a second");
+    }
+
+    #[test]
+    fn acquire_waits_for_the_next_token() {
+        let pacer = Pacer::new(20);
+        let started_at = Instant::now();
+        for _ in 0..3 {
+            pacer.acquire();
+        }
+        // The first token is there already, the other two take 50ms each
+        assert!(started_at.elapsed() >= Duration::from_millis(95));
+    }
+}
@@ -0,0 +1,1214 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::correlate::{
+    MatchStrategy, MatchedReply, PayloadMismatch, Received, ReplyCorrelator, SourceCheck,
+};
+use super::identifiers::IdentifierAllocator;
+use super::pacer::Pacer;
+use super::stats::{PingStats, PingSummary};
+use super::tag::{self, TagMismatch};
+use super::timestamp;
+use crate::icmp::endpoint::IcmpEndpoint;
+use crate::icmp::packet::IcmpPacket;
+use crate::icmp::socket::{IcmpSocket, IcmpSocketError, RecvInfo};
+use crate::icmp::transport::PacketTransport;
+use crate::icmp::types::IcmpType;
+use crate::resolve::{resolve_hostname_in, AddressFamily};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// An echo reply received by a `Pinger`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeReply {
+    pub host: IpAddr,
+    /// The address the reply came from, another than the host's for
+    /// anycast, NAT or a broadcast answered by several hosts
+    pub source: IpAddr,
+    pub sequence_no: u16,
+    pub num_bytes: usize,
+    pub rtt: Duration,
+    /// TTL or hop limit the reply arrived with, if the socket reported it
+    pub ttl: Option<u8>,
+    /// Length of the IP header the reply arrived with, if the socket
+    /// delivers it (raw IPv4 sockets only)
+    pub ip_header_len: Option<usize>,
+    /// How the echoed payload differs from the one sent, if it does
+    pub corruption: Option<PayloadMismatch>,
+    /// How the payload failed to echo the tag, see `Pinger::with_tag`
+    pub tag_mismatch: Option<TagMismatch>,
+    /// Number of times the request was sent, more than one if it was retried
+    pub attempts: usize,
+}
+
+/// Something that happened to a probe, passed to the hooks registered with
+/// `Pinger::on_event`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeEvent {
+    /// An echo request was sent
+    Sent { host: IpAddr, sequence_no: u16 },
+    /// A request still unanswered was sent again, for the `attempt`th time,
+    /// see `Pinger::with_retries`
+    Resent {
+        host: IpAddr,
+        sequence_no: u16,
+        attempt: usize,
+    },
+    /// An echo reply was matched to its request
+    Reply(ProbeReply),
+    /// Another reply to a request already answered, e.g. from one more host
+    /// answering a broadcast, which the statistics ignore
+    Duplicate(ProbeReply),
+    /// A reply to a request to `host` came from `source` and was rejected,
+    /// see `SourceCheck::Strict`
+    Rejected {
+        host: IpAddr,
+        sequence_no: u16,
+        source: IpAddr,
+    },
+    /// A reply from `host` arrived while these earlier requests to it were
+    /// still unanswered
+    Missing {
+        host: IpAddr,
+        sequence_nos: Vec<u16>,
+    },
+    /// A request to `host` was given up on by `Pinger::expire`
+    Timeout { host: IpAddr, sequence_no: u16 },
+    /// A request to `host` was answered with an ICMP error from `source`,
+    /// e.g. destination unreachable, of `kind` as counted in
+    /// `PingStats::errors`
+    IcmpError {
+        host: IpAddr,
+        sequence_no: u16,
+        source: IpAddr,
+        kind: String,
+        description: String,
+    },
+    /// Sending a request to `host` failed with `message`
+    NotSent {
+        host: IpAddr,
+        sequence_no: u16,
+        message: String,
+    },
+    /// Receiving failed with `message`
+    Error { message: String },
+}
+
+/// A socket a `Pinger` sends its probes over: an `IcmpSocket`, or an
+/// `IcmpEndpoint` to ping either address family
+pub trait ProbeSocket {
+    /// Send `packet` to `ip`
+    fn send_probe(&self, packet: &mut IcmpPacket, ip: IpAddr) -> Result<usize>;
+
+    /// Wait up to `timeout` for a packet, see `IcmpSocket::recv_timeout`
+    fn recv_probe(&self, timeout: Duration) -> Result<Option<(IcmpPacket, IpAddr, RecvInfo)>>;
+}
+
+impl<T: PacketTransport> ProbeSocket for IcmpSocket<T> {
+    fn send_probe(&self, packet: &mut IcmpPacket, ip: IpAddr) -> Result<usize> {
+        match ip {
+            IpAddr::V4(ip) => self.send_to(packet, ip),
+            IpAddr::V6(_) => Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{ip} can't be reached over an IPv4 socket"),
+            ))),
+        }
+    }
+
+    fn recv_probe(&self, timeout: Duration) -> Result<Option<(IcmpPacket, IpAddr, RecvInfo)>> {
+        let received = self.recv_timeout(timeout)?;
+        Ok(received.map(|(packet, source, info)| (packet, source.into(), info)))
+    }
+}
+
+impl ProbeSocket for IcmpEndpoint {
+    fn send_probe(&self, packet: &mut IcmpPacket, ip: IpAddr) -> Result<usize> {
+        self.send_to(packet, ip)
+    }
+
+    fn recv_probe(&self, timeout: Duration) -> Result<Option<(IcmpPacket, IpAddr, RecvInfo)>> {
+        self.recv_timeout(timeout)
+    }
+}
+
+/// Pings a set of hosts over one shared socket.
+///
+/// Sending and receiving are separate steps: `send_all` sends every probe,
+/// and `collect` then waits for replies still in flight for as long as the
+/// caller wants, so a reply arriving just after the last send isn't counted
+/// as lost merely because the run ended. `expire` then gives up on the
+/// requests still unanswered, or resends those with retries left.
+///
+/// The pinger prints nothing itself; register hooks with `on_event` to log,
+/// count or display probes as they happen.
+pub struct Pinger<S: ProbeSocket = IcmpSocket> {
+    socket: S,
+    hosts: Vec<(IpAddr, PingStats)>,
+    correlator: ReplyCorrelator,
+    /// Echo identifier of each host, in the order of `hosts`
+    identifiers: Vec<u16>,
+    next_sequence_no: u16,
+    count: u16,
+    interval: Duration,
+    packet_size: usize,
+    icmp_code: u8,
+    tag: Option<Vec<u8>>,
+    retries: usize,
+    /// Times each request not yet settled was sent, by host and sequence
+    attempts: HashMap<(usize, u16), usize>,
+    broadcast: bool,
+    stop_on_unreachable: Option<u32>,
+    /// Requests to each host answered with destination unreachable in a row
+    unreachable_streaks: Vec<u32>,
+    pacer: Option<Pacer>,
+    hooks: Vec<Box<dyn FnMut(ProbeEvent)>>,
+}
+
+impl Pinger {
+    /// Ping `hosts` over a new ICMP socket, whose receive timeout is `timeout`
+    pub fn new(hosts: &[Ipv4Addr], timeout: Duration) -> Result<Self> {
+        for host in hosts {
+            IcmpSocket::validate_destination(*host)?;
+        }
+        let socket = IcmpSocket::new(timeout)?;
+        let strategy = MatchStrategy::for_socket(socket.kind());
+        let hosts: Vec<IpAddr> = hosts.iter().map(|&host| host.into()).collect();
+        Ok(Self::with_socket(socket, &hosts)?.with_match_strategy(strategy))
+    }
+
+    /// Resolve `host` and send it a single echo request, returning the round
+    /// trip time of its reply or `None` if none arrived within `timeout`.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use pingoc::ping::pinger::Pinger;
+    /// match Pinger::ping_once("example.com", Duration::from_secs(1))? {
+    ///     Some(rtt) => println!("up, {:.3} ms", rtt.as_secs_f64() * 1000.0),
+    ///     None => println!("down"),
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn ping_once(host: &str, timeout: Duration) -> Result<Option<Duration>> {
+        let IpAddr::V4(ip) = resolve_hostname_in(host, AddressFamily::V4)?.0 else {
+            return Err(format!("{host} did not resolve to an IPv4 address").into());
+        };
+
+        let failure = Rc::new(RefCell::new(None));
+        let hook_failure = Rc::clone(&failure);
+        let mut pinger = Pinger::new(&[ip], timeout)?.on_event(move |event| {
+            if let ProbeEvent::NotSent { message, .. } | ProbeEvent::Error { message } = event {
+                *hook_failure.borrow_mut() = Some(message);
+            }
+        });
+
+        pinger.send_round()?;
+        let reply = pinger.collect(timeout).first().map(|reply| reply.rtt);
+        match (reply, failure.take()) {
+            (None, Some(message)) => Err(message.into()),
+            (reply, _) => Ok(reply),
+        }
+    }
+}
+
+impl<S: ProbeSocket> Pinger<S> {
+    /// Ping `hosts` over an existing socket, e.g. one with a mock transport.
+    /// Fails if there are more hosts than echo identifiers.
+    pub fn with_socket(socket: S, hosts: &[IpAddr]) -> Result<Self> {
+        let mut allocator = IdentifierAllocator::for_process();
+        let mut correlator = ReplyCorrelator::new();
+        let mut identifiers = Vec::with_capacity(hosts.len());
+        for (i, host) in hosts.iter().enumerate() {
+            let identifier = allocator.allocate()?;
+            correlator.register(identifier, i, *host);
+            identifiers.push(identifier);
+        }
+
+        Ok(Self {
+            socket,
+            hosts: hosts.iter().map(|host| (*host, PingStats::new())).collect(),
+            correlator,
+            identifiers,
+            next_sequence_no: 1,
+            count: 1,
+            interval: Duration::from_secs(1),
+            packet_size: 56,
+            icmp_code: 0,
+            tag: None,
+            retries: 0,
+            attempts: HashMap::new(),
+            broadcast: false,
+            stop_on_unreachable: None,
+            unreachable_streaks: vec![0; hosts.len()],
+            pacer: None,
+            hooks: Vec::new(),
+        })
+    }
+
+    /// How replies are matched to requests, by identifier unless set. `new`
+    /// picks the strategy suiting the socket it opens.
+    pub fn with_match_strategy(mut self, strategy: MatchStrategy) -> Self {
+        self.correlator = std::mem::take(&mut self.correlator).with_strategy(strategy);
+        self
+    }
+
+    /// Accept or reject replies from another address than their host's
+    pub fn with_source_check(mut self, source_check: SourceCheck) -> Self {
+        self.correlator = std::mem::take(&mut self.correlator).with_source_check(source_check);
+        self
+    }
+
+    /// Number of probes `send_all` sends to each host
+    pub fn with_count(mut self, count: u16) -> Self {
+        self.count = count;
+        self
+    }
+
+    /// Time between rounds of probes in `send_all`
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Payload size of each probe
+    pub fn with_packet_size(mut self, packet_size: usize) -> Self {
+        self.packet_size = packet_size;
+        self
+    }
+
+    /// ICMP code of each probe, 0 as RFC 792 requires unless set
+    pub fn with_icmp_code(mut self, icmp_code: u8) -> Self {
+        self.icmp_code = icmp_code;
+        self
+    }
+
+    /// Put `tag` in every payload, after the timestamp and cut short to fit,
+    /// and check that replies echo it back
+    pub fn with_tag(mut self, tag: &[u8]) -> Self {
+        self.tag = Some(tag.to_vec());
+        self
+    }
+
+    /// Have `expire` resend a request up to `retries` times, under the same
+    /// sequence number, before giving up on it as timed out. Requests that
+    /// fail to send or are answered with an ICMP error are reported at once.
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Have `send_all` stop once `limit` requests in a row to a host were
+    /// answered with destination unreachable, see `unreachable`
+    pub fn with_stop_on_unreachable(mut self, limit: u32) -> Self {
+        self.stop_on_unreachable = Some(limit);
+        self
+    }
+
+    /// Cap the rate probes are sent at
+    pub fn with_pacer(mut self, pacer: Pacer) -> Self {
+        self.pacer = Some(pacer);
+        self
+    }
+
+    /// Ping broadcast addresses, whose every request may be answered by any
+    /// number of hosts. `collect` listens for the whole linger, and the
+    /// replies after the first are reported as `ProbeEvent::Duplicate`.
+    /// The socket must allow broadcasting, see `IcmpSocket::set_broadcast`.
+    pub fn with_broadcast(mut self) -> Self {
+        self.broadcast = true;
+        self.with_source_check(SourceCheck::Lenient)
+    }
+
+    /// Call `hook` on every probe event, after any hooks registered before
+    pub fn on_event(mut self, hook: impl FnMut(ProbeEvent) + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    fn emit(&mut self, event: ProbeEvent) {
+        for hook in &mut self.hooks {
+            hook(event.clone());
+        }
+    }
+
+    /// Send `count` probes to every host, `interval` apart. Replies arriving
+    /// between rounds are received while waiting, replies to the last round
+    /// are left for `collect`. Stops early once a host is `unreachable`.
+    pub fn send_all(&mut self) -> Result<Vec<ProbeReply>> {
+        let mut replies = Vec::new();
+
+        for round in 0..self.count {
+            if round > 0 {
+                let next_round = Instant::now() + self.interval;
+                replies.extend(self.collect(self.interval));
+                if self.unreachable().is_some() {
+                    break;
+                }
+                thread::sleep(next_round.saturating_duration_since(Instant::now()));
+            }
+            self.send_round()?;
+        }
+
+        Ok(replies)
+    }
+
+    /// Send one probe to every host. Only an error that would recur on every
+    /// request, a packet too large, is returned; other send failures are
+    /// reported as `ProbeEvent::NotSent`.
+    pub fn send_round(&mut self) -> Result<()> {
+        let sequence_no = self.next_sequence_no;
+        self.next_sequence_no = sequence_no.wrapping_add(1);
+
+        for host in 0..self.hosts.len() {
+            self.hosts[host].1.record_sent(self.packet_size);
+            self.send_attempt(host, sequence_no, 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Send request `sequence_no` to `host` for the `attempt`th time
+    fn send_attempt(&mut self, host: usize, sequence_no: u16, attempt: usize) -> Result<()> {
+        if let Some(pacer) = &self.pacer {
+            pacer.acquire();
+        }
+        let address = self.hosts[host].0;
+        let identifier = self.identifiers[host];
+        let mut packet = self.echo_request(identifier, sequence_no);
+        self.attempts.insert((host, sequence_no), attempt);
+
+        // Before sending, as the kernel may timestamp the reply before the
+        // send returns, e.g. over loopback
+        let sent_at = Instant::now();
+        if let Err(err) = self.socket.send_probe(&mut packet, address) {
+            if is_fatal_send_error(&*err) {
+                return Err(err);
+            }
+            self.emit(ProbeEvent::NotSent {
+                host: address,
+                sequence_no,
+                message: err.to_string(),
+            });
+            self.attempts.remove(&(host, sequence_no));
+            self.hosts[host].1.record_sent_failure();
+            self.unreachable_streaks[host] = 0;
+            return Ok(());
+        }
+
+        self.correlator.record_request_with_payload(
+            identifier,
+            sequence_no,
+            sent_at,
+            &packet.payload,
+        );
+        self.emit(match attempt {
+            1 => ProbeEvent::Sent {
+                host: address,
+                sequence_no,
+            },
+            attempt => ProbeEvent::Resent {
+                host: address,
+                sequence_no,
+                attempt,
+            },
+        });
+        Ok(())
+    }
+
+    /// The request `sequence_no` to the host with `identifier`, carrying
+    /// the tag, if any, and timestamped when its payload has room
+    fn echo_request(&self, identifier: u16, sequence_no: u16) -> IcmpPacket {
+        let mut packet = IcmpPacket::echo_request_with_code(
+            identifier,
+            sequence_no,
+            self.packet_size,
+            self.icmp_code,
+        );
+        if let Some(tag) = &self.tag {
+            tag::write(&mut packet, tag);
+        }
+        timestamp::stamp(&mut packet);
+        packet
+    }
+
+    /// Receive replies until none are outstanding or `linger` has elapsed.
+    /// Requests still unanswered afterwards stay outstanding, so a later call
+    /// can pick up their replies.
+    pub fn collect(&mut self, linger: Duration) -> Vec<ProbeReply> {
+        let deadline = Instant::now() + linger;
+        let mut replies = Vec::new();
+
+        // Any number of hosts may answer a broadcast, so listen throughout
+        while self.broadcast || self.correlator.outstanding() > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let (packet, source, info) = match self.socket.recv_probe(remaining) {
+                Ok(Some(received)) => received,
+                Ok(None) => continue,
+                Err(err) => {
+                    if !is_timeout(&*err) {
+                        self.emit(ProbeEvent::Error {
+                            message: err.to_string(),
+                        });
+                    }
+                    break;
+                }
+            };
+            replies.extend(self.receive(&packet, source, info));
+        }
+
+        replies
+    }
+
+    /// Account for a packet from `source`, returning the reply it is if it
+    /// answers an outstanding request
+    fn receive(
+        &mut self,
+        packet: &IcmpPacket,
+        source: IpAddr,
+        info: RecvInfo,
+    ) -> Option<ProbeReply> {
+        let received_at = received_at(info);
+        match self.correlator.classify(packet, source, received_at) {
+            Received::Reply(matched) => {
+                let attempts = self
+                    .attempts
+                    .remove(&(matched.host, matched.sequence_no))
+                    .unwrap_or(1);
+                let reply = ProbeReply {
+                    corruption: matched.corruption,
+                    attempts,
+                    ..self.probe_reply(
+                        packet,
+                        source,
+                        info,
+                        matched.host,
+                        matched.sequence_no,
+                        reply_rtt(packet, self.packet_size, &matched, received_at),
+                    )
+                };
+                let (host, stats) = &mut self.hosts[matched.host];
+                let host = *host;
+                stats.record_reply(reply.num_bytes, reply.rtt, attempts > 1);
+                if reply.corruption.is_some() {
+                    stats.record_corrupted();
+                }
+                if reply.tag_mismatch.is_some() {
+                    stats.record_mistagged();
+                }
+                if let Some(ttl) = reply.ttl {
+                    stats.record_ttl(ttl);
+                }
+                if matched.foreign_source.is_some() && !self.broadcast {
+                    stats.record_foreign_reply();
+                }
+                let missing = stats.record_sequence(matched.sequence_no);
+                self.unreachable_streaks[matched.host] = 0;
+
+                self.emit(ProbeEvent::Reply(reply.clone()));
+                if !missing.is_empty() {
+                    self.emit(ProbeEvent::Missing {
+                        host,
+                        sequence_nos: missing,
+                    });
+                }
+                Some(reply)
+            }
+            Received::Duplicate {
+                host,
+                sequence_no,
+                rtt,
+            } => {
+                let reply = self.probe_reply(packet, source, info, host, sequence_no, rtt);
+                self.emit(ProbeEvent::Duplicate(reply));
+                None
+            }
+            Received::ForeignSource {
+                host,
+                sequence_no,
+                source,
+            } => {
+                let (address, stats) = &mut self.hosts[host];
+                let address = *address;
+                stats.record_foreign_reply();
+                self.emit(ProbeEvent::Rejected {
+                    host: address,
+                    sequence_no,
+                    source,
+                });
+                None
+            }
+            Received::Error {
+                host,
+                sequence_no,
+                source,
+            } => {
+                self.receive_error(packet, host, sequence_no, source);
+                None
+            }
+            Received::Orphan | Received::Unrelated => None,
+        }
+    }
+
+    /// A reply from `source` to request `sequence_no` of `host`, as far as
+    /// the packet tells
+    fn probe_reply(
+        &self,
+        packet: &IcmpPacket,
+        source: IpAddr,
+        info: RecvInfo,
+        host: usize,
+        sequence_no: u16,
+        rtt: Duration,
+    ) -> ProbeReply {
+        ProbeReply {
+            host: self.hosts[host].0,
+            source,
+            sequence_no,
+            num_bytes: packet.payload.len(),
+            rtt,
+            ttl: info.ttl,
+            ip_header_len: info.ip_header_len,
+            corruption: None,
+            tag_mismatch: self
+                .tag
+                .as_ref()
+                .and_then(|tag| tag::verify(packet, tag, self.packet_size).err()),
+            attempts: 1,
+        }
+    }
+
+    /// Account for the ICMP error `packet` from `source` answering request
+    /// `sequence_no` of `host`
+    fn receive_error(
+        &mut self,
+        packet: &IcmpPacket,
+        host: usize,
+        sequence_no: u16,
+        source: IpAddr,
+    ) {
+        self.attempts.remove(&(host, sequence_no));
+        let kind = error_kind(packet.msg_type);
+        let (address, stats) = &mut self.hosts[host];
+        let address = *address;
+        stats.record_error(&kind);
+        if matches!(packet.msg_type, IcmpType::DestinationUnreachable) {
+            self.unreachable_streaks[host] += 1;
+        } else {
+            self.unreachable_streaks[host] = 0;
+        }
+        self.emit(ProbeEvent::IcmpError {
+            host: address,
+            sequence_no,
+            source,
+            kind,
+            description: packet.description(),
+        });
+    }
+
+    /// Give up on every probe still waiting for a reply, reporting a
+    /// `ProbeEvent::Timeout` for each, unless it has retries left. Those are
+    /// resent instead. Returns the number resent, whose replies a further
+    /// `collect` picks up.
+    pub fn expire(&mut self) -> Result<usize> {
+        let mut resend = Vec::new();
+        let mut expired = self.correlator.expire();
+        expired.sort_unstable_by_key(|(host, _)| *host);
+        for (host, lost) in expired {
+            for sequence_no in lost {
+                let attempts = self
+                    .attempts
+                    .get(&(host, sequence_no))
+                    .copied()
+                    .unwrap_or(1);
+                if attempts <= self.retries {
+                    resend.push((host, sequence_no));
+                    continue;
+                }
+                self.attempts.remove(&(host, sequence_no));
+                self.hosts[host].1.record_timeouts(1);
+                self.unreachable_streaks[host] = 0;
+                let host = self.hosts[host].0;
+                self.emit(ProbeEvent::Timeout { host, sequence_no });
+            }
+        }
+
+        for &(host, sequence_no) in &resend {
+            let attempt = self
+                .attempts
+                .get(&(host, sequence_no))
+                .copied()
+                .unwrap_or(1)
+                + 1;
+            self.hosts[host].1.record_resent(self.packet_size);
+            self.send_attempt(host, sequence_no, attempt)?;
+        }
+        Ok(resend.len())
+    }
+
+    /// Number of probes still waiting for a reply
+    pub fn outstanding(&self) -> usize {
+        self.correlator.outstanding()
+    }
+
+    /// Index of a host, in the order given, the last `with_stop_on_unreachable`
+    /// requests to which were all answered with destination unreachable
+    pub fn unreachable(&self) -> Option<usize> {
+        let limit = self.stop_on_unreachable?;
+        self.unreachable_streaks
+            .iter()
+            .position(|&streak| streak >= limit)
+    }
+
+    /// Number of duplicate replies received, which the statistics ignore
+    pub fn duplicates(&self) -> u64 {
+        self.correlator.duplicates()
+    }
+
+    /// Number of replies, and errors, received for requests that weren't
+    /// outstanding or answered, which the statistics ignore
+    pub fn orphans(&self) -> u64 {
+        self.correlator.orphans()
+    }
+
+    /// Statistics so far of the host with index `host`, in the order given
+    pub fn stats(&self, host: usize) -> &PingStats {
+        &self.hosts[host].1
+    }
+
+    /// Start the statistics of every host afresh, e.g. to measure again
+    /// after changing something, leaving the requests in flight be
+    pub fn reset_stats(&mut self) {
+        for (_, stats) in &mut self.hosts {
+            *stats = PingStats::new();
+        }
+    }
+
+    /// Statistics so far for every host, in the order they were given.
+    /// Probes still outstanding count as lost.
+    pub fn summaries(&self) -> Vec<PingSummary> {
+        self.hosts
+            .iter()
+            .map(|(host, stats)| stats.summary(&host.to_string()))
+            .collect()
+    }
+
+    /// Send the next requests to the host with index `host` to `address`
+    /// instead, e.g. another address its name resolves to. Replies to the
+    /// requests sent before are no longer matched, so call it once they
+    /// were collected and expired.
+    pub fn set_address(&mut self, host: usize, address: IpAddr) {
+        self.hosts[host].0 = address;
+        self.correlator
+            .register(self.identifiers[host], host, address);
+    }
+
+    /// The socket probes are sent over
+    pub fn socket(&self) -> &S {
+        &self.socket
+    }
+
+    /// The socket probes are sent over, e.g. to point it elsewhere along
+    /// with `set_address`
+    pub fn socket_mut(&mut self) -> &mut S {
+        &mut self.socket
+    }
+}
+
+/// When a packet was received, by the kernel's timestamp if the socket
+/// reported one, so the time it waited to be read doesn't count in its RTT
+fn received_at(info: RecvInfo) -> Instant {
+    let now = Instant::now();
+    info.timestamp
+        .and_then(|timestamp| SystemTime::now().duration_since(timestamp).ok())
+        .and_then(|waited| now.checked_sub(waited))
+        .unwrap_or(now)
+}
+
+/// Round trip time of the reply `packet`, from the timestamp its request
+/// carried if its payload had room for one and came back intact, or else
+/// timed externally by the correlator. The timestamp tells which attempt of
+/// a resent request the reply answers.
+fn reply_rtt(
+    packet: &IcmpPacket,
+    packet_size: usize,
+    matched: &MatchedReply,
+    received_at: Instant,
+) -> Duration {
+    if !timestamp::fits(packet_size) || matched.corruption.is_some() {
+        return matched.rtt;
+    }
+    timestamp::sent_at(packet)
+        .map(|sent_at| received_at.saturating_duration_since(sent_at))
+        .unwrap_or(matched.rtt)
+}
+
+/// Kind of an ICMP error as broken down in the statistics, e.g.
+/// "unreachable"
+fn error_kind(msg_type: IcmpType) -> String {
+    match msg_type {
+        IcmpType::DestinationUnreachable => "unreachable".to_string(),
+        IcmpType::TimeExceeded => "time exceeded".to_string(),
+        IcmpType::ParameterProblem => "parameter problem".to_string(),
+        IcmpType::SourceQuench => "source quench".to_string(),
+        IcmpType::Redirect => "redirected".to_string(),
+        msg_type => format!("ICMP type {}", msg_type.to_u8()),
+    }
+}
+
+/// Whether a send error will recur on every request, so sending is better
+/// stopped than the request counted as not sent: a packet too large is too
+/// large every time
+fn is_fatal_send_error(err: &(dyn Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<IcmpSocketError>(),
+        Some(IcmpSocketError::PacketTooLarge(_))
+    )
+}
+
+/// Whether a receive error only means nothing arrived before the timeout
+fn is_timeout(err: &(dyn Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<io::Error>().map(io::Error::kind),
+        Some(io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icmp::mock::{echo_reply, process_identifier, unreachable_reply, MockTransport};
+
+    const HOST: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+    /// A pinger of `HOST` over a socket replaying `transport`, recording
+    /// every event it emits
+    fn scripted(
+        transport: MockTransport,
+    ) -> (
+        Pinger<IcmpSocket<MockTransport>>,
+        Rc<RefCell<Vec<ProbeEvent>>>,
+    ) {
+        let socket = IcmpSocket::with_transport(transport, HOST);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&events);
+        let pinger = Pinger::with_socket(socket, &[HOST.into()])
+            .unwrap()
+            .on_event(move |event| seen.borrow_mut().push(event));
+        (pinger, events)
+    }
+
+    #[test]
+    fn hooks_see_every_probe_event() {
+        let host = Ipv4Addr::new(192, 0, 2, 1);
+        let reply = echo_reply(process_identifier(), 1);
+        let transport = MockTransport::new()
+            .reply_after(Duration::ZERO, host, reply)
+            .error_after(Duration::ZERO, io::ErrorKind::ConnectionRefused);
+        let socket = IcmpSocket::with_transport(transport, host);
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&events);
+        let mut pinger = Pinger::with_socket(socket, &[host.into()])
+            .unwrap()
+            .on_event(move |event| seen.borrow_mut().push(event));
+
+        pinger.send_round().unwrap();
+        pinger.collect(Duration::from_secs(1));
+        pinger.send_round().unwrap();
+        pinger.collect(Duration::from_secs(1));
+        assert_eq!(pinger.expire().unwrap(), 0);
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 5, "{events:?}");
+        assert_eq!(
+            events[0],
+            ProbeEvent::Sent {
+                host: host.into(),
+                sequence_no: 1
+            }
+        );
+        assert!(matches!(
+            events[1],
+            ProbeEvent::Reply(ProbeReply {
+                host: replied,
+                sequence_no: 1,
+                num_bytes: 56,
+                ..
+            }) if replied == IpAddr::V4(host)
+        ));
+        assert_eq!(
+            events[2],
+            ProbeEvent::Sent {
+                host: host.into(),
+                sequence_no: 2
+            }
+        );
+        assert!(matches!(events[3], ProbeEvent::Error { .. }));
+        assert_eq!(
+            events[4],
+            ProbeEvent::Timeout {
+                host: host.into(),
+                sequence_no: 2
+            }
+        );
+    }
+
+    #[test]
+    fn orphan_reply_leaves_the_statistics_alone() {
+        // A reply to a request never sent comes first
+        let transport = MockTransport::new()
+            .reply_after(Duration::ZERO, HOST, echo_reply(process_identifier(), 9))
+            .reply_after(Duration::ZERO, HOST, echo_reply(process_identifier(), 1));
+        let socket = IcmpSocket::with_transport(transport, HOST);
+        let mut pinger = Pinger::with_socket(socket, &[HOST.into()]).unwrap();
+
+        pinger.send_round().unwrap();
+        let replies = pinger.collect(Duration::from_secs(1));
+
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].sequence_no, 1);
+        assert_eq!(pinger.orphans(), 1);
+        assert_eq!(pinger.duplicates(), 0);
+        let summary = &pinger.summaries()[0];
+        assert_eq!(summary.packets_sent, 1);
+        assert_eq!(summary.packets_recv, 1);
+        assert_eq!(summary.bytes_recv, 56);
+    }
+
+    #[test]
+    fn reply_with_an_altered_payload_is_flagged() {
+        // Too small a payload to be timestamped, so the script can echo it
+        let mut reply = IcmpPacket {
+            msg_type: IcmpType::EchoReply,
+            ..IcmpPacket::echo_request(process_identifier(), 1, 7)
+        };
+        reply.payload[2..6].copy_from_slice(b"oops");
+        let transport = MockTransport::new().reply_after(Duration::ZERO, HOST, reply);
+        let socket = IcmpSocket::with_transport(transport, HOST);
+        let mut pinger = Pinger::with_socket(socket, &[HOST.into()])
+            .unwrap()
+            .with_packet_size(7);
+
+        pinger.send_round().unwrap();
+        let replies = pinger.collect(Duration::from_secs(1));
+
+        assert_eq!(
+            replies[0].corruption,
+            Some(PayloadMismatch {
+                differing: 4,
+                first_offset: 2
+            })
+        );
+        let summary = &pinger.summaries()[0];
+        assert_eq!(summary.packets_recv, 1);
+        assert_eq!(summary.packets_corrupted, 1);
+    }
+
+    #[test]
+    fn late_reply_is_collected_after_sending_all_probes() {
+        let transport = MockTransport::new()
+            .reply_after(Duration::ZERO, HOST, echo_reply(process_identifier(), 1))
+            .reply_after(
+                Duration::from_millis(50),
+                HOST,
+                echo_reply(process_identifier(), 2),
+            );
+        let socket = IcmpSocket::with_transport(transport, HOST);
+        let mut pinger = Pinger::with_socket(socket, &[HOST.into()])
+            .unwrap()
+            .with_count(2)
+            .with_interval(Duration::from_millis(10));
+
+        let replies = pinger.send_all().unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].sequence_no, 1);
+        assert_eq!(pinger.outstanding(), 1);
+
+        // The reply to the last probe is still on its way
+        assert!(pinger.collect(Duration::ZERO).is_empty());
+        let late = pinger.collect(Duration::from_secs(1));
+        assert_eq!(late.len(), 1);
+        assert_eq!(late[0].sequence_no, 2);
+        assert!(
+            late[0].rtt >= Duration::from_millis(50),
+            "{:?}",
+            late[0].rtt
+        );
+        assert_eq!(pinger.outstanding(), 0);
+
+        let summary = &pinger.summaries()[0];
+        assert_eq!(summary.packets_sent, 2);
+        assert_eq!(summary.packets_recv, 2);
+        assert_eq!(summary.packet_loss, 0.0);
+    }
+
+    #[test]
+    fn timed_out_request_is_retried() {
+        let transport = MockTransport::new().reply_after(
+            Duration::from_millis(80),
+            HOST,
+            echo_reply(process_identifier(), 1),
+        );
+        let (pinger, events) = scripted(transport);
+        let mut pinger = pinger.with_retries(1);
+
+        pinger.send_round().unwrap();
+        assert!(pinger.collect(Duration::from_millis(50)).is_empty());
+        assert_eq!(pinger.expire().unwrap(), 1);
+        let replies = pinger.collect(Duration::from_secs(1));
+
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].attempts, 2);
+        assert_eq!(pinger.socket().transport().sent().len(), 2);
+        assert_eq!(
+            events.borrow()[1],
+            ProbeEvent::Resent {
+                host: HOST.into(),
+                sequence_no: 1,
+                attempt: 2
+            }
+        );
+        // Both transmissions count, as one request
+        let stats = pinger.stats(0);
+        assert_eq!(stats.packets_sent, 1);
+        assert_eq!(stats.bytes_sent, 2 * 56);
+        assert_eq!(stats.packets_retried, 1);
+    }
+
+    #[test]
+    fn request_is_lost_once_every_attempt_times_out() {
+        let (pinger, events) = scripted(MockTransport::new());
+        let mut pinger = pinger.with_retries(2);
+
+        pinger.send_round().unwrap();
+        loop {
+            pinger.collect(Duration::ZERO);
+            if pinger.expire().unwrap() == 0 {
+                break;
+            }
+        }
+
+        assert_eq!(pinger.socket().transport().sent().len(), 3);
+        assert_eq!(
+            events.borrow().last(),
+            Some(&ProbeEvent::Timeout {
+                host: HOST.into(),
+                sequence_no: 1
+            })
+        );
+        let summary = &pinger.summaries()[0];
+        assert_eq!(summary.bytes_sent, 3 * 56);
+        assert_eq!(summary.timeouts, 1);
+    }
+
+    #[test]
+    fn requests_failing_to_send_are_reported_without_retries() {
+        let transport = MockTransport::new().fail_send(io::ErrorKind::PermissionDenied);
+        let (pinger, events) = scripted(transport);
+        let mut pinger = pinger.with_retries(1);
+
+        pinger.send_round().unwrap();
+        assert!(pinger.collect(Duration::from_secs(1)).is_empty());
+        assert_eq!(pinger.expire().unwrap(), 0);
+
+        assert!(pinger.socket().transport().sent().is_empty());
+        assert_eq!(events.borrow().len(), 1);
+        assert!(matches!(
+            events.borrow()[0],
+            ProbeEvent::NotSent { sequence_no: 1, .. }
+        ));
+        assert_eq!(pinger.stats(0).sent_failures, 1);
+    }
+
+    #[test]
+    fn icmp_errors_are_reported_without_retries() {
+        let router = Ipv4Addr::new(198, 51, 100, 1);
+        let transport = MockTransport::new().reply_after(
+            Duration::ZERO,
+            router,
+            unreachable_reply(process_identifier(), 1, HOST),
+        );
+        let (pinger, events) = scripted(transport);
+        let mut pinger = pinger.with_retries(2);
+
+        pinger.send_round().unwrap();
+        assert!(pinger.collect(Duration::from_secs(1)).is_empty());
+        assert_eq!(pinger.expire().unwrap(), 0);
+
+        assert_eq!(pinger.socket().transport().sent().len(), 1);
+        assert!(matches!(
+            events.borrow()[1],
+            ProbeEvent::IcmpError { sequence_no: 1, .. }
+        ));
+        let summary = &pinger.summaries()[0];
+        assert_eq!(summary.errors["unreachable"], 1);
+        assert_eq!(summary.packets_retried, 0);
+    }
+
+    #[test]
+    fn reply_without_the_tag_is_flagged() {
+        let mut reply = echo_reply(process_identifier(), 1);
+        tag::write(&mut reply, b"pingoc");
+        let mut mistagged = echo_reply(process_identifier(), 2);
+        tag::write(&mut mistagged, b"other!");
+        let transport = MockTransport::new()
+            .reply_after(Duration::ZERO, HOST, reply)
+            .reply_after(Duration::from_millis(20), HOST, mistagged);
+        let (pinger, _) = scripted(transport);
+        let mut pinger = pinger.with_tag(b"pingoc");
+
+        pinger.send_round().unwrap();
+        pinger.send_round().unwrap();
+        let replies = pinger.collect(Duration::from_secs(1));
+
+        // The timestamps differ from those sent, but the tags don't
+        assert_eq!(replies[0].tag_mismatch, None);
+        assert_eq!(
+            replies[1].tag_mismatch,
+            Some(TagMismatch {
+                sent: b"pingoc".to_vec(),
+                echoed: b"other!".to_vec()
+            })
+        );
+        assert_eq!(pinger.stats(0).packets_mistagged, 1);
+    }
+
+    #[test]
+    fn sending_stops_once_enough_requests_in_a_row_are_unreachable() {
+        let router = Ipv4Addr::new(198, 51, 100, 1);
+        // A reply breaks the streak the first two requests started
+        let transport =
+            (1..=10).fold(
+                MockTransport::new(),
+                |transport, sequence_no| match sequence_no {
+                    3 => transport.reply_after(
+                        Duration::ZERO,
+                        HOST,
+                        echo_reply(process_identifier(), 3),
+                    ),
+                    _ => transport.reply_after(
+                        Duration::ZERO,
+                        router,
+                        unreachable_reply(process_identifier(), sequence_no, HOST),
+                    ),
+                },
+            );
+        let (pinger, events) = scripted(transport);
+        let mut pinger = pinger
+            .with_count(10)
+            .with_interval(Duration::from_millis(10))
+            .with_stop_on_unreachable(3);
+
+        pinger.send_all().unwrap();
+        pinger.collect(Duration::from_secs(1));
+
+        assert_eq!(pinger.unreachable(), Some(0));
+        assert_eq!(pinger.socket().transport().sent().len(), 6);
+        assert_eq!(pinger.summaries()[0].errors["unreachable"], 5);
+        assert!(events.borrow().contains(&ProbeEvent::IcmpError {
+            host: HOST.into(),
+            sequence_no: 6,
+            source: router.into(),
+            kind: "unreachable".to_string(),
+            description: unreachable_reply(process_identifier(), 6, HOST).description(),
+        }));
+    }
+
+    #[test]
+    fn every_host_answering_a_broadcast_is_reported() {
+        let broadcast = Ipv4Addr::new(192, 0, 2, 255);
+        let reply = echo_reply(process_identifier(), 1);
+        let transport = MockTransport::new()
+            .reply_after(Duration::ZERO, Ipv4Addr::new(192, 0, 2, 7), reply.clone())
+            .reply_after(Duration::ZERO, Ipv4Addr::new(192, 0, 2, 9), reply);
+        let socket = IcmpSocket::with_transport(transport, broadcast);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&events);
+        let mut pinger = Pinger::with_socket(socket, &[broadcast.into()])
+            .unwrap()
+            .with_broadcast()
+            .on_event(move |event| seen.borrow_mut().push(event));
+
+        pinger.send_round().unwrap();
+        pinger.collect(Duration::from_millis(50));
+
+        let sources: Vec<IpAddr> = events
+            .borrow()
+            .iter()
+            .filter_map(|event| match event {
+                ProbeEvent::Reply(reply) | ProbeEvent::Duplicate(reply) => Some(reply.source),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            sources,
+            [Ipv4Addr::new(192, 0, 2, 7), Ipv4Addr::new(192, 0, 2, 9)]
+        );
+        let summary = &pinger.summaries()[0];
+        assert_eq!(summary.packets_recv, 1);
+        assert_eq!(summary.foreign_replies, 0);
+    }
+
+    #[test]
+    fn replies_still_match_once_the_sequence_wraps() {
+        let transport = (1..=70_000u32).fold(MockTransport::new(), |transport, n| {
+            transport.reply_after(
+                Duration::ZERO,
+                HOST,
+                echo_reply(process_identifier(), n as u16),
+            )
+        });
+        let (mut pinger, _) = scripted(transport);
+
+        for _ in 0..70_000 {
+            pinger.send_round().unwrap();
+            assert_eq!(pinger.collect(Duration::from_secs(1)).len(), 1);
+            assert_eq!(pinger.expire().unwrap(), 0);
+        }
+
+        let summary = &pinger.summaries()[0];
+        assert_eq!(summary.packets_sent, 70_000);
+        assert_eq!(summary.packets_recv, 70_000);
+        assert_eq!(summary.bytes_recv, 70_000 * 56);
+        assert_eq!(summary.packet_loss, 0.0);
+    }
+
+    #[test]
+    fn replies_are_timed_by_their_timestamp_when_the_payload_has_room() {
+        let matched = MatchedReply {
+            host: 0,
+            sequence_no: 1,
+            rtt: Duration::from_millis(1),
+            corruption: None,
+            foreign_source: None,
+        };
+        let mut packet = echo_reply(process_identifier(), 1);
+        assert!(timestamp::stamp(&mut packet));
+        thread::sleep(Duration::from_millis(20));
+        let received_at = Instant::now();
+
+        let rtt = reply_rtt(&packet, 56, &matched, received_at);
+        assert!(rtt >= Duration::from_millis(20), "{rtt:?}");
+        // Too small a payload for a timestamp, or one that came back
+        // altered, is timed externally
+        for size in [0, 7] {
+            assert_eq!(reply_rtt(&packet, size, &matched, received_at), matched.rtt);
+        }
+        let altered = MatchedReply {
+            corruption: Some(PayloadMismatch {
+                differing: 1,
+                first_offset: 0,
+            }),
+            ..matched
+        };
+        assert_eq!(reply_rtt(&packet, 56, &altered, received_at), matched.rtt);
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+/// Schedule for periodic statistics snapshots, independent of how often
+/// requests are sent.
+///
+/// Takes the current time as an argument rather than reading the clock, so
+/// callers decide what "now" is.
+#[derive(Debug, Clone)]
+pub struct SnapshotTimer {
+    interval: Duration,
+    next: Instant,
+}
+
+impl SnapshotTimer {
+    /// Fire every `interval`, the first time one `interval` after `now`
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            next: now + interval,
+        }
+    }
+
+    /// When the next snapshot is due
+    pub fn next_due(&self) -> Instant {
+        self.next
+    }
+
+    /// How long from `now` until the next snapshot is due, zero if overdue
+    pub fn until_due(&self, now: Instant) -> Duration {
+        self.next.saturating_duration_since(now)
+    }
+
+    /// Whether a snapshot is due at `now`, scheduling the next one if so.
+    /// Snapshots missed while the caller was busy are skipped rather than
+    /// fired back to back.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        while self.next <= now {
+            self.next += self.interval.max(Duration::from_millis(1));
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fires_once_per_interval_of_the_given_clock() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut timer = SnapshotTimer::new(Duration::from_millis(100), start);
+
+        assert_eq!(timer.next_due(), at(100));
+        assert_eq!(timer.until_due(at(40)), Duration::from_millis(60));
+        assert!(!timer.poll(at(99)));
+        assert!(timer.poll(at(100)));
+        assert_eq!(timer.next_due(), at(200));
+        assert!(!timer.poll(at(150)));
+        assert_eq!(timer.until_due(at(250)), Duration::ZERO);
+    }
+
+    #[test]
+    fn missed_snapshots_are_skipped() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut timer = SnapshotTimer::new(Duration::from_millis(100), start);
+
+        assert!(timer.poll(at(350)));
+        assert_eq!(timer.next_due(), at(400));
+        assert!(!timer.poll(at(399)));
+    }
+}
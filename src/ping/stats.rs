@@ -0,0 +1,387 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Running counters collected while pinging a host.
+///
+/// Counters are exact integers and round trip times are accumulated in
+/// nanoseconds, so that long runs don't lose precision; floating point is
+/// only used when deriving the summary.
+#[derive(Debug, Default)]
+pub struct PingStats {
+    pub packets_sent: u64,
+    pub packets_recv: u64,
+    pub packets_retried: u64,
+    /// Replies whose echoed payload differed from the one sent
+    pub packets_corrupted: u64,
+    /// Replies whose payload didn't echo the tag sent
+    pub packets_mistagged: u64,
+    /// Replies that came from another address than the one pinged
+    pub foreign_replies: u64,
+    /// Requests given up on without any answer
+    pub timeouts: u64,
+    /// Requests answered with an ICMP error instead of a reply, or whose
+    /// reply couldn't be received, by kind of error
+    pub errors: BTreeMap<String, u64>,
+    /// Requests that couldn't be sent at all
+    pub sent_failures: u64,
+    pub bytes_sent: u64,
+    pub bytes_recv: u64,
+    /// Sequence numbers skipped over by a reply to a later sequence
+    pub sequence_gaps: u64,
+    highest_sequence: Option<u16>,
+    /// Replies received per TTL (or hop limit), which shifts when the route
+    /// to the host changes
+    pub ttl_counts: HashMap<u8, usize>,
+    rtt_min_ns: u64,
+    rtt_max_ns: u64,
+    rtt_sum_ns: u128,
+    rtt_sum_sq_ns: u128,
+    rtt_last_ns: Option<u64>,
+    /// Sum of the differences between consecutive round trip times
+    rtt_delta_sum_ns: u128,
+}
+
+impl PingStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account for an echo request of `num_bytes` payload bytes
+    pub fn record_sent(&mut self, num_bytes: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += num_bytes as u64;
+    }
+
+    /// Account for resending a request already recorded with `record_sent`,
+    /// whose `num_bytes` go out again without making it another request
+    pub fn record_resent(&mut self, num_bytes: usize) {
+        self.bytes_sent += num_bytes as u64;
+    }
+
+    /// Account for a reply, already recorded with `record_reply`, whose
+    /// payload came back altered
+    pub fn record_corrupted(&mut self) {
+        self.packets_corrupted += 1;
+    }
+
+    /// Account for a reply, already recorded with `record_reply`, that
+    /// didn't echo the tag its request carried
+    pub fn record_mistagged(&mut self) {
+        self.packets_mistagged += 1;
+    }
+
+    /// Account for a reply from another address than the one pinged,
+    /// whether it was accepted and recorded with `record_reply` or not
+    pub fn record_foreign_reply(&mut self) {
+        self.foreign_replies += 1;
+    }
+
+    /// Account for `lost` requests, recorded with `record_sent`, that got no
+    /// answer in time
+    pub fn record_timeouts(&mut self, lost: u64) {
+        self.timeouts += lost;
+    }
+
+    /// Account for a request, recorded with `record_sent`, answered with an
+    /// error of `kind`, e.g. "unreachable"
+    pub fn record_error(&mut self, kind: &str) {
+        *self.errors.entry(kind.to_string()).or_insert(0) += 1;
+    }
+
+    /// Account for a request, recorded with `record_sent`, that couldn't be
+    /// sent
+    pub fn record_sent_failure(&mut self) {
+        self.sent_failures += 1;
+    }
+
+    /// Account for an echo reply, `retried` being set if it took more than one attempt
+    pub fn record_reply(&mut self, num_bytes: usize, rtt: Duration, retried: bool) {
+        let rtt_ns = rtt.as_nanos().min(u64::MAX as u128) as u64;
+        if self.packets_recv == 0 || rtt_ns < self.rtt_min_ns {
+            self.rtt_min_ns = rtt_ns;
+        }
+        if rtt_ns > self.rtt_max_ns {
+            self.rtt_max_ns = rtt_ns;
+        }
+        self.rtt_sum_ns += rtt_ns as u128;
+        self.rtt_sum_sq_ns += (rtt_ns as u128) * (rtt_ns as u128);
+        if let Some(last_ns) = self.rtt_last_ns {
+            self.rtt_delta_sum_ns += rtt_ns.abs_diff(last_ns) as u128;
+        }
+        self.rtt_last_ns = Some(rtt_ns);
+
+        self.packets_recv += 1;
+        self.bytes_recv += num_bytes as u64;
+        if retried {
+            self.packets_retried += 1;
+        }
+    }
+
+    /// Account for a reply to `sequence_no`, returning the sequence numbers
+    /// between the highest one seen so far and this one, which are missing.
+    /// Replies to earlier sequences arriving late flag nothing.
+    pub fn record_sequence(&mut self, sequence_no: u16) -> Vec<u16> {
+        let Some(highest) = self.highest_sequence else {
+            self.highest_sequence = Some(sequence_no);
+            return Vec::new();
+        };
+
+        // Sequence numbers wrap, so compare by distance rather than value
+        let ahead = sequence_no.wrapping_sub(highest);
+        if ahead == 0 || ahead >= 0x8000 {
+            return Vec::new();
+        }
+
+        self.highest_sequence = Some(sequence_no);
+        let missing: Vec<u16> = (1..ahead).map(|i| highest.wrapping_add(i)).collect();
+        self.sequence_gaps += missing.len() as u64;
+        missing
+    }
+
+    /// Account for a reply that arrived with `ttl`
+    pub fn record_ttl(&mut self, ttl: u8) {
+        *self.ttl_counts.entry(ttl).or_insert(0) += 1;
+    }
+
+    /// TTLs seen, most frequent first
+    pub fn ttls(&self) -> Vec<TtlCount> {
+        let mut ttls: Vec<TtlCount> = self
+            .ttl_counts
+            .iter()
+            .map(|(&ttl, &packets)| TtlCount { ttl, packets })
+            .collect();
+        ttls.sort_by(|a, b| b.packets.cmp(&a.packets).then(b.ttl.cmp(&a.ttl)));
+        ttls
+    }
+
+    /// Percentage of requests that never got a reply
+    pub fn packet_loss(&self) -> f64 {
+        if self.packets_sent > 0 {
+            100.0 - (self.packets_recv as f64 / self.packets_sent as f64 * 100.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Round trip time statistics, `None` if no reply was received
+    pub fn rtt(&self) -> Option<RttSummary> {
+        if self.packets_recv == 0 {
+            return None;
+        }
+
+        let count = self.packets_recv as f64;
+        let avg_ns = self.rtt_sum_ns as f64 / count;
+        let variance = (self.rtt_sum_sq_ns as f64 / count - avg_ns * avg_ns).max(0.0);
+        let jitter_ns = match self.packets_recv {
+            1 => 0.0,
+            recv => self.rtt_delta_sum_ns as f64 / (recv - 1) as f64,
+        };
+
+        Some(RttSummary {
+            min_ms: ns_to_ms(self.rtt_min_ns as f64),
+            avg_ms: ns_to_ms(avg_ns),
+            max_ms: ns_to_ms(self.rtt_max_ns as f64),
+            mdev_ms: ns_to_ms(variance.sqrt()),
+            jitter_ms: ns_to_ms(jitter_ns),
+        })
+    }
+
+    /// Freeze the counters into a summary for `host`
+    pub fn summary(&self, host: &str) -> PingSummary {
+        PingSummary {
+            host: host.to_string(),
+            packets_sent: self.packets_sent,
+            packets_recv: self.packets_recv,
+            packets_retried: self.packets_retried,
+            packets_corrupted: self.packets_corrupted,
+            packets_mistagged: self.packets_mistagged,
+            foreign_replies: self.foreign_replies,
+            timeouts: self.timeouts,
+            errors: self.errors.clone(),
+            sent_failures: self.sent_failures,
+            sequence_gaps: self.sequence_gaps,
+            bytes_sent: self.bytes_sent,
+            bytes_recv: self.bytes_recv,
+            packet_loss: self.packet_loss(),
+            rtt: self.rtt(),
+            ttls: self.ttls(),
+            resolve_error: None,
+        }
+    }
+}
+
+fn ns_to_ms(ns: f64) -> f64 {
+    ns / 1_000_000.0
+}
+
+/// Round trip time statistics in milliseconds
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct RttSummary {
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+    pub mdev_ms: f64,
+    /// Mean difference between consecutive round trip times, in the order
+    /// the replies arrived (RFC 3550 style jitter, unlike `mdev_ms` which is
+    /// the deviation from the mean). Zero with a single reply.
+    pub jitter_ms: f64,
+}
+
+/// Final statistics of a ping run
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct PingSummary {
+    pub host: String,
+    pub packets_sent: u64,
+    pub packets_recv: u64,
+    pub packets_retried: u64,
+    /// Replies whose echoed payload differed from the one sent, counted in
+    /// `packets_recv` too
+    pub packets_corrupted: u64,
+    /// Replies that didn't echo the tag sent, counted in `packets_recv` too
+    pub packets_mistagged: u64,
+    /// Replies that came from another address than the host's, counted in
+    /// `packets_recv` too unless they were rejected
+    pub foreign_replies: u64,
+    /// Requests that got no answer in time
+    pub timeouts: u64,
+    /// Requests answered with an error instead of a reply, by kind of error
+    pub errors: BTreeMap<String, u64>,
+    /// Requests that couldn't be sent
+    pub sent_failures: u64,
+    /// Sequence numbers found missing when a later sequence was answered
+    pub sequence_gaps: u64,
+    pub bytes_sent: u64,
+    pub bytes_recv: u64,
+    /// Packet loss in percent
+    pub packet_loss: f64,
+    pub rtt: Option<RttSummary>,
+    /// TTLs the replies arrived with, most frequent first
+    pub ttls: Vec<TtlCount>,
+    /// Why `host` could not be resolved, or resolved to an address that
+    /// can't be pinged, in which case it wasn't pinged
+    pub resolve_error: Option<String>,
+}
+
+impl PingSummary {
+    /// Summary of a `host` that was never pinged because resolving it failed
+    pub fn unresolved(host: &str, error: impl fmt::Display) -> Self {
+        Self {
+            resolve_error: Some(error.to_string()),
+            ..PingStats::new().summary(host)
+        }
+    }
+}
+
+/// Number of replies that arrived with a given TTL
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct TtlCount {
+    pub ttl: u8,
+    pub packets: usize,
+}
+
+/// Replies counted per responding address, for broadcast pings where several
+/// hosts may answer the same request
+#[derive(Debug, Default)]
+pub struct ResponderTally {
+    replies: BTreeMap<IpAddr, u64>,
+}
+
+impl ResponderTally {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a reply from `responder`, returning whether it is a new responder
+    pub fn record(&mut self, responder: IpAddr) -> bool {
+        let count = self.replies.entry(responder).or_insert(0);
+        *count += 1;
+        *count == 1
+    }
+
+    /// Number of distinct hosts that replied
+    pub fn distinct(&self) -> usize {
+        self.replies.len()
+    }
+
+    /// Responders in address order along with their reply counts
+    pub fn iter(&self) -> impl Iterator<Item = (IpAddr, u64)> + '_ {
+        self.replies.iter().map(|(addr, count)| (*addr, *count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn responders_are_tallied_separately() {
+        let first = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7));
+        let second = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3));
+        let mut tally = ResponderTally::new();
+
+        assert!(tally.record(first));
+        assert!(tally.record(second));
+        assert!(!tally.record(first));
+
+        assert_eq!(tally.distinct(), 2);
+        assert_eq!(
+            tally.iter().collect::<Vec<_>>(),
+            vec![(second, 1), (first, 2)]
+        );
+    }
+
+    #[test]
+    fn skipped_sequence_is_flagged_as_a_gap() {
+        let mut stats = PingStats::new();
+        let missing: Vec<Vec<u16>> = [1, 2, 4, 5]
+            .into_iter()
+            .map(|sequence_no| stats.record_sequence(sequence_no))
+            .collect();
+
+        assert_eq!(missing, [vec![], vec![], vec![3], vec![]]);
+        assert_eq!(stats.sequence_gaps, 1);
+    }
+
+    #[test]
+    fn distinct_ttls_are_counted_most_common_first() {
+        let mut stats = PingStats::new();
+        for ttl in [54, 53, 54, 54, 53, 64, 54] {
+            stats.record_ttl(ttl);
+        }
+
+        let ttls: Vec<(u8, usize)> = stats
+            .ttls()
+            .iter()
+            .map(|count| (count.ttl, count.packets))
+            .collect();
+        assert_eq!(ttls, [(54, 4), (53, 2), (64, 1)]);
+    }
+
+    #[test]
+    fn jitter_is_the_mean_difference_between_consecutive_rtts() {
+        let mut stats = PingStats::new();
+        for rtt_ms in [10, 20, 15, 15] {
+            stats.record_sent(64);
+            stats.record_reply(64, Duration::from_millis(rtt_ms), false);
+        }
+
+        // |20 - 10| + |15 - 20| + |15 - 15| over three differences
+        let rtt = stats.rtt().unwrap();
+        assert!((rtt.jitter_ms - 5.0).abs() < 1e-9);
+        assert!((rtt.mdev_ms - 12.5f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn single_reply_has_no_jitter() {
+        let mut stats = PingStats::new();
+        stats.record_sent(64);
+        stats.record_reply(64, Duration::from_millis(12), false);
+        assert_eq!(stats.rtt().unwrap().jitter_ms, 0.0);
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+use std::ops::Range;
+
+use super::timestamp;
+use crate::icmp::packet::IcmpPacket;
+
+/// Where `write` puts a tag of `tag_len` bytes in a payload of
+/// `payload_len` bytes: right after the timestamp if the payload has room
+/// for one, cut short at the end of the payload
+fn span(tag_len: usize, payload_len: usize) -> Range<usize> {
+    let start = if timestamp::fits(payload_len) {
+        timestamp::TIMESTAMP_LEN
+    } else {
+        0
+    };
+    start..(start + tag_len).min(payload_len).max(start)
+}
+
+/// Write `tag` into the payload of `packet`, after the timestamp region so
+/// both fit, and truncated if the payload is too small for all of it. The
+/// rest of the payload is left as it was. Returns the number of tag bytes
+/// written.
+pub fn write(packet: &mut IcmpPacket, tag: &[u8]) -> usize {
+    let span = span(tag.len(), packet.payload.len());
+    let written = span.len();
+    packet.payload[span].copy_from_slice(&tag[..written]);
+    written
+}
+
+/// A reply whose payload didn't echo the tag its request carried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagMismatch {
+    /// The part of the tag that was sent
+    pub sent: Vec<u8>,
+    /// What came back in its place, shorter if the reply was
+    pub echoed: Vec<u8>,
+}
+
+impl fmt::Display for TagMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tag {:?} came back as {:?}",
+            String::from_utf8_lossy(&self.sent),
+            String::from_utf8_lossy(&self.echoed)
+        )
+    }
+}
+
+/// Check that `reply` echoes `tag` where `write` put it in a request of
+/// `payload_len` payload bytes
+pub fn verify(reply: &IcmpPacket, tag: &[u8], payload_len: usize) -> Result<(), TagMismatch> {
+    let span = span(tag.len(), payload_len);
+    let sent = &tag[..span.len()];
+    let echoed = reply
+        .payload
+        .get(span.start..span.end.min(reply.payload.len()))
+        .unwrap_or_default();
+    if echoed == sent {
+        return Ok(());
+    }
+    Err(TagMismatch {
+        sent: sent.to_vec(),
+        echoed: echoed.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::timestamp::TIMESTAMP_LEN;
+
+    fn packet(payload_len: usize) -> IcmpPacket {
+        IcmpPacket {
+            payload: vec![0xaa; payload_len],
+            ..IcmpPacket::default()
+        }
+    }
+
+    #[test]
+    fn tag_goes_after_the_timestamp_when_there_is_room_for_one() {
+        let mut large = packet(16);
+        assert_eq!(write(&mut large, b"tag"), 3);
+        assert_eq!(large.payload[..TIMESTAMP_LEN], [0xaa; TIMESTAMP_LEN]);
+        assert_eq!(&large.payload[TIMESTAMP_LEN..TIMESTAMP_LEN + 3], b"tag");
+        assert_eq!(large.payload[TIMESTAMP_LEN + 3..], [0xaa; 5]);
+
+        let mut small = packet(2);
+        assert_eq!(write(&mut small, b"tag"), 2);
+        assert_eq!(small.payload, b"ta");
+
+        let mut truncated = packet(TIMESTAMP_LEN + 1);
+        assert_eq!(write(&mut truncated, b"tag"), 1);
+        assert_eq!(truncated.payload[TIMESTAMP_LEN], b't');
+
+        let mut empty = packet(0);
+        assert_eq!(write(&mut empty, b"tag"), 0);
+    }
+
+    #[test]
+    fn verify_compares_the_part_of_the_tag_that_was_sent() {
+        let mut request = packet(16);
+        write(&mut request, b"tag");
+        assert_eq!(verify(&request, b"tag", 16), Ok(()));
+
+        let mut altered = request.clone();
+        altered.payload[TIMESTAMP_LEN] = b'b';
+        assert_eq!(
+            verify(&altered, b"tag", 16),
+            Err(TagMismatch {
+                sent: b"tag".to_vec(),
+                echoed: b"bag".to_vec(),
+            })
+        );
+
+        let mut short = request.clone();
+        short.payload.truncate(TIMESTAMP_LEN + 1);
+        let mismatch = verify(&short, b"tag", 16).unwrap_err();
+        assert_eq!(mismatch.echoed, b"t");
+        assert_eq!(mismatch.to_string(), r#"tag "tag" came back as "t""#);
+
+        let mut small = packet(2);
+        write(&mut small, b"tag");
+        assert_eq!(verify(&small, b"tag", 2), Ok(()));
+    }
+}
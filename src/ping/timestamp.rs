@@ -0,0 +1,41 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::icmp::packet::IcmpPacket;
+
+/// Payload bytes needed to embed a send timestamp in an echo request
+pub const TIMESTAMP_LEN: usize = 8;
+
+/// Reference point for embedded timestamps, so they come from the monotonic
+/// clock rather than wall time
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Whether a payload of `packet_size` bytes has room for a timestamp
+pub fn fits(packet_size: usize) -> bool {
+    packet_size >= TIMESTAMP_LEN
+}
+
+/// Write the current time into the start of the payload of `packet`, as
+/// nanoseconds since `epoch` in network order. Returns `false`, leaving the
+/// payload untouched, if it is too small to hold the timestamp.
+pub fn stamp(packet: &mut IcmpPacket) -> bool {
+    if !fits(packet.payload.len()) {
+        return false;
+    }
+
+    let ns = epoch().elapsed().as_nanos().min(u64::MAX as u128) as u64;
+    packet.payload[..TIMESTAMP_LEN].copy_from_slice(&ns.to_be_bytes());
+    true
+}
+
+/// When the request `reply` echoes was sent, by the timestamp it carried,
+/// `None` if the payload is too small or the timestamp isn't one of ours
+pub fn sent_at(reply: &IcmpPacket) -> Option<Instant> {
+    let bytes = reply.payload.get(..TIMESTAMP_LEN)?;
+    let sent_ns = u64::from_be_bytes(bytes.try_into().ok()?);
+    let sent_at = epoch().checked_add(Duration::from_nanos(sent_ns))?;
+    (sent_at <= Instant::now()).then_some(sent_at)
+}
@@ -1,67 +1,1677 @@
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{fmt, mem, ptr};
 
 use crate::dns::{
+    edns::{negotiate_payload_size, ExtendedDnsError, EDNS_UDP_PAYLOAD_SZ},
     header::DnsResponseCode,
-    query::DnsQueryType,
-    resolve::{lookup, recursive_lookup},
+    packet::{DnsPacket, MAX_CNAME_CHAIN},
+    query::{DnsQueryClass, DnsQueryType},
+    record::DnsRecord,
+    resolv_conf::ResolvConf,
+    resolve::{lookup_with_options, recursive_lookup_from, LookupOptions, RootHint, TraceStep},
 };
 
-const SERVER: (IpAddr, u16) = (IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 53);
+/// Recursive resolver queried before falling back to iterating from the
+/// root, when the system configures none
+pub const SERVER: (IpAddr, u16) = (IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 53);
 
-pub fn resolve_hostname(hostname: &str) -> Option<IpAddr> {
-    // First, try resolving the hostname using the system's DNS resolver.
-    if let Ok(mut resolved) = (hostname, 0).to_socket_addrs() {
-        if let Some(socket_addr) = resolved.next() {
-            return Some(socket_addr.ip());
+/// The nameservers of `conf` on the DNS port, or `SERVER` if it lists none
+fn nameservers(conf: &ResolvConf) -> Vec<(IpAddr, u16)> {
+    match conf.nameservers.as_slice() {
+        [] => vec![SERVER],
+        nameservers => nameservers.iter().map(|&ip| (ip, 53)).collect(),
+    }
+}
+
+/// Where hostnames are looked up, in order
+#[derive(Debug, Clone)]
+pub struct Sources {
+    /// Whether to ask the system resolver first
+    pub system: bool,
+    /// Recursive servers asked next, in order, by default those of the
+    /// system
+    pub servers: Vec<(IpAddr, u16)>,
+    /// Servers to iterate from if the recursive resolver has no answer,
+    /// tried in order until one does
+    pub root_hints: Vec<RootHint>,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Self {
+            system: true,
+            servers: nameservers(&ResolvConf::system().unwrap_or_default()),
+            root_hints: RootHint::root_servers(),
+        }
+    }
+}
+
+/// One step taken while resolving a hostname, see `trace_hostname`
+#[derive(Debug, Clone)]
+pub enum ResolutionStep {
+    /// The hostname was an IP address already
+    Literal(IpAddr),
+    /// What the system resolver returned, no addresses if the lookup failed
+    System(Vec<IpAddr>),
+    /// A response from the recursive resolver `server`
+    Response {
+        server: (IpAddr, u16),
+        query_name: String,
+        query_type: DnsQueryType,
+        response_code: DnsResponseCode,
+        /// The zone referred to and the nameservers named for it, if the
+        /// response was a referral
+        referral: Option<(String, Vec<String>)>,
+        answers: Vec<String>,
+    },
+    /// A response from a server asked while iterating from the root
+    Iteration(TraceStep),
+    /// A query of the recursive server `server`, or of the servers iterated
+    /// through if `None`, that got no response
+    Failed {
+        server: Option<(IpAddr, u16)>,
+        query_type: DnsQueryType,
+        error: String,
+    },
+}
+
+impl ResolutionStep {
+    fn response(server: (IpAddr, u16), response: &DnsPacket) -> Self {
+        let (query_name, query_type) = response
+            .questions
+            .first()
+            .map(|question| (question.name.clone(), question.query_type))
+            .unwrap_or((String::new(), DnsQueryType::UNKNOWN(0)));
+        let nameservers: Vec<(&str, &str)> = response.get_nameservers(&query_name).collect();
+        let referral = nameservers.first().map(|(zone, _)| {
+            let hosts = nameservers.iter().map(|(_, host)| host.to_string());
+            (zone.to_string(), hosts.collect())
+        });
+
+        ResolutionStep::Response {
+            server,
+            query_name: query_name.clone(),
+            query_type,
+            response_code: response.header.response_code,
+            referral,
+            answers: response.answers.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+/// Error returned when a hostname could not be resolved
+#[derive(Debug)]
+pub enum ResolveError {
+    /// No address was found, along with any Extended DNS Errors the servers gave
+    NotFound {
+        hostname: String,
+        extended_errors: Vec<ExtendedDnsError>,
+    },
+    /// Addresses were asked for in one family, but the host only has
+    /// addresses in the other
+    OtherFamilyOnly {
+        hostname: String,
+        found: AddressFamily,
+    },
+    /// No address was found, and `server` answered without recursion, so
+    /// it is probably authoritative rather than a recursive resolver
+    RecursionUnavailable {
+        hostname: String,
+        server: (IpAddr, u16),
+    },
+}
+
+impl ResolveError {
+    /// Extended DNS Errors reported by the servers while resolving
+    pub fn extended_errors(&self) -> &[ExtendedDnsError] {
+        match self {
+            ResolveError::NotFound {
+                extended_errors, ..
+            } => extended_errors,
+            ResolveError::OtherFamilyOnly { .. } | ResolveError::RecursionUnavailable { .. } => &[],
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::NotFound {
+                hostname,
+                extended_errors,
+            } => {
+                write!(f, "Failed to resolve hostname {}", hostname)?;
+                for (i, error) in extended_errors.iter().enumerate() {
+                    let sep = if i == 0 { ": " } else { "; " };
+                    write!(f, "{}{}", sep, error)?;
+                }
+                Ok(())
+            }
+            ResolveError::OtherFamilyOnly { hostname, found } => {
+                let flag = match found {
+                    AddressFamily::V6 => "-6",
+                    _ => "-4",
+                };
+                write!(f, "{hostname} has only {found} addresses; use {flag}")
+            }
+            ResolveError::RecursionUnavailable { hostname, server } => write!(
+                f,
+                "Failed to resolve hostname {hostname}: server {} does not provide recursion; \
+                 use recursive mode or a recursive resolver",
+                server.0
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// The address family to resolve a hostname in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// Either, preferring IPv4
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    /// The family of `addr`
+    pub fn of(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(_) => Self::V4,
+            IpAddr::V6(_) => Self::V6,
+        }
+    }
+
+    /// Whether `addr` is an address of this family
+    pub fn allows(&self, addr: IpAddr) -> bool {
+        *self == Self::Any || *self == Self::of(addr)
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressFamily::Any => write!(f, "IP"),
+            AddressFamily::V4 => write!(f, "IPv4"),
+            AddressFamily::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// Which address to pick when a name has several
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressSelection {
+    /// Always the first the server listed
+    #[default]
+    First,
+    /// Each in turn, one per resolution of the name
+    RoundRobin,
+    /// Any, picked at random on every resolution
+    Random,
+}
+
+impl AddressSelection {
+    /// Pick one of `count` addresses, given how often the name was resolved
+    /// before
+    fn pick(&self, count: usize, resolved_before: usize) -> usize {
+        match self {
+            Self::First => 0,
+            Self::RoundRobin => resolved_before % count,
+            Self::Random => rand::random::<usize>() % count,
+        }
+    }
+}
+
+impl FromStr for AddressSelection {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "first" => Ok(Self::First),
+            "round-robin" | "rr" => Ok(Self::RoundRobin),
+            "random" => Ok(Self::Random),
+            other => Err(format!(
+                "unknown address selection {other:?}, expected first, round-robin or random"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AddressSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::First => write!(f, "first"),
+            Self::RoundRobin => write!(f, "round-robin"),
+            Self::Random => write!(f, "random"),
+        }
+    }
+}
+
+/// How many dots a name needs to be tried as is before the search domains
+/// are appended to it, as with `options ndots` in resolv.conf
+pub const DEFAULT_NDOTS: usize = 1;
+
+/// Domains appended to a short name to qualify it, like the `search` list of
+/// resolv.conf
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchList {
+    domains: Vec<String>,
+    ndots: usize,
+}
+
+impl Default for SearchList {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl SearchList {
+    /// Search `domains`, in order, for names with fewer than `DEFAULT_NDOTS`
+    /// dots. Empty domains are left out.
+    pub fn new(domains: Vec<String>) -> Self {
+        let domains = domains
+            .into_iter()
+            .map(|domain| domain.trim_matches('.').to_string())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        Self {
+            domains,
+            ndots: DEFAULT_NDOTS,
+        }
+    }
+
+    /// The search list and ndots of a resolv.conf
+    pub fn from_resolv_conf(conf: &ResolvConf) -> Self {
+        Self::new(conf.search.clone()).with_ndots(conf.ndots.unwrap_or(DEFAULT_NDOTS))
+    }
+
+    /// Number of dots a name needs to be tried as is first
+    pub fn with_ndots(mut self, ndots: usize) -> Self {
+        self.ndots = ndots;
+        self
+    }
+
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    /// The names to try for `hostname`, in order. A name with fewer than
+    /// ndots dots gets each search domain appended before it is tried as
+    /// is, any other name is tried as is first. A name ending in a dot is
+    /// fully qualified and only tried as is.
+    pub fn candidates(&self, hostname: &str) -> Vec<String> {
+        if hostname.ends_with('.') || self.domains.is_empty() {
+            return vec![hostname.to_string()];
+        }
+
+        let searched = self
+            .domains
+            .iter()
+            .map(|domain| format!("{hostname}.{domain}"));
+        if hostname.matches('.').count() < self.ndots {
+            searched.chain([hostname.to_string()]).collect()
+        } else {
+            [hostname.to_string()].into_iter().chain(searched).collect()
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, ResolveError>;
+
+/// How long a lookup waits for each server by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A mail exchanger of a domain: its priority, host and addresses
+pub type MailExchanger = (u16, String, Vec<IpAddr>);
+
+/// Resolves hostnames through a set of recursive servers, falling back to
+/// iterating from the root, and caches the addresses found for as long as
+/// their records' TTL allows. Names found not to exist are cached too, for
+/// as long as the negative TTL of the SOA in the response allows.
+///
+/// The UDP payload size each server advertises over EDNS(0) is remembered
+/// too, and later queries to it advertise no more than that, so neither
+/// side sends responses large enough to fragment.
+///
+/// A name with several addresses resolves to the first unless told to
+/// rotate through them, see `with_selection`. Short names are qualified
+/// with the domains given to `with_search`.
+///
+/// Construct one and keep it around to share the cache across lookups.
+#[derive(Debug)]
+pub struct Resolver {
+    servers: Vec<(IpAddr, u16)>,
+    cache: HashMap<(String, DnsQueryType), CacheEntry>,
+    /// How many times each name was resolved, to rotate through its addresses
+    resolutions: HashMap<(String, DnsQueryType), usize>,
+    selection: AddressSelection,
+    search: SearchList,
+    /// Where to start iterating from when no recursive server answers
+    root_hints: Vec<RootHint>,
+    /// The name each name given was found under, when a search domain had to
+    /// be appended to it
+    expansions: HashMap<(String, DnsQueryType), String>,
+    /// UDP payload size negotiated with each server that answered with EDNS
+    payload_sizes: Mutex<HashMap<(IpAddr, u16), u16>>,
+    edns: bool,
+    query_class: DnsQueryClass,
+    timeout: Duration,
+    /// Every step taken, when recording them for `trace_hostname`
+    steps: Option<Mutex<Vec<ResolutionStep>>>,
+}
+
+/// The cached addresses, or the errors of a negative answer, and when they
+/// stop being valid
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    result: std::result::Result<Vec<IpAddr>, Vec<ExtendedDnsError>>,
+    /// Where the CNAMEs of the name led, if it had any
+    canonical_name: Option<String>,
+    expires_at: Instant,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    /// A resolver configured like the system's, see `from_resolv_conf`.
+    /// Without a readable /etc/resolv.conf it asks `SERVER` and doesn't
+    /// search.
+    pub fn new() -> Self {
+        Self::from_resolv_conf(&ResolvConf::system().unwrap_or_default())
+    }
+
+    /// A resolver asking the nameservers of `conf`, or `SERVER` if it lists
+    /// none, and qualifying short names with its search list. EDNS(0) is
+    /// enabled and the cache empty.
+    pub fn from_resolv_conf(conf: &ResolvConf) -> Self {
+        Self {
+            servers: nameservers(conf),
+            cache: HashMap::new(),
+            resolutions: HashMap::new(),
+            selection: AddressSelection::default(),
+            search: SearchList::from_resolv_conf(conf),
+            root_hints: RootHint::root_servers(),
+            expansions: HashMap::new(),
+            payload_sizes: Mutex::new(HashMap::new()),
+            edns: true,
+            query_class: DnsQueryClass::IN,
+            timeout: DEFAULT_TIMEOUT,
+            steps: None,
+        }
+    }
+
+    /// Recursive servers to ask, in order, before iterating from the root
+    pub fn with_servers(mut self, servers: Vec<(IpAddr, u16)>) -> Self {
+        self.servers = servers;
+        self
+    }
+
+    /// The recursive servers asked, in order
+    pub fn servers(&self) -> &[(IpAddr, u16)] {
+        &self.servers
+    }
+
+    /// The domains short names are qualified with
+    pub fn search(&self) -> &SearchList {
+        &self.search
+    }
+
+    /// Servers to iterate from when none of the recursive servers answers,
+    /// tried in order until one does. The root servers by default, or e.g. a
+    /// private root for split-horizon testing.
+    pub fn with_root_hints(mut self, root_hints: Vec<RootHint>) -> Self {
+        self.root_hints = root_hints;
+        self
+    }
+
+    /// The servers iterated from, in order
+    pub fn root_hints(&self) -> &[RootHint] {
+        &self.root_hints
+    }
+
+    /// Whether to advertise EDNS(0) in queries to the recursive servers
+    pub fn with_edns(mut self, edns: bool) -> Self {
+        self.edns = edns;
+        self
+    }
+
+    /// Class to query the recursive servers in, IN unless e.g. resolving
+    /// names of a CH zone. Only answers in this class are accepted.
+    pub fn with_class(mut self, query_class: DnsQueryClass) -> Self {
+        self.query_class = query_class;
+        self
+    }
+
+    /// How long to wait for each recursive server to answer
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Record every step taken from now on, for `take_steps` to return
+    pub fn with_recorded_steps(mut self) -> Self {
+        self.steps = Some(Mutex::new(Vec::new()));
+        self
+    }
+
+    /// The steps recorded since `with_recorded_steps` or the last call, in
+    /// the order they were taken
+    pub fn take_steps(&mut self) -> Vec<ResolutionStep> {
+        self.steps
+            .as_mut()
+            .map(|steps| std::mem::take(steps.get_mut().unwrap()))
+            .unwrap_or_default()
+    }
+
+    /// Record the step `step` builds, if recording steps
+    fn record(&self, step: impl FnOnce() -> ResolutionStep) {
+        if let Some(steps) = &self.steps {
+            steps.lock().unwrap().push(step());
+        }
+    }
+
+    /// Which address to return for a name with several, e.g. round-robin to
+    /// exercise every backend of a load balanced name in turn
+    pub fn with_selection(mut self, selection: AddressSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Domains to qualify short names with before looking them up as given
+    pub fn with_search(mut self, search: SearchList) -> Self {
+        self.search = search;
+        self
+    }
+
+    /// Number of addresses currently cached, including expired ones not yet
+    /// evicted
+    pub fn cached(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Forget every cached address, and where each name's rotation was
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+        self.resolutions.clear();
+        self.expansions.clear();
+    }
+
+    /// The name the CNAMEs of `hostname` led to when its addresses were last
+    /// looked up, or the name a search domain qualified it to. `None` if it
+    /// was neither aliased nor qualified, or wasn't looked up.
+    pub fn canonical_name(&self, hostname: &str) -> Option<&str> {
+        let hostname = hostname.to_ascii_lowercase();
+        [DnsQueryType::A, DnsQueryType::AAAA]
+            .into_iter()
+            .find_map(|query_type| {
+                let key = (hostname.clone(), query_type);
+                match self.expansions.get(&key) {
+                    Some(expanded) => {
+                        let entry = self
+                            .cache
+                            .get(&(expanded.to_ascii_lowercase(), query_type))?;
+                        Some(entry.canonical_name.as_deref().unwrap_or(expanded))
+                    }
+                    None => self.cache.get(&key)?.canonical_name.as_deref(),
+                }
+            })
+    }
+
+    /// UDP payload size advertised in queries to `server`: ours, lowered to
+    /// what the server advertised once it has answered with EDNS(0)
+    pub fn payload_size(&self, server: (IpAddr, u16)) -> u16 {
+        self.payload_sizes
+            .lock()
+            .unwrap()
+            .get(&server)
+            .copied()
+            .unwrap_or(EDNS_UDP_PAYLOAD_SZ)
+    }
+
+    /// Remember the payload size `server` advertised in `response`
+    fn negotiate_payload_size(&self, server: (IpAddr, u16), response: &DnsPacket) {
+        if let Some(theirs) = response.udp_payload_size() {
+            let size = negotiate_payload_size(EDNS_UDP_PAYLOAD_SZ, theirs);
+            self.payload_sizes.lock().unwrap().insert(server, size);
+        }
+    }
+
+    /// Resolve `hostname` to an IPv4 address, or failing that an IPv6 address
+    pub fn resolve(&mut self, hostname: &str) -> Result<IpAddr> {
+        let mut extended_errors = Vec::new();
+
+        // Attempt to resolve the hostname to an IPv4 address.
+        match self.resolve_v4(hostname) {
+            Ok(ipv4_addr) => return Ok(IpAddr::V4(ipv4_addr)),
+            Err(err) => merge_extended_errors(&mut extended_errors, err.extended_errors().to_vec()),
+        }
+
+        // If IPv4 resolution fails, attempt to resolve it to an IPv6 address.
+        match self.resolve_v6(hostname) {
+            Ok(ipv6_addr) => return Ok(IpAddr::V6(ipv6_addr)),
+            Err(err) => merge_extended_errors(&mut extended_errors, err.extended_errors().to_vec()),
+        }
+
+        // If both attempts fail, report whatever reasons the servers gave.
+        Err(ResolveError::NotFound {
+            hostname: hostname.to_string(),
+            extended_errors,
+        })
+    }
+
+    /// Resolve `hostname` to an address of `family`, IPv4 preferred for
+    /// `AddressFamily::Any`
+    pub fn resolve_in(&mut self, hostname: &str, family: AddressFamily) -> Result<IpAddr> {
+        match family {
+            AddressFamily::Any => self.resolve(hostname),
+            AddressFamily::V4 => self.resolve_v4(hostname).map(IpAddr::V4),
+            AddressFamily::V6 => self.resolve_v6(hostname).map(IpAddr::V6),
+        }
+    }
+
+    pub fn resolve_v4(&mut self, hostname: &str) -> Result<Ipv4Addr> {
+        match self.resolve_address(hostname, DnsQueryType::A)? {
+            IpAddr::V4(addr) => Ok(addr),
+            IpAddr::V6(_) => unreachable!("A lookups only yield IPv4 addresses"),
+        }
+    }
+
+    pub fn resolve_v6(&mut self, hostname: &str) -> Result<Ipv6Addr> {
+        match self.resolve_address(hostname, DnsQueryType::AAAA)? {
+            IpAddr::V6(addr) => Ok(addr),
+            IpAddr::V4(_) => unreachable!("AAAA lookups only yield IPv6 addresses"),
+        }
+    }
+
+    /// Resolve the mail exchangers of `domain`, most preferred first.
+    ///
+    /// Addresses are taken from the glue in the additional section, falling
+    /// back to resolving the exchanger's A/AAAA records. A null MX (RFC 7505)
+    /// is returned as a single exchanger with priority 0, an empty host and no
+    /// addresses, meaning the domain accepts no mail.
+    pub fn resolve_mx(&mut self, domain: &str) -> Result<Vec<MailExchanger>> {
+        let response = self.query(domain, DnsQueryType::MX)?;
+
+        let exchangers = response
+            .mx_records_sorted()
+            .into_iter()
+            .map(|(priority, host)| {
+                let mut addresses = response.glue_addresses(host);
+                if addresses.is_empty() && !host.is_empty() {
+                    addresses.extend(self.resolve_v4(host).map(IpAddr::V4));
+                    addresses.extend(self.resolve_v6(host).map(IpAddr::V6));
+                }
+                (priority, host.to_string(), addresses)
+            })
+            .collect();
+
+        Ok(exchangers)
+    }
+
+    /// The target of the CNAME of `name`, or `None` if it has none or the
+    /// lookup failed
+    pub fn resolve_cname(&self, name: &str) -> Option<String> {
+        let response = self.query(name, DnsQueryType::CNAME).ok()?;
+        let target = response.cname_chain(name).first()?.to_string();
+        Some(target)
+    }
+
+    /// Every name the CNAMEs of `name` lead through, in order, querying each
+    /// target in turn for servers that don't return the whole chain at once.
+    /// Stops at the first name without a CNAME, at a loop, or after
+    /// `MAX_CNAME_CHAIN` names.
+    pub fn resolve_cname_chain(&self, name: &str) -> Vec<String> {
+        let mut chain: Vec<String> = Vec::new();
+        let mut name = name.to_string();
+
+        while chain.len() < MAX_CNAME_CHAIN {
+            let Ok(response) = self.query(&name, DnsQueryType::CNAME) else {
+                break;
+            };
+            let mut looped = false;
+            for target in response.cname_chain(&name) {
+                looped = chain.iter().any(|seen| seen.eq_ignore_ascii_case(target));
+                if looped {
+                    break;
+                }
+                chain.push(target.to_string());
+            }
+            match chain.last() {
+                Some(last) if !looped && !last.eq_ignore_ascii_case(&name) => name = last.clone(),
+                _ => break,
+            }
+        }
+
+        chain.truncate(MAX_CNAME_CHAIN);
+        chain
+    }
+
+    /// Look up an A or AAAA record of `hostname`, qualified with the search
+    /// domains if it is short, picked as `with_selection` says among them
+    fn resolve_address(&mut self, hostname: &str, query_type: DnsQueryType) -> Result<IpAddr> {
+        let mut err = None;
+        for name in self.search.candidates(hostname) {
+            let key = (name.to_ascii_lowercase(), query_type);
+            match self.resolve_addresses(&key, &name) {
+                Ok(addrs) => {
+                    let expansion = (hostname.to_ascii_lowercase(), query_type);
+                    if name == hostname {
+                        self.expansions.remove(&expansion);
+                    } else {
+                        self.expansions.insert(expansion, name);
+                    }
+                    return Ok(self.pick_address(key, &addrs));
+                }
+                // Failing every name, report why the name as given failed
+                Err(failure) if name == hostname || err.is_none() => err = Some(failure),
+                Err(_) => {}
+            }
+        }
+        Err(err.expect("a name is always tried as given"))
+    }
+
+    /// Pick one of the addresses `key` resolved to, as `with_selection` says
+    fn pick_address(&mut self, key: (String, DnsQueryType), addrs: &[IpAddr]) -> IpAddr {
+        let resolved_before = self.resolutions.entry(key).or_insert(0);
+        let addr = addrs[self.selection.pick(addrs.len(), *resolved_before)];
+        *resolved_before += 1;
+        addr
+    }
+
+    /// Look up every A or AAAA record of `hostname`, from the cache if there
+    /// is an unexpired entry. Never empty.
+    fn resolve_addresses(
+        &mut self,
+        key: &(String, DnsQueryType),
+        hostname: &str,
+    ) -> Result<Vec<IpAddr>> {
+        let query_type = key.1;
+        if let Some(entry) = self.cache.get(key) {
+            if entry.expires_at > Instant::now() {
+                return entry
+                    .result
+                    .clone()
+                    .map_err(|extended_errors| ResolveError::NotFound {
+                        hostname: hostname.to_string(),
+                        extended_errors,
+                    });
+            }
+            self.cache.remove(key);
+        }
+
+        let (err, negative_ttl) = match self.query_answer(hostname, query_type) {
+            Ok(response) => {
+                // `query_answer` only returns responses answering `hostname`
+                let addrs = response.get_records(hostname, query_type);
+                let ttl = address_ttl(&response, hostname, query_type);
+                let canonical_name = response
+                    .cname_chain(hostname)
+                    .last()
+                    .map(|name| name.to_string());
+                self.cache_result(key.clone(), Ok(addrs.clone()), canonical_name, ttl);
+                return Ok(addrs);
+            }
+            Err(failure) => failure,
+        };
+
+        if let Some(ttl) = negative_ttl {
+            self.cache_result(key.clone(), Err(err.extended_errors().to_vec()), None, ttl);
         }
+        Err(err)
     }
 
-    // Attempt to resolve the hostname to an IPv4 address.
-    if let Some(ipv4_addr) = resolve_hostname_to_v4(hostname) {
-        return Some(IpAddr::V4(ipv4_addr));
+    fn cache_result(
+        &mut self,
+        key: (String, DnsQueryType),
+        result: std::result::Result<Vec<IpAddr>, Vec<ExtendedDnsError>>,
+        canonical_name: Option<String>,
+        ttl: u32,
+    ) {
+        self.cache.insert(
+            key,
+            CacheEntry {
+                result,
+                canonical_name,
+                expires_at: Instant::now() + Duration::from_secs(ttl.into()),
+            },
+        );
     }
 
-    // If IPv4 resolution fails, attempt to resolve it to an IPv6 address.
-    if let Some(ipv6_addr) = resolve_hostname_to_v6(hostname) {
-        return Some(IpAddr::V6(ipv6_addr));
+    /// Query `hostname` for records of `query_type`, first through the
+    /// recursive servers and then by iterating from the root servers
+    pub fn query(&self, hostname: &str, query_type: DnsQueryType) -> Result<DnsPacket> {
+        self.query_answer(hostname, query_type)
+            .map_err(|(err, _)| err)
     }
 
-    // If both attempts fail, return None.
-    None
+    /// Like `query`, but on failure also returns how long the name may be
+    /// cached as nonexistent, if a server gave a negative answer with a SOA
+    fn query_answer(
+        &self,
+        hostname: &str,
+        query_type: DnsQueryType,
+    ) -> std::result::Result<DnsPacket, (ResolveError, Option<u32>)> {
+        let mut extended_errors = Vec::new();
+        let mut negative_ttl = None;
+        let has_answer = |response: &DnsPacket| {
+            response.header.response_code == DnsResponseCode::NoError
+                && response
+                    .answers_in_class(hostname, query_type, Some(self.query_class))
+                    .next()
+                    .is_some()
+        };
+        // NXDOMAIN and NOERROR without data are negative answers, unlike
+        // e.g. SERVFAIL which may well succeed on retry
+        let is_negative = |response: &DnsPacket| {
+            matches!(
+                response.header.response_code,
+                DnsResponseCode::NoError | DnsResponseCode::NxDomain
+            )
+        };
+
+        let mut trace = |step: &TraceStep| {
+            self.record(|| ResolutionStep::Iteration(step.clone()));
+        };
+        let responses = self
+            .servers
+            .iter()
+            .map(|server| {
+                let options = LookupOptions {
+                    edns: self.edns,
+                    udp_payload_size: self.payload_size(*server),
+                    query_class: self.query_class,
+                    timeout: Some(self.timeout),
+                    ..Default::default()
+                };
+                let response = lookup_with_options(hostname, query_type, *server, &options);
+                if let Ok(response) = &response {
+                    self.negotiate_payload_size(*server, response);
+                }
+                (Some(*server), response)
+            })
+            // Referrals only exist for IN, so only then iterate from the root
+            .chain(
+                std::iter::once(())
+                    .filter(|_| self.query_class == DnsQueryClass::IN)
+                    .map(|_| {
+                        let response = recursive_lookup_from(
+                            hostname,
+                            query_type,
+                            &self.root_hints,
+                            Some(self.timeout),
+                            &mut trace,
+                        );
+                        (None, response)
+                    }),
+            );
+
+        // The first server asked for recursion that didn't offer it
+        let mut non_recursive = None;
+        for (server, response) in responses {
+            let response = match (server, response) {
+                (Some(server), Ok(response)) => {
+                    self.record(|| ResolutionStep::response(server, &response));
+                    response
+                }
+                // Each server asked while iterating was recorded already
+                (None, Ok(response)) => response,
+                (server, Err(err)) => {
+                    self.record(|| ResolutionStep::Failed {
+                        server,
+                        query_type,
+                        error: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            if has_answer(&response) {
+                return Ok(response);
+            }
+            if server.is_some() && response.lacks_recursion() {
+                non_recursive = non_recursive.or(server);
+                continue;
+            }
+            if negative_ttl.is_none() && is_negative(&response) {
+                negative_ttl = response.get_negative_ttl();
+            }
+            merge_extended_errors(&mut extended_errors, response.extended_errors());
+        }
+
+        // An empty answer from a misconfigured server is no reason to
+        // believe the name doesn't exist, so it isn't cached
+        if let Some(server) = non_recursive {
+            let err = ResolveError::RecursionUnavailable {
+                hostname: hostname.to_string(),
+                server,
+            };
+            return Err((err, None));
+        }
+
+        let err = ResolveError::NotFound {
+            hostname: hostname.to_string(),
+            extended_errors,
+        };
+        Err((err, negative_ttl))
+    }
 }
 
-pub fn resolve_hostname_to_v4(hostname: &str) -> Option<Ipv4Addr> {
-    if let Ok(response) = lookup(hostname, DnsQueryType::A, SERVER) {
-        if response.header.response_code == DnsResponseCode::NoError {
-            if let Some(IpAddr::V4(record)) = response.get_record(DnsQueryType::A) {
-                return Some(record);
+/// TTL of the first `query_type` address record answering `hostname`
+fn address_ttl(response: &DnsPacket, hostname: &str, query_type: DnsQueryType) -> u32 {
+    response
+        .answers_for(hostname, query_type)
+        .next()
+        .map(|record| match record {
+            DnsRecord::A { ttl, .. } | DnsRecord::AAAA { ttl, .. } => *ttl,
+            _ => 0,
+        })
+        .unwrap_or(0)
+}
+
+/// The host of a destination given as a URL such as
+/// `https://example.com:8443/path` or `http://[::1]/`, ignoring the scheme,
+/// credentials, port and path. Anything that isn't a URL is returned as is,
+/// apart from the brackets around an IPv6 literal.
+pub fn host_from_url(destination: &str) -> &str {
+    let Some((_, rest)) = destination.split_once("://") else {
+        return destination
+            .strip_prefix('[')
+            .and_then(|bracketed| bracketed.split_once(']'))
+            .map_or(destination, |(host, _)| host);
+    };
+
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &rest[..end];
+    let host_port = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host_port)| host_port);
+
+    match host_port.strip_prefix('[') {
+        Some(bracketed) => bracketed
+            .split_once(']')
+            .map_or(host_port, |(host, _)| host),
+        None => host_port
+            .split_once(':')
+            .map_or(host_port, |(host, _)| host),
+    }
+}
+
+/// Where `resolve_hostname` found an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolutionSource {
+    /// The hostname was an IP address already
+    Literal,
+    /// The system resolver, i.e. `getaddrinfo` with /etc/hosts and friends
+    System,
+    /// Our own DNS resolver
+    Dns,
+}
+
+impl fmt::Display for ResolutionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolutionSource::Literal => write!(f, "literal address"),
+            ResolutionSource::System => write!(f, "system resolver"),
+            ResolutionSource::Dns => write!(f, "DNS"),
+        }
+    }
+}
+
+/// The name the PTR record of `ip` lives under, e.g. `4.3.2.1.in-addr.arpa`
+/// for `1.2.3.4`, or the nibbles of an IPv6 address reversed under `ip6.arpa`
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(ip) => {
+            let nibbles: Vec<String> = ip
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|byte| [byte & 0x0F, byte >> 4])
+                .map(|nibble| format!("{nibble:x}"))
+                .collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        }
+    }
+}
+
+/// Split the zone off a scoped IPv6 address such as `fe80::1%eth0`, giving
+/// `("fe80::1", Some("eth0"))`. Anything else is returned as is, with no
+/// zone.
+pub fn split_zone(destination: &str) -> (&str, Option<&str>) {
+    match destination.split_once('%') {
+        Some((address, zone)) if !zone.is_empty() && address.parse::<Ipv6Addr>().is_ok() => {
+            (address, Some(zone))
+        }
+        _ => (destination, None),
+    }
+}
+
+pub fn resolve_hostname(hostname: &str) -> Result<IpAddr> {
+    resolve_hostname_with_source(hostname).map(|(addr, _)| addr)
+}
+
+/// Like `resolve_hostname`, also returning where the address came from
+pub fn resolve_hostname_with_source(hostname: &str) -> Result<(IpAddr, ResolutionSource)> {
+    resolve_hostname_in(hostname, AddressFamily::Any)
+}
+
+/// Like `resolve_hostname_with_source`, only returning an address of
+/// `family`. A host with addresses in the other family only is reported as
+/// `ResolveError::OtherFamilyOnly` rather than as not found.
+pub fn resolve_hostname_in(
+    hostname: &str,
+    family: AddressFamily,
+) -> Result<(IpAddr, ResolutionSource)> {
+    resolve_hostname_canonical(hostname, family).map(|(addr, source, _)| (addr, source))
+}
+
+/// Like `resolve_hostname_in`, also returning the canonical name that
+/// CNAMEs (or an alias in /etc/hosts) led from `hostname` to, if it is
+/// another name
+pub fn resolve_hostname_canonical(
+    hostname: &str,
+    family: AddressFamily,
+) -> Result<(IpAddr, ResolutionSource, Option<String>)> {
+    resolve_hostname_searched(hostname, family, &SearchList::default())
+}
+
+/// Like `resolve_hostname_canonical`, qualifying a short `hostname` with the
+/// domains of `search`. The name it was found under is reported as its
+/// canonical name, unless CNAMEs led further.
+pub fn resolve_hostname_searched(
+    hostname: &str,
+    family: AddressFamily,
+    search: &SearchList,
+) -> Result<(IpAddr, ResolutionSource, Option<String>)> {
+    resolve_hostname_from(
+        hostname,
+        family,
+        search,
+        &Sources::default(),
+        &mut Vec::new(),
+    )
+}
+
+/// The outcome of `trace_hostname` and the steps that led to it
+#[derive(Debug)]
+pub struct HostnameTrace {
+    pub steps: Vec<ResolutionStep>,
+    pub result: Result<(IpAddr, ResolutionSource, Option<String>)>,
+}
+
+/// Resolve `hostname` exactly like `resolve_hostname_searched` does with
+/// `sources`, recording every step taken: what the system resolver returned,
+/// each server asked and the records it answered or the referral it gave.
+/// For finding out why a name failed to resolve or resolved to a surprising
+/// address.
+pub fn trace_hostname(
+    hostname: &str,
+    family: AddressFamily,
+    search: &SearchList,
+    sources: &Sources,
+) -> HostnameTrace {
+    let mut steps = Vec::new();
+    let result = resolve_hostname_from(hostname, family, search, sources, &mut steps);
+    HostnameTrace { steps, result }
+}
+
+/// Resolve `hostname` to an address of `family` through `sources`, trying
+/// the names `search` qualifies it to, and adding the steps taken to `steps`
+fn resolve_hostname_from(
+    hostname: &str,
+    family: AddressFamily,
+    search: &SearchList,
+    sources: &Sources,
+    steps: &mut Vec<ResolutionStep>,
+) -> Result<(IpAddr, ResolutionSource, Option<String>)> {
+    // A literal address has no domain to search
+    if hostname.parse::<IpAddr>().is_ok() {
+        return resolve_hostname_once(hostname, family, sources, steps);
+    }
+
+    let mut err = None;
+    for name in search.candidates(hostname) {
+        match resolve_hostname_once(&name, family, sources, steps) {
+            Ok((addr, source, canonical_name)) => {
+                let qualified = (name != hostname).then_some(name);
+                return Ok((addr, source, canonical_name.or(qualified)));
             }
+            // Failing every name, report why the name as given failed
+            Err(failure) if name == hostname || err.is_none() => err = Some(failure),
+            Err(_) => {}
         }
     }
+    Err(err.expect("a name is always tried as given"))
+}
+
+/// Resolve `hostname` exactly as given, see `resolve_hostname_from`
+fn resolve_hostname_once(
+    hostname: &str,
+    family: AddressFamily,
+    sources: &Sources,
+    steps: &mut Vec<ResolutionStep>,
+) -> Result<(IpAddr, ResolutionSource, Option<String>)> {
+    let other_family_only = |found: IpAddr| ResolveError::OtherFamilyOnly {
+        hostname: hostname.to_string(),
+        found: AddressFamily::of(found),
+    };
+
+    // Only a name other than the one asked about is worth reporting
+    let other_name = |canonical: Option<&str>| {
+        canonical
+            .map(|name| name.trim_end_matches('.'))
+            .filter(|name| !name.eq_ignore_ascii_case(hostname.trim_end_matches('.')))
+            .map(str::to_string)
+    };
 
-    if let Ok(response) = recursive_lookup(hostname, DnsQueryType::A) {
-        if let Some(IpAddr::V4(record)) = response.get_record(DnsQueryType::A) {
-            return Some(record);
+    if let Ok(addr) = hostname.parse() {
+        steps.push(ResolutionStep::Literal(addr));
+        if !family.allows(addr) {
+            return Err(other_family_only(addr));
         }
+        return Ok((addr, ResolutionSource::Literal, None));
     }
 
-    None
+    // First, try resolving the hostname using the system's DNS resolver.
+    let mut other_family = None;
+    if sources.system {
+        let (addrs, canonical_name) = system_lookup(hostname).unwrap_or_default();
+        steps.push(ResolutionStep::System(addrs.clone()));
+        for addr in addrs {
+            if family.allows(addr) {
+                let canonical_name = other_name(canonical_name.as_deref());
+                return Ok((addr, ResolutionSource::System, canonical_name));
+            }
+            other_family.get_or_insert(addr);
+        }
+    }
+
+    // The system resolver already searched the system's domains
+    let mut resolver = Resolver::new()
+        .with_servers(sources.servers.clone())
+        .with_root_hints(sources.root_hints.clone())
+        .with_search(SearchList::default())
+        .with_recorded_steps();
+    let result = match resolver.resolve_in(hostname, family) {
+        Ok(addr) => {
+            let canonical_name = other_name(resolver.canonical_name(hostname));
+            Ok((addr, ResolutionSource::Dns, canonical_name))
+        }
+        Err(err) => {
+            // Tell a host without addresses apart from one in the other family
+            let other_family = other_family.or_else(|| match family {
+                AddressFamily::Any => None,
+                AddressFamily::V4 => resolver.resolve_v6(hostname).ok().map(IpAddr::V6),
+                AddressFamily::V6 => resolver.resolve_v4(hostname).ok().map(IpAddr::V4),
+            });
+            Err(other_family.map_or(err, other_family_only))
+        }
+    };
+    steps.extend(resolver.take_steps());
+    result
 }
 
-pub fn resolve_hostname_to_v6(hostname: &str) -> Option<Ipv6Addr> {
-    if let Ok(response) = lookup(hostname, DnsQueryType::AAAA, SERVER) {
-        if response.header.response_code == DnsResponseCode::NoError {
-            if let Some(IpAddr::V6(record)) = response.get_record(DnsQueryType::AAAA) {
-                return Some(record);
+/// Look `hostname` up with the system resolver, i.e. `getaddrinfo` with
+/// /etc/hosts and friends, returning its addresses and the canonical name
+/// reported, or `None` if the lookup failed
+fn system_lookup(hostname: &str) -> Option<(Vec<IpAddr>, Option<String>)> {
+    let name = CString::new(hostname).ok()?;
+    let mut hints: libc::addrinfo = unsafe { mem::zeroed() };
+    // One entry per address rather than one per socket type
+    hints.ai_socktype = libc::SOCK_STREAM;
+    hints.ai_flags = libc::AI_CANONNAME;
+
+    let mut found: *mut libc::addrinfo = ptr::null_mut();
+    if unsafe { libc::getaddrinfo(name.as_ptr(), ptr::null(), &hints, &mut found) } != 0 {
+        return None;
+    }
+
+    let mut addrs = Vec::new();
+    let mut canonical_name = None;
+    let mut entry = found;
+    while !entry.is_null() {
+        let info = unsafe { &*entry };
+        if canonical_name.is_none() && !info.ai_canonname.is_null() {
+            let canonical = unsafe { CStr::from_ptr(info.ai_canonname) };
+            canonical_name = Some(canonical.to_string_lossy().into_owned());
+        }
+        match info.ai_family {
+            libc::AF_INET => {
+                let address = unsafe { &*(info.ai_addr as *const libc::sockaddr_in) };
+                addrs.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    address.sin_addr.s_addr,
+                ))));
+            }
+            libc::AF_INET6 => {
+                let address = unsafe { &*(info.ai_addr as *const libc::sockaddr_in6) };
+                addrs.push(IpAddr::V6(Ipv6Addr::from(address.sin6_addr.s6_addr)));
             }
+            _ => {}
         }
+        entry = info.ai_next;
     }
+    unsafe { libc::freeaddrinfo(found) };
+
+    Some((addrs, canonical_name))
+}
+
+pub fn resolve_hostname_to_v4(hostname: &str) -> Result<Ipv4Addr> {
+    Resolver::new().resolve_v4(hostname)
+}
+
+pub fn resolve_hostname_to_v6(hostname: &str) -> Result<Ipv6Addr> {
+    Resolver::new().resolve_v6(hostname)
+}
+
+/// Resolve the mail exchangers of `domain`, see `Resolver::resolve_mx`
+pub fn resolve_mx(domain: &str) -> Result<Vec<MailExchanger>> {
+    Resolver::new().resolve_mx(domain)
+}
+
+/// The target of the CNAME of `name`, see `Resolver::resolve_cname`
+pub fn resolve_cname(name: &str) -> Option<String> {
+    Resolver::new().resolve_cname(name)
+}
+
+/// Whether the exchangers are a null MX, i.e. the domain accepts no mail
+pub fn is_null_mx(exchangers: &[MailExchanger]) -> bool {
+    matches!(exchangers, [(0, host, _)] if host.is_empty())
+}
 
-    if let Ok(response) = recursive_lookup(hostname, DnsQueryType::AAAA) {
-        if let Some(IpAddr::V6(record)) = response.get_record(DnsQueryType::AAAA) {
-            return Some(record);
+/// Merge Extended DNS Errors into `extended_errors`, skipping repeats
+fn merge_extended_errors(
+    extended_errors: &mut Vec<ExtendedDnsError>,
+    errors: impl IntoIterator<Item = ExtendedDnsError>,
+) {
+    for error in errors {
+        if !extended_errors.contains(&error) {
+            extended_errors.push(error);
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::record::DnsRecord;
+    use crate::dns::resolve::tests::{response_to, serve};
+    use std::sync::Arc;
 
-    None
+    /// A referral of `query` to the servers of `zone`, served on localhost
+    fn referral(query: &DnsPacket, zone: &str, nameserver: &str) -> DnsPacket {
+        let mut response = response_to(query);
+        response.header.authority_count = 1;
+        response.authorities.push(DnsRecord::NS {
+            domain: zone.to_string(),
+            class: DnsQueryClass::IN,
+            host: nameserver.to_string(),
+            ttl: 300,
+        });
+        response.header.additional_count = 1;
+        response.additional.push(DnsRecord::A {
+            domain: nameserver.to_string(),
+            class: DnsQueryClass::IN,
+            addr: Ipv4Addr::LOCALHOST,
+            ttl: 300,
+        });
+        response
+    }
+
+    /// A recursive server's answer to `query` with the A records `addrs`
+    fn answer(query: &DnsPacket, addrs: &[Ipv4Addr]) -> DnsPacket {
+        let mut response = response_to(query);
+        response.header.recursion_available = true;
+        for addr in addrs {
+            response.header.answer_count += 1;
+            response.answers.push(DnsRecord::A {
+                domain: query.questions[0].name.clone(),
+                class: DnsQueryClass::IN,
+                addr: *addr,
+                ttl: 300,
+            });
+        }
+        response
+    }
+
+    /// The CNAME record aliasing `alias` to `target`
+    fn cname(alias: &str, target: &str) -> DnsRecord {
+        DnsRecord::CNAME {
+            domain: alias.to_string(),
+            class: DnsQueryClass::IN,
+            host: target.to_string(),
+            ttl: 300,
+        }
+    }
+
+    #[test]
+    fn trace_follows_the_referral_chain() {
+        let mut queries = 0;
+        let (server, handle) = serve(4, move |query| {
+            queries += 1;
+            match queries {
+                // The recursive resolver fails, so iteration starts
+                1 => {
+                    let mut response = response_to(&query);
+                    response.header.response_code = DnsResponseCode::ServFail;
+                    response
+                }
+                2 => referral(&query, "com", "a.gtld-servers.net"),
+                3 => referral(&query, "example.com", "ns1.example.com"),
+                _ => {
+                    let mut response = response_to(&query);
+                    response.header.answer_count = 1;
+                    response.answers.push(DnsRecord::A {
+                        domain: "www.example.com".to_string(),
+                        class: DnsQueryClass::IN,
+                        addr: Ipv4Addr::new(192, 0, 2, 1),
+                        ttl: 300,
+                    });
+                    response
+                }
+            }
+        });
+
+        let sources = Sources {
+            system: false,
+            servers: vec![server],
+            root_hints: vec![RootHint::from(server.0).with_port(server.1)],
+        };
+        let trace = trace_hostname(
+            "www.example.com",
+            AddressFamily::Any,
+            &SearchList::default(),
+            &sources,
+        );
+        handle.join().unwrap();
+
+        assert_eq!(
+            trace.result.unwrap(),
+            (
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                ResolutionSource::Dns,
+                None
+            )
+        );
+        let steps: Vec<_> = trace
+            .steps
+            .iter()
+            .map(|step| match step {
+                ResolutionStep::Response {
+                    response_code,
+                    referral,
+                    answers,
+                    ..
+                } => (
+                    false,
+                    *response_code,
+                    referral.as_ref().map(|(zone, _)| zone.as_str()),
+                    answers.len(),
+                ),
+                ResolutionStep::Iteration(step) => (
+                    true,
+                    step.response_code,
+                    step.referral.as_ref().map(|(zone, _)| zone.as_str()),
+                    step.answers,
+                ),
+                step => panic!("unexpected step {step:?}"),
+            })
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                (false, DnsResponseCode::ServFail, None, 0),
+                (true, DnsResponseCode::NoError, Some("com"), 0),
+                (true, DnsResponseCode::NoError, Some("example.com"), 0),
+                (true, DnsResponseCode::NoError, None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn payload_size_advertised_by_a_server_is_used_for_the_next_query() {
+        let advertised = Arc::new(Mutex::new(Vec::new()));
+        let server_advertised = Arc::clone(&advertised);
+        let (server, handle) = serve(2, move |query| {
+            server_advertised
+                .lock()
+                .unwrap()
+                .push(query.udp_payload_size());
+            let mut response = response_to(&query);
+            response.header.answer_count = 1;
+            response.answers.push(DnsRecord::A {
+                domain: "www.example.com".to_string(),
+                class: DnsQueryClass::IN,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: 300,
+            });
+            response.header.additional_count = 1;
+            response.additional.push(DnsRecord::OPT {
+                udp_payload_size: 1232,
+                extended_rcode: 0,
+                version: 0,
+                flags: 0,
+                options: Vec::new(),
+            });
+            response
+        });
+
+        let resolver = Resolver::new().with_servers(vec![server]);
+        assert_eq!(resolver.payload_size(server), EDNS_UDP_PAYLOAD_SZ);
+        resolver.query("www.example.com", DnsQueryType::A).unwrap();
+        assert_eq!(resolver.payload_size(server), 1232);
+        resolver.query("www.example.com", DnsQueryType::A).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(
+            *advertised.lock().unwrap(),
+            [Some(EDNS_UDP_PAYLOAD_SZ), Some(1232)]
+        );
+    }
+
+    #[test]
+    fn host_is_taken_from_an_https_url() {
+        assert_eq!(
+            host_from_url("https://example.com:8443/path"),
+            "example.com"
+        );
+        assert_eq!(host_from_url("http://user@example.com?q"), "example.com");
+    }
+
+    #[test]
+    fn bare_host_is_returned_as_is() {
+        assert_eq!(host_from_url("example.com"), "example.com");
+        assert_eq!(host_from_url("192.0.2.1"), "192.0.2.1");
+        assert_eq!(host_from_url("fe80::1"), "fe80::1");
+    }
+
+    #[test]
+    fn bracketed_ipv6_url_yields_the_address() {
+        assert_eq!(host_from_url("http://[::1]:8080/"), "::1");
+        assert_eq!(host_from_url("[2001:db8::1]"), "2001:db8::1");
+    }
+
+    #[test]
+    fn zone_is_split_off_a_scoped_ipv6_address() {
+        assert_eq!(split_zone("fe80::1%eth0"), ("fe80::1", Some("eth0")));
+        assert_eq!(split_zone("fe80::1"), ("fe80::1", None));
+        assert_eq!(split_zone("fe80::1%"), ("fe80::1%", None));
+        assert_eq!(split_zone("host%eth0"), ("host%eth0", None));
+    }
+
+    #[test]
+    fn short_names_are_searched_before_being_tried_as_given() {
+        let search = SearchList::new(vec![
+            "corp.example.com".to_string(),
+            "example.com.".to_string(),
+        ]);
+        assert_eq!(
+            search.candidates("server1"),
+            ["server1.corp.example.com", "server1.example.com", "server1"]
+        );
+        assert_eq!(
+            search.candidates("www.example.org"),
+            [
+                "www.example.org",
+                "www.example.org.corp.example.com",
+                "www.example.org.example.com"
+            ]
+        );
+        assert_eq!(search.candidates("server1."), ["server1."]);
+    }
+
+    #[test]
+    fn short_name_resolves_with_a_search_domain_appended() {
+        let (server, handle) = serve(3, |query| {
+            let mut response = response_to(&query);
+            if query.questions[0].name == "server1.corp.example.com" {
+                response.header.answer_count = 1;
+                response.answers.push(DnsRecord::A {
+                    domain: "server1.corp.example.com".to_string(),
+                    class: DnsQueryClass::IN,
+                    addr: Ipv4Addr::new(192, 0, 2, 10),
+                    ttl: 300,
+                });
+            } else {
+                response.header.response_code = DnsResponseCode::NxDomain;
+            }
+            response
+        });
+
+        // With ndots 0 the name as given is asked about first, of the
+        // recursive server and then iterating, and doesn't exist
+        let search = SearchList::new(vec!["corp.example.com".to_string()]).with_ndots(0);
+        let mut resolver = Resolver::new()
+            .with_servers(vec![server])
+            .with_root_hints(vec![RootHint::from(server.0).with_port(server.1)])
+            .with_search(search);
+        let addr = resolver.resolve_v4("server1").unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(addr, Ipv4Addr::new(192, 0, 2, 10));
+        assert_eq!(
+            resolver.canonical_name("server1"),
+            Some("server1.corp.example.com")
+        );
+    }
+
+    #[test]
+    fn resolver_follows_resolv_conf() {
+        let conf = ResolvConf::parse(
+            "search example.com\nnameserver 192.0.2.53\nnameserver 198.51.100.53\noptions ndots:2\n",
+        );
+        let resolver = Resolver::from_resolv_conf(&conf);
+
+        assert_eq!(
+            resolver.servers(),
+            [
+                (IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53)), 53),
+                (IpAddr::V4(Ipv4Addr::new(198, 51, 100, 53)), 53),
+            ]
+        );
+        assert_eq!(resolver.search().domains(), ["example.com"]);
+        assert_eq!(
+            resolver.search().candidates("www.corp"),
+            ["www.corp.example.com", "www.corp"]
+        );
+    }
+
+    #[test]
+    fn resolver_without_nameservers_asks_the_default_server() {
+        let resolver = Resolver::from_resolv_conf(&ResolvConf::default());
+        assert_eq!(resolver.servers(), [SERVER]);
+        assert!(resolver.search().domains().is_empty());
+    }
+
+    #[test]
+    fn resolving_again_is_answered_from_the_cache() {
+        let (server, handle) = serve(1, |query| answer(&query, &[Ipv4Addr::new(192, 0, 2, 1)]));
+
+        let mut resolver = Resolver::new()
+            .with_servers(vec![server])
+            .with_root_hints(Vec::new());
+        assert_eq!(
+            resolver.resolve_v4("www.example.com").unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+        // Nothing answers queries any more, so a second one would fail
+        handle.join().unwrap();
+        assert_eq!(
+            resolver.resolve_v4("www.example.com").unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+        assert_eq!(resolver.cached(), 1);
+    }
+
+    #[test]
+    fn round_robin_cycles_through_the_addresses() {
+        let addrs = [
+            Ipv4Addr::new(192, 0, 2, 1),
+            Ipv4Addr::new(192, 0, 2, 2),
+            Ipv4Addr::new(192, 0, 2, 3),
+        ];
+        let (server, handle) = serve(1, move |query| answer(&query, &addrs));
+
+        let mut resolver = Resolver::new()
+            .with_servers(vec![server])
+            .with_root_hints(Vec::new())
+            .with_selection(AddressSelection::RoundRobin);
+        let picked: Vec<_> = (0..4)
+            .map(|_| resolver.resolve_v4("www.example.com").unwrap())
+            .collect();
+        handle.join().unwrap();
+
+        assert_eq!(picked, [addrs[0], addrs[1], addrs[2], addrs[0]]);
+    }
+
+    #[test]
+    fn cname_targets_are_followed_one_query_at_a_time() {
+        let (server, handle) = serve(5, |query| {
+            let mut response = answer(&query, &[]);
+            let alias = query.questions[0].name.as_str();
+            let target = match alias {
+                "www.example.com" => Some("web.example.com"),
+                "web.example.com" => Some("cdn.example.net"),
+                _ => None,
+            };
+            if let Some(target) = target {
+                response.header.answer_count = 1;
+                response.answers.push(cname(alias, target));
+            }
+            response
+        });
+
+        let resolver = Resolver::new()
+            .with_servers(vec![server])
+            .with_root_hints(Vec::new());
+        assert_eq!(
+            resolver.resolve_cname("www.example.com").as_deref(),
+            Some("web.example.com")
+        );
+        assert_eq!(
+            resolver.resolve_cname_chain("www.example.com"),
+            ["web.example.com", "cdn.example.net"]
+        );
+        assert_eq!(resolver.resolve_cname("cdn.example.net"), None);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn canonical_name_is_where_the_cname_chain_ends() {
+        let (server, handle) = serve(1, |query| {
+            let mut response = answer(&query, &[]);
+            response.header.answer_count = 3;
+            response.answers = vec![
+                cname("www.example.com", "web.example.com"),
+                cname("web.example.com", "cdn.example.net"),
+                DnsRecord::A {
+                    domain: "cdn.example.net".to_string(),
+                    class: DnsQueryClass::IN,
+                    addr: Ipv4Addr::new(192, 0, 2, 7),
+                    ttl: 300,
+                },
+            ];
+            response
+        });
+
+        let mut resolver = Resolver::new()
+            .with_servers(vec![server])
+            .with_root_hints(Vec::new());
+        let addr = resolver.resolve_v4("www.example.com").unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(addr, Ipv4Addr::new(192, 0, 2, 7));
+        assert_eq!(
+            resolver.canonical_name("www.example.com"),
+            Some("cdn.example.net")
+        );
+    }
+
+    #[test]
+    fn ipv6_only_host_asked_for_ipv4_is_told_apart_from_a_missing_one() {
+        let (server, handle) = serve(2, |query| {
+            let mut response = answer(&query, &[]);
+            if query.questions[0].query_type == DnsQueryType::AAAA {
+                response.header.answer_count = 1;
+                response.answers.push(DnsRecord::AAAA {
+                    domain: query.questions[0].name.clone(),
+                    class: DnsQueryClass::IN,
+                    addr: "2001:db8::1".parse().unwrap(),
+                    ttl: 300,
+                });
+            }
+            response
+        });
+
+        let sources = Sources {
+            system: false,
+            servers: vec![server],
+            root_hints: Vec::new(),
+        };
+        let trace = trace_hostname(
+            "www.example.com",
+            AddressFamily::V4,
+            &SearchList::default(),
+            &sources,
+        );
+        handle.join().unwrap();
+
+        assert!(matches!(
+            trace.result,
+            Err(ResolveError::OtherFamilyOnly {
+                found: AddressFamily::V6,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn empty_answer_without_recursion_is_reported_and_not_cached() {
+        // An authoritative server asked about a name outside its zones,
+        // with the SOA that would otherwise make it a cacheable negative
+        let (server, handle) = serve(1, |query| {
+            let mut response = response_to(&query);
+            response.header.recursion_desired = query.header.recursion_desired;
+            response.header.authority_count = 1;
+            response.authorities.push(DnsRecord::SOA {
+                domain: "example.com".to_string(),
+                class: DnsQueryClass::IN,
+                primary_ns: "ns1.example.com".to_string(),
+                mailbox: "hostmaster.example.com".to_string(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum_ttl: 300,
+                ttl: 300,
+            });
+            response
+        });
+
+        let mut resolver = Resolver::new()
+            .with_servers(vec![server])
+            .with_root_hints(Vec::new());
+        let err = resolver.resolve_v4("www.example.com").unwrap_err();
+        handle.join().unwrap();
+
+        assert!(matches!(
+            err,
+            ResolveError::RecursionUnavailable { server: asked, .. } if asked == server
+        ));
+        assert_eq!(resolver.cached(), 0);
+    }
 }